@@ -1,100 +1,798 @@
 use anyhow::Result;
 use lopdf::Document;
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+use crate::utils::{format_bytes, rounded};
 
 /// Analysis results for a PDF document
 #[derive(Debug)]
 pub struct PdfAnalysis {
+    pub version: String,
+    pub output_version: String, // What optimizing would declare, without --pdf-version
     pub total_objects: usize,
+    pub page_count: usize,
+    pub full_page_image_pages: usize, // Pages nearly covered by a single image, as scans are
+    pub document_kind: DocumentKind,
+    pub optimized_by: Option<crate::marker::Marker>, // Stamped by an earlier optimization
+    pub page_images: PageImages, // What the full-page images look like
     pub image_count: usize,
+    pub inline_image_count: usize,
     pub font_count: usize,
-    pub text_objects: usize,
+    pub font_file_count: usize,
+    pub duplicates: Duplicates,
+    pub text_objects: usize, // BT ... ET blocks in the pages' content
+    pub text_size: u64, // Decoded bytes of those blocks
+    pub icc_profile_count: usize,
+    pub icc_profiles_size: u64,
+    pub thumbnail_count: usize,
+    pub thumbnails_size: u64,
+    pub signatures: crate::pdf_reader::SignatureInfo,
+    pub inventory: Inventory,
+    pub private_data_size: u64, // Bytes only PieceInfo, Alternates and object metadata reach
     pub estimated_savings: EstimatedSavings,
+    pub measured_savings: Option<MeasuredSavings>, // Set by `measure_savings` for --deep
+    pub preset_simulations: Vec<MeasuredSavings>, // Set by `simulate_presets`
     pub content_breakdown: ContentBreakdown,
+    pub pages: Vec<PageWeight>, // In page order
+    pub image_resolutions: Vec<ImageResolution>, // Image XObjects other than masks, in object order
+    pub image_formats: Vec<ImageFormat>, // Largest first
+    pub objects: Vec<ObjectSize>, // Largest first
+}
+
+/// What the document holds besides its pages' content
+#[derive(Debug, Default)]
+pub struct Inventory {
+    pub annotations: std::collections::BTreeMap<String, usize>, // By subtype, over every page
+    /// Files embedded through the `/EmbeddedFiles` tree or file attachment annotations,
+    /// each counted once
+    pub attachments: usize,
+    pub attachments_size: u64,
+    pub bookmarks: usize, // Outline items at every level
+    pub acroform: bool,
+}
+
+impl Inventory {
+    pub fn annotation_count(&self) -> usize {
+        self.annotations.values().sum()
+    }
+}
+
+/// What an object holds, for the list of largest objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Image,
+    /// Font dictionaries, descriptors and embedded font programs
+    Font,
+    /// Page content streams and Form XObjects
+    Content,
+    /// XMP metadata streams
+    Metadata,
+    /// Attachments
+    EmbeddedFile,
+    Other,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Image => "image",
+            ObjectKind::Font => "font",
+            ObjectKind::Content => "content",
+            ObjectKind::Metadata => "metadata",
+            ObjectKind::EmbeddedFile => "attachment",
+            ObjectKind::Other => "other",
+        }
+    }
+}
+
+/// The size of one object: the stored data of a stream, or roughly what any other object
+/// takes up when written
+#[derive(Debug, Clone)]
+pub struct ObjectSize {
+    pub id: lopdf::ObjectId,
+    pub kind: ObjectKind,
+    pub filter: Option<String>, // The stream's filters, e.g. FlateDecode or ASCII85Decode,DCTDecode
+    pub size: u64,
+}
+
+/// The image XObjects stored with one codec, color space and bit depth
+#[derive(Debug, Clone)]
+pub struct ImageFormat {
+    pub filter: String, // The innermost codec, or "none"
+    pub color_space: String, // The family, e.g. ICCBased or Indexed, or "none" for stencil masks and JPX
+    pub bits_per_component: Option<i64>,
+    pub count: usize,
+    pub size: u64,
+}
+
+impl ImageFormat {
+    /// e.g. DCTDecode/DeviceRGB/8
+    pub fn label(&self) -> String {
+        let bits = self.bits_per_component.map_or("none".to_string(), |bits| bits.to_string());
+        format!("{}/{}/{}", self.filter, self.color_space, bits)
+    }
+}
+
+/// How finely an image XObject is drawn on the pages
+#[derive(Debug, Clone)]
+pub struct ImageResolution {
+    pub id: lopdf::ObjectId,
+    pub width: i64,
+    pub height: i64,
+    pub filter: Option<String>, // The innermost filter, e.g. DCTDecode
+    pub size: u64,
+    pub placements: usize,
+    /// The resolution at its largest placement, which downsampling has to keep; `None` when
+    /// it isn't drawn by page content that could be followed
+    pub effective_dpi: Option<f64>,
+    pub highest_dpi: Option<f64>, // At its smallest placement
+}
+
+impl ImageResolution {
+    /// The share of its pixels left after downsampling to `dpi`
+    fn kept_pixels(&self, dpi: f64) -> f64 {
+        match self.effective_dpi {
+            Some(effective_dpi) if effective_dpi > dpi => (dpi / effective_dpi).powi(2),
+            _ => 1.0,
+        }
+    }
+
+    /// Roughly the share of its stored bytes re-encoding keeps, by how it's stored now
+    fn recompressed_share(&self) -> f64 {
+        match self.filter.as_deref() {
+            Some("DCTDecode" | "JPXDecode") => 0.8,
+            // Bilevel codecs are already about as small as the image gets
+            Some("CCITTFaxDecode" | "JBIG2Decode") => 1.0,
+            _ => 0.5,
+        }
+    }
+}
+
+/// What a page weighs: its content streams and the image XObjects its resources hold,
+/// directly or through Form XObjects. Images shared by several pages count toward each.
+#[derive(Debug, Clone, Default)]
+pub struct PageWeight {
+    pub number: u32,
+    pub content_size: u64,
+    pub image_count: usize,
+    pub image_size: u64,
+    pub exclusive_image_size: u64, // Images no other page uses, which deleting the page would free
+}
+
+impl PageWeight {
+    pub fn total_size(&self) -> u64 {
+        self.content_size + self.image_size
+    }
+}
+
+/// How a document was produced, judged from its pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    /// Pages are text and vector graphics, with images placed here and there
+    BornDigital,
+    /// Nearly every page is one scanned image (possibly under an OCR text layer)
+    Scanned,
+    /// Some pages are scans, others born-digital
+    Mixed,
+}
+
+impl DocumentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentKind::BornDigital => "born-digital",
+            DocumentKind::Scanned => "scanned",
+            DocumentKind::Mixed => "mixed",
+        }
+    }
+}
+
+/// The content of the full-page images, judged from a sample of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageImages {
+    /// No full-page images, or none that could be decoded
+    Unknown,
+    /// Mostly text: bilevel conversion applies
+    Text,
+    /// Mostly photographs or illustrations: bilevel conversion would not apply
+    Pictures,
+    /// Already stored with 1 bit per pixel (CCITT, JBIG2 or plain bilevel)
+    Bilevel,
+}
+
+/// Images and embedded font programs stored more than once, which deduplication would
+/// store once. The redundant bytes are those of the extra copies.
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    pub images: usize,
+    pub image_bytes: u64,
+    pub fonts: usize,
+    pub font_bytes: u64,
 }
 
 #[derive(Debug)]
 pub struct EstimatedSavings {
     pub image_compression: f64, // Percentage
     pub structure_optimization: f64, // Percentage
+    pub duplicates: f64, // Percentage
+    pub thumbnails: f64, // Percentage
     pub total_estimated: f64, // Percentage
 }
 
+/// Savings measured by recompressing a sample of the images and of the other streams,
+/// then extrapolating to the rest
 #[derive(Debug)]
+pub struct MeasuredSavings {
+    pub preset: String,
+    pub images: Extrapolation,
+    pub streams: Extrapolation,
+    pub image_compression: f64, // Percentage
+    pub structure_optimization: f64, // Percentage
+    pub total_estimated: f64, // Percentage
+    pub lossy: bool, // Some sampled image lost detail: re-encoded as JPEG, resized or converted
+}
+
+impl MeasuredSavings {
+    /// The share of the image and stream bytes that were actually recompressed
+    pub fn measured_share(&self) -> f64 {
+        let bytes = self.images.bytes + self.streams.bytes;
+        if bytes == 0 {
+            return 1.0;
+        }
+        (self.images.sampled_bytes + self.streams.sampled_bytes) as f64 / bytes as f64
+    }
+
+    pub fn confidence(&self) -> &'static str {
+        match self.measured_share() {
+            share if share >= 0.5 => "high",
+            share if share >= 0.15 => "medium",
+            _ => "low",
+        }
+    }
+}
+
+/// Objects of one kind, a sample of which was recompressed
+#[derive(Debug, Default)]
+pub struct Extrapolation {
+    pub count: usize,
+    pub bytes: u64,
+    pub sampled: usize,
+    pub sampled_bytes: u64,
+    pub bytes_after: f64, // Estimated for all of them
+}
+
+impl Extrapolation {
+    /// The share of the bytes recompressing saves, as a percentage
+    fn savings(&self) -> f64 {
+        if self.bytes == 0 {
+            return 0.0;
+        }
+        (1.0 - self.bytes_after / self.bytes as f64) * 100.0
+    }
+}
+
+/// What the objects take up in the file, each counted once under what it is. The file's
+/// size adds the cross-reference tables, trailers and anything not loaded, such as the
+/// objects earlier revisions replaced.
+#[derive(Debug, Default)]
 pub struct ContentBreakdown {
     pub images_size: u64,
-    pub fonts_size: u64,
-    pub text_size: u64,
-    pub other_size: u64,
+    pub fonts_size: u64, // Font dictionaries, descriptors and embedded programs
+    pub content_size: u64, // Page content streams and Form XObjects
+    pub metadata_size: u64,
+    pub attachments_size: u64,
+    pub uncategorized_streams_size: u64,
+    pub other_size: u64, // Objects other than streams: pages, annotations, the catalog...
     pub total_size: u64,
 }
 
+impl ContentBreakdown {
+    fn add(&mut self, kind: ObjectKind, is_stream: bool, size: u64) {
+        let bucket = match kind {
+            ObjectKind::Image => &mut self.images_size,
+            ObjectKind::Font => &mut self.fonts_size,
+            ObjectKind::Content => &mut self.content_size,
+            ObjectKind::Metadata => &mut self.metadata_size,
+            ObjectKind::EmbeddedFile => &mut self.attachments_size,
+            ObjectKind::Other if is_stream => &mut self.uncategorized_streams_size,
+            ObjectKind::Other => &mut self.other_size,
+        };
+        *bucket += size;
+        self.total_size += size;
+    }
+}
+
+/// Share of a page's area an image must cover to count as a full-page image
+const FULL_PAGE_COVERAGE: f64 = 0.85;
+
+/// Full-page images decoded to judge their content; scans are rarely a mix
+const PAGE_IMAGE_SAMPLES: usize = 5;
+
+/// Pages listed under "Heaviest pages"
+const HEAVIEST_PAGES: usize = 5;
+
+/// Resolutions the images are counted against
+const DPI_THRESHOLDS: [u32; 3] = [150, 300, 600];
+
+/// The resolution the pixel reduction and the image savings estimate assume downsampling to
+const DOWNSAMPLE_DPI: f64 = 150.0;
+
+/// Images and other streams recompressed by `--deep`
+const IMAGE_SAMPLES: usize = 10;
+const STREAM_SAMPLES: usize = 20;
+
+/// Classify a document by the share of its pages that are full-page images. Pages of
+/// scans carry no fonts unless they were OCRed, so without any text a document made only
+/// of images counts as scanned even when its images don't quite fill the pages.
+fn classify(page_count: usize, full_page_image_pages: usize, has_text: bool, image_count: usize) -> DocumentKind {
+    if page_count == 0 {
+        return DocumentKind::BornDigital;
+    }
+    let share = full_page_image_pages as f64 / page_count as f64;
+    if share >= 0.8 || (!has_text && image_count > 0 && share > 0.0) {
+        DocumentKind::Scanned
+    } else if share >= 0.2 {
+        DocumentKind::Mixed
+    } else {
+        DocumentKind::BornDigital
+    }
+}
+
+/// Decode a few of the full-page images to see whether they hold text or pictures
+fn sample_page_images(doc: &Document, images: &[lopdf::ObjectId]) -> PageImages {
+    let mut text = 0;
+    let mut pictures = 0;
+    let mut bilevel = 0;
+    for id in images.iter().take(PAGE_IMAGE_SAMPLES) {
+        let Ok(lopdf::Object::Stream(stream)) = doc.get_object(*id) else { continue };
+        let bits = stream.dict.get(b"BitsPerComponent").and_then(lopdf::Object::as_i64).ok();
+        if bits == Some(1) || matches!(stream.dict.get(b"ImageMask"), Ok(lopdf::Object::Boolean(true))) {
+            bilevel += 1;
+            continue;
+        }
+        match crate::image_optimizer::is_text_like_image(stream) {
+            Some(true) => text += 1,
+            Some(false) => pictures += 1,
+            None => {}
+        }
+    }
+    if bilevel + text + pictures == 0 {
+        PageImages::Unknown
+    } else if bilevel >= text.max(pictures) {
+        PageImages::Bilevel
+    } else if text >= pictures {
+        PageImages::Text
+    } else {
+        PageImages::Pictures
+    }
+}
+
+/// The weight of every page. Images are counted once in the content breakdown, but here
+/// toward every page that uses them.
+fn page_weights(doc: &Document) -> Vec<PageWeight> {
+    let stream_size = |id: lopdf::ObjectId| {
+        doc.get_object(id)
+            .and_then(lopdf::Object::as_stream)
+            .map(|stream| stream.content.len() as u64)
+            .unwrap_or(0)
+    };
+    let mut weights: std::collections::BTreeMap<u32, PageWeight> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(number, page_id)| {
+            let content_size = doc.get_page_contents(page_id).into_iter().map(stream_size).sum();
+            (number, PageWeight { number, content_size, ..Default::default() })
+        })
+        .collect();
+    for (image, pages) in crate::placement::image_pages(doc) {
+        let size = stream_size(image);
+        for number in &pages {
+            let Some(weight) = weights.get_mut(number) else { continue };
+            weight.image_count += 1;
+            weight.image_size += size;
+            if pages.len() == 1 {
+                weight.exclusive_image_size += size;
+            }
+        }
+    }
+    weights.into_values().collect()
+}
+
+/// The resolution of every image XObject but masks, which keep theirs when optimizing
+fn image_resolutions(doc: &Document, display_sizes: &crate::placement::DisplaySizes) -> Vec<ImageResolution> {
+    let masks = crate::image_optimizer::collect_mask_ids(doc);
+    doc.objects
+        .iter()
+        .filter_map(|(id, object)| {
+            let lopdf::Object::Stream(stream) = object else { return None };
+            if !crate::image_optimizer::is_image_stream(stream)
+                || masks.contains(id)
+                || crate::image_optimizer::is_stencil_mask(stream)
+            {
+                return None;
+            }
+            let dimension = |key: &[u8]| stream.dict.get(key).and_then(lopdf::Object::as_i64).ok().filter(|&n| n > 0);
+            let (width, height) = (dimension(b"Width")?, dimension(b"Height")?);
+            let placements = display_sizes.placements(*id).unwrap_or_default();
+            // Placements with no area show nothing, so they don't count
+            let dpis: Vec<f64> = placements
+                .iter()
+                .map(|&size| crate::placement::effective_dpi(width, height, size))
+                .filter(|dpi| dpi.is_finite())
+                .collect();
+            let effective_dpi = display_sizes
+                .largest(*id)
+                .map(|size| crate::placement::effective_dpi(width, height, size))
+                .filter(|dpi| dpi.is_finite());
+            Some(ImageResolution {
+                id: *id,
+                width,
+                height,
+                filter: crate::filters::stream_filters(stream)
+                    .last()
+                    .map(|filter| String::from_utf8_lossy(filter).into_owned()),
+                size: stream.content.len() as u64,
+                placements: placements.len(),
+                effective_dpi,
+                highest_dpi: dpis.into_iter().reduce(f64::max),
+            })
+        })
+        .collect()
+}
+
+/// The share of the images' pixels downsampling to `dpi` would remove, as a percentage
+fn pixel_reduction(images: &[ImageResolution], dpi: f64) -> f64 {
+    let pixels = |image: &ImageResolution| (image.width * image.height) as f64;
+    let total: f64 = images.iter().map(pixels).sum();
+    if total == 0.0 {
+        return 0.0;
+    }
+    let kept: f64 = images.iter().map(|image| pixels(image) * image.kept_pixels(dpi)).sum();
+    (total - kept) / total * 100.0
+}
+
+/// The text blocks (`BT` ... `ET`) in the decoded content of every page, and their size
+/// in bytes, operators and operands included
+fn text_blocks(doc: &Document) -> (usize, u64) {
+    let mut blocks = 0;
+    let mut size = 0;
+    for page_id in doc.get_pages().into_values() {
+        let Some(content) = crate::resources::page_content(doc, page_id) else { continue };
+        let Some(operations) = crate::inline_images::split_operations(&content) else { continue };
+        let mut in_text = false;
+        for operation in &operations {
+            if operation.operator == b"BT" {
+                blocks += 1;
+                in_text = true;
+            }
+            if in_text {
+                // Each operand and the operator are followed by a separator
+                size += operation.operands.iter().map(|operand| operand.len() as u64 + 1).sum::<u64>()
+                    + operation.operator.len() as u64
+                    + 1;
+            }
+            if operation.operator == b"ET" {
+                in_text = false;
+            }
+        }
+    }
+    (blocks, size)
+}
+
+/// Count the annotations of every page, the embedded files, the outline items and the form
+fn inventory(doc: &Document) -> Inventory {
+    use crate::objects::resolve;
+
+    let mut inventory = Inventory {
+        bookmarks: crate::navigation::outline_len(doc),
+        ..Default::default()
+    };
+    let catalog = doc.catalog().ok();
+    inventory.acroform = catalog.is_some_and(|catalog| catalog.has(b"AcroForm"));
+
+    // File specifications, from the name tree and from file attachment annotations
+    let mut file_specs: Vec<lopdf::Object> = catalog
+        .and_then(|catalog| catalog.get(b"Names").ok())
+        .and_then(|names| resolve(doc, names).as_dict().ok())
+        .and_then(|names| names.get(b"EmbeddedFiles").ok())
+        .map(|tree| crate::navigation::name_tree(doc, tree).into_iter().map(|(_, spec)| spec).collect())
+        .unwrap_or_default();
+    for page_id in doc.get_pages().into_values() {
+        let Ok(page) = doc.get_dictionary(page_id) else { continue };
+        let Ok(lopdf::Object::Array(annotations)) = page.get(b"Annots").map(|annots| resolve(doc, annots)) else { continue };
+        for annotation in annotations.iter().filter_map(|annotation| resolve(doc, annotation).as_dict().ok()) {
+            let subtype = match annotation.get(b"Subtype") {
+                Ok(lopdf::Object::Name(subtype)) => String::from_utf8_lossy(subtype).into_owned(),
+                _ => "unknown".to_string(),
+            };
+            if subtype == "FileAttachment" {
+                file_specs.extend(annotation.get(b"FS").ok().cloned());
+            }
+            *inventory.annotations.entry(subtype).or_default() += 1;
+        }
+    }
+
+    // The same file may be listed in both places, so count the embedded streams
+    let mut embedded = std::collections::BTreeSet::new();
+    for spec in &file_specs {
+        let Ok(spec) = resolve(doc, spec).as_dict() else { continue };
+        let Ok(files) = spec.get(b"EF").map(|files| resolve(doc, files)).and_then(lopdf::Object::as_dict) else { continue };
+        for (_, file) in files.iter() {
+            if let Ok(id) = file.as_reference() {
+                embedded.insert(id);
+            }
+        }
+    }
+    inventory.attachments = embedded.len();
+    inventory.attachments_size = embedded
+        .iter()
+        .filter_map(|id| doc.get_object(*id).and_then(lopdf::Object::as_stream).ok())
+        .map(|stream| stream.content.len() as u64)
+        .sum();
+    inventory
+}
+
+/// Classify an object by what it is, or by what uses it
+fn object_kind(
+    id: lopdf::ObjectId,
+    object: &lopdf::Object,
+    font_files: &std::collections::HashSet<lopdf::ObjectId>,
+    page_contents: &std::collections::HashSet<lopdf::ObjectId>,
+) -> ObjectKind {
+    let dict = match object {
+        lopdf::Object::Stream(stream) => &stream.dict,
+        lopdf::Object::Dictionary(dict) => dict,
+        _ => return ObjectKind::Other,
+    };
+    let name = |key: &[u8]| match dict.get(key) {
+        Ok(lopdf::Object::Name(name)) => Some(name.as_slice()),
+        _ => None,
+    };
+    if font_files.contains(&id) || matches!(name(b"Type"), Some(b"Font" | b"FontDescriptor")) {
+        return ObjectKind::Font;
+    }
+    if page_contents.contains(&id) {
+        return ObjectKind::Content;
+    }
+    match (name(b"Type"), name(b"Subtype")) {
+        (_, Some(b"Image")) => ObjectKind::Image,
+        (_, Some(b"Form")) => ObjectKind::Content,
+        (Some(b"Metadata"), _) | (_, Some(b"XML")) => ObjectKind::Metadata,
+        (Some(b"EmbeddedFile"), _) => ObjectKind::EmbeddedFile,
+        _ => ObjectKind::Other,
+    }
+}
+
+/// The family of an image's color space: its name, or the name an array starts with
+fn color_space_family(doc: &Document, stream: &lopdf::Stream) -> String {
+    let family = match stream.dict.get(b"ColorSpace").map(|space| crate::objects::resolve(doc, space)) {
+        Ok(lopdf::Object::Name(name)) => Some(name.as_slice()),
+        Ok(lopdf::Object::Array(items)) => items.first().and_then(|first| first.as_name().ok()),
+        _ => None,
+    };
+    family.map_or("none".to_string(), |family| String::from_utf8_lossy(family).into_owned())
+}
+
+/// What each object takes up in the file. Objects stored directly take their written size
+/// and their `obj`/`endobj` lines; objects inside object streams share the stream's in
+/// proportion to their written size. Object streams and cross-reference streams themselves
+/// count for nothing, as they're the packaging.
+fn stored_sizes(doc: &Document) -> std::collections::HashMap<lopdf::ObjectId, u64> {
+    let direct = |id: lopdf::ObjectId, object: &lopdf::Object| {
+        written_size(object) + format!("{} {} obj\n", id.0, id.1).len() as u64 + "\nendobj\n".len() as u64
+    };
+    let mut containers: std::collections::HashMap<u32, Vec<lopdf::ObjectId>> = std::collections::HashMap::new();
+    let mut contained = std::collections::HashSet::new();
+    for (&number, entry) in &doc.reference_table.entries {
+        if let lopdf::xref::XrefEntry::Compressed { container, .. } = *entry {
+            containers.entry(container).or_default().push((number, 0));
+            contained.insert((number, 0));
+        }
+    }
+    let mut sizes = std::collections::HashMap::with_capacity(doc.objects.len());
+    for (id, object) in &doc.objects {
+        let packaging = matches!(object, lopdf::Object::Stream(stream) if stream.dict.type_is(b"ObjStm") || stream.dict.type_is(b"XRef"));
+        if !packaging && !contained.contains(id) {
+            sizes.insert(*id, direct(*id, object));
+        }
+    }
+    for (container, members) in &containers {
+        let Some(stream) = doc.objects.get(&(*container, 0)) else { continue };
+        let stored = direct((*container, 0), stream);
+        let members: Vec<(lopdf::ObjectId, u64)> =
+            members.iter().filter_map(|id| doc.objects.get(id).map(|object| (*id, written_size(object)))).collect();
+        let written: u64 = members.iter().map(|(_, size)| size).sum();
+        for (id, size) in members {
+            let share = if written > 0 { stored as f64 * size as f64 / written as f64 } else { 0.0 };
+            sizes.insert(id, share.round() as u64);
+        }
+    }
+    sizes
+}
+
+/// Roughly how many bytes an object takes up when written
+fn written_size(object: &lopdf::Object) -> u64 {
+    use lopdf::Object;
+    let dictionary_size = |dict: &lopdf::Dictionary| -> u64 {
+        dict.iter().map(|(key, value)| key.len() as u64 + 2 + written_size(value)).sum::<u64>() + 4
+    };
+    match object {
+        Object::Null => 4,
+        Object::Boolean(_) => 5,
+        Object::Integer(value) => value.to_string().len() as u64,
+        Object::Real(_) => 6,
+        Object::Name(name) => name.len() as u64 + 1,
+        Object::String(text, _) => text.len() as u64 + 2,
+        Object::Array(items) => items.iter().map(|item| written_size(item) + 1).sum::<u64>() + 2,
+        Object::Dictionary(dict) => dictionary_size(dict),
+        Object::Stream(stream) => dictionary_size(&stream.dict) + stream.content.len() as u64 + 18,
+        Object::Reference((number, generation)) => format!("{} {} R", number, generation).len() as u64,
+    }
+}
+
+/// The options that suit a document of this kind
+fn recommendation(analysis: &PdfAnalysis) -> String {
+    let bilevel = match analysis.page_images {
+        PageImages::Text => "; page images look like text, so they'll be stored as 1-bit images",
+        PageImages::Pictures => "; page images look like photos, so they stay grayscale JPEG (bilevel conversion won't apply)",
+        PageImages::Bilevel => "; page images are already 1-bit, so only the file structure will shrink",
+        PageImages::Unknown => "",
+    };
+    match analysis.document_kind {
+        DocumentKind::Scanned => format!("--preset scan --quality 70{}", bilevel),
+        DocumentKind::Mixed => {
+            let flags = if analysis.page_images == PageImages::Text { " --bilevel" } else { "" };
+            format!("--preset web --quality 80 --max-dpi 200{}{}", flags, bilevel)
+        }
+        DocumentKind::BornDigital if analysis.image_count + analysis.inline_image_count == 0 => {
+            "--preset archive (there are no images; the savings come from the file structure)".to_string()
+        }
+        DocumentKind::BornDigital => "--preset web --quality 80".to_string(),
+    }
+}
+
 /// Analyze a PDF document and calculate optimization potential
 pub fn analyze_pdf(doc: &Document) -> Result<PdfAnalysis> {
     let mut image_count = 0;
     let mut font_count = 0;
-    let mut text_objects = 0;
-    let mut images_size = 0u64;
-    let mut fonts_size = 0u64;
-    let mut text_size = 0u64;
-    let mut other_size = 0u64;
+    let mut content_breakdown = ContentBreakdown::default();
+    let stored_sizes = stored_sizes(doc);
+    let mut objects = Vec::with_capacity(doc.objects.len());
+    let mut image_formats: std::collections::HashMap<(String, String, Option<i64>), (usize, u64)> =
+        std::collections::HashMap::new();
+    let font_files = crate::dedup::font_file_ids(doc);
+    let font_file_set: std::collections::HashSet<lopdf::ObjectId> = font_files.iter().copied().collect();
+    let page_contents: std::collections::HashSet<lopdf::ObjectId> =
+        doc.get_pages().into_values().flat_map(|page_id| doc.get_page_contents(page_id)).collect();
 
     // Iterate through all objects to analyze content
-    for (_, obj) in &doc.objects {
+    for (id, obj) in &doc.objects {
+        let (filter, size) = match obj {
+            lopdf::Object::Stream(stream) => {
+                let filters = crate::filters::stream_filters(stream);
+                let filter = (!filters.is_empty()).then(|| {
+                    filters.iter().map(|filter| String::from_utf8_lossy(filter).into_owned()).collect::<Vec<_>>().join(",")
+                });
+                (filter, stream.content.len() as u64)
+            }
+            _ => (None, written_size(obj)),
+        };
+        let kind = object_kind(*id, obj, &font_file_set, &page_contents);
+        let stored_size = stored_sizes.get(id).copied().unwrap_or(0);
+        content_breakdown.add(kind, matches!(obj, lopdf::Object::Stream(_)), stored_size);
+        objects.push(ObjectSize { id: *id, kind, filter, size });
+
         match obj {
             lopdf::Object::Stream(ref stream) => {
                 // Check if this is an image
-                if let Ok(subtype) = stream.dict.get(b"Subtype") {
-                    if let lopdf::Object::Name(ref name) = subtype {
-                        if name == b"Image" {
-                            image_count += 1;
-                            images_size += stream.content.len() as u64;
-                        }
-                    }
-                }
-
-                // Check if this is a font stream
-                if let Ok(obj_type) = stream.dict.get(b"Type") {
-                    if let lopdf::Object::Name(ref name) = obj_type {
-                        if name == b"Font" {
-                            font_count += 1;
-                            fonts_size += stream.content.len() as u64;
-                        }
-                    }
-                }
-
-                // Estimate text content (rough heuristic)
-                if stream.dict.get(b"Length").is_ok() {
-                    let content = &stream.content;
-                    if content.windows(4).any(|w| w == b"BT\n") {
-                        text_objects += 1;
-                        text_size += content.len() as u64;
+                if let Ok(lopdf::Object::Name(name)) = stream.dict.get(b"Subtype") {
+                    if name == b"Image" {
+                        image_count += 1;
+                        // Arrays of filters end with the codec; the rest only wrap it
+                        let filter = crate::filters::stream_filters(stream)
+                            .last()
+                            .map_or("none".to_string(), |filter| String::from_utf8_lossy(filter).into_owned());
+                        let bits = stream.dict.get(b"BitsPerComponent").and_then(lopdf::Object::as_i64).ok();
+                        let format = image_formats.entry((filter, color_space_family(doc, stream), bits)).or_default();
+                        format.0 += 1;
+                        format.1 += stream.content.len() as u64;
                     }
                 }
             }
             lopdf::Object::Dictionary(ref dict) => {
                 // Check for font dictionaries
-                if let Ok(obj_type) = dict.get(b"Type") {
-                    if let lopdf::Object::Name(ref name) = obj_type {
-                        if name == b"Font" {
-                            font_count += 1;
-                        }
+                if let Ok(lopdf::Object::Name(name)) = dict.get(b"Type") {
+                    if name == b"Font" {
+                        font_count += 1;
                     }
                 }
             }
-            _ => {
-                // Other objects
-                other_size += 100; // Rough estimate
-            }
+            _ => {}
         }
     }
 
+    objects.sort_by_key(|object| (std::cmp::Reverse(object.size), object.id));
+    let mut image_formats: Vec<ImageFormat> = image_formats
+        .into_iter()
+        .map(|((filter, color_space, bits_per_component), (count, size))| ImageFormat {
+            filter,
+            color_space,
+            bits_per_component,
+            count,
+            size,
+        })
+        .collect();
+    image_formats.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.label().cmp(&b.label())));
+
+    let (text_objects, text_size) = text_blocks(doc);
+
+    // Images embedded directly in content streams
+    let inline_image_count = crate::inline_images::count_inline_images(doc);
+
+    // Embedded ICC profiles
+    let icc_profiles = crate::icc::icc_profile_ids(doc);
+    let icc_profiles_size: u64 = icc_profiles
+        .iter()
+        .filter_map(|id| doc.get_object(*id).ok())
+        .filter_map(|obj| obj.as_stream().ok())
+        .map(|stream| stream.content.len() as u64)
+        .sum();
+
+    // Embedded font programs, and the copies of them and of images deduplication would remove
+    let stream_size = |id: &lopdf::ObjectId| {
+        doc.get_object(*id)
+            .and_then(lopdf::Object::as_stream)
+            .map(|stream| stream.content.len() as u64)
+            .unwrap_or(0)
+    };
+    let duplicate_fonts = crate::dedup::find_duplicates(doc, &font_files);
+    let duplicate_images = crate::dedup::count_duplicate_images(doc);
+    let duplicates = Duplicates {
+        images: duplicate_images.merged,
+        image_bytes: duplicate_images.bytes_saved,
+        fonts: duplicate_fonts.len(),
+        font_bytes: duplicate_fonts.keys().map(stream_size).sum(),
+    };
+
+    // Scanned pages are one image drawn over (nearly) the whole page
+    let display_sizes = crate::placement::image_display_sizes(doc);
+    let image_resolutions = image_resolutions(doc, &display_sizes);
+    let page_coverage = display_sizes.page_coverage;
+    let mut full_page_images: Vec<lopdf::ObjectId> = page_coverage
+        .iter()
+        .filter(|(_, coverage)| *coverage >= FULL_PAGE_COVERAGE)
+        .filter_map(|(image, _)| *image)
+        .collect();
+    let full_page_image_pages = full_page_images.len();
+    full_page_images.dedup();
+    let document_kind = classify(
+        page_coverage.len(),
+        full_page_image_pages,
+        text_objects > 0 || font_count > 0,
+        image_count + inline_image_count,
+    );
+    let page_images = if document_kind == DocumentKind::BornDigital {
+        PageImages::Unknown
+    } else {
+        sample_page_images(doc, &full_page_images)
+    };
+
+    // Page thumbnails, which the web and maximum presets drop
+    let thumbnails = crate::objects::thumbnail_ids(doc);
+    let thumbnails_size: u64 = thumbnails.iter().map(stream_size).sum();
+
     let total_objects = doc.objects.len();
-    let total_size = images_size + fonts_size + text_size + other_size;
+    let total_size = content_breakdown.total_size;
 
     // Estimate savings potential
-    let image_compression = if image_count > 0 {
-        // Assume 30-70% savings on images depending on current compression
-        50.0
+    // Images drawn finer than the target resolution lose pixels, then all are re-encoded
+    let stored: u64 = image_resolutions.iter().map(|image| image.size).sum();
+    let image_compression = if stored > 0 {
+        let kept: f64 = image_resolutions
+            .iter()
+            .map(|image| image.size as f64 * image.kept_pixels(DOWNSAMPLE_DPI) * image.recompressed_share())
+            .sum();
+        (1.0 - kept / stored as f64) * 100.0
     } else {
         0.0
     };
@@ -106,50 +804,630 @@ pub fn analyze_pdf(doc: &Document) -> Result<PdfAnalysis> {
         10.0
     };
 
-    let total_estimated = (image_compression * 0.6) + (structure_optimization * 0.4);
+    // Thumbnails and duplicate copies go entirely; the rest shrinks by the estimates above
+    let share = |bytes: u64| if bytes > 0 { bytes as f64 / total_size as f64 * 100.0 } else { 0.0 };
+    let thumbnails_share = share(thumbnails_size);
+    let duplicates_share = share(duplicates.image_bytes + duplicates.font_bytes);
+    let removed = (thumbnails_share + duplicates_share).min(100.0);
+    let total_estimated =
+        (removed + ((image_compression * 0.6) + (structure_optimization * 0.4)) * (100.0 - removed) / 100.0).min(100.0);
 
     Ok(PdfAnalysis {
+        version: crate::pdf_reader::get_pdf_info(doc).version,
+        output_version: crate::pdf_writer::output_version(doc, None),
         total_objects,
+        page_count: page_coverage.len(),
+        full_page_image_pages,
+        document_kind,
+        optimized_by: crate::marker::read_marker(doc),
+        page_images,
         image_count,
+        inline_image_count,
         font_count,
+        font_file_count: font_files.len(),
+        duplicates,
         text_objects,
+        text_size,
+        icc_profile_count: icc_profiles.len(),
+        icc_profiles_size,
+        thumbnail_count: thumbnails.len(),
+        thumbnails_size,
+        signatures: crate::pdf_reader::signature_info(doc),
+        inventory: inventory(doc),
+        private_data_size: crate::metadata::private_data_size(doc),
         estimated_savings: EstimatedSavings {
             image_compression,
             structure_optimization,
+            duplicates: duplicates_share,
+            thumbnails: thumbnails_share,
             total_estimated,
         },
-        content_breakdown: ContentBreakdown {
-            images_size,
-            fonts_size,
-            text_size,
-            other_size,
-            total_size,
-        },
+        measured_savings: None,
+        preset_simulations: Vec::new(),
+        content_breakdown,
+        pages: page_weights(doc),
+        image_resolutions,
+        image_formats,
+        objects,
     })
 }
 
-/// Print analysis results in a human-readable format
-pub fn print_analysis(analysis: &PdfAnalysis, show_savings: bool) {
+/// Recompress a sample of the images with `image_settings` and of the other streams with
+/// `save_options`, without changing the document, and extrapolate what optimizing with
+/// them would save
+pub fn measure_savings(
+    doc: &Document,
+    analysis: &PdfAnalysis,
+    preset: &str,
+    image_settings: &crate::image_optimizer::ImageSettings,
+    save_options: &crate::pdf_writer::SaveOptions,
+) -> Result<MeasuredSavings> {
+    use crate::image_optimizer::{collect_mask_ids, is_image_stream, is_stencil_mask, optimize_image_object, ImageOutcome};
+
+    let thumbnails: std::collections::HashSet<lopdf::ObjectId> = match save_options.strip_thumbnails {
+        true => crate::objects::thumbnail_ids(doc).into_iter().collect(),
+        false => Default::default(),
+    };
+    let mut images = Vec::new();
+    let mut streams = Vec::new();
+    for (id, object) in &doc.objects {
+        let lopdf::Object::Stream(stream) = object else { continue };
+        if thumbnails.contains(id) {
+            continue;
+        }
+        match is_image_stream(stream) {
+            true => images.push((*id, stream.content.len() as u64)),
+            false => streams.push((*id, stream.content.len() as u64)),
+        }
+    }
+
+    let masks = collect_mask_ids(doc);
+    let display_sizes = image_settings.max_dpi.map(|_| crate::placement::image_display_sizes(doc));
+    let mut lossy = false;
+    let images = extrapolate(images, IMAGE_SAMPLES, |id| {
+        let Ok(lopdf::Object::Stream(stream)) = doc.get_object(id) else { return Ok(0) };
+        let is_mask = masks.contains(&id) || is_stencil_mask(stream);
+        Ok(match optimize_image_object(id, stream, image_settings, is_mask, display_sizes.as_ref())? {
+            ImageOutcome::Optimized(optimized) => {
+                lossy |= loses_detail(stream, &optimized);
+                optimized.stream.content.len() as u64
+            }
+            _ => stream.content.len() as u64,
+        })
+    })?;
+    let streams = extrapolate(streams, STREAM_SAMPLES, |id| {
+        let Ok(lopdf::Object::Stream(stream)) = doc.get_object(id) else { return Ok(0) };
+        let compressed = save_options
+            .enable_compression
+            .then(|| crate::pdf_writer::compressed_content(stream, save_options))
+            .flatten();
+        Ok(compressed.map_or(stream.content.len(), |content| content.len()) as u64)
+    })?;
+
+    // Duplicate copies go entirely, after shrinking like the rest; thumbnails go if stripped
+    let duplicates = &analysis.duplicates;
+    let ratio = |extrapolation: &Extrapolation| match extrapolation.bytes {
+        0 => 1.0,
+        bytes => extrapolation.bytes_after / bytes as f64,
+    };
+    let total = analysis.content_breakdown.total_size;
+    let thumbnails_size: u64 = thumbnails
+        .iter()
+        .filter_map(|id| doc.get_object(*id).and_then(lopdf::Object::as_stream).ok())
+        .map(|stream| stream.content.len() as u64)
+        .sum();
+    let removed = duplicates.image_bytes as f64 * ratio(&images)
+        + duplicates.font_bytes as f64 * ratio(&streams)
+        + thumbnails_size as f64;
+    let after = total as f64 - (images.bytes + streams.bytes) as f64 + images.bytes_after + streams.bytes_after - removed;
+    let total_estimated = if total > 0 { ((1.0 - after / total as f64) * 100.0).clamp(0.0, 100.0) } else { 0.0 };
+
+    Ok(MeasuredSavings {
+        preset: preset.to_string(),
+        image_compression: images.savings(),
+        structure_optimization: streams.savings(),
+        images,
+        streams,
+        total_estimated,
+        lossy,
+    })
+}
+
+/// Measure the savings of every preset, as `measure_savings` does for one
+pub fn simulate_presets(doc: &Document, analysis: &PdfAnalysis, quality: u8) -> Result<Vec<MeasuredSavings>> {
+    use clap::ValueEnum;
+
+    crate::cli::Preset::value_variants()
+        .iter()
+        .map(|preset| {
+            let name = preset.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+            let image_settings = crate::image_optimizer::create_image_settings_for_preset(preset, quality);
+            let save_options = crate::pdf_writer::create_save_options_for_preset(preset);
+            measure_savings(doc, analysis, &name, &image_settings, &save_options)
+        })
+        .collect()
+}
+
+/// Whether optimizing an image changed its pixels rather than only how they're stored
+fn loses_detail(original: &lopdf::Stream, optimized: &crate::image_optimizer::OptimizedImage) -> bool {
+    let dimensions = |stream: &lopdf::Stream| {
+        let dimension = |key: &[u8]| stream.dict.get(key).and_then(lopdf::Object::as_i64).ok();
+        (dimension(b"Width"), dimension(b"Height"))
+    };
+    let jpeg = crate::filters::stream_filters(&optimized.stream).last().is_some_and(|filter| filter == b"DCTDecode");
+    optimized.converted_to_grayscale
+        || optimized.converted_to_bilevel
+        || dimensions(original) != dimensions(&optimized.stream)
+        || (jpeg && optimized.recompression_skipped.is_none())
+}
+
+/// Split `objects` into up to `samples` runs of similar size, measure the middle object of
+/// each run and scale its ratio to the whole run
+fn extrapolate(
+    mut objects: Vec<(lopdf::ObjectId, u64)>,
+    samples: usize,
+    mut measure: impl FnMut(lopdf::ObjectId) -> Result<u64>,
+) -> Result<Extrapolation> {
+    objects.sort_by_key(|&(id, size)| (size, id));
+    let mut extrapolation = Extrapolation {
+        count: objects.len(),
+        bytes: objects.iter().map(|(_, size)| size).sum(),
+        ..Default::default()
+    };
+    let runs = samples.min(objects.len());
+    for run in 0..runs {
+        let chunk = &objects[run * objects.len() / runs..(run + 1) * objects.len() / runs];
+        let (id, size) = chunk[chunk.len() / 2];
+        let chunk_bytes: u64 = chunk.iter().map(|(_, size)| size).sum();
+        let ratio = if size > 0 { measure(id)? as f64 / size as f64 } else { 1.0 };
+        extrapolation.sampled += 1;
+        extrapolation.sampled_bytes += size;
+        extrapolation.bytes_after += chunk_bytes as f64 * ratio;
+    }
+    Ok(extrapolation)
+}
+
+/// Print analysis results in a human-readable format, listing the `top` largest objects
+pub fn print_analysis(analysis: &PdfAnalysis, file_size: u64, show_savings: bool, top: usize) {
     println!("PDF Analysis Results:");
     println!("====================");
+    if analysis.output_version == analysis.version {
+        println!("PDF version: {}", analysis.version);
+    } else {
+        println!("PDF version: {} (output: {})", analysis.version, analysis.output_version);
+    }
+    println!("Document kind: {}", analysis.document_kind.as_str());
+    if let Some(marker) = &analysis.optimized_by {
+        println!("Optimized by: {}", marker.describe());
+    }
     println!("Total objects: {}", analysis.total_objects);
+    println!("Pages: {}", analysis.page_count);
     println!("Images: {}", analysis.image_count);
+    if analysis.full_page_image_pages > 0 {
+        println!("Pages that are one full-page image: {}", analysis.full_page_image_pages);
+    }
+    if analysis.inline_image_count > 0 {
+        println!("Inline images: {}", analysis.inline_image_count);
+    }
     println!("Fonts: {}", analysis.font_count);
-    println!("Text objects: {}", analysis.text_objects);
+    if analysis.font_file_count > 0 {
+        println!(
+            "Embedded font programs: {} ({} unique, {} duplicates)",
+            analysis.font_file_count,
+            analysis.font_file_count - analysis.duplicates.fonts,
+            analysis.duplicates.fonts
+        );
+    }
+    if analysis.text_objects > 0 {
+        println!("Text objects: {} ({} of text operators)", analysis.text_objects, format_bytes(analysis.text_size));
+    } else {
+        println!("Text objects: 0");
+    }
+    println!(
+        "ICC profiles: {} ({})",
+        analysis.icc_profile_count,
+        crate::utils::format_bytes(analysis.icc_profiles_size)
+    );
+    if analysis.thumbnail_count > 0 {
+        println!(
+            "Page thumbnails: {} ({})",
+            analysis.thumbnail_count,
+            crate::utils::format_bytes(analysis.thumbnails_size)
+        );
+    }
+    let signatures = &analysis.signatures;
+    if signatures.is_signed() {
+        println!(
+            "Digitally signed: yes (signed fields: {}{})",
+            signatures.signed_fields,
+            if signatures.permissions_signed { ", plus a certification or usage rights signature" } else { "" }
+        );
+    } else {
+        println!("Digitally signed: no");
+    }
+    if signatures.empty_fields > 0 {
+        println!("Empty signature fields: {}", signatures.empty_fields);
+    }
+    if analysis.private_data_size > 0 {
+        println!(
+            "Private application data: {} (removable with --strip-private-data)",
+            crate::utils::format_bytes(analysis.private_data_size)
+        );
+    }
+    let duplicates = &analysis.duplicates;
+    let mut copies = Vec::new();
+    if duplicates.images > 0 {
+        copies.push(format!(
+            "{} duplicate image{} ({} redundant)",
+            duplicates.images,
+            if duplicates.images == 1 { "" } else { "s" },
+            format_bytes(duplicates.image_bytes)
+        ));
+    }
+    if duplicates.fonts > 0 {
+        copies.push(format!(
+            "{} duplicate font{} ({} redundant)",
+            duplicates.fonts,
+            if duplicates.fonts == 1 { "" } else { "s" },
+            format_bytes(duplicates.font_bytes)
+        ));
+    }
+    if !copies.is_empty() {
+        println!("Duplicates: {}", copies.join(", "));
+    }
     println!();
 
+    let inventory = &analysis.inventory;
+    println!("Document inventory:");
+    if inventory.annotations.is_empty() {
+        println!("Annotations: 0");
+    } else {
+        let by_subtype: Vec<String> =
+            inventory.annotations.iter().map(|(subtype, count)| format!("{} {}", count, subtype)).collect();
+        println!("Annotations: {} ({})", inventory.annotation_count(), by_subtype.join(", "));
+    }
+    if inventory.attachments > 0 {
+        println!("Attachments: {} ({})", inventory.attachments, format_bytes(inventory.attachments_size));
+    } else {
+        println!("Attachments: 0");
+    }
+    println!("Bookmarks: {}", inventory.bookmarks);
+    println!("Interactive form: {}", if inventory.acroform { "yes" } else { "no" });
+    println!();
+
+    let breakdown = &analysis.content_breakdown;
     println!("Content Breakdown:");
-    println!("Images: {}", crate::utils::format_bytes(analysis.content_breakdown.images_size));
-    println!("Fonts: {}", crate::utils::format_bytes(analysis.content_breakdown.fonts_size));
-    println!("Text: {}", crate::utils::format_bytes(analysis.content_breakdown.text_size));
-    println!("Other: {}", crate::utils::format_bytes(analysis.content_breakdown.other_size));
-    println!("Total: {}", crate::utils::format_bytes(analysis.content_breakdown.total_size));
+    println!("Images: {}", format_bytes(breakdown.images_size));
+    println!("Fonts: {}", format_bytes(breakdown.fonts_size));
+    println!("Page content: {}", format_bytes(breakdown.content_size));
+    if breakdown.metadata_size > 0 {
+        println!("Metadata: {}", format_bytes(breakdown.metadata_size));
+    }
+    if breakdown.attachments_size > 0 {
+        println!("Attachments: {}", format_bytes(breakdown.attachments_size));
+    }
+    println!("Uncategorized streams: {}", format_bytes(breakdown.uncategorized_streams_size));
+    println!("Other objects: {}", format_bytes(breakdown.other_size));
+    println!("Total: {}", format_bytes(breakdown.total_size));
+    // Sizes of objects other than streams are estimates, so the total may come out above
+    match file_size.checked_sub(breakdown.total_size) {
+        Some(overhead) => println!(
+            "File size: {} ({} of cross-reference tables, trailers and earlier revisions)",
+            format_bytes(file_size),
+            format_bytes(overhead)
+        ),
+        None => println!(
+            "File size: {} ({} less than the total, which is partly estimated)",
+            format_bytes(file_size),
+            format_bytes(breakdown.total_size - file_size)
+        ),
+    }
     println!();
 
+    let mut heaviest: Vec<&PageWeight> = analysis.pages.iter().filter(|page| page.total_size() > 0).collect();
+    heaviest.sort_by_key(|page| std::cmp::Reverse(page.total_size()));
+    if analysis.pages.len() > 1 && !heaviest.is_empty() {
+        println!("Heaviest pages:");
+        for page in heaviest.iter().take(HEAVIEST_PAGES) {
+            let mut parts = vec![format!("content {}", format_bytes(page.content_size))];
+            if page.image_count > 0 {
+                parts.push(format!(
+                    "{} image{} {}",
+                    page.image_count,
+                    if page.image_count == 1 { "" } else { "s" },
+                    format_bytes(page.image_size)
+                ));
+            }
+            if page.exclusive_image_size < page.image_size {
+                match page.exclusive_image_size {
+                    0 => parts.push("all shared with other pages".to_string()),
+                    exclusive => parts.push(format!("{} of it only on this page", format_bytes(exclusive))),
+                }
+            }
+            println!("  Page {}: {} ({})", page.number, format_bytes(page.total_size()), parts.join(", "));
+        }
+        println!();
+    }
+
+    if !analysis.image_formats.is_empty() {
+        println!("Image formats:");
+        for format in &analysis.image_formats {
+            println!(
+                "  {}: {} image{}, {}",
+                format.label(),
+                format.count,
+                if format.count == 1 { "" } else { "s" },
+                format_bytes(format.size)
+            );
+        }
+        println!();
+    }
+
+    let images = &analysis.image_resolutions;
+    if !images.is_empty() {
+        let count = |n: usize| format!("{} image{}", n, if n == 1 { "" } else { "s" });
+        println!("Image resolution (as drawn on the pages):");
+        for threshold in DPI_THRESHOLDS {
+            let over = images.iter().filter(|image| image.effective_dpi.is_some_and(|dpi| dpi > threshold as f64)).count();
+            println!("  Over {} DPI: {}", threshold, count(over));
+        }
+        let unplaced = images.iter().filter(|image| image.effective_dpi.is_none()).count();
+        if unplaced > 0 {
+            println!("  Not drawn by the page content: {}", count(unplaced));
+        }
+        println!(
+            "  Downsampling to {} DPI would remove {:.0}% of the pixels",
+            DOWNSAMPLE_DPI,
+            pixel_reduction(images, DOWNSAMPLE_DPI)
+        );
+        println!();
+    }
+
+    if top > 0 {
+        println!("Largest objects:");
+        println!("  {:<12} {:<10} {:<24} {:>10}", "Object", "Type", "Filter", "Size");
+        for object in analysis.objects.iter().take(top) {
+            println!(
+                "  {:<12} {:<10} {:<24} {:>10}",
+                format!("{} {}", object.id.0, object.id.1),
+                object.kind.as_str(),
+                object.filter.as_deref().unwrap_or("-"),
+                format_bytes(object.size)
+            );
+        }
+        println!();
+    }
+
     if show_savings {
         println!("Estimated Savings:");
         println!("Image compression: {:.1}%", analysis.estimated_savings.image_compression);
         println!("Structure optimization: {:.1}%", analysis.estimated_savings.structure_optimization);
+        if analysis.estimated_savings.thumbnails > 0.0 {
+            println!("Page thumbnails: {:.1}%", analysis.estimated_savings.thumbnails);
+        }
+        if analysis.estimated_savings.duplicates > 0.0 {
+            println!("Duplicate images and fonts: {:.1}%", analysis.estimated_savings.duplicates);
+        }
         println!("Total estimated: {:.1}%", analysis.estimated_savings.total_estimated);
     }
-}
\ No newline at end of file
+    if show_savings {
+        println!();
+    }
+    if let Some(measured) = &analysis.measured_savings {
+        let sampled = |extrapolation: &Extrapolation, noun: &str| {
+            format!(
+                "{} of {} {}{}",
+                extrapolation.sampled,
+                extrapolation.count,
+                noun,
+                if extrapolation.count == 1 { "" } else { "s" }
+            )
+        };
+        println!("Measured Savings ({} preset, by recompressing a sample):", measured.preset);
+        if measured.images.count > 0 {
+            println!("Image compression: {:.1}% (measured on {})", measured.image_compression, sampled(&measured.images, "image"));
+        }
+        println!(
+            "Structure optimization: {:.1}% (measured on {})",
+            measured.structure_optimization,
+            sampled(&measured.streams, "stream")
+        );
+        println!("Total estimated: {:.1}%", measured.total_estimated);
+        if measured.images.sampled + measured.streams.sampled == measured.images.count + measured.streams.count {
+            println!("Confidence: {} (every image and stream was recompressed)", measured.confidence());
+        } else {
+            println!(
+                "Confidence: {} ({:.0}% of the image and stream bytes were recompressed; the rest is extrapolated)",
+                measured.confidence(),
+                measured.measured_share() * 100.0
+            );
+        }
+        println!();
+    }
+    if !analysis.preset_simulations.is_empty() {
+        println!("Estimated size by preset (by recompressing a sample):");
+        println!("  {:<10} {:>12} {:>9}  Lossy", "Preset", "Size", "Savings");
+        for simulation in &analysis.preset_simulations {
+            println!(
+                "  {:<10} {:>12} {:>8.1}%  {}",
+                simulation.preset,
+                format_bytes(estimated_size(file_size, simulation)),
+                simulation.total_estimated,
+                if simulation.lossy { "yes" } else { "no" }
+            );
+        }
+        println!();
+    }
+    println!("Recommended settings: {}", recommendation(analysis));
+}
+
+/// The file size left after the measured savings
+fn estimated_size(file_size: u64, measured: &MeasuredSavings) -> u64 {
+    (file_size as f64 * (100.0 - measured.total_estimated) / 100.0).round() as u64
+}
+
+/// The analysis as a JSON object, with the weight of every page and the `top` largest objects
+pub fn analysis_json(path: &Path, file_size: u64, analysis: &PdfAnalysis, top: usize) -> String {
+    serde_json::to_string_pretty(&analysis_value(path, file_size, analysis, top)).expect("JSON values serialize")
+}
+
+/// The analysis as `analysis_json` prints it, to nest in a larger report
+pub fn analysis_value(path: &Path, file_size: u64, analysis: &PdfAnalysis, top: usize) -> Value {
+    let breakdown = &analysis.content_breakdown;
+    let savings = &analysis.estimated_savings;
+    let duplicates = &analysis.duplicates;
+    let signatures = &analysis.signatures;
+    let inventory = &analysis.inventory;
+    let pages: Vec<Value> = analysis
+        .pages
+        .iter()
+        .map(|page| {
+            json!({
+                "page": page.number,
+                "total_size": page.total_size(),
+                "content_size": page.content_size,
+                "images": page.image_count,
+                "image_size": page.image_size,
+                "exclusive_image_size": page.exclusive_image_size,
+            })
+        })
+        .collect();
+    let objects: Vec<Value> = analysis
+        .objects
+        .iter()
+        .take(top)
+        .map(|object| json!({"object": object.id, "type": object.kind.as_str(), "filter": object.filter, "size": object.size}))
+        .collect();
+    let dpi = |dpi: Option<f64>| dpi.map(|dpi| rounded(dpi, 1));
+    let images = &analysis.image_resolutions;
+    let image_details: Vec<Value> = images
+        .iter()
+        .map(|image| {
+            json!({
+                "object": image.id,
+                "width": image.width,
+                "height": image.height,
+                "filter": image.filter,
+                "size": image.size,
+                "placements": image.placements,
+                "effective_dpi": dpi(image.effective_dpi),
+                "highest_dpi": dpi(image.highest_dpi),
+            })
+        })
+        .collect();
+    let mut resolution = Map::new();
+    for threshold in DPI_THRESHOLDS {
+        let over = images.iter().filter(|image| image.effective_dpi.is_some_and(|dpi| dpi > threshold as f64)).count();
+        resolution.insert(format!("over_{}_dpi", threshold), over.into());
+    }
+    resolution.insert("not_drawn".to_string(), images.iter().filter(|image| image.effective_dpi.is_none()).count().into());
+    resolution.insert(
+        format!("pixel_reduction_at_{}_dpi", DOWNSAMPLE_DPI),
+        rounded(pixel_reduction(images, DOWNSAMPLE_DPI), 1).into(),
+    );
+    let formats: Vec<Value> = analysis
+        .image_formats
+        .iter()
+        .map(|format| {
+            json!({
+                "filter": format.filter,
+                "color_space": format.color_space,
+                "bits_per_component": format.bits_per_component,
+                "images": format.count,
+                "size": format.size,
+            })
+        })
+        .collect();
+    let measured_savings = analysis.measured_savings.as_ref().map(|measured| {
+        json!({
+            "preset": measured.preset,
+            "images": measured.images.count,
+            "images_sampled": measured.images.sampled,
+            "streams": measured.streams.count,
+            "streams_sampled": measured.streams.sampled,
+            "measured_share": rounded(measured.measured_share(), 3),
+            "confidence": measured.confidence(),
+            "image_compression": rounded(measured.image_compression, 1),
+            "structure_optimization": rounded(measured.structure_optimization, 1),
+            "total": rounded(measured.total_estimated, 1),
+            "lossy": measured.lossy,
+        })
+    });
+    let preset_simulations: Vec<Value> = analysis
+        .preset_simulations
+        .iter()
+        .map(|simulation| {
+            json!({
+                "preset": simulation.preset,
+                "estimated_size": estimated_size(file_size, simulation),
+                "savings": rounded(simulation.total_estimated, 1),
+                "lossy": simulation.lossy,
+                "confidence": simulation.confidence(),
+            })
+        })
+        .collect();
+    json!({
+        "file": path.display().to_string(),
+        "file_size": file_size,
+        "version": analysis.version,
+        "output_version": analysis.output_version,
+        "document_kind": analysis.document_kind.as_str(),
+        "optimized_by": analysis.optimized_by,
+        "total_objects": analysis.total_objects,
+        "pages": analysis.page_count,
+        "full_page_image_pages": analysis.full_page_image_pages,
+        "images": analysis.image_count,
+        "inline_images": analysis.inline_image_count,
+        "fonts": analysis.font_count,
+        "font_files": analysis.font_file_count,
+        "duplicates": {
+            "images": duplicates.images,
+            "image_bytes": duplicates.image_bytes,
+            "fonts": duplicates.fonts,
+            "font_bytes": duplicates.font_bytes,
+        },
+        "text_objects": analysis.text_objects,
+        "text_size": analysis.text_size,
+        "icc_profiles": analysis.icc_profile_count,
+        "icc_profiles_size": analysis.icc_profiles_size,
+        "thumbnails": analysis.thumbnail_count,
+        "thumbnails_size": analysis.thumbnails_size,
+        "signed": signatures.is_signed(),
+        "empty_signature_fields": signatures.empty_fields,
+        "private_data_size": analysis.private_data_size,
+        "inventory": {
+            "annotations": inventory.annotation_count(),
+            "annotations_by_subtype": inventory.annotations,
+            "attachments": inventory.attachments,
+            "attachments_size": inventory.attachments_size,
+            "bookmarks": inventory.bookmarks,
+            "acroform": inventory.acroform,
+        },
+        "content_breakdown": {
+            "images": breakdown.images_size,
+            "fonts": breakdown.fonts_size,
+            "content": breakdown.content_size,
+            "metadata": breakdown.metadata_size,
+            "attachments": breakdown.attachments_size,
+            "uncategorized_streams": breakdown.uncategorized_streams_size,
+            "other": breakdown.other_size,
+            "total": breakdown.total_size,
+            "overhead": file_size as i64 - breakdown.total_size as i64,
+        },
+        "estimated_savings": {
+            "image_compression": rounded(savings.image_compression, 1),
+            "structure_optimization": rounded(savings.structure_optimization, 1),
+            "duplicates": rounded(savings.duplicates, 1),
+            "thumbnails": rounded(savings.thumbnails, 1),
+            "total": rounded(savings.total_estimated, 1),
+        },
+        "measured_savings": measured_savings,
+        "preset_simulations": preset_simulations,
+        "image_resolution": resolution,
+        "recommended_settings": recommendation(analysis),
+        "page_weights": pages,
+        "image_formats": formats,
+        "image_details": image_details,
+        "largest_objects": objects,
+    })
+}
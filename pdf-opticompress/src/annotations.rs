@@ -0,0 +1,248 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::HashSet;
+
+use crate::inline_images::page_resources;
+use crate::objects::resolve;
+
+/// Results of the form flattening and annotation removal passes
+#[derive(Debug, Default, Clone)]
+pub struct AnnotationStats {
+    pub fields_flattened: usize,
+    /// Visible widgets without a normal appearance, whose value couldn't be drawn
+    pub fields_without_appearance: usize,
+    pub annotations_removed: usize,
+}
+
+// Annotation flags (`/F`)
+const HIDDEN: i64 = 1 << 1;
+const NO_VIEW: i64 = 1 << 5;
+
+/// Draw the normal appearance (`/AP /N`) of every form widget into its page's content,
+/// then remove the widgets and the `/AcroForm` dictionary. Fields become plain page
+/// content; the field objects are left for the unreachable-object pass.
+pub fn flatten_forms(doc: &mut Document) -> AnnotationStats {
+    let mut stats = AnnotationStats::default();
+
+    for page_id in doc.get_pages().into_values() {
+        let annotations = page_annotations(doc, page_id);
+        if annotations.is_empty() {
+            continue;
+        }
+        let count = annotations.len();
+        let mut kept = Vec::new();
+        let mut appearances = Vec::new();
+        for annotation in annotations {
+            let Ok(dict) = resolve(doc, &annotation).as_dict() else {
+                kept.push(annotation);
+                continue;
+            };
+            if !has_subtype(dict, b"Widget") {
+                kept.push(annotation);
+                continue;
+            }
+            stats.fields_flattened += 1;
+            let flags = dict.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+            if flags & (HIDDEN | NO_VIEW) != 0 {
+                continue;
+            }
+            match normal_appearance(doc, dict).and_then(|form_id| Some((form_id, placement(doc, dict, form_id)?))) {
+                Some(appearance) => appearances.push(appearance),
+                None => stats.fields_without_appearance += 1,
+            }
+        }
+        if kept.len() == count {
+            continue;
+        }
+
+        if !appearances.is_empty() {
+            draw_appearances(doc, page_id, &appearances);
+        }
+        set_annotations(doc, page_id, kept);
+    }
+
+    if let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) {
+        if let Ok(catalog) = doc.get_dictionary_mut(catalog_id) {
+            catalog.remove(b"AcroForm");
+        }
+    }
+
+    stats
+}
+
+/// Remove every annotation whose subtype is in `subtypes` (e.g. `Popup`, `FileAttachment`),
+/// along with the pop-ups of removed annotations. Links and all other subtypes are kept.
+pub fn strip_annotations(doc: &mut Document, subtypes: &[String]) -> AnnotationStats {
+    let mut stats = AnnotationStats::default();
+    let targeted = |dict: &Dictionary| {
+        matches!(dict.get(b"Subtype"), Ok(Object::Name(name))
+            if subtypes.iter().any(|subtype| subtype.as_bytes().eq_ignore_ascii_case(name)))
+    };
+
+    let mut removed: HashSet<ObjectId> = HashSet::new();
+    let mut pages = Vec::new();
+    for page_id in doc.get_pages().into_values() {
+        let annotations = page_annotations(doc, page_id);
+        let count = annotations.len();
+        let kept: Vec<Object> = annotations
+            .into_iter()
+            .filter(|annotation| {
+                let Ok(dict) = resolve(doc, annotation).as_dict() else { return true };
+                if !targeted(dict) {
+                    return true;
+                }
+                if let Object::Reference(id) = annotation {
+                    removed.insert(*id);
+                }
+                false
+            })
+            .collect();
+        stats.annotations_removed += count - kept.len();
+        pages.push((page_id, kept));
+    }
+
+    // Pop-ups belong to the annotation they annotate and go with it
+    for (_, kept) in &mut pages {
+        let count = kept.len();
+        kept.retain(|annotation| {
+            let Ok(dict) = resolve(doc, annotation).as_dict() else { return true };
+            !(has_subtype(dict, b"Popup")
+                && matches!(dict.get(b"Parent"), Ok(Object::Reference(parent)) if removed.contains(parent)))
+        });
+        stats.annotations_removed += count - kept.len();
+    }
+
+    for (page_id, kept) in pages {
+        if kept.len() != page_annotations(doc, page_id).len() {
+            set_annotations(doc, page_id, kept);
+        }
+    }
+
+    stats
+}
+
+/// The entries of a page's `/Annots` array, which may itself be indirect
+fn page_annotations(doc: &Document, page_id: ObjectId) -> Vec<Object> {
+    doc.get_dictionary(page_id)
+        .ok()
+        .and_then(|page| page.get(b"Annots").ok())
+        .and_then(|annots| resolve(doc, annots).as_array().ok())
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn set_annotations(doc: &mut Document, page_id: ObjectId, annotations: Vec<Object>) {
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        if annotations.is_empty() {
+            page.remove(b"Annots");
+        } else {
+            page.set("Annots", annotations);
+        }
+    }
+}
+
+/// The form XObject a widget shows: `/AP /N`, or the entry of `/AP /N` selected by
+/// `/AS` for check boxes and radio buttons
+fn normal_appearance(doc: &Document, widget: &Dictionary) -> Option<ObjectId> {
+    let appearances = resolve(doc, widget.get(b"AP").ok()?).as_dict().ok()?;
+    let normal = appearances.get(b"N").ok()?;
+    let id = match resolve(doc, normal) {
+        Object::Stream(_) => normal.as_reference().ok()?,
+        Object::Dictionary(states) => {
+            let state = widget.get(b"AS").and_then(Object::as_name).ok()?;
+            states.get(state).and_then(Object::as_reference).ok()?
+        }
+        _ => return None,
+    };
+    matches!(doc.get_object(id), Ok(Object::Stream(_))).then_some(id)
+}
+
+/// The matrix mapping an appearance onto its annotation rectangle: the form's bounding
+/// box, transformed by its `/Matrix`, is scaled and moved to fill `/Rect`
+fn placement(doc: &Document, widget: &Dictionary, form_id: ObjectId) -> Option<[f32; 6]> {
+    let rect = numbers(resolve(doc, widget.get(b"Rect").ok()?))?;
+    let form = doc.get_object(form_id).ok()?.as_stream().ok()?;
+    let bbox = numbers(resolve(doc, form.dict.get(b"BBox").ok()?))?;
+    let matrix = form
+        .dict
+        .get(b"Matrix")
+        .ok()
+        .and_then(|matrix| numbers(resolve(doc, matrix)))
+        .unwrap_or(vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    if rect.len() != 4 || bbox.len() != 4 || matrix.len() != 6 {
+        return None;
+    }
+
+    let corners = [(bbox[0], bbox[1]), (bbox[2], bbox[1]), (bbox[0], bbox[3]), (bbox[2], bbox[3])]
+        .map(|(x, y)| (matrix[0] * x + matrix[2] * y + matrix[4], matrix[1] * x + matrix[3] * y + matrix[5]));
+    let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min);
+    let max_x = corners.iter().map(|c| c.0).fold(f32::NEG_INFINITY, f32::max);
+    let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min);
+    let max_y = corners.iter().map(|c| c.1).fold(f32::NEG_INFINITY, f32::max);
+    if max_x - min_x <= 0.0 || max_y - min_y <= 0.0 {
+        return None;
+    }
+
+    let (left, right) = (rect[0].min(rect[2]), rect[0].max(rect[2]));
+    let (bottom, top) = (rect[1].min(rect[3]), rect[1].max(rect[3]));
+    let scale_x = (right - left) / (max_x - min_x);
+    let scale_y = (top - bottom) / (max_y - min_y);
+    Some([scale_x, 0.0, 0.0, scale_y, left - scale_x * min_x, bottom - scale_y * min_y])
+}
+
+fn numbers(object: &Object) -> Option<Vec<f32>> {
+    object.as_array().ok()?.iter().map(|item| item.as_float().ok()).collect()
+}
+
+/// Paint form XObjects over a page: the existing content is wrapped in `q`/`Q` so its
+/// graphics state can't leak, and each appearance is added to the page's own resources
+fn draw_appearances(doc: &mut Document, page_id: ObjectId, appearances: &[(ObjectId, [f32; 6])]) {
+    let Ok(page) = doc.get_dictionary(page_id) else { return };
+    let mut resources = page_resources(doc, page_id).unwrap_or_default();
+    let mut xobjects = resources
+        .get(b"XObject")
+        .ok()
+        .and_then(|xobjects| resolve(doc, xobjects).as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    let mut contents: Vec<Object> = match page.get(b"Contents") {
+        Ok(Object::Reference(id)) if matches!(doc.get_object(*id), Ok(Object::Stream(_))) => vec![Object::Reference(*id)],
+        Ok(contents) => resolve(doc, contents).as_array().cloned().unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+
+    let mut content = b"Q\n".to_vec();
+    let mut index = 0;
+    for (form_id, [a, b, c, d, e, f]) in appearances {
+        // Appearance streams are sometimes missing the keys that make them form XObjects
+        if let Ok(Object::Stream(form)) = doc.get_object_mut(*form_id) {
+            form.dict.set("Type", Object::Name(b"XObject".to_vec()));
+            form.dict.set("Subtype", Object::Name(b"Form".to_vec()));
+        }
+        let name = loop {
+            index += 1;
+            let name = format!("Fm{}", index).into_bytes();
+            if !xobjects.has(&name) {
+                break name;
+            }
+        };
+        content.extend(format!("q {} {} {} {} {} {} cm /", a, b, c, d, e, f).into_bytes());
+        content.extend(&name);
+        content.extend(b" Do Q\n");
+        xobjects.set(name, Object::Reference(*form_id));
+    }
+
+    let open = doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let close = doc.add_object(Stream::new(Dictionary::new(), content));
+    contents.insert(0, Object::Reference(open));
+    contents.push(Object::Reference(close));
+    resources.set("XObject", xobjects);
+
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        page.set("Contents", contents);
+        page.set("Resources", resources);
+    }
+}
+
+fn has_subtype(dict: &Dictionary, subtype: &[u8]) -> bool {
+    matches!(dict.get(b"Subtype"), Ok(Object::Name(name)) if name == subtype)
+}
@@ -0,0 +1,870 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::batch_state::BatchState;
+use crate::cli::Preset;
+use crate::marker::Marker;
+use crate::optimizer::{optimize_pdf_with_progress, BelowThreshold, Deadline, InPlace, OptimizationResult, OptimizeOptions, TimedOutError};
+use crate::pdf_reader::SignedDocumentError;
+use crate::report::{ReportRow, Status};
+use crate::utils::{calculate_compression_ratio, format_bytes, InputError, InputFile};
+
+const PLACEHOLDERS: [&str; 6] = ["stem", "ext", "preset", "quality", "date", "index"];
+
+/// A `--name-template` such as `{stem}_{preset}_q{quality}.pdf`, naming each output after
+/// its input and the settings
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    template: String,
+    date: String, // Taken once, so a run that passes midnight names every output alike
+}
+
+impl NameTemplate {
+    /// Fails on an unknown placeholder or an unclosed brace
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                anyhow::bail!("Invalid name template {:?}: '{{' without a closing '}}'", template);
+            };
+            let placeholder = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                anyhow::bail!(
+                    "Invalid name template {:?}: unknown placeholder {{{}}} (use {})",
+                    template,
+                    placeholder,
+                    PLACEHOLDERS.map(|name| format!("{{{}}}", name)).join(", ")
+                );
+            }
+            rest = &rest[start + end + 1..];
+        }
+        let now = crate::xmp::pdf_date_now();
+        Ok(NameTemplate { template: template.to_string(), date: format!("{}-{}-{}", &now[2..6], &now[6..8], &now[8..10]) })
+    }
+
+    /// The file name of the output of the `index`th input (counting from 1)
+    pub fn expand(&self, input: &Path, index: usize, preset: &Preset, quality: u8) -> Result<String> {
+        let part = |part: Option<&std::ffi::OsStr>| part.map_or(String::new(), |part| part.to_string_lossy().into_owned());
+        let mut name = String::new();
+        let mut rest = self.template.as_str();
+        // In one pass, so that a stem holding braces is left as it is
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}').unwrap();
+            name.push_str(&rest[..start]);
+            match &rest[start + 1..end] {
+                "stem" => name.push_str(&part(input.file_stem())),
+                "ext" => name.push_str(&part(input.extension())),
+                "preset" => name.push_str(preset.to_possible_value().as_ref().map_or("", |value| value.get_name())),
+                "quality" => name.push_str(&quality.to_string()),
+                "date" => name.push_str(&self.date),
+                _ => name.push_str(&index.to_string()),
+            }
+            rest = &rest[end + 1..];
+        }
+        name.push_str(rest);
+        if name.is_empty() || name == "." || name == ".." {
+            anyhow::bail!("The name template {:?} gives {} an empty file name", self.template, input.display());
+        }
+        Ok(name)
+    }
+}
+
+/// How a batch names its outputs
+pub struct Naming<'a> {
+    pub template: Option<&'a NameTemplate>,
+    pub preset: &'a Preset,
+    pub quality: u8,
+}
+
+/// One line of a --files-from manifest
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub input: PathBuf,
+    /// Given after a tab, in place of the one the batch would pick
+    pub output: Option<PathBuf>,
+}
+
+/// Read the inputs listed in a manifest, or on stdin for `-`: one per line, skipping blank
+/// lines and `#` comments, each optionally followed by a tab and its output. With `null`
+/// the entries are separated by NUL bytes instead, as `find -print0` writes them, and taken
+/// as they are: paths only, no comments.
+pub fn read_manifest(path: &Path, null: bool) -> Result<Vec<ManifestEntry>> {
+    let name = if path == Path::new("-") { "stdin".to_string() } else { path.display().to_string() };
+    let content = if path == Path::new("-") {
+        let mut content = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut content).context("Failed to read the inputs from stdin")?;
+        content
+    } else {
+        std::fs::read(path).with_context(|| format!("Failed to read the manifest {}", name))?
+    };
+
+    let separator = if null { b'\0' } else { b'\n' };
+    let mut entries = Vec::new();
+    for (i, line) in content.split(|byte| *byte == separator).enumerate() {
+        let line = std::str::from_utf8(line).map_err(|_| anyhow::anyhow!("{} line {}: not valid UTF-8", name, i + 1))?;
+        if null {
+            if !line.is_empty() {
+                entries.push(ManifestEntry { input: PathBuf::from(line), output: None });
+            }
+            continue;
+        }
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let entry = match fields[..] {
+            [input] => ManifestEntry { input: PathBuf::from(input), output: None },
+            [input, output] if !input.is_empty() && !output.is_empty() => {
+                ManifestEntry { input: PathBuf::from(input), output: Some(PathBuf::from(output)) }
+            }
+            [_, _] => anyhow::bail!("{} line {}: empty path in \"{}\" (expected INPUT or INPUT<TAB>OUTPUT)", name, i + 1, line.escape_debug()),
+            _ => anyhow::bail!(
+                "{} line {}: {} tab-separated fields in \"{}\" (expected INPUT or INPUT<TAB>OUTPUT)",
+                name,
+                i + 1,
+                fields.len(),
+                line.escape_debug()
+            ),
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// What makes two inputs the same file
+#[derive(PartialEq, Eq, Hash)]
+enum FileIdentity {
+    /// Device and inode, which links and differently spelled paths share
+    #[cfg(unix)]
+    Inode(u64, u64),
+    Path(PathBuf),
+}
+
+fn file_identity(path: &Path) -> FileIdentity {
+    #[cfg(unix)]
+    if let Ok(metadata) = std::fs::metadata(path) {
+        use std::os::unix::fs::MetadataExt;
+        return FileIdentity::Inode(metadata.dev(), metadata.ino());
+    }
+    // URLs and files that can't be read are told apart by name
+    FileIdentity::Path(case_folded(std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())))
+}
+
+/// Whether two paths lead to the same existing file, through links, `..` or a different
+/// spelling
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    a.exists() && b.exists() && file_identity(a) == file_identity(b)
+}
+
+/// The path in lower case where file systems usually ignore case (macOS and Windows), so
+/// that paths differing only in case compare equal there
+fn case_folded(path: PathBuf) -> PathBuf {
+    if cfg!(any(target_os = "macos", windows)) {
+        PathBuf::from(path.to_string_lossy().to_lowercase())
+    } else {
+        path
+    }
+}
+
+/// What an output path will be on disk, to tell whether two outputs are the same file
+/// before either exists: absolute, through symlinked directories, and case-folded
+fn output_key(path: &Path) -> PathBuf {
+    let absolute = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            std::fs::canonicalize(parent).map(|parent| parent.join(name)).unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => path.to_path_buf(),
+    };
+    case_folded(absolute)
+}
+
+/// Drop the inputs given more than once, through the same path or another (overlapping
+/// globs, links, or another case where case is ignored), keeping the first and printing a
+/// notice for the others. `explicit` holds the output each input was given, if any; the
+/// same input given again with another output isn't a duplicate.
+pub fn dedupe_inputs(files: Vec<PathBuf>, explicit: Vec<Option<PathBuf>>) -> (Vec<PathBuf>, Vec<Option<PathBuf>>) {
+    let mut first_seen: HashMap<(FileIdentity, Option<PathBuf>), usize> = HashMap::new();
+    let (mut kept, mut kept_explicit): (Vec<PathBuf>, Vec<Option<PathBuf>>) = (Vec::new(), Vec::new());
+    for (file, output) in files.into_iter().zip(explicit) {
+        let key = (file_identity(&file), output.as_deref().map(output_key));
+        match first_seen.get(&key) {
+            Some(&index) => eprintln!("Note: {} is the same file as {}; it's only processed once", file.display(), kept[index].display()),
+            None => {
+                first_seen.insert(key, kept.len());
+                kept.push(file);
+                kept_explicit.push(output);
+            }
+        }
+    }
+    (kept, kept_explicit)
+}
+
+/// Where each input of a batch is written: next to it as `<NAME>.optimized.pdf`, or under
+/// the output directory. There the inputs keep their paths relative to the deepest directory
+/// they share (`in/2023/q1/report.pdf` becomes `out/2023/q1/report.pdf`), unless `flatten`
+/// puts them all directly in it. A name template replaces the file name in either case, and
+/// an output given explicitly (`explicit[i]` for `files[i]`, when there is one) replaces the
+/// whole path. Fails, listing them, when two inputs would be written to the same file.
+pub fn output_paths(
+    files: &[PathBuf],
+    explicit: &[Option<PathBuf>],
+    output_dir: Option<&Path>,
+    flatten: bool,
+    naming: &Naming,
+) -> Result<Vec<PathBuf>> {
+    let outputs: Vec<PathBuf> = match output_dir {
+        None => files.iter().map(|file| file.with_extension("optimized.pdf")).collect(),
+        Some(dir) if flatten => files.iter().map(|file| dir.join(file.file_name().unwrap())).collect(),
+        Some(dir) => {
+            // Inputs that can't be resolved (URLs) go directly in the output directory
+            let absolute: Vec<Option<PathBuf>> = files.iter().map(|file| std::fs::canonicalize(file).ok()).collect();
+            let root = common_root(absolute.iter().flatten());
+            files
+                .iter()
+                .zip(&absolute)
+                .map(|(file, absolute)| {
+                    match (absolute, &root) {
+                        (Some(absolute), Some(root)) => absolute.strip_prefix(root).ok().map(|relative| dir.join(relative)),
+                        _ => None,
+                    }
+                    .unwrap_or_else(|| dir.join(file.file_name().unwrap()))
+                })
+                .collect()
+        }
+    };
+    let outputs: Vec<PathBuf> = match naming.template {
+        Some(template) => files
+            .iter()
+            .zip(outputs)
+            .enumerate()
+            .map(|(i, (file, output))| Ok(output.with_file_name(template.expand(file, i + 1, naming.preset, naming.quality)?)))
+            .collect::<Result<_>>()?,
+        None => outputs,
+    };
+    let outputs: Vec<PathBuf> = outputs
+        .into_iter()
+        .enumerate()
+        .map(|(i, output)| explicit.get(i).cloned().flatten().unwrap_or(output))
+        .collect();
+
+    let mut inputs_by_output: HashMap<PathBuf, Vec<&Path>> = HashMap::new();
+    for (file, output) in files.iter().zip(&outputs) {
+        inputs_by_output.entry(output_key(output)).or_default().push(file);
+    }
+    let mut collisions: Vec<String> = outputs
+        .iter()
+        .filter_map(|output| {
+            let inputs = inputs_by_output.remove(&output_key(output))?;
+            (inputs.len() > 1).then(|| {
+                let inputs: Vec<String> = inputs.iter().map(|input| input.display().to_string()).collect();
+                format!("  {}: {}", output.display(), inputs.join(", "))
+            })
+        })
+        .collect();
+    if !collisions.is_empty() {
+        collisions.insert(0, "Several inputs would be written to the same output:".to_string());
+        anyhow::bail!(collisions.join("\n"));
+    }
+    Ok(outputs)
+}
+
+/// The deepest directory holding all the files
+fn common_root<'a>(mut files: impl Iterator<Item = &'a PathBuf>) -> Option<PathBuf> {
+    let mut root = files.next()?.parent()?.to_path_buf();
+    for file in files {
+        while !file.starts_with(&root) {
+            if !root.pop() {
+                return None;
+            }
+        }
+    }
+    Some(root)
+}
+
+/// What to do when an input's output already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingOutputs {
+    /// Stop before starting, listing them
+    Refuse,
+    Overwrite,
+    Skip,
+    /// Skip the outputs at least as recent as their input, redo the others
+    SkipIfNewer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkStatus {
+    Pending,
+    /// The output exists and is kept
+    OutputExists,
+    /// An earlier run with the same --state-file went through the input, unchanged since
+    Done,
+    /// The input is missing, isn't a file, or doesn't look like a PDF
+    Unusable(InputError),
+    /// The input couldn't be downloaded
+    Invalid(String),
+}
+
+/// One input of a batch and where it goes
+#[derive(Debug)]
+pub struct WorkItem {
+    pub input: PathBuf, // As given
+    pub resolved: InputFile, // Downloaded, for URLs
+    pub output: PathBuf,
+    pub status: WorkStatus,
+}
+
+/// Download the inputs given as URLs and check that each one is a readable file, which
+/// starts like a PDF when `sniff` is on. A bad input is an error of its own and doesn't stop
+/// the others.
+pub fn resolve_inputs(files: &[PathBuf], sniff: bool) -> Vec<Result<InputFile>> {
+    files.iter().map(|file| crate::utils::open_input(file, sniff)).collect()
+}
+
+/// Fail listing the backups an earlier `--in-place` run left, which would be replaced by
+/// copies of its outputs, losing the originals
+pub fn refuse_existing_backups(resolved: &[Result<InputFile>], in_place: &InPlace) -> Result<()> {
+    let mut existing: Vec<String> = resolved
+        .iter()
+        .filter_map(|input| input.as_ref().ok())
+        .filter_map(|input| in_place.existing_backup(&std::fs::canonicalize(input.path()).ok()?))
+        .map(|backup| format!("  {}", backup.display()))
+        .collect();
+    if !existing.is_empty() {
+        existing.insert(0, "These backups already exist (use --overwrite to replace them):".to_string());
+        anyhow::bail!(existing.join("\n"));
+    }
+    Ok(())
+}
+
+/// Fail, listing them, when outputs would be written over their own inputs, which only
+/// `--in-place` does safely
+pub fn refuse_outputs_over_inputs(resolved: &[Result<InputFile>], outputs: &[PathBuf]) -> Result<()> {
+    let mut same: Vec<String> = resolved
+        .iter()
+        .zip(outputs)
+        .filter(|(input, output)| input.as_ref().is_ok_and(|input| same_file(input.path(), output)))
+        .map(|(_, output)| format!("  {}", output.display()))
+        .collect();
+    if !same.is_empty() {
+        same.insert(0, "These outputs are their inputs (use --in-place to replace the inputs):".to_string());
+        anyhow::bail!(same.join("\n"));
+    }
+    Ok(())
+}
+
+/// Pair the inputs with their outputs and decide which to process, leaving out those the
+/// state says are done. With `ExistingOutputs::Refuse`, fails listing the outputs that
+/// already exist.
+pub fn work_items(
+    files: &[PathBuf],
+    resolved: Vec<Result<InputFile>>,
+    outputs: Vec<PathBuf>,
+    existing: ExistingOutputs,
+    state: Option<&BatchState>,
+) -> Result<Vec<WorkItem>> {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let mut conflicts = Vec::new();
+    let items: Vec<WorkItem> = files
+        .iter()
+        .zip(resolved)
+        .zip(outputs)
+        .map(|((input, resolved), output)| {
+            let resolved = match resolved {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    let status = match e.downcast::<InputError>() {
+                        Ok(e) => WorkStatus::Unusable(e),
+                        Err(e) => WorkStatus::Invalid(format!("{:#}", e)),
+                    };
+                    return WorkItem { input: input.clone(), resolved: InputFile::local(input), output, status };
+                }
+            };
+            if state.is_some_and(|state| state.is_done(input, resolved.path())) {
+                return WorkItem { input: input.clone(), resolved, output, status: WorkStatus::Done };
+            }
+            let status = match (existing, modified(&output)) {
+                (_, None) | (ExistingOutputs::Overwrite, _) => WorkStatus::Pending,
+                (ExistingOutputs::Skip, Some(_)) => WorkStatus::OutputExists,
+                (ExistingOutputs::SkipIfNewer, Some(output_time)) => match modified(resolved.path()) {
+                    Some(input_time) if input_time > output_time => WorkStatus::Pending,
+                    _ => WorkStatus::OutputExists,
+                },
+                (ExistingOutputs::Refuse, Some(_)) => {
+                    conflicts.push(format!("  {}", output.display()));
+                    WorkStatus::Pending
+                }
+            };
+            WorkItem { input: input.clone(), resolved, output, status }
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        conflicts.insert(0, "These outputs already exist (use --overwrite to replace them or --skip-existing to keep them):".to_string());
+        anyhow::bail!(conflicts.join("\n"));
+    }
+    Ok(items)
+}
+
+/// Create the directories the outputs to be written go in
+pub fn create_output_dirs(items: &[WorkItem]) -> Result<()> {
+    let pending = items.iter().filter(|item| item.status == WorkStatus::Pending);
+    for dir in pending.filter_map(|item| item.output.parent()).filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// What happened to one input of a batch
+#[derive(Debug)]
+pub enum Outcome {
+    Optimized(OptimizationResult),
+    /// Saved less than --min-savings, so the output wasn't kept
+    BelowThreshold(OptimizationResult),
+    /// Digitally signed and left alone
+    Signed(anyhow::Error),
+    OutputExists,
+    /// Done by an earlier run
+    Done,
+    /// Stamped by an earlier optimization at least as aggressive, with --skip-optimized
+    AlreadyOptimized(Marker),
+    Failed(anyhow::Error),
+}
+
+/// One input of a batch once it's been through
+#[derive(Debug)]
+pub struct FileRecord {
+    pub item: WorkItem,
+    pub outcome: Outcome,
+    pub processing_time: Duration,
+    pub dry_run: bool, // Nothing was written; sizes are estimates
+}
+
+impl FileRecord {
+    /// How the input went, on one line: its status as in the report, the input, and what
+    /// was saved or why not (or would be, in a dry run)
+    pub fn log_line(&self) -> String {
+        let row = self.report_row();
+        let saved = if self.dry_run { "would save" } else { "saved" };
+        let detail = match &self.outcome {
+            Outcome::Optimized(result) => format!(
+                "{} {:.1}% ({})",
+                saved,
+                result.compression_ratio,
+                format_bytes(result.original_size.saturating_sub(result.optimized_size))
+            ),
+            Outcome::BelowThreshold(result) => format!(
+                "{} {:.1}%, below --min-savings ({})",
+                saved,
+                result.compression_ratio,
+                match (result.below_threshold, self.dry_run) {
+                    (Some(BelowThreshold::OriginalCopied), false) => "original copied",
+                    (Some(BelowThreshold::OriginalCopied), true) => "original would be copied",
+                    (Some(BelowThreshold::OriginalKept), false) => "original kept",
+                    (Some(BelowThreshold::OriginalKept), true) => "original would be kept",
+                    (_, false) => "output deleted",
+                    (_, true) => "output wouldn't be kept",
+                }
+            ),
+            _ => row.error.unwrap_or_default(),
+        };
+        let status = if self.dry_run { format!("{} (dry run)", row.status.as_str()) } else { row.status.as_str().to_string() };
+        format!("{} {}: {}", status, self.item.input.display(), detail)
+    }
+
+    pub fn report_row(&self) -> ReportRow {
+        let result = match &self.outcome {
+            Outcome::Optimized(result) | Outcome::BelowThreshold(result) => Some(result),
+            _ => None,
+        };
+        let (status, error) = match &self.outcome {
+            Outcome::Optimized(_) => (Status::Ok, None),
+            Outcome::BelowThreshold(_) => (Status::BelowThreshold, None),
+            Outcome::Signed(e) => (Status::Skipped, Some(format!("{:#}", e))),
+            Outcome::OutputExists => (Status::Skipped, Some(format!("{} already exists", self.item.output.display()))),
+            Outcome::Done => (Status::Skipped, Some("Done in an earlier run".to_string())),
+            Outcome::AlreadyOptimized(marker) => (Status::Skipped, Some(format!("Already optimized by {}", marker.describe()))),
+            Outcome::Failed(e) => (Status::Failed, Some(format!("{:#}", e))),
+        };
+        // Only set when the output holds something, or would in a dry run: the optimized
+        // file or a copy of the input
+        let written = match &self.outcome {
+            Outcome::Optimized(_) => true,
+            Outcome::BelowThreshold(result) => result.below_threshold == Some(BelowThreshold::OriginalCopied),
+            _ => false,
+        };
+        ReportRow {
+            input: self.item.input.clone(),
+            output: written.then(|| self.item.output.clone()),
+            status,
+            original_size: result.map(|result| result.original_size).or_else(|| crate::utils::get_file_size(self.item.resolved.path()).ok()),
+            optimized_size: result.map(|result| result.optimized_size),
+            percent_saved: result.map(|result| result.compression_ratio),
+            images_optimized: result.map(|result| result.images_optimized),
+            processing_time: self.processing_time,
+            error,
+            dry_run: self.dry_run,
+        }
+    }
+}
+
+/// How a batch shows its progress: on a terminal, a bar for the batch and one for each file
+/// being optimized, with a line above them for each file that's through; otherwise just the
+/// lines, one per file in the order they finish, which is what logs want
+struct Progress {
+    bars: Option<(MultiProgress, ProgressBar)>, // All the bars, and the batch's
+    finished: AtomicUsize,
+    count: usize,
+}
+
+impl Progress {
+    fn new(count: usize, show_bars: bool) -> Self {
+        let terminal = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+        let bars = (show_bars && terminal).then(|| {
+            let multi = MultiProgress::new();
+            let overall = multi.add(ProgressBar::new(count as u64));
+            overall.set_style(
+                ProgressStyle::default_bar()
+                    .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} files, {eta} left")
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+            (multi, overall)
+        });
+        Progress { bars, finished: AtomicUsize::new(0), count }
+    }
+
+    /// A bar for one file being optimized, above the batch's
+    fn file_bar(&self, input: &Path) -> Option<ProgressBar> {
+        let (multi, overall) = self.bars.as_ref()?;
+        let bar = multi.insert_before(overall, ProgressBar::new(100));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("  {spinner:.green} {prefix:30!} [{bar:20.cyan/blue}] {msg}")
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        bar.set_prefix(input.file_name().unwrap_or(input.as_os_str()).to_string_lossy().into_owned());
+        bar.enable_steady_tick(Duration::from_millis(100));
+        Some(bar)
+    }
+
+    /// Print a line without tearing the bars
+    fn println(&self, line: &str) {
+        match &self.bars {
+            Some((multi, _)) => {
+                let _ = multi.println(line);
+            }
+            None => println!("{}", line),
+        }
+    }
+
+    fn file_done(&self, record: &FileRecord) {
+        let finished = self.finished.fetch_add(1, Ordering::Relaxed) + 1;
+        self.println(&format!("[{}/{}] {}", finished, self.count, record.log_line()));
+        if let Some((_, overall)) = &self.bars {
+            overall.inc(1);
+        }
+    }
+
+    fn finish(&self) {
+        if let Some((_, overall)) = &self.bars {
+            overall.finish_and_clear();
+        }
+    }
+}
+
+/// How a batch runs, apart from how each file is optimized
+pub struct RunSettings {
+    pub threads: usize,
+    /// Give up on a file after this long
+    pub timeout: Option<Duration>,
+    /// Skip the inputs stamped by an optimization at least as aggressive as this one
+    pub skip_optimized: bool,
+    /// Show progress bars, when both stdout and stderr are terminals
+    pub show_progress: bool,
+}
+
+/// Whether the input was stamped by an optimization at least as aggressive as `options`
+/// asks for; only its dictionaries are read
+fn already_optimized(path: &Path, options: &OptimizeOptions) -> Option<Marker> {
+    let info = crate::pdf_reader::read_pdf_info(path, options.password.as_deref().unwrap_or("")).ok()?;
+    info.optimized_by.filter(|marker| marker.covers(options.preset, options.quality))
+}
+
+/// Optimize one input as `settings` say, showing its stages on `bar`
+fn process(item: WorkItem, options: &OptimizeOptions, settings: &RunSettings, bar: Option<&ProgressBar>) -> FileRecord {
+    let started = Instant::now();
+    let options = &OptimizeOptions { deadline: settings.timeout.map(Deadline::after), ..options.clone() };
+    let previous = match &item.status {
+        WorkStatus::Pending if settings.skip_optimized => already_optimized(item.resolved.path(), options),
+        _ => None,
+    };
+    let outcome = match (&item.status, previous) {
+        (WorkStatus::OutputExists, _) => Outcome::OutputExists,
+        (WorkStatus::Done, _) => Outcome::Done,
+        (WorkStatus::Unusable(e), _) => Outcome::Failed(e.clone().into()),
+        (WorkStatus::Invalid(e), _) => Outcome::Failed(anyhow::anyhow!(e.clone())),
+        (WorkStatus::Pending, Some(marker)) => Outcome::AlreadyOptimized(marker),
+        (WorkStatus::Pending, None) => match optimize_pdf_with_progress(item.resolved.path(), &item.output, options, bar) {
+            Ok(result) if result.below_threshold.is_some() => Outcome::BelowThreshold(result),
+            Ok(result) => Outcome::Optimized(result),
+            Err(e) if e.is::<SignedDocumentError>() => Outcome::Signed(e),
+            Err(e) => Outcome::Failed(e),
+        },
+    };
+    FileRecord { item, outcome, processing_time: started.elapsed(), dry_run: options.dry_run }
+}
+
+/// Optimize the inputs in parallel on a pool of `settings.threads` threads of its own, so
+/// that each batch gets the number asked for and the global pool is left alone. An input
+/// that takes longer than the timeout fails, freeing its thread for the next. Each input is
+/// added to the state as soon as it's through, except in a dry run.
+pub fn run(items: Vec<WorkItem>, options: &OptimizeOptions, settings: &RunSettings, state: Option<&BatchState>) -> Result<Vec<FileRecord>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(settings.threads)
+        .build()
+        .with_context(|| format!("Failed to start {} threads", settings.threads))?;
+    let progress = Progress::new(items.len(), settings.show_progress);
+    let records = pool.install(|| {
+        items
+            .into_par_iter()
+            .map(|item| {
+                let bar = progress.file_bar(&item.input);
+                let record = process(item, options, settings, bar.as_ref());
+                if let Some(bar) = bar {
+                    bar.finish_and_clear();
+                }
+                progress.file_done(&record);
+                if let Some(state) = state.filter(|_| !record.dry_run && !matches!(record.outcome, Outcome::Done)) {
+                    if let Err(e) = state.record(&record) {
+                        progress.println(&format!("Warning: {:#}", e));
+                    }
+                }
+                record
+            })
+            .collect()
+    });
+    progress.finish();
+    Ok(records)
+}
+
+/// The totals of the files kept as optimized and how many ended otherwise; the failed
+/// files are listed with their error on stderr
+pub fn print_summary(records: &[FileRecord]) {
+    let count = |outcome: fn(&Outcome) -> bool| records.iter().filter(|record| outcome(&record.outcome)).count();
+    let optimized: Vec<&OptimizationResult> = records
+        .iter()
+        .filter_map(|record| match &record.outcome {
+            Outcome::Optimized(result) => Some(result),
+            _ => None,
+        })
+        .collect();
+    let total_original: u64 = optimized.iter().map(|result| result.original_size).sum();
+    let total_optimized: u64 = optimized.iter().map(|result| result.optimized_size).sum();
+    let total_image_bytes_saved: i64 =
+        optimized.iter().map(|result| result.image_bytes_before as i64 - result.image_bytes_after as i64).sum();
+    let failed: Vec<&FileRecord> = records.iter().filter(|record| matches!(record.outcome, Outcome::Failed(_))).collect();
+    let failed_verification =
+        count(|outcome| matches!(outcome, Outcome::Failed(e) if e.is::<crate::verification::VerificationError>()));
+    let timed_out = count(|outcome| matches!(outcome, Outcome::Failed(e) if e.is::<TimedOutError>()));
+    let input_error = |outcome: &Outcome| match outcome {
+        Outcome::Failed(e) => e.downcast_ref::<InputError>().cloned(),
+        _ => None,
+    };
+    let not_found = records.iter().filter(|record| matches!(input_error(&record.outcome), Some(InputError::NotFound { .. }))).count();
+    let not_pdf = records.iter().filter(|record| matches!(input_error(&record.outcome), Some(InputError::NotPdf { .. }))).count();
+
+    let dry_run = records.iter().any(|record| record.dry_run);
+    if dry_run {
+        println!("\nBatch Summary (dry run, nothing written):");
+        println!("=========================================");
+    } else {
+        println!("\nBatch Summary:");
+        println!("==============");
+    }
+    println!("Files processed: {}/{}", optimized.len(), records.len());
+    let skipped_signed = count(|outcome| matches!(outcome, Outcome::Signed(_)));
+    if skipped_signed > 0 {
+        println!("Files skipped (digitally signed): {}", skipped_signed);
+    }
+    let skipped_existing = count(|outcome| matches!(outcome, Outcome::OutputExists));
+    if skipped_existing > 0 {
+        println!("Files skipped (output exists): {}", skipped_existing);
+    }
+    let skipped_done = count(|outcome| matches!(outcome, Outcome::Done));
+    if skipped_done > 0 {
+        println!("Files skipped (done in an earlier run): {}", skipped_done);
+    }
+    let skipped_optimized = count(|outcome| matches!(outcome, Outcome::AlreadyOptimized(_)));
+    if skipped_optimized > 0 {
+        println!("Files skipped (already optimized): {}", skipped_optimized);
+    }
+    let copied = count(|outcome| {
+        matches!(outcome, Outcome::BelowThreshold(result) if result.below_threshold == Some(BelowThreshold::OriginalCopied))
+    });
+    let kept = count(|outcome| {
+        matches!(outcome, Outcome::BelowThreshold(result) if result.below_threshold == Some(BelowThreshold::OriginalKept))
+    });
+    let deleted = count(|outcome| matches!(outcome, Outcome::BelowThreshold(_))) - copied - kept;
+    if deleted > 0 {
+        println!("Files below --min-savings ({}): {}", if dry_run { "outputs wouldn't be kept" } else { "outputs deleted" }, deleted);
+    }
+    if copied > 0 {
+        println!("Files below --min-savings ({}): {}", if dry_run { "originals would be copied" } else { "originals copied" }, copied);
+    }
+    if kept > 0 {
+        println!("Files below --min-savings ({}): {}", if dry_run { "originals would be kept" } else { "originals kept" }, kept);
+    }
+    if !failed.is_empty() {
+        println!("Files failed: {}", failed.len());
+    }
+    if failed_verification > 0 {
+        println!("Files that failed verification: {}", failed_verification);
+    }
+    if timed_out > 0 {
+        println!("Files that timed out: {}", timed_out);
+    }
+    if not_found > 0 {
+        println!("Files not found: {}", not_found);
+    }
+    if not_pdf > 0 {
+        println!("Files that aren't PDFs: {}", not_pdf);
+    }
+    println!("Total original size: {}", format_bytes(total_original));
+    println!("Total {} size: {}", if dry_run { "estimated" } else { "optimized" }, format_bytes(total_optimized));
+    println!("Total space saved: {:.1}%", calculate_compression_ratio(total_original, total_optimized));
+    println!("Total images optimized: {}", optimized.iter().map(|result| result.images_optimized).sum::<usize>());
+    println!("Total images resized: {}", optimized.iter().map(|result| result.images_resized).sum::<usize>());
+    println!("Total saved on images: {}", format_bytes(total_image_bytes_saved.max(0) as u64));
+
+    if !failed.is_empty() {
+        eprintln!("\nFailed files:");
+        for record in failed {
+            if let Outcome::Failed(e) = &record.outcome {
+                eprintln!("  {}: {:#}", record.item.input.display(), e);
+            }
+        }
+    }
+}
+
+/// 0 when no file failed, 1 when they all did, 2 when some did
+pub fn exit_code(records: &[FileRecord]) -> i32 {
+    let failed = records.iter().filter(|record| matches!(record.outcome, Outcome::Failed(_))).count();
+    match failed {
+        0 => 0,
+        _ if failed == records.len() => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMING: Naming = Naming { template: None, preset: &Preset::Web, quality: 80 };
+
+    /// A directory holding `names`, each an empty file
+    fn files(names: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for name in names {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "%PDF-1.7\n").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn inputs_given_twice_are_kept_once() {
+        let dir = files(&["a.pdf", "b.pdf", "sub/c.pdf"]);
+        let path = |name: &str| dir.path().join(name);
+        let given = vec![path("a.pdf"), path("b.pdf"), path("a.pdf"), path("sub/../a.pdf"), path("sub/c.pdf")];
+        let (kept, explicit) = dedupe_inputs(given, vec![None; 5]);
+        assert_eq!(kept, [path("a.pdf"), path("b.pdf"), path("sub/c.pdf")]);
+        assert_eq!(explicit, [None, None, None]);
+
+        // The same input with outputs of its own is written to each
+        let outputs = vec![Some(path("one.pdf")), Some(path("two.pdf")), Some(path("./one.pdf"))];
+        let (kept, explicit) = dedupe_inputs(vec![path("a.pdf"); 3], outputs);
+        assert_eq!(kept, [path("a.pdf"), path("a.pdf")]);
+        assert_eq!(explicit, [Some(path("one.pdf")), Some(path("two.pdf"))]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn inputs_given_through_a_symlink_are_kept_once() {
+        let dir = files(&["a.pdf"]);
+        std::os::unix::fs::symlink("a.pdf", dir.path().join("link.pdf")).unwrap();
+        std::os::unix::fs::symlink(".", dir.path().join("here")).unwrap();
+        let given = vec![dir.path().join("link.pdf"), dir.path().join("a.pdf"), dir.path().join("here/a.pdf")];
+        let (kept, _) = dedupe_inputs(given, vec![None; 3]);
+        assert_eq!(kept, [dir.path().join("link.pdf")]);
+    }
+
+    #[test]
+    fn same_named_inputs_collide_when_flattened() {
+        let dir = files(&["one/x.pdf", "two/x.pdf", "two/y.pdf"]);
+        let inputs = [dir.path().join("one/x.pdf"), dir.path().join("two/x.pdf"), dir.path().join("two/y.pdf")];
+        let out = dir.path().join("out");
+
+        let outputs = output_paths(&inputs, &[], Some(&out), false, &NAMING).unwrap();
+        assert_eq!(outputs, [out.join("one/x.pdf"), out.join("two/x.pdf"), out.join("two/y.pdf")]);
+
+        let error = output_paths(&inputs, &[], Some(&out), true, &NAMING).unwrap_err().to_string();
+        let expected = format!(
+            "Several inputs would be written to the same output:\n  {}: {}, {}",
+            out.join("x.pdf").display(),
+            inputs[0].display(),
+            inputs[1].display()
+        );
+        assert_eq!(error, expected);
+    }
+
+    #[test]
+    fn outputs_given_explicitly_collide_too() {
+        let dir = files(&["a.pdf", "b.pdf"]);
+        let inputs = [dir.path().join("a.pdf"), dir.path().join("b.pdf")];
+        let explicit = [Some(dir.path().join("out.pdf")), Some(dir.path().join("sub/../out.pdf"))];
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let error = output_paths(&inputs, &explicit, None, false, &NAMING).unwrap_err().to_string();
+        assert!(error.starts_with("Several inputs would be written to the same output:\n  "), "{}", error);
+        assert!(error.ends_with(&format!("{}, {}", inputs[0].display(), inputs[1].display())), "{}", error);
+    }
+
+    #[cfg(any(target_os = "macos", windows))]
+    #[test]
+    fn paths_differing_in_case_are_the_same_file() {
+        let dir = files(&["Report.pdf"]);
+        assert_eq!(case_folded(PathBuf::from("Out/Report.PDF")), PathBuf::from("out/report.pdf"));
+
+        let given = vec![dir.path().join("Report.pdf"), dir.path().join("REPORT.pdf")];
+        let (kept, _) = dedupe_inputs(given, vec![None; 2]);
+        assert_eq!(kept, [dir.path().join("Report.pdf")]);
+
+        let inputs = [dir.path().join("Report.pdf"), dir.path().join("other.pdf")];
+        let explicit = [Some(dir.path().join("OUT.pdf")), Some(dir.path().join("out.pdf"))];
+        let error = output_paths(&inputs, &explicit, None, false, &NAMING).unwrap_err().to_string();
+        assert!(error.starts_with("Several inputs would be written to the same output:"), "{}", error);
+    }
+
+    #[cfg(not(any(target_os = "macos", windows)))]
+    #[test]
+    fn paths_differing_in_case_are_different_files() {
+        let dir = files(&["Report.pdf", "report.pdf"]);
+        assert_eq!(case_folded(PathBuf::from("Out/Report.PDF")), PathBuf::from("Out/Report.PDF"));
+        let inputs = [dir.path().join("Report.pdf"), dir.path().join("report.pdf")];
+        let (kept, _) = dedupe_inputs(inputs.to_vec(), vec![None; 2]);
+        assert_eq!(kept, inputs);
+        let outputs = output_paths(&inputs, &[], Some(&dir.path().join("out")), true, &NAMING).unwrap();
+        assert_eq!(outputs, [dir.path().join("out/Report.pdf"), dir.path().join("out/report.pdf")]);
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::batch::FileRecord;
+use crate::report::{serialize_optional_path, Status};
+
+const STATE_VERSION: u64 = 1;
+
+/// What an earlier run did with one input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    input: String, // As given
+    #[serde(serialize_with = "serialize_optional_path")]
+    output: Option<PathBuf>,
+    status: Status,
+    size: Option<u64>,
+    modified: Option<u64>, // Nanoseconds since the Unix epoch
+    sha256: Option<String>,
+}
+
+/// The inputs a batch has been through, kept in a file that's rewritten after each one so
+/// that an interrupted batch can pick up where it stopped
+pub struct BatchState {
+    path: PathBuf,
+    retry_failed: bool,
+    entries: Mutex<BTreeMap<String, Entry>>, // By input, as given
+}
+
+impl BatchState {
+    /// Read the state file, starting afresh when it doesn't exist yet
+    pub fn load(path: &Path, retry_failed: bool) -> Result<Self> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(content) => parse_state(&content).with_context(|| format!("Failed to read the state file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read the state file {}", path.display())),
+        };
+        Ok(BatchState { path: path.to_path_buf(), retry_failed, entries: Mutex::new(entries) })
+    }
+
+    /// How many inputs earlier runs went through
+    pub fn recorded(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether an earlier run already went through the input and it hasn't changed since:
+    /// same size and modification time. Failures count as done unless they're to be
+    /// retried, and optimized files whose output has gone are done again.
+    pub fn is_done(&self, input: &Path, resolved: &Path) -> bool {
+        let entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get(&input.display().to_string()) else {
+            return false;
+        };
+        let (size, modified) = fingerprint(resolved);
+        let unchanged = entry.size.is_some() && entry.size == size && entry.modified.is_some() && entry.modified == modified;
+        let redo = match entry.status {
+            Status::Failed => self.retry_failed,
+            Status::Ok => entry.output.as_deref().is_none_or(|output| !output.exists()),
+            Status::Skipped | Status::BelowThreshold => false,
+        };
+        unchanged && !redo
+    }
+
+    /// Record how the input went and save the state
+    pub fn record(&self, record: &FileRecord) -> Result<()> {
+        let row = record.report_row();
+        let (size, modified) = fingerprint(record.item.resolved.path());
+        let input = record.item.input.display().to_string();
+        let entry = Entry { input: input.clone(), output: row.output, status: row.status, size, modified, sha256: sha256(record.item.resolved.path()).ok() };
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(input, entry);
+        // Saved while still holding the lock, so that saves don't overtake each other
+        save_state(&self.path, &entries).with_context(|| format!("Failed to save the state file {}", self.path.display()))
+    }
+}
+
+/// Size and modification time of a file, to tell whether it changed between runs
+fn fingerprint(path: &Path) -> (Option<u64>, Option<u64>) {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return (None, None);
+    };
+    let modified = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|since| since.as_nanos() as u64);
+    (Some(metadata.len()), modified)
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    let mut hasher = openssl::hash::Hasher::new(openssl::hash::MessageDigest::sha256())?;
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finish()?.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The state file: its format's version and what happened to each input
+#[derive(Serialize, Deserialize)]
+struct StateFile<E> {
+    version: u64,
+    files: Vec<E>,
+}
+
+/// Just the version, checked before the rest, which another version may lay out differently
+#[derive(Deserialize)]
+struct Version {
+    version: u64,
+}
+
+/// Write to a temporary file next to the state and rename it over the state once synced,
+/// so that a crash leaves either the old state or the new one
+fn save_state(path: &Path, entries: &BTreeMap<String, Entry>) -> Result<()> {
+    let mut content = serde_json::to_string_pretty(&StateFile { version: STATE_VERSION, files: entries.values().collect() })?;
+    content.push('\n');
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = format!(".{}.", path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default());
+    let mut temp = tempfile::Builder::new().prefix(&prefix).suffix(".tmp").tempfile_in(directory)?;
+    temp.write_all(content.as_bytes())?;
+    temp.as_file().sync_all()?;
+    temp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+fn parse_state(content: &str) -> Result<BTreeMap<String, Entry>> {
+    match serde_json::from_str::<Version>(content) {
+        Ok(Version { version: STATE_VERSION }) => {}
+        Err(e) if e.is_syntax() || e.is_eof() => return Err(e.into()),
+        _ => anyhow::bail!("not a state file of this version of pdf-opticompress"),
+    }
+    let state: StateFile<Entry> = serde_json::from_str(content)?;
+    Ok(state.files.into_iter().map(|entry| (entry.input.clone(), entry)).collect())
+}
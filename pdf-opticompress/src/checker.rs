@@ -0,0 +1,306 @@
+use anyhow::Result;
+use lopdf::{Document, Object, ObjectId};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+use crate::encryption::decrypt_document;
+use crate::filters::{decode_stream, stream_filters};
+use crate::objects::for_each_reference;
+use crate::pdf_reader::{load_repaired, signature_info, validate_pdf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The file is broken: readers may reject it or show it wrongly
+    Error,
+    /// Readers cope, but something is off or affects optimization
+    Warning,
+    /// Worth knowing before optimizing
+    Info,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+/// One problem found by `check`
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckFinding {
+    pub severity: Severity,
+    /// Stable identifier of the kind of problem, e.g. `dangling-reference`
+    pub code: &'static str,
+    pub message: String,
+    /// The object the problem is in, when there is one
+    pub object: Option<ObjectId>,
+}
+
+impl CheckFinding {
+    fn new(severity: Severity, code: &'static str, message: impl Into<String>, object: Option<ObjectId>) -> Self {
+        Self { severity, code, message: message.into(), object }
+    }
+}
+
+/// The filters the PDF specification defines for streams
+const STANDARD_FILTERS: [&[u8]; 10] = [
+    b"FlateDecode",
+    b"LZWDecode",
+    b"ASCIIHexDecode",
+    b"ASCII85Decode",
+    b"RunLengthDecode",
+    b"CCITTFaxDecode",
+    b"JBIG2Decode",
+    b"DCTDecode",
+    b"JPXDecode",
+    b"Crypt",
+];
+
+/// Abbreviated names, which are only valid for inline images but which readers accept
+const ABBREVIATED_FILTERS: [&[u8]; 7] = [b"Fl", b"LZW", b"AHx", b"A85", b"RL", b"CCF", b"DCT"];
+
+/// Filters `decode_stream` undoes, so their data can be checked
+const DECODABLE_FILTERS: [&[u8]; 8] = [b"FlateDecode", b"Fl", b"LZWDecode", b"LZW", b"ASCIIHexDecode", b"AHx", b"ASCII85Decode", b"A85"];
+
+/// Check a file's structure without changing anything: whether it can be read, its
+/// encryption, the catalog and page tree, references, stream lengths, filters and data,
+/// and signatures. Errors come first.
+pub fn check_pdf(path: &Path, password: &str) -> Result<Vec<CheckFinding>> {
+    let mut findings = Vec::new();
+    let (mut doc, repair) = match load_repaired(path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            findings.push(CheckFinding::new(Severity::Error, "unreadable", format!("{:#}", e), None));
+            return Ok(findings);
+        }
+    };
+    if let Some(repair) = &repair {
+        if repair.objects_found.is_some() {
+            let message = format!("{}; the file had to be rebuilt from the objects found in it (see `repair`)", repair.problem);
+            findings.push(CheckFinding::new(Severity::Error, "broken-xref", message, None));
+        }
+        for id in &repair.streams_recovered {
+            let message = "The stream's /Length disagrees with the length of its data";
+            findings.push(CheckFinding::new(Severity::Error, "stream-length", message, Some(*id)));
+        }
+    }
+
+    // Stream data can only be checked once it's decrypted
+    let readable = match decrypt_document(&mut doc, password) {
+        Ok(Some(_)) => {
+            let how = if password.is_empty() { "it opens without a password" } else { "the password opens it" };
+            findings.push(CheckFinding::new(Severity::Info, "encrypted", format!("The document is encrypted; {}", how), None));
+            true
+        }
+        Ok(None) => true,
+        Err(e) => {
+            findings.push(CheckFinding::new(Severity::Error, "encrypted", format!("{:#}", e), None));
+            false
+        }
+    };
+
+    if let Err(e) = validate_pdf(&doc) {
+        findings.push(CheckFinding::new(Severity::Error, "invalid-document", e.to_string(), None));
+    }
+    check_page_tree(&doc, &mut findings);
+    check_references(&doc, &mut findings);
+    check_streams(&doc, readable, &mut findings);
+
+    let signatures = signature_info(&doc);
+    if signatures.is_signed() {
+        let message = format!(
+            "The document is digitally signed ({} signature field{}{}); optimizing it invalidates the signatures",
+            signatures.signed_fields,
+            if signatures.signed_fields == 1 { "" } else { "s" },
+            if signatures.permissions_signed { ", plus a certification or usage rights signature" } else { "" }
+        );
+        findings.push(CheckFinding::new(Severity::Warning, "signed", message, None));
+    }
+
+    findings.sort_by_key(|finding| finding.severity);
+    Ok(findings)
+}
+
+/// Follow the page tree from the catalog, reporting links to missing objects, cycles,
+/// nodes that aren't pages, wrong `/Parent` links and wrong `/Count` values
+fn check_page_tree(doc: &Document, findings: &mut Vec<CheckFinding>) {
+    // A missing catalog is reported by `validate_pdf`
+    let Ok(catalog) = doc.catalog() else { return };
+    let Ok(root) = catalog.get(b"Pages").and_then(Object::as_reference) else {
+        let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference).ok();
+        findings.push(CheckFinding::new(Severity::Error, "page-tree", "The catalog has no /Pages reference", catalog_id));
+        return;
+    };
+    let mut visited = HashSet::new();
+    check_page_node(doc, root, None, &mut visited, findings);
+}
+
+/// Check a page tree node and its descendants, returning how many pages they hold
+fn check_page_node(
+    doc: &Document,
+    id: ObjectId,
+    parent: Option<ObjectId>,
+    visited: &mut HashSet<ObjectId>,
+    findings: &mut Vec<CheckFinding>,
+) -> i64 {
+    if !visited.insert(id) {
+        let message = format!("The page tree visits object {} {} R twice", id.0, id.1);
+        findings.push(CheckFinding::new(Severity::Error, "page-tree", message, parent));
+        return 0;
+    }
+    let Ok(node) = doc.get_dictionary(id) else {
+        let message = format!("The page tree links to object {} {} R, which is missing or not a dictionary", id.0, id.1);
+        findings.push(CheckFinding::new(Severity::Error, "page-tree", message, parent));
+        return 0;
+    };
+    if let Some(parent) = parent {
+        if node.get(b"Parent").and_then(Object::as_reference).ok() != Some(parent) {
+            let message = "The page tree node's /Parent isn't the node that lists it";
+            findings.push(CheckFinding::new(Severity::Warning, "page-tree", message, Some(id)));
+        }
+    }
+
+    let is_pages = node.type_is(b"Pages") || (!node.type_is(b"Page") && node.has(b"Kids"));
+    if !is_pages {
+        if !node.type_is(b"Page") {
+            let message = "The page tree leaf has no /Type /Page";
+            findings.push(CheckFinding::new(Severity::Warning, "page-tree", message, Some(id)));
+        }
+        return 1;
+    }
+
+    let kids = match node.get(b"Kids").map(|kids| crate::objects::resolve(doc, kids)) {
+        Ok(Object::Array(kids)) => kids.as_slice(),
+        _ => {
+            findings.push(CheckFinding::new(Severity::Error, "page-tree", "The page tree node has no /Kids array", Some(id)));
+            &[]
+        }
+    };
+    let mut pages = 0;
+    for kid in kids {
+        match kid {
+            Object::Reference(kid_id) => pages += check_page_node(doc, *kid_id, Some(id), visited, findings),
+            _ => {
+                let message = "The page tree node lists a kid that isn't an indirect reference";
+                findings.push(CheckFinding::new(Severity::Error, "page-tree", message, Some(id)));
+            }
+        }
+    }
+    match node.get(b"Count").and_then(Object::as_i64) {
+        Ok(count) if count == pages => {}
+        Ok(count) => {
+            let message = format!("The page tree node's /Count is {} but it holds {} pages", count, pages);
+            findings.push(CheckFinding::new(Severity::Warning, "page-count", message, Some(id)));
+        }
+        Err(_) => findings.push(CheckFinding::new(Severity::Warning, "page-count", "The page tree node has no /Count", Some(id))),
+    }
+    pages
+}
+
+/// Report references to objects the file doesn't contain (readers treat them as null)
+fn check_references(doc: &Document, findings: &mut Vec<CheckFinding>) {
+    let mut dangling = BTreeSet::new();
+    for_each_reference(&Object::Dictionary(doc.trailer.clone()), &mut |target| {
+        if !doc.objects.contains_key(&target) {
+            dangling.insert((None, target));
+        }
+    });
+    for (&id, object) in &doc.objects {
+        // Cross-reference streams repeat the trailer as it was before decryption, and are
+        // never written out
+        if matches!(object, Object::Stream(stream) if stream.dict.type_is(b"XRef")) {
+            continue;
+        }
+        for_each_reference(object, &mut |target| {
+            if !doc.objects.contains_key(&target) {
+                dangling.insert((Some(id), target));
+            }
+        });
+    }
+
+    for (from, target) in dangling {
+        let message = match from {
+            Some(_) => format!("Refers to object {} {} R, which doesn't exist", target.0, target.1),
+            None => format!("The trailer refers to object {} {} R, which doesn't exist", target.0, target.1),
+        };
+        findings.push(CheckFinding::new(Severity::Warning, "dangling-reference", message, from));
+    }
+}
+
+/// Check every stream's `/Filter` and, when it can be decrypted and decoded, its data
+fn check_streams(doc: &Document, readable: bool, findings: &mut Vec<CheckFinding>) {
+    for (&id, object) in &doc.objects {
+        let Object::Stream(stream) = object else { continue };
+        if matches!(stream.dict.get(b"Filter"), Ok(filter) if !matches!(filter, Object::Name(_) | Object::Array(_))) {
+            findings.push(CheckFinding::new(Severity::Error, "invalid-filter", "/Filter is neither a name nor an array", Some(id)));
+            continue;
+        }
+
+        let filters = stream_filters(stream);
+        if let Some(unknown) = filters.iter().find(|filter| !STANDARD_FILTERS.contains(&filter.as_slice())) {
+            let name = String::from_utf8_lossy(unknown);
+            if !ABBREVIATED_FILTERS.contains(&unknown.as_slice()) {
+                findings.push(CheckFinding::new(Severity::Error, "invalid-filter", format!("Unknown filter /{}", name), Some(id)));
+                continue;
+            }
+            let message = format!("The abbreviated filter name /{} is only valid for inline images", name);
+            findings.push(CheckFinding::new(Severity::Warning, "invalid-filter", message, Some(id)));
+        }
+
+        let decodable = !filters.is_empty() && filters.iter().all(|filter| DECODABLE_FILTERS.contains(&filter.as_slice()));
+        if readable && decodable {
+            if let Err(e) = decode_stream(stream) {
+                let message = format!("The stream's data can't be decoded: {}", e);
+                findings.push(CheckFinding::new(Severity::Error, "corrupt-stream", message, Some(id)));
+            }
+        }
+    }
+}
+
+/// Print findings as a list, errors first
+pub fn print_findings(path: &Path, findings: &[CheckFinding]) {
+    println!("Checked {}", path.display());
+    for finding in findings {
+        let object = finding.object.map(|(number, generation)| format!(" (object {} {})", number, generation)).unwrap_or_default();
+        println!("  {:<7} [{}] {}{}", finding.severity.as_str(), finding.code, finding.message, object);
+    }
+
+    if findings.is_empty() {
+        println!("No problems found");
+        return;
+    }
+    let summary: Vec<String> = [(Severity::Error, "error"), (Severity::Warning, "warning"), (Severity::Info, "note")]
+        .into_iter()
+        .map(|(severity, noun)| {
+            let count = findings.iter().filter(|finding| finding.severity == severity).count();
+            format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+        })
+        .collect();
+    println!("{}", summary.join(", "));
+}
+
+/// What `check --format json` prints for a file
+#[derive(Serialize)]
+struct FindingsJson<'a> {
+    file: String,
+    errors: usize,
+    warnings: usize,
+    findings: &'a [CheckFinding],
+}
+
+/// The findings as a JSON object: `{"file": ..., "errors": n, "warnings": n, "findings": [...]}`
+pub fn findings_json(path: &Path, findings: &[CheckFinding]) -> String {
+    let count = |severity| findings.iter().filter(|finding| finding.severity == severity).count();
+    let json = FindingsJson {
+        file: path.display().to_string(),
+        errors: count(Severity::Error),
+        warnings: count(Severity::Warning),
+        findings,
+    };
+    serde_json::to_string_pretty(&json).expect("the findings serialize to JSON")
+}
@@ -1,43 +1,221 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "pdf-opticompress")]
 #[command(about = "High-performance PDF optimizer")]
 #[command(version = "0.1.0")]
-#[command(subcommand_required = false)]
+#[command(subcommand_required = false, subcommand_precedence_over_arg = true)]
+#[command(override_usage = "pdf-opticompress [OPTIONS] [FILES]...\n       pdf-opticompress <COMMAND>")]
+#[command(after_help = "Without a command, one PDF is optimized to <STEM>.optimized.pdf next to it, several are \
+optimized as a batch (as `batch` does), and none starts interactive mode.")]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Commands>,
+
+    /// PDFs to optimize with the default settings, when no command is given
+    pub files: Vec<PathBuf>,
+
+    /// Image quality (0-100) for the PDFs given without a command [default: 80]
+    #[arg(short, long, requires = "files")]
+    pub quality: Option<u8>,
+
+    /// Optimization preset for the PDFs given without a command [default: web]
+    #[arg(short, long, value_enum, requires = "files")]
+    pub preset: Option<Preset>,
+
+    /// Replace the outputs of the PDFs given without a command if they already exist
+    #[arg(short, long, requires = "files")]
+    pub force: bool,
+
+    /// Load inputs that don't start with a %PDF- header instead of refusing them
+    #[arg(long, global = true)]
+    pub no_sniff: bool,
+
+    /// Read default options from this file instead of $PDF_OPTICOMPRESS_CONFIG or
+    /// ~/.config/pdf-opticompress/config.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+impl Cli {
+    /// The command that `pdf-opticompress FILE...` stands for: `optimize` writing
+    /// <STEM>.optimized.pdf for one file, `batch` for more, with the preset and quality given
+    pub fn shorthand(&self, config: &crate::config::Config) -> Option<Commands> {
+        let command = match self.files.len() {
+            0 => return None,
+            1 => "optimize",
+            _ => "batch",
+        };
+        let mut args: Vec<std::ffi::OsString> = vec!["pdf-opticompress".into(), command.into()];
+        args.extend(self.files.iter().map(|file| file.clone().into_os_string()));
+        if self.files.len() == 1 {
+            args.push("--suffix=.optimized".into());
+        }
+        if let Some(preset) = self.preset.and_then(|preset| clap::ValueEnum::to_possible_value(&preset)) {
+            args.push(format!("--preset={}", preset.get_name()).into());
+        }
+        if let Some(quality) = self.quality {
+            args.push(format!("--quality={}", quality).into());
+        }
+        if self.force {
+            args.push("--force".into());
+        }
+        if self.no_sniff {
+            args.push("--no-sniff".into());
+        }
+        config.try_parse_from(args).unwrap_or_else(|e| e.exit()).command
+    }
 }
 
+// Parsed once at startup, so the size of the Optimize variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     /// Optimize a single PDF file
     Optimize {
-        /// Input PDF file
+        /// Input PDF file (- for stdin)
         input: PathBuf,
 
-        /// Output PDF file
-        output: PathBuf,
+        /// Output PDF file (- for stdout, with the results on stderr), or a directory to
+        /// write it in, named after the input and --suffix
+        #[arg(required_unless_present_any = ["name_template", "in_place", "suffix"])]
+        output: Option<PathBuf>,
 
-        /// Image quality (0-100)
-        #[arg(short, long, default_value = "80")]
-        quality: u8,
+        /// Name the output <STEM><SUFFIX>.pdf after the input, in the directory given as the
+        /// output, or next to the input when no output is given [default: .optimized]
+        #[arg(long, value_name = "SUFFIX", conflicts_with = "name_template", value_parser = parse_name_suffix)]
+        suffix: Option<String>,
 
-        /// Optimization preset
-        #[arg(short, long, value_enum, default_value = "web")]
-        preset: Preset,
+        /// Create the output's directory when it doesn't exist
+        #[arg(long)]
+        create_dirs: bool,
+
+        /// Replace the output when it already exists (by default the run stops before
+        /// starting), or with --in-place, the backup an earlier run left
+        #[arg(long, visible_alias = "overwrite")]
+        force: bool,
+
+        /// Name the output after the input and the settings, next to the input, e.g.
+        /// "{stem}_{preset}_q{quality}.pdf" (placeholders: {stem}, {ext}, {preset},
+        /// {quality}, {date}, {index})
+        #[arg(long, value_name = "TEMPLATE", conflicts_with = "output")]
+        name_template: Option<String>,
+
+        /// Replace the input with its optimized version once that's verified; the input is
+        /// left untouched when anything fails or it saves less than --min-savings. The output
+        /// can be left out, or be the input itself
+        #[arg(long, conflicts_with_all = ["name_template", "suffix", "keep_failed", "copy_below_threshold"])]
+        in_place: bool,
+
+        /// With --in-place, keep the original as <INPUT><SUFFIX> [default: .bak]
+        #[arg(long, value_name = "SUFFIX", requires = "in_place", value_parser = parse_backup_suffix)]
+        backup: Option<String>,
+
+        /// With --in-place, don't keep the original
+        #[arg(long, requires = "in_place", conflicts_with = "backup")]
+        no_backup: bool,
+
+        #[command(flatten)]
+        optimize: OptimizeArgs,
+
+        /// Pick the highest quality whose output fits in this size (e.g. 10MB)
+        #[arg(long, value_parser = crate::utils::parse_size)]
+        target_size: Option<u64>,
+
+        /// Only optimize the images used on these pages, e.g. 180-420 or 1-5,9 (images
+        /// shared with other pages included); structure optimization still covers every page
+        #[arg(long, value_name = "RANGES")]
+        pages: Option<String>,
+
+        /// Report what was done to each image (action, sizes, reasons for skipping)
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Output format of the results; with json, stdout holds only the JSON object and
+        /// progress and warnings go to stderr
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// Produce byte-identical output for the same input and options: objects are
+        /// numbered in a fixed order and the file identifier is derived from the content
+        #[arg(long, conflicts_with_all = ["keep_encryption", "encrypt_owner"])]
+        deterministic: bool,
+
+        /// User password of an encrypted input (documents that only restrict
+        /// permissions open without one)
+        #[arg(long)]
+        password: Option<String>,
+
+        /// Encrypt the output with the input's passwords and permissions. Fails rather
+        /// than writing an unencrypted file
+        #[arg(long)]
+        keep_encryption: bool,
+
+        /// Protect the output with this owner password, which lifts the permission
+        /// restrictions
+        #[arg(long, value_name = "PASSWORD", conflicts_with = "keep_encryption")]
+        encrypt_owner: Option<String>,
+
+        /// Password needed to open the protected output (default: none, so only the
+        /// permissions are restricted)
+        #[arg(long, value_name = "PASSWORD", requires = "encrypt_owner")]
+        encrypt_user: Option<String>,
+
+        /// What the protected output allows without the owner password, e.g. print,extract
+        /// (default: everything)
+        #[arg(long, value_enum, value_delimiter = ',', requires = "encrypt_owner")]
+        permissions: Vec<Permission>,
+
+        /// Cipher for the protected output
+        #[arg(long, value_enum, default_value = "aes256", requires = "encrypt_owner")]
+        cipher: EncryptionCipher,
+
+        #[command(flatten)]
+        info: DocumentInfoArgs,
     },
 
-    /// Analyze a PDF file and show optimization potential
+    /// Analyze PDF files and show optimization potential
     Analyze {
-        /// Input PDF file
-        input: PathBuf,
+        /// Input PDF files (- for stdin); with more than one, each gets a one-line summary
+        /// and the totals follow
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Analyze two PDFs (e.g. a file and its optimized version) and show what changed
+        /// between them, failing if their pages or text differ
+        #[arg(long, conflicts_with_all = ["deep", "simulate_presets"])]
+        compare: bool,
 
         /// Show potential savings
         #[arg(long)]
         show_savings: bool,
+
+        /// List the N largest objects with their type, filter and size
+        #[arg(long, value_name = "N")]
+        top: Option<usize>,
+
+        /// Also measure the savings by recompressing a sample of the images and other
+        /// streams with the preset's settings (slower, but closer to what optimize does)
+        #[arg(long)]
+        deep: bool,
+
+        /// Preset whose settings --deep recompresses with
+        #[arg(short, long, value_enum, default_value = "web", requires = "deep")]
+        preset: Preset,
+
+        /// Image quality (0-100) --deep and --simulate-presets recompress at
+        #[arg(short, long, default_value = "80")]
+        quality: u8,
+
+        /// Estimate the output size under every preset by recompressing a sample, as --deep
+        /// does, without writing anything
+        #[arg(long)]
+        simulate_presets: bool,
+
+        /// Output format; json includes the estimated savings and the weight of every page
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
     },
 
     /// Batch process multiple PDF files
@@ -45,17 +223,642 @@ pub enum Commands {
         /// Input PDF files
         files: Vec<PathBuf>,
 
-        /// Output directory
+        /// Also process the inputs listed in this file (- for stdin), one per line; blank
+        /// lines and lines starting with # are skipped, and "INPUT<TAB>OUTPUT" gives an
+        /// input its own output path
+        #[arg(long, value_name = "FILE")]
+        files_from: Option<PathBuf>,
+
+        /// Separate the inputs of --files-from with NUL bytes instead of lines, as
+        /// `find -print0` writes them
+        #[arg(long, requires = "files_from")]
+        null: bool,
+
+        /// Output directory; the inputs keep their paths relative to the directory they
+        /// share, which are created as needed
         #[arg(short, long)]
         output_dir: Option<PathBuf>,
 
+        /// Write every output directly in the output directory, without subdirectories
+        #[arg(long, requires = "output_dir")]
+        flatten: bool,
+
+        /// Name each output after its input and the settings instead of <NAME>.optimized.pdf
+        /// (or the input's name under --output-dir), e.g. "{stem}_{preset}_q{quality}.pdf"
+        /// (placeholders: {stem}, {ext}, {preset}, {quality}, {date}, and {index}, the input's
+        /// position from 1)
+        #[arg(long, value_name = "TEMPLATE")]
+        name_template: Option<String>,
+
+        /// Replace each input with its optimized version once that's verified; an input is
+        /// left untouched when anything fails or it saves less than --min-savings. Implies
+        /// --skip-optimized, so that running again doesn't recompress earlier outputs
+        #[arg(long, conflicts_with_all = ["output_dir", "name_template", "keep_failed", "copy_below_threshold"])]
+        in_place: bool,
+
+        /// With --in-place, keep each original as <INPUT><SUFFIX> [default: .bak]
+        #[arg(long, value_name = "SUFFIX", requires = "in_place", value_parser = parse_backup_suffix)]
+        backup: Option<String>,
+
+        /// With --in-place, don't keep the originals
+        #[arg(long, requires = "in_place", conflicts_with = "backup")]
+        no_backup: bool,
+
+        /// Skip the inputs whose output already exists
+        #[arg(long, conflicts_with = "in_place")]
+        skip_existing: bool,
+
+        /// Skip the inputs whose output already exists and is at least as recent as the
+        /// input, optimizing the others again
+        #[arg(long, conflicts_with_all = ["skip_existing", "in_place"])]
+        skip_if_newer: bool,
+
+        /// Replace outputs that already exist (by default the batch stops before starting
+        /// and lists them), or with --in-place, the backups an earlier run left
+        #[arg(long, visible_alias = "force", conflicts_with_all = ["skip_existing", "skip_if_newer"])]
+        overwrite: bool,
+
+        /// Record each input in this file as soon as it's through, and skip the inputs it
+        /// already records (unchanged since), so that an interrupted batch can be resumed
+        #[arg(long, value_name = "FILE")]
+        state_file: Option<PathBuf>,
+
+        /// Optimize the inputs that failed in an earlier run again instead of skipping them
+        #[arg(long, requires = "state_file")]
+        retry_failed: bool,
+
+        /// Skip the inputs this tool already optimized with a preset and quality at least as
+        /// aggressive as this run's (presets from the most faithful: archive, print, web,
+        /// scan, maximum)
+        #[arg(long)]
+        skip_optimized: bool,
+
         /// Number of threads to use
         #[arg(short, long, default_value = "4")]
         threads: usize,
+
+        /// Give up on an input that takes longer than this many seconds, failing it and
+        /// moving on to the next
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+        timeout: Option<u64>,
+
+        /// Keep the images being decoded at once by all the threads under this many
+        /// megabytes (counted as width × height × 4 bytes each). A thread about to decode an
+        /// image that doesn't fit waits for others to finish theirs, so with a small budget
+        /// and many --threads, threads mostly wait; an image larger than the whole budget is
+        /// decoded while no other is. Images under a megapixel aren't counted.
+        #[arg(long, value_name = "MB", value_parser = clap::value_parser!(u64).range(1..))]
+        max_memory: Option<u64>,
+
+        /// Print a plain line per file as it's done instead of progress bars (the default
+        /// when the output isn't a terminal)
+        #[arg(long)]
+        no_progress: bool,
+
+        /// Produce byte-identical outputs for the same inputs and options
+        #[arg(long)]
+        deterministic: bool,
+
+        /// Write a line per input (paths, status, sizes, images optimized, time, error) to
+        /// this file, as CSV or JSON depending on its extension
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+
+        #[command(flatten)]
+        optimize: OptimizeArgs,
+    },
+
+    /// Show a PDF's page count, version, page sizes, document information and more, without
+    /// reading its streams
+    Info {
+        /// PDF file to describe
+        input: PathBuf,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// User password of an encrypted input, to read its document information
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Write a copy of a PDF that can be read in a text editor: streams decoded, object
+    /// streams unpacked and dictionaries spread over lines. For diagnosing problems.
+    Decompress {
+        /// Input PDF file
+        input: PathBuf,
+
+        /// Output PDF file
+        output: PathBuf,
+
+        /// User or owner password of an encrypted input; the output isn't encrypted
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Write page ranges of a PDF as separate files, each with only the objects its pages use
+    Split {
+        /// Input PDF file
+        input: PathBuf,
+
+        /// Pages of each output file, e.g. 1-10,11-20,21- (a range without an end runs to
+        /// the last page)
+        #[arg(long, value_name = "RANGES", required_unless_present = "every", conflicts_with = "every")]
+        ranges: Option<String>,
+
+        /// Write a file for every N pages
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        every: Option<u32>,
+
+        /// Directory for the output files, created if missing (default: the input's directory)
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+
+        /// Optimize each output file
+        #[arg(long)]
+        optimize: bool,
+
+        /// Optimization preset for --optimize
+        #[arg(short, long, value_enum, default_value = "web", requires = "optimize")]
+        preset: Preset,
+
+        /// Image quality (0-100) for --optimize
+        #[arg(short, long, default_value = "80", requires = "optimize")]
+        quality: u8,
+
+        /// User or owner password of an encrypted input; the output files aren't encrypted
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Combine PDFs into one, in the order given, with each file's bookmarks under a
+    /// bookmark of its own
+    Merge {
+        /// Input PDF files
+        #[arg(required = true, num_args = 2..)]
+        files: Vec<PathBuf>,
+
+        /// Output PDF file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Optimize the merged file
+        #[arg(long)]
+        optimize: bool,
+
+        /// Optimization preset for --optimize
+        #[arg(short, long, value_enum, default_value = "web", requires = "optimize")]
+        preset: Preset,
+
+        /// Image quality (0-100) for --optimize
+        #[arg(short, long, default_value = "80", requires = "optimize")]
+        quality: u8,
+
+        /// User or owner password of the encrypted inputs, which are refused without one;
+        /// the output isn't encrypted
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Remove pages from a PDF, along with the bookmarks and links leading to them and the
+    /// objects only they used
+    DeletePages {
+        /// Input PDF file
+        input: PathBuf,
+
+        /// Output PDF file
+        output: PathBuf,
+
+        /// Pages to delete, e.g. 3,7,20-25
+        #[arg(long, value_name = "RANGES")]
+        pages: String,
+
+        /// Point bookmarks and links to deleted pages at the nearest remaining page
+        /// instead of removing them
+        #[arg(long)]
+        remap_links: bool,
+
+        /// User password of an encrypted input; the output keeps its encryption
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Show, set or remove the document information and XMP metadata
+    Metadata {
+        #[command(subcommand)]
+        action: MetadataAction,
+    },
+
+    /// Inspect the configuration file of default options
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Report structural problems of a PDF without writing anything; exits with status 1
+    /// when there are errors
+    Check {
+        /// PDF file to check
+        input: PathBuf,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// User password of an encrypted input, so its streams can be checked too
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Rewrite a damaged PDF (missing, truncated or wrong cross-reference table, wrong
+    /// stream lengths) as a clean file, leaving its content as it is
+    Repair {
+        /// Damaged PDF file
+        input: PathBuf,
+
+        /// Repaired PDF file
+        output: PathBuf,
+
+        /// User password of an encrypted input; the output keeps its encryption
+        #[arg(long)]
+        password: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print the options the commands take when no flag sets them, from the environment,
+    /// the configuration file or the built-in defaults, as a configuration file
+    Show,
+}
+
+#[derive(Subcommand)]
+pub enum MetadataAction {
+    /// Print the document information dictionary and the XMP metadata
+    Show {
+        /// Input PDF file
+        input: PathBuf,
+
+        /// User or owner password of an encrypted input
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Write new values into both the document information and the XMP metadata, and
+    /// record the modification date
+    Set {
+        /// Input PDF file
+        input: PathBuf,
+
+        /// Output PDF file
+        output: PathBuf,
+
+        /// New document title
+        #[arg(long)]
+        title: Option<String>,
+
+        /// New document author
+        #[arg(long)]
+        author: Option<String>,
+
+        /// New document subject
+        #[arg(long)]
+        subject: Option<String>,
+
+        /// New document keywords
+        #[arg(long)]
+        keywords: Option<String>,
+
+        /// User or owner password of an encrypted input; the output keeps its encryption
+        #[arg(long)]
+        password: Option<String>,
+    },
+
+    /// Remove the document information dictionary and every XMP metadata stream
+    Strip {
+        /// Input PDF file
+        input: PathBuf,
+
+        /// Output PDF file
+        output: PathBuf,
+
+        /// User or owner password of an encrypted input; the output keeps its encryption
+        #[arg(long)]
+        password: Option<String>,
     },
 }
 
-#[derive(Clone, clap::ValueEnum)]
+/// Optimization options shared by the Optimize and Batch commands
+#[derive(Args, Clone)]
+pub struct OptimizeArgs {
+    /// Image quality (0-100)
+    #[arg(short, long, default_value = "80")]
+    pub quality: u8,
+
+    /// Optimization preset
+    #[arg(short, long, value_enum, default_value = "web")]
+    pub preset: Preset,
+
+    /// JPEG chroma subsampling (defaults to the preset's choice)
+    #[arg(long, value_enum)]
+    pub chroma: Option<ChromaSubsampling>,
+
+    /// Remove the document information dictionary and all XMP metadata
+    #[arg(long)]
+    pub strip_metadata: bool,
+
+    /// Remove private application data (PieceInfo, image Alternates, object-level
+    /// XMP metadata) that editors such as Illustrator and InDesign leave behind
+    #[arg(long)]
+    pub strip_private_data: bool,
+
+    /// Remove the tagged-PDF structure tree. Screen readers and reflow stop working,
+    /// so no preset ever does this
+    #[arg(long)]
+    pub strip_structure: bool,
+
+    /// Remove active content: JavaScript, embedded files, open and additional actions,
+    /// and Launch/SubmitForm/ImportData actions (links to pages and URLs are kept)
+    #[arg(long)]
+    pub sanitize: bool,
+
+    /// Draw filled-in form fields into the page content and remove the form
+    #[arg(long)]
+    pub flatten_forms: bool,
+
+    /// Remove annotations of these subtypes, e.g. Popup,FileAttachment (links and
+    /// other subtypes are kept)
+    #[arg(long, value_name = "SUBTYPES", value_delimiter = ',')]
+    pub strip_annotations: Vec<String>,
+
+    /// Optimize digitally signed documents too, invalidating their signatures (by
+    /// default they are refused, or skipped in a batch)
+    #[arg(long)]
+    pub force_signed: bool,
+
+    /// PDF version to write (e.g. 1.4 for legacy viewers). Defaults to the input's,
+    /// raised when the output needs newer features
+    #[arg(long, value_name = "VERSION", value_parser = parse_pdf_version)]
+    pub pdf_version: Option<String>,
+
+    /// Aim for PDF/A-2b: images only recompressed losslessly, fonts kept embedded,
+    /// XMP identification and an sRGB output intent added, LZW replaced, and a report of
+    /// what still isn't compliant (no substitute for a validator)
+    #[arg(long)]
+    pub pdfa: bool,
+
+    /// Read a damaged input anyway, rebuilding its cross-reference table by scanning
+    /// the file for objects and recovering streams with a wrong length
+    #[arg(long)]
+    pub repair: bool,
+
+    /// Re-open each output and check that it loads, has the same pages with the same
+    /// text, and that bookmarks, named destinations and links lead to the same pages
+    /// (the default; in a batch, failing files count as failed)
+    #[arg(long, overrides_with = "no_verify")]
+    pub verify: bool,
+
+    /// Skip checking the output
+    #[arg(long, overrides_with = "verify")]
+    pub no_verify: bool,
+
+    /// Keep output that fails verification, renamed to <OUTPUT>.failed (by default
+    /// it is deleted)
+    #[arg(long, conflicts_with = "no_verify")]
+    pub keep_failed: bool,
+
+    /// Delete the output when it saves less than this percentage of the input's size (in
+    /// a batch, it counts as below the threshold)
+    #[arg(long, value_name = "PERCENT")]
+    pub min_savings: Option<f64>,
+
+    /// With --min-savings, write a copy of the input in place of an output that saves too
+    /// little instead of deleting it
+    #[arg(long, requires = "min_savings")]
+    pub copy_below_threshold: bool,
+
+    /// Go through every stage in memory and print what would be done to each image and
+    /// the estimated output size, without writing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Give the output the input's modification and access times (always done with
+    /// --in-place)
+    #[arg(long)]
+    pub preserve_times: bool,
+
+    /// Give the output the input's permissions: its mode on Unix, whether it's read-only
+    /// on Windows (always done with --in-place)
+    #[arg(long)]
+    pub preserve_mode: bool,
+
+    #[command(flatten)]
+    pub image: ImageArgs,
+
+    #[command(flatten)]
+    pub stages: StageArgs,
+
+    #[command(flatten)]
+    pub structure: StructureArgs,
+}
+
+/// Document information to write into the output; other entries are kept unless
+/// `--strip-metadata` is given
+#[derive(Args, Clone, Default)]
+pub struct DocumentInfoArgs {
+    /// Set the document title
+    #[arg(long, value_name = "TITLE")]
+    pub set_title: Option<String>,
+
+    /// Set the document author
+    #[arg(long, value_name = "AUTHOR")]
+    pub set_author: Option<String>,
+
+    /// Set the document subject
+    #[arg(long, value_name = "SUBJECT")]
+    pub set_subject: Option<String>,
+
+    /// Set the document keywords
+    #[arg(long, value_name = "KEYWORDS")]
+    pub set_keywords: Option<String>,
+}
+
+/// Restrict a run to one optimization stage, e.g. to isolate a rendering regression
+#[derive(Args, Clone, Default)]
+pub struct StageArgs {
+    /// Only optimize images; skip structural compression when saving
+    #[arg(long)]
+    pub images_only: bool,
+
+    /// Only optimize the document structure; leave images untouched
+    #[arg(long, conflicts_with = "images_only")]
+    pub structure_only: bool,
+}
+
+/// Document structure options shared by the Optimize and Batch commands
+#[derive(Args, Clone, Default)]
+pub struct StructureArgs {
+    /// Remove XObject, Font and ExtGState resource entries a page's content never uses
+    #[arg(long)]
+    pub prune_resources: bool,
+
+    /// Reduce embedded TrueType fonts to the glyphs the document uses
+    #[arg(long)]
+    pub subset_fonts: bool,
+
+    /// Drop embedded copies of the standard 14 fonts (Helvetica, Times, Courier, ...)
+    #[arg(long)]
+    pub unembed_standard_fonts: bool,
+
+    /// Remove embedded page thumbnails (done by default for web and maximum)
+    #[arg(long)]
+    pub strip_thumbnails: bool,
+
+    /// Re-encode legacy LZW-compressed streams with Flate (done by default for archive
+    /// and maximum)
+    #[arg(long)]
+    pub recompress_lzw: bool,
+
+    /// Flate compression level (1-9) for non-image streams (default: 6 for web, 9 otherwise,
+    /// or zopfli for archive and maximum when built with the `zopfli` feature)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=9))]
+    pub flate_level: Option<u32>,
+
+    /// Leave non-image streams as they are: no Flate compression of unfiltered streams
+    /// and no re-deflating of Flate ones
+    #[arg(long, conflicts_with = "flate_level")]
+    pub no_recompress_streams: bool,
+
+    /// Rewrite page and form content streams without operators that change nothing (empty
+    /// `q Q` pairs, identity `cm`, repeated settings) and with coordinates rounded
+    #[arg(long)]
+    pub optimize_content_streams: bool,
+
+    /// Decimal places of a point to keep when rounding coordinates
+    #[arg(long, value_name = "DECIMALS", default_value_t = 2, value_parser = clap::value_parser!(u32).range(0..=6), requires = "optimize_content_streams")]
+    pub content_precision: u32,
+}
+
+/// Image options shared by the Optimize and Batch commands
+#[derive(Args, Clone, Default)]
+pub struct ImageArgs {
+    /// Never re-encode image pixels: no JPEG recompression, resizing or color conversion.
+    /// Only lossless steps (metadata stripping, Flate/PNG recompression) run
+    #[arg(long, conflicts_with_all = ["grayscale", "bilevel", "max_dimension", "max_dpi", "min_ssim"])]
+    pub lossless: bool,
+
+    /// Convert color images to grayscale (DeviceGray)
+    #[arg(long)]
+    pub grayscale: bool,
+
+    /// Store text-like scanned images as 1-bit bilevel images
+    #[arg(long)]
+    pub bilevel: bool,
+
+    /// Apply bilevel conversion to every image, including photos
+    #[arg(long, requires = "bilevel")]
+    pub force_bilevel: bool,
+
+    /// Never convert lossless (Flate) images to JPEG, even photographic ones
+    #[arg(long)]
+    pub no_lossy_conversion: bool,
+
+    /// oxipng optimization level for PNG images (1-6, defaults to the preset's choice)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=6))]
+    pub png_level: Option<u8>,
+
+    /// Reject lossy re-encodes whose SSIM against the source is below this (0.0-1.0),
+    /// retrying at higher quality before keeping the original
+    #[arg(long, value_parser = parse_unit_interval)]
+    pub min_ssim: Option<f64>,
+
+    /// Downscale images larger than this many pixels on their longest side
+    /// (overrides the preset's limit)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_dimension: Option<u32>,
+
+    /// Downscale images drawn at more than this many pixels per inch of the page
+    /// (overrides the preset's limit)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_dpi: Option<u32>,
+
+    /// Never downscale images, whatever the preset
+    #[arg(long, conflicts_with_all = ["max_dimension", "max_dpi"])]
+    pub no_resize: bool,
+
+    /// Filter used when downscaling (defaults to lanczos3, catmullrom for batch runs)
+    #[arg(long, value_enum)]
+    pub resize_filter: Option<ResizeFilter>,
+
+    /// Leave images whose stream is smaller than this alone (e.g. 512, 10KB, 1MB; default 10KB)
+    #[arg(long, value_parser = crate::utils::parse_size)]
+    pub min_image_size: Option<u64>,
+
+    /// Leave images with fewer pixels than this alone
+    #[arg(long)]
+    pub min_image_pixels: Option<u64>,
+
+    /// Refuse to decode images with more pixels than this (default 250 million)
+    #[arg(long)]
+    pub max_decode_pixels: Option<u64>,
+
+    /// Refuse to decode images needing more memory than this (e.g. 512MB; default 1GB)
+    #[arg(long, value_parser = crate::utils::parse_size)]
+    pub max_decode_memory: Option<u64>,
+
+    /// Replace embedded ICC profiles of images with device color spaces
+    /// (default for the web and maximum presets)
+    #[arg(long, conflicts_with = "keep_icc")]
+    pub strip_icc: bool,
+
+    /// Keep embedded ICC profiles, storing identical ones only once
+    #[arg(long)]
+    pub keep_icc: bool,
+
+    /// JPEG encoder backend (mozjpeg requires the `mozjpeg` cargo feature)
+    #[arg(long, value_enum)]
+    pub jpeg_encoder: Option<JpegBackend>,
+}
+
+/// Parse a PDF version between 1.0 and 2.0
+fn parse_pdf_version(value: &str) -> Result<String, String> {
+    match crate::pdf_writer::parse_version(value) {
+        Some((1, 0..=7)) | Some((2, 0)) => Ok(value.trim().to_string()),
+        _ => Err(format!("{} is not a PDF version (1.0 to 1.7, or 2.0)", value)),
+    }
+}
+
+/// Parse the suffix of a backup's name, which must keep it next to the original
+fn parse_backup_suffix(value: &str) -> Result<String, String> {
+    if value.is_empty() || value.contains(std::path::is_separator) {
+        Err(format!("{:?} can't be added to a file name", value))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// Parse a suffix for an output's name, which may be empty
+fn parse_name_suffix(value: &str) -> Result<String, String> {
+    if value.contains(std::path::is_separator) {
+        Err(format!("{:?} can't be added to a file name", value))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// Parse a number between 0.0 and 1.0
+fn parse_unit_interval(value: &str) -> Result<f64, String> {
+    let number: f64 = value.parse().map_err(|_| format!("invalid number: {}", value))?;
+    if (0.0..=1.0).contains(&number) {
+        Ok(number)
+    } else {
+        Err(format!("{} is not between 0.0 and 1.0", number))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Preset {
     /// Web optimization (smaller file size, good quality)
     Web,
@@ -65,4 +868,89 @@ pub enum Preset {
     Archive,
     /// Maximum compression (aggressive optimization)
     Maximum,
-}
\ No newline at end of file
+    /// Scanned documents (grayscale or bilevel page images at 200 DPI)
+    Scan,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChromaSubsampling {
+    /// Full chroma resolution
+    #[value(name = "444")]
+    Yuv444,
+    /// Half horizontal chroma resolution
+    #[value(name = "422")]
+    Yuv422,
+    /// Half horizontal and vertical chroma resolution
+    #[value(name = "420")]
+    Yuv420,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ResizeFilter {
+    /// Sharpest result, slowest
+    #[default]
+    Lanczos3,
+    /// Close to Lanczos3 at a fraction of the cost
+    #[value(name = "catmullrom")]
+    CatmullRom,
+    /// Bilinear, fast
+    Triangle,
+    /// No filtering, fastest
+    Nearest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Readable text
+    Text,
+    /// JSON, for scripts
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Permission {
+    /// Print at full quality
+    Print,
+    /// Print at low resolution only
+    PrintLow,
+    /// Change the document
+    Modify,
+    /// Copy text and graphics
+    Extract,
+    /// Add annotations and fill in forms
+    Annotate,
+    /// Fill in forms, even without annotate
+    FillForms,
+    /// Insert, rotate and delete pages
+    Assemble,
+    /// Everything
+    All,
+    /// Nothing beyond accessibility
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum EncryptionCipher {
+    /// AES-128 (PDF 1.6)
+    Aes128,
+    /// AES-256 (PDF 1.7 extension level 8, PDF 2.0)
+    Aes256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum JpegBackend {
+    /// Built-in pure Rust encoder
+    Image,
+    /// mozjpeg with trellis quantization (smaller output, slower)
+    Mozjpeg,
+}
+
+impl Default for JpegBackend {
+    /// mozjpeg when it is compiled in, the built-in encoder otherwise
+    fn default() -> Self {
+        if cfg!(feature = "mozjpeg") {
+            JpegBackend::Mozjpeg
+        } else {
+            JpegBackend::Image
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::analyzer::{analysis_value, PdfAnalysis};
+use crate::utils::{format_bytes, rounded};
+
+/// One document analyzed for a comparison
+pub struct Side<'a> {
+    pub path: &'a Path,
+    pub file_size: u64,
+    pub analysis: &'a PdfAnalysis,
+}
+
+/// A quantity measured in both documents
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub name: &'static str,
+    pub key: &'static str, // The name in JSON
+    pub before: u64,
+    pub after: u64,
+    pub bytes: bool, // Shown as a size rather than a count
+}
+
+impl Row {
+    /// The change from the first document to the second, as a percentage
+    pub fn change(&self) -> Option<f64> {
+        (self.before > 0).then(|| (self.after as f64 - self.before as f64) / self.before as f64 * 100.0)
+    }
+
+    fn delta(&self) -> i64 {
+        self.after as i64 - self.before as i64
+    }
+
+    fn show(&self, value: u64) -> String {
+        if self.bytes {
+            format_bytes(value)
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+/// The sizes by category and the counts of both documents, side by side
+pub fn compare(before: &Side, after: &Side) -> Vec<Row> {
+    let row = |name, key, bytes, field: fn(&Side) -> u64| Row { name, key, before: field(before), after: field(after), bytes };
+    vec![
+        row("File size", "file_size", true, |side| side.file_size),
+        row("Image data", "images_size", true, |side| side.analysis.content_breakdown.images_size),
+        row("Font data", "fonts_size", true, |side| side.analysis.content_breakdown.fonts_size),
+        row("Page content", "content_size", true, |side| side.analysis.content_breakdown.content_size),
+        row("Metadata", "metadata_size", true, |side| side.analysis.content_breakdown.metadata_size),
+        row("Attachments", "attachments_size", true, |side| side.analysis.content_breakdown.attachments_size),
+        row("Uncategorized streams", "uncategorized_streams_size", true, |side| {
+            side.analysis.content_breakdown.uncategorized_streams_size
+        }),
+        row("Other objects", "other_size", true, |side| side.analysis.content_breakdown.other_size),
+        row("Pages", "pages", false, |side| side.analysis.page_count as u64),
+        row("Images", "images", false, |side| side.analysis.image_count as u64),
+        row("Embedded font programs", "font_files", false, |side| side.analysis.font_file_count as u64),
+        row("Objects", "objects", false, |side| side.analysis.total_objects as u64),
+    ]
+}
+
+pub fn print_comparison(before: &Side, after: &Side, rows: &[Row], problems: &[String]) {
+    println!("Comparing {} with {}", before.path.display(), after.path.display());
+    println!();
+    println!("  {:<24} {:>12} {:>12} {:>9}", "", "Before", "After", "Change");
+    for row in rows {
+        let change = row.change().map_or("-".to_string(), |change| format!("{:+.1}%", change));
+        println!("  {:<24} {:>12} {:>12} {:>9}", row.name, row.show(row.before), row.show(row.after), change);
+    }
+    println!();
+    if problems.is_empty() {
+        println!("PASS: same pages with the same text");
+    } else {
+        println!("FAIL: {}", problems.join("; "));
+    }
+}
+
+/// Both analyses, the rows with their deltas, and whether the pages and text match
+pub fn comparison_json(before: &Side, after: &Side, rows: &[Row], problems: &[String]) -> String {
+    let analysis = |side: &Side| analysis_value(side.path, side.file_size, side.analysis, 0);
+    let rows: Vec<Value> = rows
+        .iter()
+        .map(|row| {
+            json!({
+                "name": row.key,
+                "before": row.before,
+                "after": row.after,
+                "delta": row.delta(),
+                "change_percent": row.change().map(|change| rounded(change, 1)),
+            })
+        })
+        .collect();
+    let json = json!({
+        "before": analysis(before),
+        "after": analysis(after),
+        "changes": rows,
+        "passed": problems.is_empty(),
+        "problems": problems,
+    });
+    serde_json::to_string_pretty(&json).expect("JSON values serialize")
+}
@@ -0,0 +1,291 @@
+use anyhow::{Context, Result};
+use clap::{Arg, Command, CommandFactory, FromArgMatches};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::cli::Cli;
+
+/// Names the configuration file, unless --config does
+const CONFIG_VARIABLE: &str = "PDF_OPTICOMPRESS_CONFIG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    String,
+    Integer,
+    Boolean,
+    List, // Of strings
+}
+
+/// The options a configuration file can set, named after their flags, which each command
+/// having the flag takes as its default (or only the command given). PDF_OPTICOMPRESS_ and
+/// the key in capitals sets one from the environment, over the file.
+const KEYS: [(&str, Kind, Option<&str>); 10] = [
+    ("preset", Kind::String, None),
+    ("quality", Kind::Integer, None),
+    ("max_dimension", Kind::Integer, None),
+    ("resize_filter", Kind::String, None),
+    ("strip_metadata", Kind::Boolean, None),
+    ("strip_private_data", Kind::Boolean, None),
+    ("strip_structure", Kind::Boolean, None),
+    ("strip_annotations", Kind::List, None),
+    ("threads", Kind::Integer, None),
+    ("name_template", Kind::String, Some("batch")),
+];
+
+fn variable(key: &str) -> String {
+    format!("PDF_OPTICOMPRESS_{}", key.to_ascii_uppercase())
+}
+
+/// Where a setting's effective value comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Environment(String), // The variable
+    File,
+    Default,
+}
+
+/// The defaults read from the configuration file, if there is one
+#[derive(Debug, Default)]
+pub struct Config {
+    pub path: Option<PathBuf>,
+    values: Vec<(&'static str, Vec<String>)>, // By key, as they'd be given on the command line
+}
+
+impl Config {
+    /// Read the file given with --config, or else named by PDF_OPTICOMPRESS_CONFIG, or else
+    /// config.toml in the user's configuration directory when it exists. Unknown keys are
+    /// warned about; a file that can't be parsed, or holding a value its flag wouldn't
+    /// take, is an error.
+    pub fn load(args: &[OsString]) -> Result<Self> {
+        let (path, required) = match config_argument(args) {
+            Some(path) => (path, true),
+            None => match std::env::var_os(CONFIG_VARIABLE).filter(|path| !path.is_empty()) {
+                Some(path) => (PathBuf::from(path), true),
+                None => match default_path() {
+                    Some(path) => (path, false),
+                    None => return Ok(Config::default()),
+                },
+            },
+        };
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok(Config::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read the configuration file {}", path.display())),
+        };
+        let settings = parse_config(&content).with_context(|| format!("Invalid configuration file {}", path.display()))?;
+
+        let command = Cli::command();
+        let mut values: Vec<(&'static str, Vec<String>)> = Vec::new();
+        for setting in settings {
+            let Some(&(key, kind, _)) = KEYS.iter().find(|(key, _, _)| *key == setting.key) else {
+                eprintln!(
+                    "Warning: {}: unknown key \"{}\" on line {} (known keys: {})",
+                    path.display(),
+                    setting.key,
+                    setting.line,
+                    KEYS.map(|(key, _, _)| key).join(", ")
+                );
+                continue;
+            };
+            let at = format!("{}, {}", path.display(), setting.location);
+            let value = as_args(&setting.value, kind).with_context(|| format!("{}: {} must be {}", at, key, kind_name(kind)))?;
+            // Checked as the flag checks it, with its parser on an argument of its own
+            if let Some(arg) = find_arg(&command, key).filter(|_| kind != Kind::Boolean) {
+                let probe = Command::new("config").arg(Arg::new(key).value_name(key).value_parser(arg.get_value_parser().clone()));
+                for item in &value {
+                    probe.clone().try_get_matches_from([key, item]).map_err(|e| anyhow::anyhow!("{}: {}", at, clap_message(&e)))?;
+                }
+            }
+            values.push((key, value));
+        }
+        Ok(Config { path: Some(path), values })
+    }
+
+    /// Parse a command line, each command taking the options set here, and in the
+    /// environment, as its defaults
+    pub fn try_parse_from<I, T>(&self, args: I) -> Result<Cli, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut command = Cli::command();
+        let names: Vec<String> = command.get_subcommands().map(|subcommand| subcommand.get_name().to_string()).collect();
+        for name in names {
+            command = command.mut_subcommand(name, |subcommand| self.layer(subcommand));
+        }
+        Cli::from_arg_matches(&command.try_get_matches_from(args)?)
+    }
+
+    fn layer(&self, mut subcommand: Command) -> Command {
+        for (key, _, only) in KEYS {
+            if only.is_some_and(|only| only != subcommand.get_name()) || !subcommand.get_arguments().any(|arg| arg.get_id() == key) {
+                continue;
+            }
+            let value = self.value(key).map(<[String]>::to_vec);
+            subcommand = subcommand.mut_arg(key, |arg| {
+                let arg = arg.env(variable(key));
+                match value {
+                    Some(value) => arg.default_values(value),
+                    None => arg,
+                }
+            });
+        }
+        subcommand
+    }
+
+    fn value(&self, key: &str) -> Option<&[String]> {
+        self.values.iter().find(|(name, _)| *name == key).map(|(_, value)| value.as_slice())
+    }
+
+    /// What a command takes for a key when no flag sets it, and where that comes from;
+    /// `None` when nothing sets it
+    pub fn effective(&self, key: &str) -> Option<(Vec<String>, Source)> {
+        if let Some(value) = std::env::var_os(variable(key)).filter(|value| !value.is_empty()) {
+            return Some((vec![value.to_string_lossy().into_owned()], Source::Environment(variable(key))));
+        }
+        if let Some(value) = self.value(key) {
+            return Some((value.to_vec(), Source::File));
+        }
+        let command = Cli::command();
+        let arg = find_arg(&command, key)?;
+        let mut defaults: Vec<String> = arg.get_default_values().iter().map(|value| value.to_string_lossy().into_owned()).collect();
+        // Flags only get their "false" once the command is built
+        if defaults.is_empty() && matches!(arg.get_action(), clap::ArgAction::SetTrue) {
+            defaults.push("false".to_string());
+        }
+        (!defaults.is_empty()).then_some((defaults, Source::Default))
+    }
+
+    /// The effective settings, as a configuration file would set them, with where each
+    /// comes from
+    pub fn print_effective(&self) {
+        match (&self.path, default_path()) {
+            (Some(path), _) => println!("# Configuration file: {}", path.display()),
+            (None, Some(path)) => println!("# No configuration file ({} doesn't exist)", path.display()),
+            (None, None) => println!("# No configuration file"),
+        }
+        for (key, kind, _) in KEYS {
+            match self.effective(key) {
+                Some((value, source)) => {
+                    let from = match source {
+                        Source::Environment(variable) => format!("from {}", variable),
+                        Source::File => "from the configuration file".to_string(),
+                        Source::Default => "default".to_string(),
+                    };
+                    println!("{} = {}  # {}", key, toml_value(&value, kind), from);
+                }
+                None => println!("# {} is not set", key),
+            }
+        }
+    }
+}
+
+/// The --config argument, looked for before the command line is parsed since parsing
+/// depends on the file
+fn config_argument(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// pdf-opticompress/config.toml in $XDG_CONFIG_HOME, ~/.config or %APPDATA%
+fn default_path() -> Option<PathBuf> {
+    let variable = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let directory = variable("XDG_CONFIG_HOME").or_else(|| variable("HOME").map(|home| home.join(".config"))).or_else(|| variable("APPDATA"))?;
+    Some(directory.join("pdf-opticompress").join("config.toml"))
+}
+
+/// The flag, from the first command it applies to
+fn find_arg<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    let only = KEYS.iter().find(|(name, _, _)| *name == key).and_then(|(_, _, only)| *only);
+    command
+        .get_subcommands()
+        .filter(|subcommand| only.is_none_or(|only| only == subcommand.get_name()))
+        .find_map(|subcommand| subcommand.get_arguments().find(|arg| arg.get_id() == key))
+}
+
+/// A clap error on one line, without its advice to try --help
+fn clap_message(error: &clap::Error) -> String {
+    let message = error.to_string();
+    let message = message.trim_start_matches("error: ").split("\n\n").next().unwrap_or_default();
+    message.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::String => "a string",
+        Kind::Integer => "an integer",
+        Kind::Boolean => "true or false",
+        Kind::List => "a list of strings",
+    }
+}
+
+fn toml_value(value: &[String], kind: Kind) -> String {
+    match kind {
+        Kind::String => toml::Value::from(value.first().map_or("", String::as_str)).to_string(),
+        Kind::Integer | Kind::Boolean => value.join(","),
+        Kind::List => toml::Value::from(value.to_vec()).to_string(),
+    }
+}
+
+/// `key = value` from the file, and where
+struct Setting {
+    key: String,
+    value: toml::Value,
+    line: usize,
+    location: String, // Of the value, as "line L, column C"
+}
+
+/// As the flag would be given, or `None` when of another kind
+fn as_args(value: &toml::Value, kind: Kind) -> Option<Vec<String>> {
+    match (value, kind) {
+        (toml::Value::String(text), Kind::String) => Some(vec![text.clone()]),
+        (toml::Value::Integer(number), Kind::Integer) => Some(vec![number.to_string()]),
+        (toml::Value::Boolean(flag), Kind::Boolean) => Some(vec![flag.to_string()]),
+        (toml::Value::Array(items), Kind::List) => items.iter().map(|item| item.as_str().map(str::to_string)).collect(),
+        (toml::Value::String(text), Kind::List) => Some(vec![text.clone()]),
+        _ => None,
+    }
+}
+
+/// The `key = value` settings of the file, in the order they appear. Dashes in keys are
+/// taken as underscores, so that flags can be copied as they are. Tables aren't needed, and
+/// refused.
+fn parse_config(content: &str) -> Result<Vec<Setting>> {
+    let table: BTreeMap<String, toml::Spanned<toml::Value>> = toml::from_str(content).map_err(|e| match e.span() {
+        Some(span) => anyhow::anyhow!("{}: {}", location(content, span.start), e.message().trim_end().replace('\n', "; ")),
+        None => anyhow::anyhow!("{}", e.message().trim_end().replace('\n', "; ")),
+    })?;
+    let mut settings: Vec<(usize, Setting)> = Vec::new();
+    for (key, value) in table {
+        let start = value.span().start;
+        let line = content[..start].matches('\n').count() + 1;
+        let value = value.into_inner();
+        if value.is_table() {
+            anyhow::bail!("{}: tables aren't supported; set the keys at the top of the file", location(content, start));
+        }
+        let key = key.replace('-', "_");
+        if let Some((_, other)) = settings.iter().find(|(_, other)| other.key == key) {
+            anyhow::bail!("line {}: {} is set twice", line.max(other.line), key);
+        }
+        settings.push((start, Setting { key, value, line, location: location(content, start) }));
+    }
+    settings.sort_by_key(|(start, _)| *start);
+    Ok(settings.into_iter().map(|(_, setting)| setting).collect())
+}
+
+/// Line and column of a byte offset in the file, from 1
+fn location(content: &str, offset: usize) -> String {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+    format!("line {}, column {}", line, column)
+}
@@ -0,0 +1,356 @@
+use lopdf::{Document, Object, ObjectId};
+use std::collections::HashMap;
+
+use crate::filters::decode_stream;
+use crate::inline_images::{split_operations, RawOperation};
+use crate::pdf_writer::SaveOptions;
+
+/// Results of the content stream cleanup pass
+#[derive(Debug, Default, Clone)]
+pub struct ContentStats {
+    pub streams: usize,
+    pub operators_removed: usize,
+    pub numbers_rounded: usize,
+    /// Encoded bytes before minus after
+    pub bytes_saved: i64,
+}
+
+/// Every operator of the PDF content stream syntax. Anything else stops the cleanup from
+/// assuming it knows the graphics state.
+const KNOWN_OPERATORS: &[&[u8]] = &[
+    b"b", b"B", b"b*", b"B*", b"BDC", b"BI", b"BMC", b"BT", b"BX", b"c", b"cm", b"CS", b"cs", b"d", b"d0",
+    b"d1", b"Do", b"DP", b"EMC", b"ET", b"EX", b"f", b"F", b"f*", b"G", b"g", b"gs", b"h", b"i", b"j", b"J",
+    b"K", b"k", b"l", b"m", b"M", b"MP", b"n", b"q", b"Q", b"re", b"RG", b"rg", b"ri", b"s", b"S", b"SC",
+    b"sc", b"SCN", b"scn", b"sh", b"T*", b"Tc", b"Td", b"TD", b"Tf", b"Tj", b"TJ", b"TL", b"Tm", b"Tr", b"Ts",
+    b"Tw", b"Tz", b"v", b"w", b"W", b"W*", b"y", b"'", b"\"",
+];
+
+/// The graphics state parameter an operator sets. Color operators share one parameter
+/// per direction, since e.g. `rg` replaces the color `g` set.
+fn setting(operator: &[u8]) -> Option<&'static str> {
+    Some(match operator {
+        b"w" => "line width",
+        b"J" => "line cap",
+        b"j" => "line join",
+        b"M" => "miter limit",
+        b"d" => "dash",
+        b"ri" => "intent",
+        b"i" => "flatness",
+        b"gs" => "extgstate",
+        b"Tc" => "char spacing",
+        b"Tw" => "word spacing",
+        b"Tz" => "horizontal scaling",
+        b"TL" => "leading",
+        b"Tf" => "font",
+        b"Tr" => "render mode",
+        b"Ts" => "rise",
+        b"g" | b"rg" | b"k" | b"cs" | b"sc" | b"scn" => "fill color",
+        b"G" | b"RG" | b"K" | b"CS" | b"SC" | b"SCN" => "stroke color",
+        _ => return None,
+    })
+}
+
+/// Values every page's content starts with, as they are usually written
+const PAGE_DEFAULTS: &[(&str, &str)] = &[
+    ("line width", "1 w"),
+    ("line cap", "0 J"),
+    ("line join", "0 j"),
+    ("char spacing", "0 Tc"),
+    ("word spacing", "0 Tw"),
+    ("horizontal scaling", "100 Tz"),
+    ("leading", "0 TL"),
+    ("render mode", "0 Tr"),
+    ("rise", "0 Ts"),
+    ("fill color", "0 g"),
+    ("stroke color", "0 G"),
+];
+
+/// What is known about the graphics state at a point of a content stream
+#[derive(Clone)]
+struct GraphicsState {
+    /// How far the CTM stretches distances of default user space; `None` if unknown
+    scale: Option<f64>,
+    /// The source text of the current setting of each parameter (see `setting`)
+    settings: HashMap<&'static str, Vec<u8>>,
+}
+
+impl GraphicsState {
+    /// The state a page's content starts in
+    fn page(user_unit: f64) -> Self {
+        Self {
+            scale: Some(user_unit),
+            settings: PAGE_DEFAULTS.iter().map(|(name, value)| (*name, value.as_bytes().to_vec())).collect(),
+        }
+    }
+
+    fn unknown() -> Self {
+        Self {
+            scale: None,
+            settings: HashMap::new(),
+        }
+    }
+}
+
+/// Tracks the state across the operations of a page's content streams
+struct Cleanup {
+    state: GraphicsState,
+    saved: Vec<GraphicsState>,
+    /// Decimal places to keep, in default user space; `None` disables rounding
+    precision: Option<u32>,
+}
+
+impl Cleanup {
+    fn new(state: GraphicsState, precision: Option<u32>) -> Self {
+        Self {
+            state,
+            saved: Vec::new(),
+            precision,
+        }
+    }
+
+    /// Rewrite one content stream, returning the new data (if it's shorter) and the number
+    /// of operators removed and numbers rounded
+    fn rewrite(&mut self, content: &[u8]) -> Option<(Vec<u8>, usize, usize)> {
+        let Some(operations) = split_operations(content) else {
+            // Nothing is known about the state after content we can't read
+            self.state = GraphicsState::unknown();
+            self.saved.clear();
+            return None;
+        };
+
+        let mut kept: Vec<(&[u8], Vec<Vec<u8>>)> = Vec::with_capacity(operations.len());
+        let mut removed = 0;
+        let mut rounded = 0;
+        for RawOperation { operator, operands } in operations {
+            let mut operands: Vec<Vec<u8>> = operands.into_iter().map(<[u8]>::to_vec).collect();
+            if !self.apply(operator, &mut operands, &mut rounded) {
+                removed += 1;
+                continue;
+            }
+            // `q` directly followed by `Q` saves and restores nothing
+            if operator == b"Q" && kept.last().is_some_and(|(last, _)| *last == b"q") {
+                kept.pop();
+                removed += 2;
+                continue;
+            }
+            // Likewise a text object without operators in it
+            if operator == b"ET" && kept.last().is_some_and(|(last, _)| *last == b"BT") {
+                kept.pop();
+                removed += 2;
+                continue;
+            }
+            kept.push((operator, operands));
+        }
+
+        let mut output = Vec::with_capacity(content.len());
+        for (operator, operands) in kept {
+            if operator == b"BI" {
+                // The operand is the whole inline image
+                output.extend(&operands[0]);
+            } else {
+                for operand in operands {
+                    output.extend(operand);
+                    output.push(b' ');
+                }
+                output.extend(operator);
+            }
+            output.push(b'\n');
+        }
+        (output.len() < content.len()).then_some((output, removed, rounded))
+    }
+
+    /// Update the state for an operation and round its coordinates; returns false if the
+    /// operation changes nothing and can be dropped
+    fn apply(&mut self, operator: &[u8], operands: &mut [Vec<u8>], rounded: &mut usize) -> bool {
+        if !KNOWN_OPERATORS.contains(&operator) {
+            self.state = GraphicsState::unknown();
+            self.saved.clear();
+            return true;
+        }
+
+        match operator {
+            b"q" => self.saved.push(self.state.clone()),
+            b"Q" => self.state = self.saved.pop().unwrap_or_else(GraphicsState::unknown),
+            b"cm" => {
+                let Some(matrix) = numbers(operands) else {
+                    self.state.scale = None;
+                    return true;
+                };
+                if matrix == [1.0, 0.0, 0.0, 1.0, 0.0, 0.0] {
+                    return false;
+                }
+                // The translation isn't rounded: successive `cm`s add up their errors
+                self.state.scale = self.state.scale.map(|scale| scale * stretch(&matrix));
+                // A soft mask set by `gs` is positioned by the CTM at that point
+                self.state.settings.remove("extgstate");
+            }
+            b"m" | b"l" | b"c" | b"v" | b"y" | b"re" => {
+                let expected = match operator {
+                    b"m" | b"l" => 2,
+                    b"c" => 6,
+                    _ => 4,
+                };
+                if operands.len() == expected {
+                    self.round(operands, self.state.scale, rounded);
+                }
+            }
+            // The text matrix maps onto user space, so its translation is a position in user
+            // space. `Td` and `TD` move relative to the last line and are left alone.
+            b"Tm" if operands.len() == 6 => self.round(&mut operands[4..], self.state.scale, rounded),
+            b"gs" => {
+                // An ExtGState can set any of the other parameters
+                self.state.settings.clear();
+            }
+            b"\"" => {
+                self.state.settings.remove("word spacing");
+                self.state.settings.remove("char spacing");
+            }
+            _ => {}
+        }
+
+        if let Some(name) = setting(operator) {
+            let mut value = operands.join(&b' ');
+            if !value.is_empty() {
+                value.push(b' ');
+            }
+            value.extend(operator);
+            if self.state.settings.get(name) == Some(&value) {
+                return false;
+            }
+            self.state.settings.insert(name, value);
+        }
+        true
+    }
+
+    /// Round real operands to the precision, raised by however much the CTM (times
+    /// `scale`) magnifies them, so the error on the page stays within the precision
+    fn round(&self, operands: &mut [Vec<u8>], scale: Option<f64>, rounded: &mut usize) {
+        let (Some(precision), Some(scale)) = (self.precision, scale) else { return };
+        if !scale.is_finite() || scale <= 0.0 {
+            return;
+        }
+        let decimals = precision as i32 + scale.log10().ceil() as i32;
+        if decimals > 10 {
+            return;
+        }
+        let decimals = decimals.max(0) as usize;
+        for operand in operands {
+            if !operand.contains(&b'.') {
+                continue;
+            }
+            let Some(value) = std::str::from_utf8(operand).ok().and_then(|text| text.parse::<f64>().ok()) else {
+                continue;
+            };
+            if !value.is_finite() {
+                continue;
+            }
+            let mut text = format!("{:.*}", decimals, value);
+            if text.contains('.') {
+                text = text.trim_end_matches('0').trim_end_matches('.').to_string();
+            }
+            if text == "-0" {
+                text = "0".to_string();
+            }
+            if text.len() < operand.len() {
+                *operand = text.into_bytes();
+                *rounded += 1;
+            }
+        }
+    }
+}
+
+/// The six numbers of a matrix operand list
+fn numbers(operands: &[Vec<u8>]) -> Option<[f64; 6]> {
+    if operands.len() != 6 {
+        return None;
+    }
+    let mut matrix = [0.0; 6];
+    for (value, operand) in matrix.iter_mut().zip(operands) {
+        *value = std::str::from_utf8(operand).ok()?.parse().ok()?;
+    }
+    Some(matrix)
+}
+
+/// The most a matrix stretches any distance: the largest singular value of its linear part
+fn stretch(matrix: &[f64; 6]) -> f64 {
+    let [a, b, c, d, _, _] = *matrix;
+    let sum = a * a + b * b + c * c + d * d;
+    let det = a * d - b * c;
+    ((sum + (sum * sum - 4.0 * det * det).max(0.0).sqrt()) / 2.0).sqrt()
+}
+
+/// Drop operators that provably change nothing (empty `q Q` pairs and text objects,
+/// identity `cm`, settings repeating the value in effect) from page and form XObject
+/// content streams, and round the absolute coordinates (path points and text matrix
+/// positions) of page content to `precision` decimal places of a point.
+///
+/// Operators are never reordered and the operands of unknown operators are never touched;
+/// after an unknown operator, nothing more of that page is assumed about the state. Form
+/// XObjects are drawn at whatever scale their user chooses, so their coordinates are kept.
+pub fn optimize_content_streams(doc: &mut Document, precision: u32, options: &SaveOptions) -> ContentStats {
+    let mut stats = ContentStats::default();
+    let pages: Vec<(ObjectId, Vec<ObjectId>)> = doc
+        .get_pages()
+        .into_values()
+        .map(|page_id| (page_id, doc.get_page_contents(page_id)))
+        .collect();
+    let mut uses: HashMap<ObjectId, usize> = HashMap::new();
+    for (_, contents) in &pages {
+        for id in contents {
+            *uses.entry(*id).or_default() += 1;
+        }
+    }
+
+    for (page_id, contents) in &pages {
+        let user_unit = doc
+            .get_dictionary(*page_id)
+            .ok()
+            .and_then(|page| page.get(b"UserUnit").ok())
+            .and_then(|unit| unit.as_float().ok())
+            .map_or(1.0, f64::from);
+        let mut cleanup = Cleanup::new(GraphicsState::page(user_unit), Some(precision));
+        for id in contents {
+            // A stream shared with other pages follows different content on each
+            if uses[id] > 1 {
+                cleanup = Cleanup::new(GraphicsState::unknown(), None);
+                continue;
+            }
+            rewrite_stream(doc, *id, &mut cleanup, options, &mut stats);
+        }
+    }
+
+    let forms: Vec<ObjectId> = doc
+        .objects
+        .iter()
+        .filter(|(id, object)| {
+            !uses.contains_key(id)
+                && matches!(object, Object::Stream(stream)
+                    if matches!(stream.dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Form"))
+        })
+        .map(|(id, _)| *id)
+        .collect();
+    for id in forms {
+        rewrite_stream(doc, id, &mut Cleanup::new(GraphicsState::unknown(), None), options, &mut stats);
+    }
+
+    stats
+}
+
+fn rewrite_stream(doc: &mut Document, id: ObjectId, cleanup: &mut Cleanup, options: &SaveOptions, stats: &mut ContentStats) {
+    let Ok(Object::Stream(stream)) = doc.get_object_mut(id) else { return };
+    let Ok(content) = decode_stream(stream) else {
+        *cleanup = Cleanup::new(GraphicsState::unknown(), None);
+        return;
+    };
+    let Some((output, removed, rounded)) = cleanup.rewrite(&content) else { return };
+    let Ok(encoded) = options.deflate(&output) else { return };
+
+    let before = stream.content.len() as i64;
+    stream.dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+    stream.dict.remove(b"DecodeParms");
+    stream.set_content(encoded);
+    stats.streams += 1;
+    stats.operators_removed += removed;
+    stats.numbers_rounded += rounded;
+    stats.bytes_saved += before - stream.content.len() as i64;
+}
@@ -0,0 +1,146 @@
+use lopdf::{Document, Object, ObjectId, Stream};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::filters::decode_stream;
+use crate::objects::replace_references;
+
+/// Streams already kept, grouped by their dictionary and a hash of their data
+type Survivors = HashMap<(Vec<u8>, u64), Vec<(ObjectId, Vec<u8>)>>;
+
+/// Results of a deduplication pass
+#[derive(Debug, Default, Clone)]
+pub struct DedupStats {
+    pub merged: usize,
+    pub bytes_saved: u64,
+}
+
+/// Store identical image XObjects only once, pointing every reference at a single copy.
+///
+/// Images match when their pixel data and every rendering-relevant dictionary entry
+/// (ColorSpace, Decode, SMask, ...) are equal; only the encoding may differ. Masks are
+/// merged first, so images whose soft masks were duplicates merge on the next round.
+pub fn deduplicate_images(doc: &mut Document) -> DedupStats {
+    let mut stats = DedupStats::default();
+    loop {
+        let ids = image_ids(doc);
+        if merge_duplicates(doc, &ids, &mut stats) == 0 {
+            break;
+        }
+    }
+    stats
+}
+
+/// What [`deduplicate_images`] would merge, without changing the document
+pub fn count_duplicate_images(doc: &Document) -> DedupStats {
+    // Images only refer to other images (their masks), so a document of them is enough
+    let mut images = Document::new();
+    for id in image_ids(doc) {
+        images.objects.insert(id, doc.objects[&id].clone());
+    }
+    deduplicate_images(&mut images)
+}
+
+/// Store identical embedded font programs only once, repointing every font descriptor
+pub fn deduplicate_fonts(doc: &mut Document) -> DedupStats {
+    let mut stats = DedupStats::default();
+    let ids = font_file_ids(doc);
+    merge_duplicates(doc, &ids, &mut stats);
+    stats
+}
+
+/// Ids of the embedded font programs (`/FontFile`, `/FontFile2`, `/FontFile3`)
+/// referenced from font descriptors
+pub fn font_file_ids(doc: &Document) -> Vec<ObjectId> {
+    let mut ids: Vec<ObjectId> = doc
+        .objects
+        .values()
+        .filter_map(|object| match object {
+            Object::Dictionary(dict) => Some(dict),
+            _ => None,
+        })
+        .filter(|dict| matches!(dict.get(b"Type"), Ok(Object::Name(name)) if name == b"FontDescriptor"))
+        .flat_map(|dict| {
+            FONT_FILE_KEYS
+                .iter()
+                .filter_map(|key| dict.get(key).and_then(Object::as_reference).ok())
+        })
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+const FONT_FILE_KEYS: [&[u8]; 3] = [b"FontFile", b"FontFile2", b"FontFile3"];
+
+/// Map each duplicate among `ids` to the first identical stream, without changing the document
+pub fn find_duplicates(doc: &Document, ids: &[ObjectId]) -> HashMap<ObjectId, ObjectId> {
+    // Candidates are grouped by dictionary and a content hash; the content itself is
+    // compared before merging so a hash collision can never alias two streams
+    let mut canonical = Survivors::new();
+    let mut replacements = HashMap::new();
+    for &id in ids {
+        let Ok(Object::Stream(stream)) = doc.get_object(id) else { continue };
+        let (dict, content) = identity(stream);
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let survivors = canonical.entry((dict, hasher.finish())).or_default();
+        match survivors.iter().find(|(_, data)| *data == content) {
+            Some((survivor, _)) => {
+                replacements.insert(id, *survivor);
+            }
+            None => survivors.push((id, content)),
+        }
+    }
+    replacements
+}
+
+fn merge_duplicates(doc: &mut Document, ids: &[ObjectId], stats: &mut DedupStats) -> usize {
+    let replacements = find_duplicates(doc, ids);
+    replace_references(doc, &replacements);
+    for id in replacements.keys() {
+        if let Some(Object::Stream(stream)) = doc.objects.remove(id) {
+            stats.merged += 1;
+            stats.bytes_saved += stream.content.len() as u64;
+        }
+    }
+    replacements.len()
+}
+
+fn image_ids(doc: &Document) -> Vec<ObjectId> {
+    let mut ids: Vec<ObjectId> = doc
+        .objects
+        .iter()
+        .filter(|(_, object)| matches!(object, Object::Stream(stream) if is_image(stream)))
+        .map(|(id, _)| *id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// The dictionary entries and data that decide whether two streams are interchangeable. Streams the
+/// Flate decoder understands compare by decoded data, so differently compressed
+/// copies still match; anything else compares by its encoded bytes and filters.
+fn identity(stream: &Stream) -> (Vec<u8>, Vec<u8>) {
+    let decoded = decode_stream(stream).ok();
+    let ignored: &[&[u8]] = if decoded.is_some() {
+        &[b"Length", b"Filter", b"DecodeParms"]
+    } else {
+        &[b"Length"]
+    };
+    let mut entries: Vec<String> = stream
+        .dict
+        .iter()
+        .filter(|(key, _)| !ignored.contains(&key.as_slice()))
+        .map(|(key, value)| format!("{:?}={:?};", key, value))
+        .collect();
+    entries.sort();
+    let mut dict = entries.concat().into_bytes();
+    dict.push(u8::from(decoded.is_some()));
+    (dict, decoded.unwrap_or_else(|| stream.content.clone()))
+}
+
+fn is_image(stream: &Stream) -> bool {
+    matches!(stream.dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Image")
+}
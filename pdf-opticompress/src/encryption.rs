@@ -0,0 +1,641 @@
+use anyhow::{Context, Result};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, StringFormat};
+use openssl::hash::{hash, MessageDigest};
+use openssl::symm::{Cipher as SymmCipher, Crypter, Mode};
+
+use crate::cli::{EncryptionCipher, Permission};
+
+/// Padding for passwords of the RC4 and AES-128 security handlers (Algorithm 2)
+const PAD: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08, 0x2E, 0x2E, 0x00,
+    0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// How strings and streams are enciphered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Rc4,
+    Aes128,
+    Aes256,
+}
+
+/// How a document is encrypted: its `/Encrypt` dictionary and the file key. For the RC4
+/// and AES-128 handlers the key depends only on the dictionary's `/O`, `/P` and `/Length`
+/// and the first file identifier, so as long as those are written back unchanged the
+/// output opens with the same user and owner passwords and has the same permissions.
+#[derive(Debug, Clone)]
+pub struct Encryption {
+    dict: Dictionary,
+    key: Vec<u8>,
+    method: Method,
+    encrypt_metadata: bool,
+    /// `false` when the crypt filter for streams (or strings) is `/Identity`
+    streams: bool,
+    strings: bool,
+}
+
+/// Password protection to add to the output
+#[derive(Debug, Clone)]
+pub struct EncryptionSettings {
+    pub user_password: String,
+    pub owner_password: String,
+    pub permissions: Vec<Permission>,
+    pub cipher: EncryptionCipher,
+}
+
+/// The `/P` flags for a list of permissions; an empty list allows everything. Extraction
+/// for accessibility (bit 10) is always allowed, as PDF 2.0 requires.
+pub fn permission_flags(permissions: &[Permission]) -> i32 {
+    // Bits 7-8 and 13-32 must be set, 1-2 clear
+    let mut flags: u32 = 0xFFFF_F0C0 | 1 << 9;
+    let allowed: &[Permission] = if permissions.is_empty() { &[Permission::All] } else { permissions };
+    for permission in allowed {
+        flags |= match permission {
+            Permission::Print => 1 << 2 | 1 << 11,
+            Permission::PrintLow => 1 << 2,
+            Permission::Modify => 1 << 3,
+            Permission::Extract => 1 << 4,
+            Permission::Annotate => 1 << 5,
+            Permission::FillForms => 1 << 8,
+            Permission::Assemble => 1 << 10,
+            Permission::All => 0xF3C,
+            Permission::None => 0,
+        };
+    }
+    flags as i32
+}
+
+/// Decrypt every string and stream of an encrypted document and remove the `/Encrypt`
+/// dictionary. `password` may be the user or the owner password; documents that only
+/// restrict permissions have an empty user password. Returns `None` for documents that
+/// aren't encrypted.
+///
+/// The standard security handler is supported with RC4 (`/V 1`, `2` and `4`), AES-128
+/// (`/V 4`) and AES-256 (`/V 5`) ciphers.
+pub fn decrypt_document(doc: &mut Document, password: &str) -> Result<Option<Encryption>> {
+    let Ok(dict) = doc.get_encrypted().cloned() else {
+        return Ok(None);
+    };
+    let encryption = authenticate(doc, dict, password)?;
+
+    if let Ok(Object::Reference(id)) = doc.trailer.get(b"Encrypt") {
+        let id = *id;
+        doc.objects.remove(&id);
+    }
+    doc.trailer.remove(b"Encrypt");
+    crypt(doc, &encryption, Mode::Decrypt)?;
+    Ok(Some(encryption))
+}
+
+/// Set up new password protection, adding a file identifier if the document has none
+pub fn new_encryption(doc: &mut Document, settings: &EncryptionSettings) -> Result<Encryption> {
+    crate::pdf_writer::ensure_file_id(doc, false)?;
+    let permissions = permission_flags(&settings.permissions);
+    match settings.cipher {
+        EncryptionCipher::Aes128 => aes128_encryption(doc, settings, permissions),
+        EncryptionCipher::Aes256 => aes256_encryption(settings, permissions),
+    }
+}
+
+/// The oldest version that can hold documents encrypted this way (AES-256 also needs the
+/// Adobe extension level 8 declared below 2.0)
+pub fn required_version(encryption: &Encryption) -> (u8, u8) {
+    match encryption.method {
+        Method::Rc4 if encryption.key.len() > 5 => (1, 4),
+        Method::Rc4 => (1, 1),
+        Method::Aes128 => (1, 6),
+        Method::Aes256 => (1, 7),
+    }
+}
+
+/// Encrypt the document with `encryption`, call `write` with it, then decrypt it again so
+/// the caller's copy stays plain. Object numbers must not change in between.
+pub fn with_encryption<T>(
+    doc: &mut Document,
+    encryption: &Encryption,
+    write: impl FnOnce(&mut Document) -> Result<T>,
+) -> Result<T> {
+    if !doc.trailer.has(b"ID") {
+        anyhow::bail!("the file identifier the encryption key depends on is gone; refusing to write an unencrypted file");
+    }
+    declare_version(doc, encryption);
+    crypt(doc, encryption, Mode::Encrypt)?;
+    let id = doc.add_object(encryption.dict.clone());
+    doc.trailer.set("Encrypt", id);
+
+    let result = write(doc).context("Failed to write the encrypted document");
+
+    doc.trailer.remove(b"Encrypt");
+    doc.objects.remove(&id);
+    crypt(doc, encryption, Mode::Decrypt)?;
+    result
+}
+
+/// Raise the declared version to what the cipher needs
+fn declare_version(doc: &mut Document, encryption: &Encryption) {
+    let required = required_version(encryption);
+    let current = crate::pdf_writer::parse_version(&doc.version).unwrap_or((1, 0));
+    if current < required {
+        doc.version = format!("{}.{}", required.0, required.1);
+    }
+    if encryption.method == Method::Aes256 && current < (2, 0) {
+        let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) else { return };
+        let Ok(catalog) = doc.get_dictionary_mut(catalog_id) else { return };
+        let mut extensions = match catalog.get(b"Extensions") {
+            Ok(Object::Dictionary(extensions)) => extensions.clone(),
+            _ => Dictionary::new(),
+        };
+        let level = extensions
+            .get(b"ADBE")
+            .and_then(Object::as_dict)
+            .and_then(|adbe| adbe.get(b"ExtensionLevel"))
+            .and_then(Object::as_i64)
+            .unwrap_or(0);
+        if level < 8 {
+            extensions.set("ADBE", dictionary! { "BaseVersion" => Object::Name(b"1.7".to_vec()), "ExtensionLevel" => 8 });
+            catalog.set("Extensions", extensions);
+        }
+    }
+}
+
+/// Encipher or decipher every string and stream. Cross-reference streams are never
+/// encrypted, and metadata streams only when `/EncryptMetadata` says so.
+fn crypt(doc: &mut Document, encryption: &Encryption, mode: Mode) -> Result<()> {
+    for (&id, object) in doc.objects.iter_mut() {
+        let key = object_key(encryption, id);
+        match object {
+            Object::Stream(stream) => {
+                let kind = stream.dict.get(b"Type").and_then(Object::as_name).ok();
+                if kind == Some(b"XRef") {
+                    continue;
+                }
+                if encryption.streams && (kind != Some(b"Metadata") || encryption.encrypt_metadata) {
+                    let content = cipher(encryption.method, &key, &stream.content, mode)
+                        .with_context(|| format!("object {} {} could not be {}", id.0, id.1, describe_mode(mode)))?;
+                    stream.set_content(content);
+                }
+                if encryption.strings {
+                    crypt_dictionary(&mut stream.dict, encryption.method, &key, mode)?;
+                }
+            }
+            object if encryption.strings => crypt_strings(object, encryption.method, &key, mode)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn crypt_strings(object: &mut Object, method: Method, key: &[u8], mode: Mode) -> Result<()> {
+    match object {
+        Object::String(bytes, format) => {
+            *bytes = cipher(method, key, bytes, mode)?;
+            // lopdf doesn't read back every byte sequence it writes as a literal string
+            if matches!(mode, Mode::Encrypt) {
+                *format = StringFormat::Hexadecimal;
+            }
+        }
+        Object::Array(items) => {
+            for item in items {
+                crypt_strings(item, method, key, mode)?;
+            }
+        }
+        Object::Dictionary(dict) => crypt_dictionary(dict, method, key, mode)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn crypt_dictionary(dict: &mut Dictionary, method: Method, key: &[u8], mode: Mode) -> Result<()> {
+    for (_, value) in dict.iter_mut() {
+        crypt_strings(value, method, key, mode)?;
+    }
+    Ok(())
+}
+
+fn describe_mode(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Encrypt => "encrypted",
+        Mode::Decrypt => "decrypted",
+    }
+}
+
+/// The key for the strings and streams of object `id` (Algorithm 1; AES-256 uses the
+/// file key itself)
+fn object_key(encryption: &Encryption, id: ObjectId) -> Vec<u8> {
+    if encryption.method == Method::Aes256 {
+        return encryption.key.clone();
+    }
+    let mut data = encryption.key.clone();
+    data.extend_from_slice(&id.0.to_le_bytes()[..3]);
+    data.extend_from_slice(&id.1.to_le_bytes()[..2]);
+    if encryption.method == Method::Aes128 {
+        data.extend_from_slice(b"sAlT");
+    }
+    let mut key = md5(&data);
+    key.truncate((encryption.key.len() + 5).min(16));
+    key
+}
+
+/// RC4 is its own inverse; AES data starts with its initialization vector
+fn cipher(method: Method, key: &[u8], data: &[u8], mode: Mode) -> Result<Vec<u8>> {
+    let aes = match method {
+        Method::Rc4 => return Ok(rc4(key, data)),
+        Method::Aes128 => SymmCipher::aes_128_cbc(),
+        Method::Aes256 => SymmCipher::aes_256_cbc(),
+    };
+    match mode {
+        Mode::Encrypt => {
+            let iv = random_bytes(16)?;
+            let mut output = iv.clone();
+            output.extend(openssl::symm::encrypt(aes, key, Some(&iv), data)?);
+            Ok(output)
+        }
+        // Empty strings are sometimes written without an initialization vector
+        Mode::Decrypt if data.len() < 16 => Ok(Vec::new()),
+        Mode::Decrypt => Ok(openssl::symm::decrypt(aes, key, Some(&data[..16]), &data[16..])?),
+    }
+}
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+/// AES-CBC without padding, for the key wrapping of the AES-256 handler
+fn aes_no_padding(aes: SymmCipher, key: &[u8], iv: Option<&[u8]>, data: &[u8], mode: Mode) -> Result<Vec<u8>> {
+    let mut crypter = Crypter::new(aes, mode, key, iv)?;
+    crypter.pad(false);
+    let mut output = vec![0; data.len() + aes.block_size()];
+    let mut length = crypter.update(data, &mut output)?;
+    length += crypter.finalize(&mut output[length..])?;
+    output.truncate(length);
+    Ok(output)
+}
+
+fn md5(data: &[u8]) -> Vec<u8> {
+    hash(MessageDigest::md5(), data).map(|digest| digest.to_vec()).unwrap_or_default()
+}
+
+fn random_bytes(count: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; count];
+    openssl::rand::rand_bytes(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Check the password against the encryption dictionary and derive the file key
+fn authenticate(doc: &Document, dict: Dictionary, password: &str) -> Result<Encryption> {
+    if !matches!(dict.get(b"Filter"), Ok(Object::Name(name)) if name == b"Standard") {
+        anyhow::bail!("the document is encrypted with a security handler other than the standard password one");
+    }
+    let version = dict.get(b"V").and_then(Object::as_i64).unwrap_or(0);
+    let revision = dict.get(b"R").and_then(Object::as_i64).unwrap_or(0);
+    let (method, streams, strings) = match version {
+        1 | 2 => (Method::Rc4, true, true),
+        4 | 5 => crypt_filters(&dict)?,
+        _ => anyhow::bail!("the document's encryption (/V {}) isn't supported", version),
+    };
+    let encrypt_metadata = dict.get(b"EncryptMetadata").and_then(Object::as_bool).unwrap_or(true);
+
+    let key = if method == Method::Aes256 {
+        aes256_file_key(&dict, revision, password)?
+    } else {
+        let id = first_file_id(doc)?;
+        let length = match version {
+            1 => 40,
+            _ => dict.get(b"Length").and_then(Object::as_i64).unwrap_or(40),
+        };
+        if !(40..=128).contains(&length) || length % 8 != 0 {
+            anyhow::bail!("the document's encryption dictionary is damaged (key length {})", length);
+        }
+        legacy_file_key(&dict, &id, revision, length as usize / 8, encrypt_metadata, password)?
+    };
+    Ok(Encryption {
+        dict,
+        key,
+        method,
+        encrypt_metadata,
+        streams,
+        strings,
+    })
+}
+
+fn first_file_id(doc: &Document) -> Result<Vec<u8>> {
+    doc.trailer
+        .get(b"ID")
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|id| id.first())
+        .and_then(|id| id.as_str().ok())
+        .map(<[u8]>::to_vec)
+        .context("the encrypted document has no file identifier")
+}
+
+/// The cipher of the `/StdCF` crypt filter, and whether streams and strings use it
+fn crypt_filters(dict: &Dictionary) -> Result<(Method, bool, bool)> {
+    let uses_filter = |key: &[u8]| !matches!(dict.get(key), Ok(Object::Name(name)) if name == b"Identity");
+    let (streams, strings) = (uses_filter(b"StmF"), uses_filter(b"StrF"));
+    let method = dict
+        .get(b"CF")
+        .and_then(Object::as_dict)
+        .and_then(|filters| filters.get(b"StdCF"))
+        .and_then(Object::as_dict)
+        .and_then(|filter| filter.get(b"CFM"))
+        .and_then(Object::as_name);
+    let method = match method {
+        Ok(b"V2") => Method::Rc4,
+        Ok(b"AESV2") => Method::Aes128,
+        Ok(b"AESV3") => Method::Aes256,
+        // Every filter is /Identity: nothing is actually encrypted
+        Err(_) if !streams && !strings => Method::Rc4,
+        _ => anyhow::bail!("the document uses a crypt filter that isn't supported"),
+    };
+    Ok((method, streams, strings))
+}
+
+fn string_entry<'a>(dict: &'a Dictionary, key: &[u8]) -> Result<&'a [u8]> {
+    dict.get(key)
+        .and_then(Object::as_str)
+        .map_err(|_| anyhow::anyhow!("the document's encryption dictionary has no /{}", String::from_utf8_lossy(key)))
+}
+
+fn padded_password(password: &[u8]) -> Vec<u8> {
+    let mut padded = password[..password.len().min(32)].to_vec();
+    padded.extend_from_slice(&PAD[..32 - padded.len()]);
+    padded
+}
+
+/// The RC4 and AES-128 file key for a password, trying it as the user password and then
+/// as the owner password (Algorithms 2, 6 and 7)
+fn legacy_file_key(
+    dict: &Dictionary,
+    id: &[u8],
+    revision: i64,
+    length: usize,
+    encrypt_metadata: bool,
+    password: &str,
+) -> Result<Vec<u8>> {
+    let owner_entry = string_entry(dict, b"O")?;
+    let user_entry = string_entry(dict, b"U")?;
+    let permissions = dict.get(b"P").and_then(Object::as_i64).context("the document's encryption dictionary has no /P")?;
+
+    let key_for = |user_password: &[u8]| {
+        let mut data = padded_password(user_password);
+        data.extend_from_slice(&owner_entry[..owner_entry.len().min(32)]);
+        data.extend_from_slice(&(permissions as u32).to_le_bytes());
+        data.extend_from_slice(id);
+        if revision >= 4 && !encrypt_metadata {
+            data.extend_from_slice(&[0xFF; 4]);
+        }
+        let mut key = md5(&data);
+        if revision >= 3 {
+            for _ in 0..50 {
+                key = md5(&key[..length]);
+            }
+        }
+        key.truncate(length);
+        key
+    };
+    let matches_user_entry = |key: &[u8]| {
+        let expected = user_entry_for(key, id, revision);
+        let compared = if revision >= 3 { 16 } else { 32 };
+        user_entry.len() >= compared && user_entry[..compared] == expected[..compared]
+    };
+
+    let key = key_for(password.as_bytes());
+    if matches_user_entry(&key) {
+        return Ok(key);
+    }
+    // The owner password decrypts /O to the user password
+    let owner_key = owner_key(password.as_bytes(), revision, length);
+    let mut user_password = owner_entry[..owner_entry.len().min(32)].to_vec();
+    if revision >= 3 {
+        for round in (0..20u8).rev() {
+            let round_key: Vec<u8> = owner_key.iter().map(|byte| byte ^ round).collect();
+            user_password = rc4(&round_key, &user_password);
+        }
+    } else {
+        user_password = rc4(&owner_key, &user_password);
+    }
+    let key = key_for(&user_password);
+    if matches_user_entry(&key) {
+        return Ok(key);
+    }
+    Err(password_error(password))
+}
+
+/// The RC4 key that encrypts the user password into `/O` (Algorithm 3, steps a-d)
+fn owner_key(owner_password: &[u8], revision: i64, length: usize) -> Vec<u8> {
+    let mut key = md5(&padded_password(owner_password));
+    if revision >= 3 {
+        for _ in 0..50 {
+            key = md5(&key);
+        }
+    }
+    key.truncate(length);
+    key
+}
+
+/// The `/U` entry for a file key (Algorithms 4 and 5; the last 16 bytes are arbitrary)
+fn user_entry_for(key: &[u8], id: &[u8], revision: i64) -> Vec<u8> {
+    if revision < 3 {
+        return rc4(key, &PAD);
+    }
+    let mut data = PAD.to_vec();
+    data.extend_from_slice(id);
+    let mut entry = rc4(key, &md5(&data));
+    for round in 1..=19u8 {
+        let round_key: Vec<u8> = key.iter().map(|byte| byte ^ round).collect();
+        entry = rc4(&round_key, &entry);
+    }
+    entry.extend_from_slice(&[0; 16]);
+    entry
+}
+
+fn password_error(password: &str) -> anyhow::Error {
+    if password.is_empty() {
+        anyhow::anyhow!("the document is encrypted with a user password (supply it with --password)")
+    } else {
+        anyhow::anyhow!("the password is incorrect")
+    }
+}
+
+/// The AES-256 file key: unwrapped from `/UE` with the user password or `/OE` with the
+/// owner password (Algorithm 2.A)
+fn aes256_file_key(dict: &Dictionary, revision: i64, password: &str) -> Result<Vec<u8>> {
+    let password = utf8_password(password);
+    let owner_entry = string_entry(dict, b"O")?;
+    let user_entry = string_entry(dict, b"U")?;
+    if owner_entry.len() < 48 || user_entry.len() < 48 {
+        anyhow::bail!("the document's encryption dictionary is damaged (/O or /U is too short)");
+    }
+
+    let (wrapped, wrapping_key) = if hash_r6(revision, password, &owner_entry[32..40], &user_entry[..48])? == owner_entry[..32] {
+        (string_entry(dict, b"OE")?, hash_r6(revision, password, &owner_entry[40..48], &user_entry[..48])?)
+    } else if hash_r6(revision, password, &user_entry[32..40], &[])? == user_entry[..32] {
+        (string_entry(dict, b"UE")?, hash_r6(revision, password, &user_entry[40..48], &[])?)
+    } else {
+        return Err(password_error(&String::from_utf8_lossy(password)));
+    };
+    aes_no_padding(SymmCipher::aes_256_cbc(), &wrapping_key, Some(&[0; 16]), wrapped, Mode::Decrypt)
+}
+
+/// Passwords of the AES-256 handler are UTF-8, at most 127 bytes
+fn utf8_password(password: &str) -> &[u8] {
+    let mut end = password.len().min(127);
+    while !password.is_char_boundary(end) {
+        end -= 1;
+    }
+    &password.as_bytes()[..end]
+}
+
+/// The password hash of the AES-256 handler: plain SHA-256 for revision 5, the iterated
+/// hash of Algorithm 2.B for revision 6. `user_entry` is empty for user passwords.
+fn hash_r6(revision: i64, password: &[u8], salt: &[u8], user_entry: &[u8]) -> Result<Vec<u8>> {
+    let mut input = password.to_vec();
+    input.extend_from_slice(salt);
+    input.extend_from_slice(user_entry);
+    let mut key = hash(MessageDigest::sha256(), &input)?.to_vec();
+    if revision < 6 {
+        return Ok(key);
+    }
+
+    let mut round = 0;
+    loop {
+        let mut block = password.to_vec();
+        block.extend_from_slice(&key);
+        block.extend_from_slice(user_entry);
+        let repeated = block.repeat(64);
+        let encrypted = aes_no_padding(SymmCipher::aes_128_cbc(), &key[..16], Some(&key[16..32]), &repeated, Mode::Encrypt)?;
+        let digest = match encrypted[..16].iter().map(|&byte| byte as u32).sum::<u32>() % 3 {
+            0 => MessageDigest::sha256(),
+            1 => MessageDigest::sha384(),
+            _ => MessageDigest::sha512(),
+        };
+        key = hash(digest, &encrypted)?.to_vec();
+        round += 1;
+        let last = *encrypted.last().unwrap_or(&0) as i32;
+        if round >= 64 && last <= round - 32 {
+            break;
+        }
+    }
+    key.truncate(32);
+    Ok(key)
+}
+
+/// The AES-128 handler (`/V 4 /R 4`) with the `/O` and `/U` entries of Algorithms 3 and 5
+fn aes128_encryption(doc: &Document, settings: &EncryptionSettings, permissions: i32) -> Result<Encryption> {
+    let id = first_file_id(doc)?;
+    let user_password = settings.user_password.as_bytes();
+    let owner_password = match settings.owner_password.as_str() {
+        "" => user_password,
+        owner => owner.as_bytes(),
+    };
+
+    let owner_key = owner_key(owner_password, 4, 16);
+    let mut owner_entry = rc4(&owner_key, &padded_password(user_password));
+    for round in 1..=19u8 {
+        let round_key: Vec<u8> = owner_key.iter().map(|byte| byte ^ round).collect();
+        owner_entry = rc4(&round_key, &owner_entry);
+    }
+
+    let mut data = padded_password(user_password);
+    data.extend_from_slice(&owner_entry);
+    data.extend_from_slice(&(permissions as u32).to_le_bytes());
+    data.extend_from_slice(&id);
+    let mut key = md5(&data);
+    for _ in 0..50 {
+        key = md5(&key[..16]);
+    }
+    key.truncate(16);
+    let user_entry = user_entry_for(&key, &id, 4);
+
+    let dict = dictionary! {
+        "Filter" => "Standard",
+        "V" => 4,
+        "R" => 4,
+        "Length" => 128,
+        "CF" => dictionary! {
+            "StdCF" => dictionary! { "AuthEvent" => "DocOpen", "CFM" => "AESV2", "Length" => 16 },
+        },
+        "StmF" => "StdCF",
+        "StrF" => "StdCF",
+        "O" => Object::String(owner_entry, StringFormat::Hexadecimal),
+        "U" => Object::String(user_entry, StringFormat::Hexadecimal),
+        "P" => permissions as i64,
+    };
+    Ok(Encryption {
+        dict,
+        key,
+        method: Method::Aes128,
+        encrypt_metadata: true,
+        streams: true,
+        strings: true,
+    })
+}
+
+/// The AES-256 handler (`/V 5 /R 6`): a random file key wrapped for each password
+/// (Algorithms 8, 9 and 10)
+fn aes256_encryption(settings: &EncryptionSettings, permissions: i32) -> Result<Encryption> {
+    let key = random_bytes(32)?;
+    let user_password = utf8_password(&settings.user_password);
+    let owner_password = match settings.owner_password.as_str() {
+        "" => user_password,
+        owner => utf8_password(owner),
+    };
+    let wrap = |wrapping_key: &[u8]| aes_no_padding(SymmCipher::aes_256_cbc(), wrapping_key, Some(&[0; 16]), &key, Mode::Encrypt);
+
+    let user_salts = random_bytes(16)?;
+    let mut user_entry = hash_r6(6, user_password, &user_salts[..8], &[])?;
+    user_entry.extend_from_slice(&user_salts);
+    let user_key_entry = wrap(&hash_r6(6, user_password, &user_salts[8..], &[])?)?;
+
+    let owner_salts = random_bytes(16)?;
+    let mut owner_entry = hash_r6(6, owner_password, &owner_salts[..8], &user_entry)?;
+    owner_entry.extend_from_slice(&owner_salts);
+    let owner_key_entry = wrap(&hash_r6(6, owner_password, &owner_salts[8..], &user_entry)?)?;
+
+    let mut perms = (permissions as u32).to_le_bytes().to_vec();
+    perms.extend_from_slice(&[0xFF; 4]);
+    perms.extend_from_slice(b"Tadb");
+    perms.extend(random_bytes(4)?);
+    let perms = aes_no_padding(SymmCipher::aes_256_ecb(), &key, None, &perms, Mode::Encrypt)?;
+
+    let dict = dictionary! {
+        "Filter" => "Standard",
+        "V" => 5,
+        "R" => 6,
+        "Length" => 256,
+        "CF" => dictionary! {
+            "StdCF" => dictionary! { "AuthEvent" => "DocOpen", "CFM" => "AESV3", "Length" => 32 },
+        },
+        "StmF" => "StdCF",
+        "StrF" => "StdCF",
+        "O" => Object::String(owner_entry, StringFormat::Hexadecimal),
+        "U" => Object::String(user_entry, StringFormat::Hexadecimal),
+        "OE" => Object::String(owner_key_entry, StringFormat::Hexadecimal),
+        "UE" => Object::String(user_key_entry, StringFormat::Hexadecimal),
+        "P" => permissions as i64,
+        "Perms" => Object::String(perms, StringFormat::Hexadecimal),
+    };
+    Ok(Encryption {
+        dict,
+        key,
+        method: Method::Aes256,
+        encrypt_metadata: true,
+        streams: true,
+        strings: true,
+    })
+}
@@ -0,0 +1,615 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use crate::filters::decode_stream;
+use crate::pdf_writer::SaveOptions;
+use crate::inline_images::{for_each_operation, page_resources};
+use crate::objects::{referenced_ids, resolve};
+use crate::standard_fonts::{is_standard_font, standard_width, strip_subset_tag};
+use crate::truetype::TrueTypeFont;
+
+/// Results of a font pass
+#[derive(Debug, Default, Clone)]
+pub struct FontStats {
+    pub fonts: usize,
+    pub bytes_saved: u64,
+}
+
+/// Character codes shown with each font dictionary, or `None` when its codes can't be
+/// interpreted (e.g. a Type0 font with a non-identity CMap)
+type FontUsage = HashMap<ObjectId, Option<BTreeSet<u32>>>;
+
+/// Reduce embedded TrueType programs (simple and CIDFontType2) to the glyphs the
+/// document's content streams actually show.
+///
+/// Unused glyphs are emptied rather than removed, so glyph ids, `/Widths`, `/W` and
+/// `/CIDToGIDMap` stay valid; simple fonts additionally get their `/Widths` narrowed to
+/// the codes in use. Fonts are left alone when any content can't be parsed, when the
+/// code-to-glyph mapping is uncertain, when they are already subset, when interactive
+/// forms may type new text with them, or when they aren't TrueType (Type1, CFF, Type3).
+pub fn subset_fonts(doc: &mut Document, save_options: &SaveOptions) -> FontStats {
+    let mut stats = FontStats::default();
+    let Some(usage) = collect_font_usage(doc) else { return stats };
+    let form_fonts = acroform_fonts(doc);
+
+    // Font dictionaries grouped by the program they embed (deduplicated programs are shared)
+    let mut programs: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+    for (&font_id, object) in &doc.objects {
+        let Object::Dictionary(font) = object else { continue };
+        if !is_type(font, b"Font") {
+            continue;
+        }
+        if let Some(program_id) = truetype_program(doc, font) {
+            programs.entry(program_id).or_default().push(font_id);
+        }
+    }
+
+    for (program_id, fonts) in programs {
+        if fonts.iter().any(|id| form_fonts.contains(id)) {
+            continue;
+        }
+        let Ok(Object::Stream(program)) = doc.get_object(program_id) else { continue };
+        let Ok(data) = decode_stream(program) else { continue };
+        let Some(font_program) = TrueTypeFont::parse(&data) else { continue };
+
+        let mut glyphs = BTreeSet::new();
+        let mut safe = true;
+        for font_id in &fonts {
+            let Ok(font) = doc.get_dictionary(*font_id) else { continue };
+            safe &= !font_names(doc, font).iter().any(|name| has_subset_tag(name));
+            match usage.get(font_id) {
+                Some(Some(codes)) => match glyphs_for_codes(doc, font, &font_program, codes) {
+                    Some(used) => glyphs.extend(used),
+                    None => safe = false,
+                },
+                Some(None) => safe = false,
+                None => {} // Never shown
+            }
+        }
+        if !safe {
+            continue;
+        }
+
+        let Some(subset) = font_program.subset(&glyphs) else { continue };
+        let Ok(encoded) = save_options.deflate(&subset) else { continue };
+        if encoded.len() >= program.content.len() {
+            continue;
+        }
+
+        stats.fonts += 1;
+        stats.bytes_saved += (program.content.len() - encoded.len()) as u64;
+        if let Ok(Object::Stream(program)) = doc.get_object_mut(program_id) {
+            program.dict.set("Filter", "FlateDecode");
+            program.dict.remove(b"DecodeParms");
+            program.dict.set("Length1", subset.len() as i64);
+            program.set_content(encoded);
+        }
+
+        let tag = subset_tag(&glyphs);
+        for font_id in &fonts {
+            rename_font(doc, *font_id, &tag);
+            if let Some(Some(codes)) = usage.get(font_id) {
+                narrow_widths(doc, *font_id, codes);
+            }
+        }
+    }
+
+    stats
+}
+
+/// Remove the embedded programs of standard 14 fonts (Helvetica, Times, Courier, ...),
+/// which every viewer provides. Only simple fonts whose shown characters have the
+/// standard widths under a standard encoding qualify; CID fonts, fonts with custom
+/// encodings and fonts whose metrics we can't verify keep their programs.
+pub fn unembed_standard_fonts(doc: &mut Document) -> FontStats {
+    let mut stats = FontStats::default();
+    let Some(usage) = collect_font_usage(doc) else { return stats };
+
+    let mut candidates = Vec::new();
+    let mut kept_descriptors = HashSet::new();
+    for (&font_id, object) in &doc.objects {
+        let Object::Dictionary(font) = object else { continue };
+        if !is_type(font, b"Font") {
+            continue;
+        }
+        let Ok(descriptor_id) = font.get(b"FontDescriptor").and_then(Object::as_reference) else { continue };
+        let Ok(descriptor) = doc.get_dictionary(descriptor_id) else { continue };
+        if !FONT_FILE_KEYS.iter().any(|key| descriptor.has(key)) {
+            continue;
+        }
+        if has_standard_metrics(doc, font, usage.get(&font_id)) {
+            candidates.push((font_id, descriptor_id));
+        } else {
+            kept_descriptors.insert(descriptor_id);
+        }
+    }
+
+    let mut programs = HashSet::new();
+    for (font_id, descriptor_id) in candidates {
+        // A descriptor shared with a font that still needs its program stays as is
+        if kept_descriptors.contains(&descriptor_id) {
+            continue;
+        }
+        if let Ok(descriptor) = doc.get_dictionary_mut(descriptor_id) {
+            for key in FONT_FILE_KEYS {
+                if let Ok(program) = descriptor.get(key).and_then(Object::as_reference) {
+                    programs.insert(program);
+                }
+                descriptor.remove(key);
+            }
+            // Glyph lists describing the embedded subset
+            descriptor.remove(b"CharSet");
+            descriptor.remove(b"CIDSet");
+            if let Ok(name) = descriptor.get(b"FontName").and_then(Object::as_name) {
+                let name = strip_subset_tag(name).to_vec();
+                descriptor.set("FontName", Object::Name(name));
+            }
+        }
+        if let Ok(font) = doc.get_dictionary_mut(font_id) {
+            if let Ok(name) = font.get(b"BaseFont").and_then(Object::as_name) {
+                let name = strip_subset_tag(name).to_vec();
+                font.set("BaseFont", Object::Name(name));
+            }
+        }
+        stats.fonts += 1;
+    }
+
+    // Programs still embedded by other descriptors don't shrink the file
+    let referenced = referenced_ids(doc);
+    stats.bytes_saved = programs
+        .iter()
+        .filter(|id| !referenced.contains(id))
+        .filter_map(|id| doc.get_object(*id).and_then(Object::as_stream).ok())
+        .map(|program| program.content.len() as u64)
+        .sum();
+    stats
+}
+
+const FONT_FILE_KEYS: [&[u8]; 3] = [b"FontFile", b"FontFile2", b"FontFile3"];
+
+/// Whether a simple standard 14 font renders identically without its program: a
+/// standard encoding and, for every code shown, the standard width (within rounding)
+fn has_standard_metrics(doc: &Document, font: &Dictionary, usage: Option<&Option<BTreeSet<u32>>>) -> bool {
+    if !matches!(font.get(b"Subtype"), Ok(Object::Name(subtype)) if subtype == b"Type1" || subtype == b"TrueType") {
+        return false;
+    }
+    let Ok(name) = font.get(b"BaseFont").and_then(Object::as_name) else { return false };
+    if !is_standard_font(name) {
+        return false;
+    }
+    let encoding = match font.get(b"Encoding").map(|e| resolve(doc, e)) {
+        Ok(Object::Name(encoding)) => encoding.as_slice(),
+        Ok(Object::Dictionary(encoding)) if !encoding.has(b"Differences") => {
+            match encoding.get(b"BaseEncoding").and_then(Object::as_name) {
+                Ok(encoding) => encoding,
+                Err(_) => return false,
+            }
+        }
+        _ => return false,
+    };
+
+    let codes = match usage {
+        Some(Some(codes)) => codes,
+        Some(None) => return false,
+        None => return true, // Never shown
+    };
+    let first = font.get(b"FirstChar").and_then(Object::as_i64).unwrap_or(0);
+    let Ok(widths) = font.get(b"Widths").map(|w| resolve(doc, w)).and_then(Object::as_array) else { return false };
+    codes.iter().all(|&code| {
+        let width = usize::try_from(code as i64 - first).ok().and_then(|i| widths.get(i)).and_then(|w| match w {
+            Object::Integer(w) => Some(*w as f32),
+            Object::Real(w) => Some(*w),
+            _ => None,
+        });
+        match (width, standard_width(name, encoding, code)) {
+            (Some(width), Some(standard)) => (width - standard as f32).abs() <= 1.0,
+            _ => false,
+        }
+    })
+}
+
+/// The embedded TrueType program (`/FontFile2`) of a simple TrueType or a Type0 font
+/// with a CIDFontType2 descendant
+fn truetype_program(doc: &Document, font: &Dictionary) -> Option<ObjectId> {
+    let font = match font.get(b"Subtype").and_then(Object::as_name).ok()? {
+        b"TrueType" => font,
+        b"Type0" => {
+            let descendant = descendant_font(doc, font)?;
+            if !matches!(descendant.get(b"Subtype"), Ok(Object::Name(name)) if name == b"CIDFontType2") {
+                return None;
+            }
+            descendant
+        }
+        _ => return None,
+    };
+    let descriptor = resolve(doc, font.get(b"FontDescriptor").ok()?).as_dict().ok()?;
+    descriptor.get(b"FontFile2").and_then(Object::as_reference).ok()
+}
+
+fn descendant_font<'a>(doc: &'a Document, font: &'a Dictionary) -> Option<&'a Dictionary> {
+    let descendants = resolve(doc, font.get(b"DescendantFonts").ok()?).as_array().ok()?;
+    resolve(doc, descendants.first()?).as_dict().ok()
+}
+
+/// Glyphs the given codes may select. `None` when a code can't be mapped with certainty,
+/// since a glyph we fail to keep would silently vanish from the page.
+fn glyphs_for_codes(
+    doc: &Document,
+    font: &Dictionary,
+    program: &TrueTypeFont,
+    codes: &BTreeSet<u32>,
+) -> Option<BTreeSet<u16>> {
+    let num_glyphs = program.num_glyphs()? as u32;
+    let mut glyphs = BTreeSet::new();
+
+    if let Some(descendant) = descendant_font(doc, font) {
+        // Identity-encoded CIDFontType2: the code is the CID, mapped through /CIDToGIDMap
+        let map = match descendant.get(b"CIDToGIDMap").map(|map| resolve(doc, map)) {
+            Ok(Object::Stream(stream)) => Some(decode_stream(stream).ok()?),
+            Ok(Object::Name(name)) if name == b"Identity" => None,
+            Err(_) => None,
+            _ => return None,
+        };
+        for &cid in codes {
+            let gid = match &map {
+                Some(map) => {
+                    let entry = map.get(cid as usize * 2..cid as usize * 2 + 2)?;
+                    u16::from_be_bytes([entry[0], entry[1]]) as u32
+                }
+                None => cid,
+            };
+            if gid < num_glyphs {
+                glyphs.insert(gid as u16);
+            }
+        }
+        return Some(glyphs);
+    }
+
+    // Simple fonts: keep every glyph any of the lookups viewers use could pick
+    let to_unicode = font
+        .get(b"ToUnicode")
+        .ok()
+        .and_then(|cmap| resolve(doc, cmap).as_stream().ok())
+        .and_then(|stream| decode_stream(stream).ok())
+        .map(|data| parse_to_unicode(&data));
+    let (base_encoding, differences) = match font.get(b"Encoding").map(|e| resolve(doc, e)) {
+        Ok(Object::Name(name)) => (Some(name.as_slice()), false),
+        Ok(Object::Dictionary(encoding)) => (
+            encoding.get(b"BaseEncoding").and_then(Object::as_name).ok(),
+            encoding.get(b"Differences").is_ok(),
+        ),
+        _ => (None, false),
+    };
+
+    for &code in codes {
+        let mut found = false;
+        let mut keep = |glyph: Option<u16>| {
+            if let Some(glyph) = glyph {
+                glyphs.insert(glyph);
+                found = true;
+            }
+        };
+        keep(program.glyph_for(3, 0, code));
+        keep(program.glyph_for(3, 0, 0xF000 | code));
+        keep(program.glyph_for(1, 0, code));
+
+        let unicode = match &to_unicode {
+            Some(map) if map.contains_key(&code) => map.get(&code).copied(),
+            _ if differences => None, // Glyph names we don't resolve
+            _ => standard_unicode(base_encoding, code),
+        };
+        if let Some(unicode) = unicode {
+            keep(program.glyph_for(3, 1, unicode));
+            keep(program.glyph_for(3, 10, unicode));
+        }
+
+        if !found {
+            return None;
+        }
+    }
+    Some(glyphs)
+}
+
+/// Unicode value of a code under the standard encodings whose mapping we know
+fn standard_unicode(encoding: Option<&[u8]>, code: u32) -> Option<u32> {
+    const WIN_ANSI_HIGH: [u16; 32] = [
+        0x20AC, 0, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039, 0x0152, 0,
+        0x017D, 0, 0, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014, 0x02DC, 0x2122, 0x0161, 0x203A,
+        0x0153, 0, 0x017E, 0x0178,
+    ];
+    match (encoding, code) {
+        (Some(b"WinAnsiEncoding"), 0x80..=0x9F) => Some(WIN_ANSI_HIGH[code as usize - 0x80] as u32).filter(|&u| u != 0),
+        (Some(b"WinAnsiEncoding"), 0x20..=0xFF) => Some(code),
+        (_, 0x27) | (_, 0x60) => None, // quoteright/quoteleft in StandardEncoding
+        (_, 0x20..=0x7E) => Some(code),
+        _ => None,
+    }
+}
+
+/// Single-character mappings of a `/ToUnicode` CMap
+fn parse_to_unicode(data: &[u8]) -> HashMap<u32, u32> {
+    let mut map = HashMap::new();
+    let code = |bytes: &[u8]| bytes.iter().fold(0u32, |code, &b| code << 8 | b as u32);
+    let unicode = |bytes: &[u8]| -> Option<u32> {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        let mut chars = char::decode_utf16(units);
+        let first = chars.next()?.ok()?;
+        chars.next().is_none().then_some(first as u32)
+    };
+
+    for_each_operation(data, |operator, operands| match operator {
+        b"endbfchar" => {
+            for pair in operands.chunks_exact(2) {
+                if let [Object::String(src, _), Object::String(dst, _)] = pair {
+                    if let Some(u) = unicode(dst) {
+                        map.insert(code(src), u);
+                    }
+                }
+            }
+        }
+        b"endbfrange" => {
+            for range in operands.chunks_exact(3) {
+                let [Object::String(lo, _), Object::String(hi, _), dst] = range else { continue };
+                let (lo, hi) = (code(lo), code(hi));
+                match dst {
+                    Object::String(dst, _) => {
+                        let Some(start) = unicode(dst) else { continue };
+                        for (offset, src) in (lo..=hi).take(0x10000).enumerate() {
+                            map.insert(src, start + offset as u32);
+                        }
+                    }
+                    Object::Array(items) => {
+                        for (src, item) in (lo..=hi).zip(items) {
+                            if let Some(u) = item.as_str().ok().and_then(unicode) {
+                                map.insert(src, u);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    });
+    map
+}
+
+/// Character codes shown with each indirect font, from the content of every page and of
+/// every stream with its own resources (Form XObjects, patterns, appearance streams).
+/// `None` when any of that content can't be parsed.
+fn collect_font_usage(doc: &Document) -> Option<FontUsage> {
+    let mut usage = FontUsage::new();
+
+    for (_, page_id) in doc.get_pages() {
+        let resources = page_resources(doc, page_id).unwrap_or_default();
+        let mut content = Vec::new();
+        for content_id in doc.get_page_contents(page_id) {
+            content.extend(decode_stream(doc.get_object(content_id).ok()?.as_stream().ok()?).ok()?);
+            content.push(b'\n');
+        }
+        scan_text(doc, &content, &resources, &mut usage, &mut Vec::new())?;
+    }
+
+    for (id, object) in &doc.objects {
+        let Object::Stream(stream) = object else { continue };
+        let Some(resources) = stream.dict.get(b"Resources").ok().and_then(|r| resolve(doc, r).as_dict().ok()) else {
+            continue;
+        };
+        let content = decode_stream(stream).ok()?;
+        scan_text(doc, &content, resources, &mut usage, &mut vec![*id])?;
+    }
+
+    // Type3 glyph procedures draw with their font's resources
+    for object in doc.objects.values() {
+        let Object::Dictionary(font) = object else { continue };
+        if !is_type_key(font, b"Subtype", b"Type3") {
+            continue;
+        }
+        let Some(resources) = font.get(b"Resources").ok().and_then(|r| resolve(doc, r).as_dict().ok()) else {
+            continue;
+        };
+        let Ok(procs) = font.get(b"CharProcs").map(|p| resolve(doc, p)).and_then(Object::as_dict) else { continue };
+        for (_, proc) in procs.iter() {
+            let content = decode_stream(resolve(doc, proc).as_stream().ok()?).ok()?;
+            scan_text(doc, &content, resources, &mut usage, &mut Vec::new())?;
+        }
+    }
+
+    Some(usage)
+}
+
+/// Record the codes `content` shows, following Form XObjects that use the same resources
+fn scan_text(
+    doc: &Document,
+    content: &[u8],
+    resources: &Dictionary,
+    usage: &mut FontUsage,
+    stack: &mut Vec<ObjectId>,
+) -> Option<()> {
+    let fonts = resources.get(b"Font").ok().and_then(|f| resolve(doc, f).as_dict().ok());
+    let xobjects = resources.get(b"XObject").ok().and_then(|x| resolve(doc, x).as_dict().ok());
+    let mut current: Option<ObjectId> = None;
+    let mut saved = Vec::new();
+    let mut invoked = Vec::new();
+
+    let mut show = |font: Option<ObjectId>, text: &[u8]| {
+        let Some(font_id) = font else { return };
+        let width = doc.get_dictionary(font_id).ok().and_then(|font| code_width(doc, font));
+        let entry = usage.entry(font_id).or_insert_with(|| Some(BTreeSet::new()));
+        match (entry.as_mut(), width) {
+            (Some(codes), Some(width)) => codes.extend(text.chunks_exact(width).map(|code| {
+                code.iter().fold(0u32, |code, &b| code << 8 | b as u32)
+            })),
+            _ => *entry = None,
+        }
+    };
+
+    let parsed = for_each_operation(content, |operator, operands| match (operator, operands) {
+        (b"Tf", [Object::Name(name), ..]) => {
+            current = fonts.and_then(|fonts| fonts.get(name).and_then(Object::as_reference).ok());
+        }
+        (b"q", _) => saved.push(current),
+        (b"Q", _) => current = saved.pop().unwrap_or(current),
+        (b"Tj" | b"'", [Object::String(text, _)]) | (b"\"", [_, _, Object::String(text, _)]) => show(current, text),
+        (b"TJ", [Object::Array(items)]) => {
+            for item in items {
+                if let Object::String(text, _) = item {
+                    show(current, text);
+                }
+            }
+        }
+        (b"Do", [Object::Name(name)]) => invoked.push(name.clone()),
+        _ => {}
+    });
+    if !parsed {
+        return None;
+    }
+
+    for name in invoked {
+        let Some(Ok(Object::Reference(form_id))) = xobjects.map(|x| x.get(&name)) else { continue };
+        let Ok(Object::Stream(form)) = doc.get_object(*form_id) else { continue };
+        // Forms with resources of their own are scanned on their own
+        if form.dict.get(b"Resources").is_ok() || stack.contains(form_id) || !is_type_key(&form.dict, b"Subtype", b"Form") {
+            continue;
+        }
+        let form_content = decode_stream(form).ok()?;
+        stack.push(*form_id);
+        scan_text(doc, &form_content, resources, usage, stack)?;
+        stack.pop();
+    }
+    Some(())
+}
+
+/// Bytes per character code: one for simple fonts, two for Identity-encoded Type0 fonts
+fn code_width(doc: &Document, font: &Dictionary) -> Option<usize> {
+    if !is_type_key(font, b"Subtype", b"Type0") {
+        return Some(1);
+    }
+    match resolve(doc, font.get(b"Encoding").ok()?) {
+        Object::Name(name) if name == b"Identity-H" || name == b"Identity-V" => Some(2),
+        _ => None,
+    }
+}
+
+/// Fonts interactive form fields may use to render newly typed text
+fn acroform_fonts(doc: &Document) -> HashSet<ObjectId> {
+    let fonts = (|| {
+        let root = doc.trailer.get(b"Root").ok()?;
+        let acroform = resolve(doc, resolve(doc, root).as_dict().ok()?.get(b"AcroForm").ok()?).as_dict().ok()?;
+        let resources = resolve(doc, acroform.get(b"DR").ok()?).as_dict().ok()?;
+        resolve(doc, resources.get(b"Font").ok()?).as_dict().ok()
+    })();
+    fonts
+        .map(|fonts| fonts.iter().filter_map(|(_, font)| font.as_reference().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Base font names of a font and its descendant, plus the descriptor's font name
+fn font_names(doc: &Document, font: &Dictionary) -> Vec<Vec<u8>> {
+    let mut dicts = vec![font];
+    dicts.extend(descendant_font(doc, font));
+    let mut names: Vec<Vec<u8>> = dicts
+        .iter()
+        .filter_map(|dict| dict.get(b"BaseFont").and_then(Object::as_name).ok())
+        .map(<[u8]>::to_vec)
+        .collect();
+    names.extend(
+        dicts
+            .iter()
+            .filter_map(|dict| dict.get(b"FontDescriptor").ok())
+            .filter_map(|descriptor| resolve(doc, descriptor).as_dict().ok())
+            .filter_map(|descriptor| descriptor.get(b"FontName").and_then(Object::as_name).ok())
+            .map(<[u8]>::to_vec),
+    );
+    names
+}
+
+/// Whether a font name carries a subset tag (`ABCDEF+Name`)
+pub fn has_subset_tag(name: &[u8]) -> bool {
+    name.len() > 7 && name[..6].iter().all(u8::is_ascii_uppercase) && name[6] == b'+'
+}
+
+/// Six uppercase letters derived from the glyph set, so identical subsets get the same tag
+fn subset_tag(glyphs: &BTreeSet<u16>) -> Vec<u8> {
+    let mut hasher = DefaultHasher::new();
+    glyphs.hash(&mut hasher);
+    let mut hash = hasher.finish();
+    (0..6)
+        .map(|_| {
+            let letter = b'A' + (hash % 26) as u8;
+            hash /= 26;
+            letter
+        })
+        .collect()
+}
+
+/// Prefix the subset tag to every name of a font: its BaseFont, its descendant's and
+/// the descriptor's FontName
+fn rename_font(doc: &mut Document, font_id: ObjectId, tag: &[u8]) {
+    let tagged = |name: &[u8]| [tag, b"+", name].concat();
+    let mut targets = vec![font_id];
+    if let Ok(font) = doc.get_dictionary(font_id) {
+        if let Ok(Object::Array(descendants)) = font.get(b"DescendantFonts").map(|d| resolve(doc, d)) {
+            targets.extend(descendants.iter().filter_map(|d| d.as_reference().ok()));
+        }
+    }
+
+    let mut descriptors = Vec::new();
+    for id in targets {
+        let Ok(dict) = doc.get_dictionary_mut(id) else { continue };
+        if let Ok(name) = dict.get(b"BaseFont").and_then(Object::as_name) {
+            let name = tagged(name);
+            dict.set("BaseFont", Object::Name(name));
+        }
+        if let Ok(descriptor) = dict.get(b"FontDescriptor").and_then(Object::as_reference) {
+            descriptors.push(descriptor);
+        }
+    }
+    for id in descriptors {
+        let Ok(descriptor) = doc.get_dictionary_mut(id) else { continue };
+        if let Ok(name) = descriptor.get(b"FontName").and_then(Object::as_name) {
+            if !has_subset_tag(name) {
+                let name = tagged(name);
+                descriptor.set("FontName", Object::Name(name));
+            }
+        }
+    }
+}
+
+/// Restrict a simple font's `/FirstChar`..`/LastChar` and `/Widths` to the codes in use
+fn narrow_widths(doc: &mut Document, font_id: ObjectId, codes: &BTreeSet<u32>) {
+    let (Some(&first_used), Some(&last_used)) = (codes.first(), codes.last()) else { return };
+    let Ok(font) = doc.get_dictionary(font_id) else { return };
+    if !is_type_key(font, b"Subtype", b"TrueType") {
+        return;
+    }
+    let (Ok(first), Ok(last)) = (font.get(b"FirstChar").and_then(Object::as_i64), font.get(b"LastChar").and_then(Object::as_i64))
+    else {
+        return;
+    };
+    let Ok(widths) = font.get(b"Widths").map(|w| resolve(doc, w)).and_then(Object::as_array) else { return };
+    let (first_used, last_used) = (first_used as i64, last_used as i64);
+    if widths.len() as i64 != last - first + 1 || first_used < first || last_used > last {
+        return;
+    }
+    if first_used == first && last_used == last {
+        return;
+    }
+    let narrowed = widths[(first_used - first) as usize..=(last_used - first) as usize].to_vec();
+
+    if let Ok(font) = doc.get_dictionary_mut(font_id) {
+        font.set("FirstChar", first_used);
+        font.set("LastChar", last_used);
+        font.set("Widths", narrowed);
+    }
+}
+
+fn is_type(dict: &Dictionary, kind: &[u8]) -> bool {
+    is_type_key(dict, b"Type", kind)
+}
+
+fn is_type_key(dict: &Dictionary, key: &[u8], value: &[u8]) -> bool {
+    matches!(dict.get(key), Ok(Object::Name(name)) if name == value)
+}
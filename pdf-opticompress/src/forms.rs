@@ -0,0 +1,167 @@
+use anyhow::Result;
+use lopdf::{Dictionary, Document, Object};
+
+use crate::filters::decode_stream;
+use crate::objects::resolve;
+
+/// Everything about an interactive form that filling it in depends on, independent of
+/// object numbers and stream encodings, so a form can be compared across optimization
+#[derive(Debug, Default, PartialEq)]
+pub struct FormSnapshot {
+    pub need_appearances: Option<bool>,
+    pub default_appearance: Option<String>,
+    /// Names of the fonts in the form's default resources (`/DR /Font`)
+    pub resource_fonts: Vec<Vec<u8>>,
+    pub fields: Vec<FieldSnapshot>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldSnapshot {
+    /// Fully qualified name (`parent.child`)
+    pub name: String,
+    pub field_type: Option<Vec<u8>>,
+    pub value: Option<String>,
+    pub flags: i64,
+    pub default_appearance: Option<String>,
+    pub widget_rects: Vec<Vec<f32>>,
+}
+
+/// Nesting deeper than this is treated as a cycle
+const MAX_DEPTH: usize = 32;
+
+/// Snapshot of the document's AcroForm, or `None` if it has no interactive form
+pub fn form_snapshot(doc: &Document) -> Option<FormSnapshot> {
+    let form = doc.catalog().ok()?.get(b"AcroForm").ok()?;
+    let form = resolve(doc, form).as_dict().ok()?;
+
+    let mut snapshot = FormSnapshot {
+        need_appearances: form.get(b"NeedAppearances").and_then(Object::as_bool).ok(),
+        default_appearance: form.get(b"DA").ok().map(|da| describe(doc, da)),
+        ..Default::default()
+    };
+    let fonts = form
+        .get(b"DR")
+        .ok()
+        .and_then(|dr| resolve(doc, dr).as_dict().ok())
+        .and_then(|dr| dr.get(b"Font").ok())
+        .and_then(|fonts| resolve(doc, fonts).as_dict().ok());
+    if let Some(fonts) = fonts {
+        snapshot.resource_fonts = fonts.iter().map(|(name, _)| name.clone()).collect();
+    }
+    if let Ok(Object::Array(fields)) = form.get(b"Fields").map(|fields| resolve(doc, fields)) {
+        for field in fields {
+            collect_fields(doc, field, "", None, 0, &mut snapshot.fields);
+        }
+    }
+    Some(snapshot)
+}
+
+/// Compare the form before and after optimization, naming the first field that changed
+pub fn verify_form(before: &FormSnapshot, after: Option<&FormSnapshot>) -> Result<()> {
+    let Some(after) = after else {
+        anyhow::bail!("the interactive form (/AcroForm) was lost during optimization");
+    };
+    if before == after {
+        return Ok(());
+    }
+    let changed = before
+        .fields
+        .iter()
+        .zip(&after.fields)
+        .find(|(old, new)| old != new)
+        .map(|(old, _)| format!("field '{}' changed", old.name));
+    let detail = match changed {
+        Some(detail) => detail,
+        None if before.fields.len() != after.fields.len() => {
+            format!("{} fields became {}", before.fields.len(), after.fields.len())
+        }
+        None => "the form's default appearance or resources changed".to_string(),
+    };
+    anyhow::bail!("form fields were altered during optimization: {}", detail)
+}
+
+/// Add a field and its descendants; `/FT` and `/DA` are inherited from parent fields.
+/// Kids without a `/T` are the field's widgets.
+fn collect_fields(
+    doc: &Document,
+    field: &Object,
+    parent_name: &str,
+    inherited: Option<(&Option<Vec<u8>>, &Option<String>)>,
+    depth: usize,
+    fields: &mut Vec<FieldSnapshot>,
+) {
+    let Ok(dict) = resolve(doc, field).as_dict() else { return };
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let partial = match dict.get(b"T") {
+        Ok(Object::String(name, _)) => String::from_utf8_lossy(name).into_owned(),
+        _ => String::new(),
+    };
+    let name = match (parent_name.is_empty(), partial.is_empty()) {
+        (true, _) => partial,
+        (false, true) => parent_name.to_string(),
+        (false, false) => format!("{}.{}", parent_name, partial),
+    };
+    let field_type = dict
+        .get(b"FT")
+        .and_then(Object::as_name)
+        .ok()
+        .map(<[u8]>::to_vec)
+        .or_else(|| inherited.and_then(|(field_type, _)| field_type.clone()));
+    let default_appearance = dict
+        .get(b"DA")
+        .ok()
+        .map(|da| describe(doc, da))
+        .or_else(|| inherited.and_then(|(_, da)| da.clone()));
+
+    let kids: Vec<&Object> = match dict.get(b"Kids").map(|kids| resolve(doc, kids)) {
+        Ok(Object::Array(kids)) => kids.iter().collect(),
+        _ => Vec::new(),
+    };
+    let (child_fields, widgets): (Vec<&Object>, Vec<&Object>) = kids
+        .into_iter()
+        .partition(|kid| resolve(doc, kid).as_dict().is_ok_and(|kid| kid.has(b"T")));
+
+    let mut widget_rects: Vec<Vec<f32>> = widgets
+        .iter()
+        .filter_map(|widget| resolve(doc, widget).as_dict().ok())
+        .filter_map(|widget| rect(doc, widget))
+        .collect();
+    if is_widget(dict) {
+        widget_rects.extend(rect(doc, dict));
+    }
+
+    fields.push(FieldSnapshot {
+        name: name.clone(),
+        field_type: field_type.clone(),
+        value: dict.get(b"V").ok().map(|value| describe(doc, value)),
+        flags: dict.get(b"Ff").and_then(Object::as_i64).unwrap_or(0),
+        default_appearance: default_appearance.clone(),
+        widget_rects,
+    });
+    for child in child_fields {
+        collect_fields(doc, child, &name, Some((&field_type, &default_appearance)), depth + 1, fields);
+    }
+}
+
+/// A comparable description of a value: references are followed and streams compared
+/// by their decoded data, so renumbering or recompression doesn't count as a change
+fn describe(doc: &Document, object: &Object) -> String {
+    match resolve(doc, object) {
+        Object::Stream(stream) => match decode_stream(stream) {
+            Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+            Err(_) => format!("{:?}", stream.content),
+        },
+        resolved => format!("{:?}", resolved),
+    }
+}
+
+fn rect(doc: &Document, widget: &Dictionary) -> Option<Vec<f32>> {
+    let rect = resolve(doc, widget.get(b"Rect").ok()?).as_array().ok()?;
+    rect.iter().map(|n| n.as_float().ok()).collect()
+}
+
+fn is_widget(dict: &Dictionary) -> bool {
+    matches!(dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Widget")
+}
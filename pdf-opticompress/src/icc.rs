@@ -0,0 +1,257 @@
+use lopdf::{Document, Object, ObjectId};
+use std::collections::{HashMap, HashSet};
+
+use crate::objects::{referenced_ids, replace_references, resolve};
+
+/// Results of the ICC profile pass
+#[derive(Debug, Default, Clone)]
+pub struct IccStats {
+    pub profiles_removed: usize,
+    pub profiles_deduplicated: usize,
+    pub bytes_removed: u64,
+}
+
+/// Ids of all ICC profile streams referenced from `[/ICCBased <stream>]` color spaces
+pub fn icc_profile_ids(doc: &Document) -> HashSet<ObjectId> {
+    let mut ids = HashSet::new();
+    for object in doc.objects.values() {
+        collect_icc_profiles(object, &mut ids);
+    }
+    ids
+}
+
+fn collect_icc_profiles(object: &Object, ids: &mut HashSet<ObjectId>) {
+    match object {
+        Object::Array(items) => {
+            if let [Object::Name(name), Object::Reference(id)] = items.as_slice() {
+                if name == b"ICCBased" {
+                    ids.insert(*id);
+                    return;
+                }
+            }
+            items.iter().for_each(|item| collect_icc_profiles(item, ids));
+        }
+        Object::Dictionary(dict) => dict.iter().for_each(|(_, value)| collect_icc_profiles(value, ids)),
+        Object::Stream(stream) => stream.dict.iter().for_each(|(_, value)| collect_icc_profiles(value, ids)),
+        _ => {}
+    }
+}
+
+/// Strip ICC profiles from images (replacing them with the equivalent device color space),
+/// or, when preserving them, store identical profiles only once. Stripping is limited to
+/// the `only` images when given.
+pub fn process_icc_profiles(doc: &mut Document, strip: bool, only: Option<&HashSet<ObjectId>>) -> IccStats {
+    if strip {
+        strip_image_icc_profiles(doc, only)
+    } else {
+        deduplicate_icc_profiles(doc)
+    }
+}
+
+fn strip_image_icc_profiles(doc: &mut Document, only: Option<&HashSet<ObjectId>>) -> IccStats {
+    let mut stats = IccStats::default();
+    let profiles = icc_profile_ids(doc);
+    if profiles.is_empty() {
+        return stats;
+    }
+
+    // Indirect color space arrays may become orphaned along with their profiles
+    let mut candidates: HashSet<ObjectId> = profiles.clone();
+    let mut replacements = Vec::new();
+
+    for (id, object) in &doc.objects {
+        let Object::Stream(stream) = object else { continue };
+        if !matches!(stream.dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Image") {
+            continue;
+        }
+        if only.is_some_and(|only| !only.contains(id)) {
+            continue;
+        }
+        let Ok(color_space) = stream.dict.get(b"ColorSpace") else { continue };
+        if let Object::Reference(cs_id) = color_space {
+            candidates.insert(*cs_id);
+        }
+        if let Some(device) = device_equivalent(doc, resolve(doc, color_space)) {
+            replacements.push((*id, device));
+        }
+    }
+
+    for (id, device) in replacements {
+        if let Ok(Object::Stream(stream)) = doc.get_object_mut(id) {
+            stream.dict.set("ColorSpace", device);
+        }
+    }
+
+    // Drop profiles (and color space arrays) nothing refers to anymore
+    loop {
+        let referenced = referenced_ids(doc);
+        let unused: Vec<ObjectId> = candidates.iter().filter(|id| !referenced.contains(id)).copied().collect();
+        if unused.is_empty() {
+            break;
+        }
+        for id in unused {
+            candidates.remove(&id);
+            if let Some(object) = doc.objects.remove(&id) {
+                if profiles.contains(&id) {
+                    stats.profiles_removed += 1;
+                    if let Object::Stream(stream) = object {
+                        stats.bytes_removed += stream.content.len() as u64;
+                    }
+                }
+            }
+        }
+    }
+
+    stats
+}
+
+/// The device color space equivalent to an ICC-based one (also inside `/Indexed`)
+fn device_equivalent(doc: &Document, color_space: &Object) -> Option<Object> {
+    let Object::Array(items) = color_space else { return None };
+    match items.as_slice() {
+        [Object::Name(name), profile] if name == b"ICCBased" => {
+            let Object::Stream(profile) = resolve(doc, profile) else { return None };
+            if let Ok(Object::Name(alternate)) = profile.dict.get(b"Alternate") {
+                return Some(Object::Name(alternate.clone()));
+            }
+            let device: &[u8] = match profile.dict.get(b"N").and_then(Object::as_i64).ok()? {
+                1 => b"DeviceGray",
+                3 => b"DeviceRGB",
+                4 => b"DeviceCMYK",
+                _ => return None,
+            };
+            Some(Object::Name(device.to_vec()))
+        }
+        [Object::Name(name), base, rest @ ..] if name == b"Indexed" => {
+            let base = device_equivalent(doc, resolve(doc, base))?;
+            let mut indexed = vec![Object::Name(b"Indexed".to_vec()), base];
+            indexed.extend(rest.iter().cloned());
+            Some(Object::Array(indexed))
+        }
+        _ => None,
+    }
+}
+
+fn deduplicate_icc_profiles(doc: &mut Document) -> IccStats {
+    let mut stats = IccStats::default();
+    let mut profiles: Vec<ObjectId> = icc_profile_ids(doc).into_iter().collect();
+    profiles.sort();
+
+    let mut canonical: HashMap<(Vec<u8>, Vec<u8>), ObjectId> = HashMap::new();
+    let mut replacements = HashMap::new();
+    for id in profiles {
+        let Ok(Object::Stream(stream)) = doc.get_object(id) else { continue };
+        // Profiles are identical when their content and dictionary (minus /Length) match
+        let dict: Vec<u8> = stream
+            .dict
+            .iter()
+            .filter(|(key, _)| key.as_slice() != b"Length")
+            .flat_map(|(key, value)| format!("{:?}={:?};", key, value).into_bytes())
+            .collect();
+        let key = (dict, stream.content.clone());
+        match canonical.get(&key) {
+            Some(&survivor) => {
+                replacements.insert(id, survivor);
+            }
+            None => {
+                canonical.insert(key, id);
+            }
+        }
+    }
+
+    replace_references(doc, &replacements);
+    for id in replacements.keys() {
+        if let Some(Object::Stream(stream)) = doc.objects.remove(id) {
+            stats.profiles_deduplicated += 1;
+            stats.bytes_removed += stream.content.len() as u64;
+        }
+    }
+
+    stats
+}
+
+/// A compact ICC v2 display profile for sRGB IEC61966-2.1: the standard colorants
+/// adapted to D50 and the sRGB transfer curve sampled at 1024 points
+pub fn srgb_profile() -> Vec<u8> {
+    fn xyz(values: [f64; 3]) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for value in values {
+            tag.extend_from_slice(&((value * 65536.0).round() as i32).to_be_bytes());
+        }
+        tag
+    }
+
+    let mut description = b"desc\0\0\0\0".to_vec();
+    let name = b"sRGB IEC61966-2.1\0";
+    description.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    description.extend_from_slice(name);
+    // No Unicode or ScriptCode description
+    description.extend_from_slice(&[0; 8 + 3 + 67]);
+
+    let mut copyright = b"text\0\0\0\0".to_vec();
+    copyright.extend_from_slice(b"No copyright, use freely\0");
+
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    curve.extend_from_slice(&1024u32.to_be_bytes());
+    for i in 0..1024 {
+        let v = i as f64 / 1023.0;
+        let linear = if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) };
+        curve.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+
+    let tags: [(&[u8; 4], usize); 9] = [
+        (b"desc", 0),
+        (b"cprt", 1),
+        (b"wtpt", 2),
+        (b"rXYZ", 3),
+        (b"gXYZ", 4),
+        (b"bXYZ", 5),
+        // The three channels share one curve
+        (b"rTRC", 6),
+        (b"gTRC", 6),
+        (b"bTRC", 6),
+    ];
+    let data = [
+        description,
+        copyright,
+        xyz([0.9505, 1.0, 1.0891]),
+        xyz([0.4361, 0.2225, 0.0139]),
+        xyz([0.3851, 0.7169, 0.0971]),
+        xyz([0.1431, 0.0606, 0.7141]),
+        curve,
+    ];
+
+    let mut offsets = Vec::new();
+    let mut body = Vec::new();
+    let table_end = 128 + 4 + 12 * tags.len();
+    for element in &data {
+        offsets.push(table_end + body.len());
+        body.extend_from_slice(element);
+        // Tag data starts on a four-byte boundary
+        body.resize(body.len().next_multiple_of(4), 0);
+    }
+
+    let mut profile = Vec::with_capacity(table_end + body.len());
+    profile.extend_from_slice(&((table_end + body.len()) as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]);
+    profile.extend_from_slice(&[2, 0x10, 0, 0]);
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    // Created 2024-01-01 00:00:00, so the output doesn't change from run to run
+    for field in [2024u16, 1, 1, 0, 0, 0] {
+        profile.extend_from_slice(&field.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 28]);
+    // The PCS illuminant, D50
+    profile.extend_from_slice(&xyz([0.9642, 1.0, 0.8249])[8..]);
+    profile.extend_from_slice(&[0; 48]);
+
+    profile.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+    for (signature, element) in tags {
+        profile.extend_from_slice(signature);
+        profile.extend_from_slice(&(offsets[element] as u32).to_be_bytes());
+        profile.extend_from_slice(&(data[element].len() as u32).to_be_bytes());
+    }
+    profile.extend(body);
+    profile
+}
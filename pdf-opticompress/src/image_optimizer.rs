@@ -1,21 +1,80 @@
 use anyhow::{Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
 use image::{DynamicImage, GenericImageView, ImageFormat};
-use lopdf::{Document, Object, Stream};
+use lopdf::{Document, Object, ObjectId, Stream};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::cli::{ChromaSubsampling, JpegBackend, ResizeFilter};
+use crate::optimizer::{check_deadline, Deadline};
+use crate::utils::format_bytes;
 
 /// Image optimization settings
 #[derive(Clone)]
 pub struct ImageSettings {
     pub jpeg_quality: u8, // 0-100
+    pub chroma_subsampling: ChromaSubsampling,
+    pub progressive_jpeg: bool,
+    pub jpeg_encoder: JpegBackend,
     pub enable_png_optimization: bool,
+    pub png_level: u8, // oxipng optimization level, 1-6
+    pub png_strip_metadata: bool, // Drop ancillary chunks that don't affect display
+    pub png_reductions: bool, // Allow bit depth, color type and palette reduction
     pub max_dimension: Option<u32>, // Maximum width/height, None = no limit
+    pub max_dpi: Option<u32>, // Maximum resolution at the size images are drawn on the page
+    pub resize_filter: ResizeFilter,
+    pub min_image_bytes: u64, // Smaller streams are left untouched
+    pub min_image_pixels: u64, // Images with fewer pixels are left untouched
+    pub max_decode_pixels: u64, // Larger images are never decoded (decompression bomb guard)
+    pub max_decode_bytes: u64, // Upper bound on memory for decoded samples
+    pub convert_to_grayscale: bool,
+    pub bilevel: bool, // Threshold text-like images to 1 bit per pixel
+    pub force_bilevel: bool, // Apply bilevel conversion to photos as well
+    pub lossy_conversion: bool, // Allow photographic lossless images to become JPEG
+    pub lossless: bool, // Never re-encode pixels lossily; JPEGs only lose metadata
+    pub min_ssim: Option<f64>, // Reject lossy encodes less similar than this to the source
+    pub decode_cache: Option<Arc<DecodeCache>>, // Reuse decoded pixels across repeated runs
+    pub memory_budget: Option<Arc<MemoryBudget>>, // Shared by the threads of a batch
+    pub strip_icc_profiles: bool, // Replace ICC-based color spaces with device ones
+    pub pages: Option<BTreeSet<u32>>, // Only touch images used on these pages (numbered from 1)
+    pub deadline: Option<Deadline>, // Checked before each image
 }
 
 impl Default for ImageSettings {
     fn default() -> Self {
         Self {
             jpeg_quality: 80,
+            chroma_subsampling: ChromaSubsampling::Yuv420,
+            progressive_jpeg: false,
+            jpeg_encoder: JpegBackend::default(),
             enable_png_optimization: true,
+            png_level: 2,
+            png_strip_metadata: false,
+            png_reductions: true,
             max_dimension: None,
+            max_dpi: None,
+            resize_filter: ResizeFilter::Lanczos3,
+            min_image_bytes: 10 * 1024, // Icons and bullets rarely shrink meaningfully
+            min_image_pixels: 0,
+            max_decode_pixels: 250_000_000,
+            max_decode_bytes: 1 << 30,
+            convert_to_grayscale: false,
+            bilevel: false,
+            force_bilevel: false,
+            lossy_conversion: true,
+            lossless: false,
+            min_ssim: None,
+            decode_cache: None,
+            memory_budget: None,
+            strip_icc_profiles: false,
+            pages: None,
+            deadline: None,
         }
     }
 }
@@ -25,181 +84,1451 @@ pub fn create_image_settings_for_preset(preset: &crate::cli::Preset, quality: u8
     match preset {
         crate::cli::Preset::Web => ImageSettings {
             jpeg_quality: quality,
+            chroma_subsampling: ChromaSubsampling::Yuv420,
+            progressive_jpeg: true, // Renders incrementally and is usually smaller
             enable_png_optimization: true,
+            png_level: 3,
+            png_strip_metadata: true,
+            png_reductions: true,
             max_dimension: Some(1920), // Limit for web viewing
+            strip_icc_profiles: true,
+            ..ImageSettings::default()
         },
         crate::cli::Preset::Print => ImageSettings {
             jpeg_quality: quality.max(85), // Higher quality for print
+            chroma_subsampling: ChromaSubsampling::Yuv444, // Keep full color resolution
+            progressive_jpeg: false,
             enable_png_optimization: true,
+            png_level: 3,
+            png_strip_metadata: true,
+            png_reductions: true,
             max_dimension: None, // No limit for print
+            strip_icc_profiles: false, // Color management matters for print
+            ..ImageSettings::default()
         },
         crate::cli::Preset::Archive => ImageSettings {
             jpeg_quality: quality,
+            chroma_subsampling: ChromaSubsampling::Yuv444,
+            progressive_jpeg: false,
             enable_png_optimization: true,
+            png_level: 2,
+            png_strip_metadata: false,
+            png_reductions: true,
             max_dimension: None,
+            strip_icc_profiles: false,
+            ..ImageSettings::default()
         },
         crate::cli::Preset::Maximum => ImageSettings {
             jpeg_quality: quality.min(70), // More aggressive compression
+            chroma_subsampling: ChromaSubsampling::Yuv420,
+            progressive_jpeg: true,
             enable_png_optimization: true,
+            png_level: 6,
+            png_strip_metadata: true,
+            png_reductions: true,
             max_dimension: Some(1024), // Smaller for maximum compression
+            strip_icc_profiles: true,
+            ..ImageSettings::default()
+        },
+        crate::cli::Preset::Scan => ImageSettings {
+            jpeg_quality: quality.min(70), // Scanner noise isn't worth keeping
+            chroma_subsampling: ChromaSubsampling::Yuv420,
+            progressive_jpeg: true,
+            enable_png_optimization: true,
+            png_level: 4,
+            png_strip_metadata: true,
+            png_reductions: true,
+            max_dimension: None, // Page images are limited by resolution instead
+            max_dpi: Some(200), // Enough for legible text and OCR
+            convert_to_grayscale: true,
+            bilevel: true, // Pages of text become 1-bit; photos stay grayscale
+            strip_icc_profiles: true,
+            ..ImageSettings::default()
         },
     }
 }
 
-/// Optimize images in a PDF document
-pub fn optimize_images_in_pdf(doc: &mut Document, settings: &ImageSettings) -> Result<usize> {
-    let mut optimized_count = 0;
+/// Decoded images keyed by a hash of their encoded data, so repeated runs over the same
+/// document (e.g. the `--target-size` search) only decode each image once
+#[derive(Default)]
+pub struct DecodeCache {
+    images: Mutex<HashMap<u64, DynamicImage>>,
+}
 
-    // Get all objects that might contain images
-    let objects = doc.objects.clone();
+impl DecodeCache {
+    fn get_or_decode<E>(&self, data: &[u8], decode: impl FnOnce() -> Result<DynamicImage, E>) -> Result<DynamicImage, E> {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let key = hasher.finish();
 
-    for (id, obj) in objects {
-        if let Object::Stream(ref stream) = obj {
-            // Check if this is an image
-            if is_image_stream(stream) {
-                if let Some(optimized_stream) = optimize_image_stream(stream, settings)? {
-                    doc.objects.insert(id, Object::Stream(optimized_stream));
-                    optimized_count += 1;
-                }
+        if let Some(img) = self.images.lock().unwrap().get(&key) {
+            return Ok(img.clone());
+        }
+        let img = decode()?;
+        self.images.lock().unwrap().insert(key, img.clone());
+        Ok(img)
+    }
+}
+
+/// Decode through the settings' cache when there is one
+fn decode_cached<E>(
+    settings: &ImageSettings,
+    data: &[u8],
+    decode: impl FnOnce() -> Result<DynamicImage, E>,
+) -> Result<DynamicImage, E> {
+    match &settings.decode_cache {
+        Some(cache) => cache.get_or_decode(data, decode),
+        None => decode(),
+    }
+}
+
+/// Images smaller than this decoded (a megapixel) don't reserve any of the budget; waiting
+/// on each other would cost more than the little memory they take
+const MEMORY_BUDGET_FLOOR: u64 = 4 << 20;
+
+/// Memory for decoded images shared by threads optimizing at once. Each image reserves
+/// `width * height * 4` bytes before it's decoded and waits while that would exceed the
+/// budget, releasing them once re-encoded; an image larger than the whole budget waits until
+/// it has the budget to itself.
+pub struct MemoryBudget {
+    limit: u64,
+    reserved: Mutex<u64>,
+    released: Condvar,
+    peak: AtomicU64, // The most reserved at once
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        MemoryBudget { limit, reserved: Mutex::new(0), released: Condvar::new(), peak: AtomicU64::new(0) }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn peak(&self) -> u64 {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let bytes = bytes.min(self.limit);
+        let mut reserved = self.reserved.lock().unwrap();
+        while *reserved + bytes > self.limit {
+            reserved = self.released.wait(reserved).unwrap();
+        }
+        *reserved += bytes;
+        self.peak.fetch_max(*reserved, Ordering::Relaxed);
+        Reservation { budget: self, bytes }
+    }
+}
+
+/// Part of a `MemoryBudget`, given back when dropped
+struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.reserved.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Reserve the memory decoding the image will take from the settings' budget, when there is
+/// one and the image is large enough to count
+fn reserve_memory<'a>(stream: &Stream, settings: &'a ImageSettings) -> Option<Reservation<'a>> {
+    let budget = settings.memory_budget.as_deref()?;
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64).unwrap_or(0).max(0) as u64;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64).unwrap_or(0).max(0) as u64;
+    let bytes = width.saturating_mul(height).saturating_mul(4);
+    (bytes >= MEMORY_BUDGET_FLOOR).then(|| budget.reserve(bytes))
+}
+
+/// Counters collected while optimizing the images of a document
+#[derive(Debug, Default, Clone)]
+pub struct ImageStats {
+    pub optimized: usize,
+    pub skipped: usize,
+    pub converted_to_grayscale: usize,
+    pub converted_to_bilevel: usize,
+    pub jpeg_metadata_bytes_removed: u64,
+    pub inline_optimized: usize,
+    pub duplicates_merged: usize,
+    pub duplicate_bytes_saved: u64,
+    pub records: Vec<ImageOptimizationRecord>,
+}
+
+/// Where an image lives in the document
+#[derive(Debug, Clone, Copy)]
+pub enum ImageSource {
+    Object(ObjectId),
+    Inline { page: u32 }, // Inline image in a page content stream
+}
+
+impl fmt::Display for ImageSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageSource::Object((number, generation)) => write!(f, "Image {} {}", number, generation),
+            ImageSource::Inline { page } => write!(f, "Inline image (page {})", page),
+        }
+    }
+}
+
+/// What was done to an image
+#[derive(Debug, Clone)]
+pub enum ImageAction {
+    Recompressed,
+    Resized { width: u32, height: u32 },
+    MetadataStripped { reason: String }, // Pixels kept, metadata segments removed
+    Skipped { reason: String },
+}
+
+impl fmt::Display for ImageAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageAction::Recompressed => write!(f, "recompressed"),
+            ImageAction::Resized { width, height } => write!(f, "resized to {}x{}", width, height),
+            ImageAction::MetadataStripped { reason } => {
+                write!(f, "metadata stripped, recompression skipped: {}", reason)
             }
+            ImageAction::Skipped { reason } => write!(f, "skipped: {}", reason),
         }
     }
+}
 
-    Ok(optimized_count)
+/// Per-image report of an optimization run
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ImageOptimizationRecord {
+    #[serde(flatten)]
+    pub source: ImageSource,
+    pub filter: String,
+    pub width: u32,
+    pub height: u32,
+    pub original_size: u64,
+    pub new_size: u64,
+    #[serde(flatten)]
+    pub action: ImageAction,
+    #[serde(serialize_with = "serialize_ssim")]
+    pub ssim: Option<f64>, // Similarity of a checked lossy re-encode to the source
 }
 
-/// Check if a stream contains an image
-fn is_image_stream(stream: &Stream) -> bool {
-    if let Ok(subtype) = stream.dict.get(b"Subtype") {
-        if let lopdf::Object::Name(ref name) = subtype {
-            return name == b"Image";
+/// As `object`, the object number and generation, or as `page`, the page of an inline image;
+/// the other is null
+impl Serialize for ImageSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (object, page) = match *self {
+            ImageSource::Object(id) => (Some(id), None),
+            ImageSource::Inline { page } => (None, Some(page)),
+        };
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("object", &object)?;
+        map.serialize_entry("page", &page)?;
+        map.end()
+    }
+}
+
+/// As `action`, with `resized_to` and `reason` when they apply and null otherwise
+impl Serialize for ImageAction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (action, resized_to, reason) = match self {
+            ImageAction::Recompressed => ("recompressed", None, None),
+            ImageAction::Resized { width, height } => ("resized", Some((width, height)), None),
+            ImageAction::MetadataStripped { reason } => ("metadata_stripped", None, Some(reason)),
+            ImageAction::Skipped { reason } => ("skipped", None, Some(reason)),
+        };
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("action", action)?;
+        map.serialize_entry("resized_to", &resized_to)?;
+        map.serialize_entry("reason", &reason)?;
+        map.end()
+    }
+}
+
+/// To four decimals, as it's printed
+fn serialize_ssim<S: Serializer>(ssim: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    ssim.map(|ssim| (ssim * 1e4).round() / 1e4).serialize(serializer)
+}
+
+impl fmt::Display for ImageOptimizationRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} {}x{}, {} -> {} ({})",
+            self.source,
+            self.filter,
+            self.width,
+            self.height,
+            format_bytes(self.original_size),
+            format_bytes(self.new_size),
+            self.action
+        )?;
+        if let Some(ssim) = self.ssim {
+            write!(f, " SSIM {:.4}", ssim)?;
+        }
+        Ok(())
+    }
+}
+
+/// An image stream after optimization
+pub struct OptimizedImage {
+    pub stream: Stream,
+    pub converted_to_grayscale: bool,
+    pub converted_to_bilevel: bool,
+    pub metadata_bytes_removed: u64,
+    pub recompression_skipped: Option<String>, // Why the pixels were kept as they are
+    pub ssim: Option<f64>, // Measured similarity of a lossy re-encode, when checked
+}
+
+/// What happened to a single image stream
+pub enum ImageOutcome {
+    Optimized(OptimizedImage),
+    Unchanged(String), // Nothing to gain, with the reason
+    Skipped(String), // Deliberately left alone, with the reason
+    Rejected(String), // Exceeds the decoding limits; left alone with a warning
+}
+
+/// How re-encoded pixel data is stored in the image stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageEncoding {
+    Jpeg,
+    Flate,
+    Bilevel,
+}
+
+/// Storage format of an image stream as found in the document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SourceFormat {
+    Jpeg,
+    Png,
+    Raw, // Unfiltered or Flate-compressed 8-bit Gray/RGB samples
+}
+
+/// Pixel data re-encoded for an image stream
+struct EncodedImage {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    encoding: ImageEncoding,
+    converted_to_grayscale: bool,
+    ssim: Option<f64>,
+}
+
+/// Optimize images in a PDF document
+pub fn optimize_images_in_pdf(doc: &mut Document, settings: &ImageSettings) -> Result<ImageStats> {
+    let mut stats = ImageStats::default();
+
+    // Merge identical copies first so each image is only optimized once
+    let dedup = crate::dedup::deduplicate_images(doc);
+    stats.duplicates_merged = dedup.merged;
+    stats.duplicate_bytes_saved = dedup.bytes_saved;
+
+    // Collect just the ids up front; streams are borrowed one at a time so the
+    // object table is never duplicated
+    let selected = selected_images(doc, settings);
+    let image_ids: Vec<ObjectId> = doc
+        .objects
+        .iter()
+        .filter(|(_, obj)| matches!(obj, Object::Stream(stream) if is_image_stream(stream)))
+        .map(|(id, _)| *id)
+        .filter(|id| selected.as_ref().is_none_or(|selected| selected.contains(id)))
+        .collect();
+    let masks = collect_mask_ids(doc);
+    let display_sizes = settings.max_dpi.map(|_| crate::placement::image_display_sizes(doc));
+
+    for id in image_ids {
+        check_deadline(settings.deadline)?;
+        let Some(Object::Stream(stream)) = doc.objects.get(&id) else {
+            continue;
+        };
+        let is_mask = masks.contains(&id) || is_stencil_mask(stream);
+        let outcome = optimize_image_object(id, stream, settings, is_mask, display_sizes.as_ref())?;
+        if let Some(optimized) = record_outcome(&mut stats, ImageSource::Object(id), stream, outcome) {
+            doc.objects.insert(id, Object::Stream(optimized));
+        }
+    }
+
+    // Images embedded directly in page content streams
+    crate::inline_images::optimize_inline_images(doc, settings, &mut stats)?;
+
+    Ok(stats)
+}
+
+/// Optimize one image XObject, limited to `settings.max_dpi` at the largest size
+/// `display_sizes` says it's drawn at
+pub fn optimize_image_object(
+    id: ObjectId,
+    stream: &Stream,
+    settings: &ImageSettings,
+    is_mask: bool,
+    display_sizes: Option<&crate::placement::DisplaySizes>,
+) -> Result<ImageOutcome> {
+    // Masks keep their full resolution so their edges stay exact
+    let resolution_limit = match (display_sizes, settings.max_dpi) {
+        (Some(sizes), Some(dpi)) if !is_mask => sizes.largest(id).and_then(|size| dpi_limit(stream, size, dpi)),
+        _ => None,
+    };
+    match resolution_limit {
+        Some(limit) if settings.max_dimension.is_none_or(|max_dim| limit < max_dim) => {
+            let settings = ImageSettings {
+                max_dimension: Some(limit),
+                ..settings.clone()
+            };
+            optimize_image_stream(stream, &settings, is_mask)
+        }
+        _ => optimize_image_stream(stream, settings, is_mask),
+    }
+}
+
+/// The image XObjects used on the pages `settings` restricts optimization to, or `None`
+/// when every image is optimized. An image shared with other pages is included.
+pub fn selected_images(doc: &Document, settings: &ImageSettings) -> Option<HashSet<ObjectId>> {
+    let pages = settings.pages.as_ref()?;
+    let selected = crate::placement::image_pages(doc)
+        .into_iter()
+        .filter(|(_, used_on)| used_on.iter().any(|page| pages.contains(page)))
+        .map(|(id, _)| id)
+        .collect();
+    Some(selected)
+}
+
+/// Update the statistics and per-image records with the outcome for one image,
+/// returning the replacement stream when there is one
+pub fn record_outcome(
+    stats: &mut ImageStats,
+    source: ImageSource,
+    original: &Stream,
+    outcome: ImageOutcome,
+) -> Option<Stream> {
+    let (width, height) = declared_dimensions(original);
+    let original_size = original.content.len() as u64;
+    let mut record = ImageOptimizationRecord {
+        source,
+        filter: filter_description(original),
+        width,
+        height,
+        original_size,
+        new_size: original_size,
+        action: ImageAction::Recompressed,
+        ssim: None,
+    };
+
+    let replacement = match outcome {
+        ImageOutcome::Optimized(optimized) => {
+            if optimized.converted_to_grayscale {
+                stats.converted_to_grayscale += 1;
+            }
+            if optimized.converted_to_bilevel {
+                stats.converted_to_bilevel += 1;
+            }
+            stats.jpeg_metadata_bytes_removed += optimized.metadata_bytes_removed;
+            match source {
+                ImageSource::Object(_) => stats.optimized += 1,
+                ImageSource::Inline { .. } => stats.inline_optimized += 1,
+            }
+
+            let new_dimensions = declared_dimensions(&optimized.stream);
+            record.new_size = optimized.stream.content.len() as u64;
+            record.ssim = optimized.ssim;
+            record.action = if let Some(reason) = optimized.recompression_skipped {
+                ImageAction::MetadataStripped { reason }
+            } else if new_dimensions != (width, height) {
+                ImageAction::Resized {
+                    width: new_dimensions.0,
+                    height: new_dimensions.1,
+                }
+            } else {
+                ImageAction::Recompressed
+            };
+            Some(optimized.stream)
+        }
+        ImageOutcome::Unchanged(reason) => {
+            record.action = ImageAction::Skipped { reason };
+            None
+        }
+        ImageOutcome::Skipped(reason) => {
+            stats.skipped += 1;
+            record.action = ImageAction::Skipped { reason };
+            None
         }
+        ImageOutcome::Rejected(reason) => {
+            stats.skipped += 1;
+            eprintln!("Warning: {} left untouched: {}", source, reason);
+            record.action = ImageAction::Skipped { reason };
+            None
+        }
+    };
+
+    stats.records.push(record);
+    replacement
+}
+
+/// Width and height from an image dictionary, 0 when missing
+fn declared_dimensions(stream: &Stream) -> (u32, u32) {
+    let dimension = |key: &[u8]| stream.dict.get(key).and_then(Object::as_i64).unwrap_or(0).max(0) as u32;
+    (dimension(b"Width"), dimension(b"Height"))
+}
+
+/// The stream's filters for reports, e.g. `DCTDecode` or `none`
+fn filter_description(stream: &Stream) -> String {
+    let filters = crate::filters::stream_filters(stream);
+    if filters.is_empty() {
+        return "none".to_string();
+    }
+    filters
+        .iter()
+        .map(|filter| String::from_utf8_lossy(filter))
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// Check if a stream contains an image
+pub fn is_image_stream(stream: &Stream) -> bool {
+    if let Ok(Object::Name(name)) = stream.dict.get(b"Subtype") {
+        return name == b"Image";
     }
     false
 }
 
+/// The longest side, in pixels, that keeps an image drawn at `size` points within `dpi`,
+/// or `None` if it already is
+fn dpi_limit(stream: &Stream, size: (f64, f64), dpi: u32) -> Option<u32> {
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64).ok()?;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64).ok()?;
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+    let scale = dpi as f64 / crate::placement::effective_dpi(width, height, size);
+    if scale >= 1.0 {
+        return None;
+    }
+    Some(((width.max(height) as f64 * scale).ceil() as u32).max(1))
+}
+
+/// Check if an image is a 1-bit stencil mask (`/ImageMask true`)
+pub fn is_stencil_mask(stream: &Stream) -> bool {
+    matches!(stream.dict.get(b"ImageMask"), Ok(Object::Boolean(true)))
+}
+
+/// Collect the ids of image streams used as `/SMask` or `/Mask` by other images
+pub fn collect_mask_ids(doc: &Document) -> HashSet<ObjectId> {
+    let mut masks = HashSet::new();
+    for obj in doc.objects.values() {
+        if let Object::Stream(ref stream) = obj {
+            if !is_image_stream(stream) {
+                continue;
+            }
+            for key in [&b"SMask"[..], &b"Mask"[..]] {
+                if let Ok(Object::Reference(id)) = stream.dict.get(key) {
+                    masks.insert(*id);
+                }
+            }
+        }
+    }
+    masks
+}
+
 /// Optimize an image stream
-fn optimize_image_stream(stream: &Stream, settings: &ImageSettings) -> Result<Option<Stream>> {
+pub fn optimize_image_stream(stream: &Stream, settings: &ImageSettings, is_mask: bool) -> Result<ImageOutcome> {
     // Extract image data
     let image_data = &stream.content;
 
-    // Determine image format
-    let format = detect_image_format(stream)?;
+    if let Some(reason) = below_size_threshold(stream, settings) {
+        return Ok(ImageOutcome::Skipped(reason));
+    }
+    if let Some(reason) = exceeds_decode_limits(stream, settings) {
+        return Ok(ImageOutcome::Rejected(reason));
+    }
+
+    // Determine image format; anything we can't decode is left untouched
+    let Some(format) = detect_image_format(stream) else {
+        return Ok(ImageOutcome::Unchanged("unsupported filter or color space".to_string()));
+    };
+    // Held until the image is re-encoded
+    let _reservation = reserve_memory(stream, settings);
 
     match format {
-        ImageFormat::Jpeg => {
-            let optimized = optimize_jpeg_image(image_data, settings)?;
-            Ok(Some(create_optimized_stream(stream, &optimized)))
+        SourceFormat::Jpeg => {
+            // Cheap lossless first pass: drop EXIF, XMP, thumbnails and other metadata
+            let stripped = crate::jpeg::strip_app_segments(image_data, settings.strip_icc_profiles);
+            let source = stripped.as_deref().unwrap_or(image_data);
+            let metadata_bytes_removed = (image_data.len() - source.len()) as u64;
+
+            // Recompressing an already heavily-compressed JPEG loses quality and rarely saves space
+            let reencoded = match recompression_skip_reason(stream, source, settings) {
+                _ if settings.lossless => Err(ImageOutcome::Unchanged("lossless mode".to_string())),
+                Some(reason) => Err(ImageOutcome::Skipped(reason)),
+                None => match optimize_jpeg_image(source, settings, is_mask) {
+                    Ok(Some(encoded)) if is_plain_reencode(stream, &encoded) && encoded.data.len() >= source.len() => {
+                        Err(ImageOutcome::Unchanged("re-encoding would not be smaller".to_string()))
+                    }
+                    Ok(Some(encoded)) => Ok(encoded),
+                    Ok(None) => Err(ImageOutcome::Skipped(ssim_failure_reason(settings))),
+                    Err(err) if is_limit_error(&err) => {
+                        return Ok(ImageOutcome::Rejected(format!("{:#}", err)));
+                    }
+                    Err(err) => return Err(err),
+                },
+            };
+
+            let mut optimized = match reencoded {
+                Ok(encoded) => create_optimized_image(stream, encoded),
+                // Nothing gained at all; report why the image was left alone
+                Err(outcome) if stripped.is_none() => return Ok(outcome),
+                // Keep the original scan data, minus the metadata
+                Err(outcome) => OptimizedImage {
+                    stream: create_optimized_stream(stream, source),
+                    converted_to_grayscale: false,
+                    converted_to_bilevel: false,
+                    metadata_bytes_removed: 0,
+                    recompression_skipped: match outcome {
+                        ImageOutcome::Skipped(reason) | ImageOutcome::Unchanged(reason) => Some(reason),
+                        _ => None,
+                    },
+                    ssim: None,
+                },
+            };
+            optimized.metadata_bytes_removed = metadata_bytes_removed;
+            Ok(ImageOutcome::Optimized(optimized))
         }
-        ImageFormat::Png => {
+        SourceFormat::Png => {
             if settings.enable_png_optimization {
                 let optimized = optimize_png_image(image_data, settings)?;
-                Ok(Some(create_optimized_stream(stream, &optimized)))
+                Ok(ImageOutcome::Optimized(OptimizedImage {
+                    stream: create_optimized_stream(stream, &optimized),
+                    converted_to_grayscale: false,
+                    converted_to_bilevel: false,
+                    metadata_bytes_removed: 0,
+                    recompression_skipped: None,
+                    ssim: None,
+                }))
             } else {
-                Ok(None) // No optimization needed
+                Ok(ImageOutcome::Unchanged("PNG optimization disabled".to_string()))
             }
         }
-        _ => {
-            // For other formats, try to convert to JPEG
-            let optimized = convert_and_optimize_image(image_data, format, settings)?;
-            Ok(Some(create_optimized_stream(stream, &optimized)))
+        SourceFormat::Raw => {
+            // Lossless samples: keep them lossless unless JPEG is clearly better
+            let encoded = convert_and_optimize_image(stream, settings, is_mask)?;
+            Ok(match encoded {
+                Some(encoded) => ImageOutcome::Optimized(create_optimized_image(stream, encoded)),
+                None => ImageOutcome::Unchanged("no smaller encoding found".to_string()),
+            })
+        }
+    }
+}
+
+/// Report images too small to be worth optimizing
+fn below_size_threshold(stream: &Stream, settings: &ImageSettings) -> Option<String> {
+    let bytes = stream.content.len() as u64;
+    if bytes < settings.min_image_bytes {
+        return Some(format!("{} bytes is below the minimum image size", bytes));
+    }
+
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64).unwrap_or(0).max(0) as u64;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64).unwrap_or(0).max(0) as u64;
+    if width.saturating_mul(height) < settings.min_image_pixels {
+        return Some(format!("{}x{} is below the minimum pixel count", width, height));
+    }
+    None
+}
+
+/// Check the declared dimensions against the decoding limits before touching the data
+fn exceeds_decode_limits(stream: &Stream, settings: &ImageSettings) -> Option<String> {
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64).unwrap_or(0).max(0) as u64;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64).unwrap_or(0).max(0) as u64;
+    let pixels = width.saturating_mul(height);
+    if pixels > settings.max_decode_pixels {
+        return Some(format!(
+            "declared size {}x{} exceeds the limit of {} pixels",
+            width, height, settings.max_decode_pixels
+        ));
+    }
+
+    // Decoded images are held as 8-bit samples with up to 4 components
+    let components = match stream.dict.get(b"ColorSpace").and_then(Object::as_name) {
+        Ok(b"DeviceGray") => 1,
+        Ok(b"DeviceRGB") => 3,
+        _ => 4,
+    };
+    let bytes = pixels.saturating_mul(components);
+    if bytes > settings.max_decode_bytes {
+        return Some(format!(
+            "decoding would need {}, more than the limit of {}",
+            crate::utils::format_bytes(bytes),
+            crate::utils::format_bytes(settings.max_decode_bytes)
+        ));
+    }
+    None
+}
+
+/// Whether an error comes from the image crate refusing to exceed its limits
+fn is_limit_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|cause| matches!(cause.downcast_ref::<image::ImageError>(), Some(image::ImageError::Limits(_))))
+}
+
+/// Decide whether a JPEG should be left as it is because its estimated quality is already
+/// at or below the target and nothing else (resizing, color conversion) requires re-encoding
+fn recompression_skip_reason(stream: &Stream, data: &[u8], settings: &ImageSettings) -> Option<String> {
+    if settings.convert_to_grayscale || settings.bilevel {
+        return None;
+    }
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64).ok()?;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64).ok()?;
+    if let Some(max_dim) = settings.max_dimension {
+        if width.max(height) > max_dim as i64 {
+            return None;
         }
     }
+
+    let source_quality = crate::jpeg::estimate_quality(data)?;
+    (source_quality <= settings.jpeg_quality).then(|| {
+        format!("source quality ≈ {} ≤ target {}", source_quality, settings.jpeg_quality)
+    })
 }
 
 /// Detect image format from stream dictionary
-fn detect_image_format(stream: &Stream) -> Result<ImageFormat> {
+fn detect_image_format(stream: &Stream) -> Option<SourceFormat> {
+    let filters = crate::filters::stream_filters(stream);
+
     // Check filter
-    if let Ok(filter) = stream.dict.get(b"Filter") {
-        if let lopdf::Object::Name(ref name) = filter {
-            match name.as_slice() {
-                b"DCTDecode" => return Ok(ImageFormat::Jpeg),
-                b"FlateDecode" => {
-                    // Could be PNG or other, check for PNG signature
-                    if stream.content.starts_with(b"\x89PNG") {
-                        return Ok(ImageFormat::Png);
-                    }
-                }
-                _ => {}
-            }
+    match filters.as_slice() {
+        [filter] if filter == b"DCTDecode" => return Some(SourceFormat::Jpeg),
+        // Could be PNG or other, check for PNG signature
+        [filter] if filter == b"FlateDecode" && stream.content.starts_with(b"\x89PNG") => {
+            return Some(SourceFormat::Png);
         }
+        [] => {}
+        [filter] if filter == b"FlateDecode" => {}
+        _ => return None,
     }
 
     // Check for PNG signature in content
     if stream.content.starts_with(b"\x89PNG") {
-        return Ok(ImageFormat::Png);
+        return Some(SourceFormat::Png);
     }
 
-    // Default to JPEG for DCTDecode or unknown
-    Ok(ImageFormat::Jpeg)
+    // Raw samples we know how to interpret
+    let bits = stream.dict.get(b"BitsPerComponent").and_then(Object::as_i64).ok();
+    let color_space = stream.dict.get(b"ColorSpace").and_then(Object::as_name).ok();
+    match (bits, color_space) {
+        (Some(8), Some(b"DeviceRGB" | b"DeviceGray")) if !is_stencil_mask(stream) => Some(SourceFormat::Raw),
+        _ => None,
+    }
+}
+
+/// Whether a re-encoded JPEG only differs from the original in compression, so the
+/// original can be kept instead (same size, same color space, still JPEG)
+fn is_plain_reencode(original: &Stream, encoded: &EncodedImage) -> bool {
+    let width = original.dict.get(b"Width").and_then(Object::as_i64).ok();
+    let height = original.dict.get(b"Height").and_then(Object::as_i64).ok();
+    encoded.encoding == ImageEncoding::Jpeg
+        && !encoded.converted_to_grayscale
+        && width == Some(encoded.width as i64)
+        && height == Some(encoded.height as i64)
 }
 
 /// Optimize JPEG image
-fn optimize_jpeg_image(data: &[u8], settings: &ImageSettings) -> Result<Vec<u8>> {
-    let img = image::load_from_memory_with_format(data, ImageFormat::Jpeg)
-        .context("Failed to load JPEG image")?;
+///
+/// Returns `None` when no JPEG quality meets the minimum SSIM.
+fn optimize_jpeg_image(data: &[u8], settings: &ImageSettings, is_mask: bool) -> Result<Option<EncodedImage>> {
+    let img = decode_cached(settings, data, || decode_jpeg(data, settings)).context("Failed to load JPEG image")?;
 
     // Resize if needed
     let img = resize_image_if_needed(img, settings);
+    // Decoding and encoding a huge image each take long on their own
+    check_deadline(settings.deadline)?;
 
     // Re-encode with specified quality
-    let mut output = Vec::new();
-    img.write_to(&mut std::io::Cursor::new(&mut output), ImageFormat::Jpeg)
-        .context("Failed to encode JPEG")?;
+    encode_image(img, settings, is_mask).context("Failed to encode JPEG")
+}
 
-    Ok(output)
+/// Decode a JPEG, letting the decoder enforce the configured limits on the real header values
+fn decode_jpeg(data: &[u8], settings: &ImageSettings) -> image::ImageResult<DynamicImage> {
+    let mut limits = image::io::Limits::default();
+    limits.max_alloc = Some(settings.max_decode_bytes);
+    let side = settings.max_decode_pixels.min(u32::MAX as u64) as u32;
+    limits.max_image_width = Some(side);
+    limits.max_image_height = Some(side);
+
+    let mut reader = image::io::Reader::with_format(std::io::Cursor::new(data), ImageFormat::Jpeg);
+    reader.limits(limits);
+    let img = reader.decode()?;
+
+    let (width, height) = img.dimensions();
+    if width as u64 * height as u64 > settings.max_decode_pixels {
+        return Err(image::ImageError::Limits(image::error::LimitError::from_kind(
+            image::error::LimitErrorKind::DimensionError,
+        )));
+    }
+    Ok(img)
 }
 
 /// Optimize PNG image using oxipng
-fn optimize_png_image(data: &[u8], _settings: &ImageSettings) -> Result<Vec<u8>> {
-    use oxipng::{optimize_from_memory, Options};
-
-    let options = Options::default();
-    optimize_from_memory(data, &options)
+fn optimize_png_image(data: &[u8], settings: &ImageSettings) -> Result<Vec<u8>> {
+    oxipng::optimize_from_memory(data, &png_options(settings))
         .context("Failed to optimize PNG with oxipng")
 }
 
-/// Convert and optimize other image formats
-fn convert_and_optimize_image(data: &[u8], format: ImageFormat, settings: &ImageSettings) -> Result<Vec<u8>> {
-    let img = image::load_from_memory_with_format(data, format)
-        .context("Failed to load image")?;
+/// Build the oxipng options for the configured level, stripping and reductions
+fn png_options(settings: &ImageSettings) -> oxipng::Options {
+    let mut options = oxipng::Options::from_preset(settings.png_level.clamp(1, 6));
+
+    options.strip = if settings.png_strip_metadata {
+        oxipng::StripChunks::Safe
+    } else {
+        oxipng::StripChunks::None
+    };
+
+    options.bit_depth_reduction = settings.png_reductions;
+    options.color_type_reduction = settings.png_reductions;
+    options.palette_reduction = settings.png_reductions;
+    options.grayscale_reduction = settings.png_reductions;
+
+    options
+}
+
+/// Decode raw samples, then store them as Flate, bilevel or (for photos) JPEG
+///
+/// Returns `None` when no representation beats the original stream.
+fn convert_and_optimize_image(stream: &Stream, settings: &ImageSettings, is_mask: bool) -> Result<Option<EncodedImage>> {
+    let img = decode_cached(settings, &stream.content, || decode_raw_image(stream)).context("Failed to load image")?;
+    let original_dimensions = img.dimensions();
 
     // Resize if needed
     let img = resize_image_if_needed(img, settings);
+    let resized = img.dimensions() != original_dimensions;
+    check_deadline(settings.deadline)?;
+
+    let encoded = encode_lossless_image(img, settings, is_mask).context("Failed to encode image")?;
+    if !resized && encoded.data.len() >= stream.content.len() {
+        return Ok(None);
+    }
+    Ok(Some(encoded))
+}
+
+/// Build an image from the raw samples of an 8-bit DeviceGray or DeviceRGB stream
+fn decode_raw_image(stream: &Stream) -> Result<DynamicImage> {
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64)? as u32;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64)? as u32;
+    let gray = stream.dict.get(b"ColorSpace").and_then(Object::as_name)? == b"DeviceGray";
+
+    let mut samples = crate::filters::decode_stream(stream)?;
+    let expected = width as usize * height as usize * if gray { 1 } else { 3 };
+    if samples.len() < expected {
+        anyhow::bail!("image data is truncated ({} of {} bytes)", samples.len(), expected);
+    }
+    samples.truncate(expected);
+
+    let img = if gray {
+        image::GrayImage::from_raw(width, height, samples).map(DynamicImage::ImageLuma8)
+    } else {
+        image::RgbImage::from_raw(width, height, samples).map(DynamicImage::ImageRgb8)
+    };
+    img.context("invalid image dimensions")
+}
+
+/// Encode lossless source pixels, switching to JPEG only for photographic content
+fn encode_lossless_image(img: DynamicImage, settings: &ImageSettings, is_mask: bool) -> Result<EncodedImage> {
+    let (width, height) = img.dimensions();
+
+    if let Some(bilevel) = encode_bilevel_if_suitable(&img, settings, is_mask)? {
+        return Ok(bilevel);
+    }
+
+    let (img, converted_to_grayscale) =
+        convert_to_grayscale_if_needed(img, settings.convert_to_grayscale && !is_mask);
+    let lossless = crate::filters::flate_encode(img.as_bytes(), 9)?;
+
+    if settings.lossy_conversion && !is_mask && is_photographic(&img) {
+        // Only give up losslessness for a substantial saving (and acceptable fidelity)
+        if let Some(jpeg) = encode_jpeg_checked(&img, settings)? {
+            if jpeg.data.len() * 2 <= lossless.len() {
+                return Ok(EncodedImage {
+                    data: jpeg.data,
+                    width,
+                    height,
+                    encoding: ImageEncoding::Jpeg,
+                    converted_to_grayscale,
+                    ssim: jpeg.ssim,
+                });
+            }
+        }
+    }
 
-    // Convert to JPEG
+    Ok(EncodedImage {
+        data: lossless,
+        width,
+        height,
+        encoding: ImageEncoding::Flate,
+        converted_to_grayscale,
+        ssim: None,
+    })
+}
+
+/// Judge whether an image is photographic: screenshots and diagrams use few distinct colors
+fn is_photographic(img: &DynamicImage) -> bool {
+    const MAX_FLAT_COLORS: usize = 256;
+
+    let mut colors = HashSet::new();
+    match img {
+        DynamicImage::ImageLuma8(gray) => {
+            for pixel in gray.pixels() {
+                colors.insert([pixel.0[0]; 3]);
+                if colors.len() > MAX_FLAT_COLORS {
+                    return true;
+                }
+            }
+        }
+        _ => {
+            for pixel in img.to_rgb8().pixels() {
+                colors.insert(pixel.0);
+                if colors.len() > MAX_FLAT_COLORS {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Encode decoded JPEG pixels as bilevel Flate or JPEG depending on the settings and content
+fn encode_image(img: DynamicImage, settings: &ImageSettings, is_mask: bool) -> Result<Option<EncodedImage>> {
+    let (width, height) = img.dimensions();
+
+    if let Some(bilevel) = encode_bilevel_if_suitable(&img, settings, is_mask)? {
+        return Ok(Some(bilevel));
+    }
+
+    let (img, converted_to_grayscale) =
+        convert_to_grayscale_if_needed(img, settings.convert_to_grayscale && !is_mask);
+    let Some(jpeg) = encode_jpeg_checked(&img, settings)? else {
+        return Ok(None);
+    };
+    Ok(Some(EncodedImage {
+        data: jpeg.data,
+        width,
+        height,
+        encoding: ImageEncoding::Jpeg,
+        converted_to_grayscale,
+        ssim: jpeg.ssim,
+    }))
+}
+
+/// A JPEG encoding with its measured similarity to the source pixels
+struct CheckedJpeg {
+    data: Vec<u8>,
+    ssim: Option<f64>,
+}
+
+/// Encode as JPEG. With a minimum SSIM configured, decode the result again and raise the
+/// quality until it is similar enough to the source; `None` if even quality 100 falls short.
+fn encode_jpeg_checked(img: &DynamicImage, settings: &ImageSettings) -> Result<Option<CheckedJpeg>> {
+    let Some(min_ssim) = settings.min_ssim else {
+        return Ok(Some(CheckedJpeg {
+            data: encode_jpeg(img, settings)?,
+            ssim: None,
+        }));
+    };
+
+    let reference = img.to_luma8();
+    let mut attempt = settings.clone();
+    loop {
+        let data = encode_jpeg(img, &attempt)?;
+        let decoded = image::load_from_memory_with_format(&data, ImageFormat::Jpeg)?.to_luma8();
+        let ssim = crate::quality::ssim(&reference, &decoded);
+        if ssim >= min_ssim {
+            return Ok(Some(CheckedJpeg { data, ssim: Some(ssim) }));
+        }
+        if attempt.jpeg_quality >= 100 {
+            return Ok(None);
+        }
+        attempt.jpeg_quality = (attempt.jpeg_quality + 10).min(100);
+    }
+}
+
+/// Why an image was kept when no JPEG quality met the similarity requirement
+fn ssim_failure_reason(settings: &ImageSettings) -> String {
+    format!("no JPEG quality reaches the minimum SSIM of {}", settings.min_ssim.unwrap_or(0.0))
+}
+
+/// Encode an image as JPEG with the configured backend, falling back to the built-in encoder
+fn encode_jpeg(img: &DynamicImage, settings: &ImageSettings) -> Result<Vec<u8>> {
+    #[cfg(feature = "mozjpeg")]
+    if settings.jpeg_encoder == JpegBackend::Mozjpeg {
+        match encode_jpeg_mozjpeg(img, settings) {
+            Ok(output) => return Ok(output),
+            Err(e) => eprintln!("Warning: mozjpeg encoding failed, using built-in encoder: {}", e),
+        }
+    }
+
+    encode_jpeg_builtin(img, settings)
+}
+
+/// Encode an image with mozjpeg (trellis quantization, optimized scans)
+#[cfg(feature = "mozjpeg")]
+fn encode_jpeg_mozjpeg(img: &DynamicImage, settings: &ImageSettings) -> Result<Vec<u8>> {
+    use mozjpeg::{ColorSpace, Compress};
+
+    let (width, height) = img.dimensions();
+    let (color_space, pixels) = match img {
+        DynamicImage::ImageLuma8(gray) => (ColorSpace::JCS_GRAYSCALE, gray.as_raw().clone()),
+        _ => (ColorSpace::JCS_RGB, img.to_rgb8().into_raw()),
+    };
+    let chroma = match settings.chroma_subsampling {
+        ChromaSubsampling::Yuv444 => (1, 1),
+        ChromaSubsampling::Yuv422 => (2, 1),
+        ChromaSubsampling::Yuv420 => (2, 2),
+    };
+    let quality = settings.jpeg_quality.clamp(1, 100) as f32;
+    let progressive = settings.progressive_jpeg;
+
+    // mozjpeg reports libjpeg errors by unwinding, so contain them here
+    std::panic::catch_unwind(move || -> std::io::Result<Vec<u8>> {
+        let mut compress = Compress::new(color_space);
+        compress.set_size(width as usize, height as usize);
+        compress.set_quality(quality);
+        if color_space == ColorSpace::JCS_RGB {
+            compress.set_chroma_sampling_pixel_sizes(chroma, chroma);
+        }
+        if progressive {
+            compress.set_progressive_mode();
+        }
+        let mut started = compress.start_compress(Vec::new())?;
+        started.write_scanlines(&pixels)?;
+        started.finish()
+    })
+    .map_err(|_| anyhow::anyhow!("mozjpeg panicked while encoding"))?
+    .context("mozjpeg failed to encode image")
+}
+
+/// Encode an image as JPEG at the configured quality, chroma subsampling and scan mode
+fn encode_jpeg_builtin(img: &DynamicImage, settings: &ImageSettings) -> Result<Vec<u8>> {
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    let (width, height) = img.dimensions();
     let mut output = Vec::new();
-    img.write_to(&mut std::io::Cursor::new(&mut output), ImageFormat::Jpeg)
-        .context("Failed to encode image as JPEG")?;
+
+    // jpeg-encoder is limited to 16-bit dimensions; fall back to the image crate's
+    // baseline encoder beyond that
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        let mut encoder = JpegEncoder::new_with_quality(&mut output, settings.jpeg_quality);
+        encoder.encode_image(img)?;
+        return Ok(output);
+    };
+
+    let mut encoder = Encoder::new(&mut output, settings.jpeg_quality.clamp(1, 100));
+    encoder.set_sampling_factor(match settings.chroma_subsampling {
+        ChromaSubsampling::Yuv444 => SamplingFactor::F_1_1,
+        ChromaSubsampling::Yuv422 => SamplingFactor::F_2_1,
+        ChromaSubsampling::Yuv420 => SamplingFactor::F_2_2,
+    });
+    if settings.progressive_jpeg {
+        // Progressive scans only pay off with Huffman tables tuned to the image
+        encoder.set_progressive(true);
+        encoder.set_optimized_huffman_tables(true);
+    }
+
+    match img {
+        DynamicImage::ImageLuma8(gray) => encoder.encode(gray.as_raw(), width, height, ColorType::Luma)?,
+        _ => encoder.encode(img.to_rgb8().as_raw(), width, height, ColorType::Rgb)?,
+    }
 
     Ok(output)
 }
 
+/// Convert a color image to 8-bit luma; images that are already grayscale are left alone
+fn convert_to_grayscale_if_needed(img: DynamicImage, grayscale: bool) -> (DynamicImage, bool) {
+    if !grayscale || img.color().channel_count() < 3 {
+        return (img, false);
+    }
+    (DynamicImage::ImageLuma8(img.to_luma8()), true)
+}
+
+/// Whether an image stream looks like scanned text, so bilevel conversion would apply to
+/// it; `None` if it can't be decoded
+pub fn is_text_like_image(stream: &Stream) -> Option<bool> {
+    let settings = ImageSettings::default();
+    if exceeds_decode_limits(stream, &settings).is_some() {
+        return None;
+    }
+    let img = match detect_image_format(stream)? {
+        SourceFormat::Jpeg => decode_jpeg(&stream.content, &settings).ok()?,
+        SourceFormat::Raw => decode_raw_image(stream).ok()?,
+        SourceFormat::Png => return None,
+    };
+    Some(is_text_like(&img))
+}
+
+/// Judge whether an image looks like scanned text: nearly no mid-tones and almost no color
+fn is_text_like(img: &DynamicImage) -> bool {
+    let gray = img.to_luma8();
+    let total = gray.len() as f64;
+    if total == 0.0 {
+        return false;
+    }
+
+    let midtones = gray.pixels().filter(|p| (48..=208).contains(&p.0[0])).count() as f64;
+    if midtones / total > 0.1 {
+        return false;
+    }
+
+    if img.color().channel_count() >= 3 {
+        let colorful = img
+            .to_rgb8()
+            .pixels()
+            .filter(|p| {
+                let max = p.0.iter().max().copied().unwrap_or(0);
+                let min = p.0.iter().min().copied().unwrap_or(0);
+                max - min > 48
+            })
+            .count() as f64;
+        if colorful / total > 0.05 {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Compute a global threshold separating foreground from background (Otsu's method)
+fn otsu_threshold(gray: &image::GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let total = gray.len() as f64;
+    let weighted_sum: f64 = histogram.iter().enumerate().map(|(i, &n)| i as f64 * n as f64).sum();
+
+    let mut background_weight = 0.0;
+    let mut background_sum = 0.0;
+    let mut best_threshold = 128u8;
+    let mut best_variance = 0.0;
+
+    for (i, &count) in histogram.iter().enumerate() {
+        background_weight += count as f64;
+        if background_weight == 0.0 {
+            continue;
+        }
+        let foreground_weight = total - background_weight;
+        if foreground_weight == 0.0 {
+            break;
+        }
+
+        background_sum += i as f64 * count as f64;
+        let background_mean = background_sum / background_weight;
+        let foreground_mean = (weighted_sum - background_sum) / foreground_weight;
+        let variance = background_weight * foreground_weight * (background_mean - foreground_mean).powi(2);
+
+        if variance > best_variance {
+            best_variance = variance;
+            best_threshold = i as u8;
+        }
+    }
+
+    best_threshold
+}
+
+/// Encode as bilevel when enabled and the image is text-like (or bilevel is forced)
+fn encode_bilevel_if_suitable(img: &DynamicImage, settings: &ImageSettings, is_mask: bool) -> Result<Option<EncodedImage>> {
+    // Masks must keep their exact pixel layout, only the base image is converted
+    if is_mask || !settings.bilevel || !(settings.force_bilevel || is_text_like(img)) {
+        return Ok(None);
+    }
+
+    let (width, height) = img.dimensions();
+    Ok(Some(EncodedImage {
+        data: encode_bilevel(img)?,
+        width,
+        height,
+        encoding: ImageEncoding::Bilevel,
+        converted_to_grayscale: false,
+        ssim: None,
+    }))
+}
+
+/// Threshold an image to 1 bit per pixel (1 = white) and Flate-compress the packed rows
+fn encode_bilevel(img: &DynamicImage) -> Result<Vec<u8>> {
+    let gray = img.to_luma8();
+    let threshold = otsu_threshold(&gray);
+    let (width, height) = gray.dimensions();
+    let row_bytes = (width as usize).div_ceil(8);
+
+    let mut packed = vec![0u8; row_bytes * height as usize];
+    for (x, y, pixel) in gray.enumerate_pixels() {
+        if pixel.0[0] > threshold {
+            packed[y as usize * row_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+        }
+    }
+
+    crate::filters::flate_encode(&packed, 9)
+}
+
 /// Resize image if it exceeds maximum dimensions
 fn resize_image_if_needed(img: DynamicImage, settings: &ImageSettings) -> DynamicImage {
     if let Some(max_dim) = settings.max_dimension {
         let (width, height) = img.dimensions();
         if width > max_dim || height > max_dim {
-            let aspect_ratio = width as f32 / height as f32;
-            let (new_width, new_height) = if width > height {
-                (max_dim, (max_dim as f32 / aspect_ratio) as u32)
-            } else {
-                ((max_dim as f32 * aspect_ratio) as u32, max_dim)
-            };
-
-            return img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3);
+            let (new_width, new_height) = fit_within(width, height, max_dim);
+            return img.resize_exact(new_width, new_height, filter_type(settings.resize_filter));
         }
     }
     img
 }
 
+/// The size of a `width` by `height` image scaled down so neither side exceeds `max_dim`,
+/// keeping the aspect ratio; a side that would round to nothing keeps a pixel
+fn fit_within(width: u32, height: u32, max_dim: u32) -> (u32, u32) {
+    let max_dim = max_dim.max(1);
+    let scale = max_dim as f64 / width.max(height) as f64;
+    let scaled = |side: u32| ((side as f64 * scale) as u32).clamp(1, max_dim);
+    (scaled(width), scaled(height))
+}
+
+/// The image crate filter for a resize filter choice
+fn filter_type(filter: ResizeFilter) -> FilterType {
+    match filter {
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        ResizeFilter::CatmullRom => FilterType::CatmullRom,
+        ResizeFilter::Triangle => FilterType::Triangle,
+        ResizeFilter::Nearest => FilterType::Nearest,
+    }
+}
+
 /// Create an optimized stream with new content
 fn create_optimized_stream(original: &Stream, new_content: &[u8]) -> Stream {
-    let mut new_stream = original.clone();
-    new_stream.content = new_content.to_vec();
+    // Copy only the dictionary; cloning the whole stream would duplicate the old data.
+    // `Stream::new` replaces a `/Length` reference with the direct new length.
+    let mut new_stream = Stream::new(original.dict.clone(), new_content.to_vec());
+    new_stream.allows_compression = original.allows_compression;
+    new_stream
+}
 
-    // Update length in dictionary
-    new_stream.dict.set("Length", new_content.len() as i64);
+/// Create an optimized image stream, rewriting the dictionary to describe the new encoding
+fn create_optimized_image(original: &Stream, encoded: EncodedImage) -> OptimizedImage {
+    let mut stream = create_optimized_stream(original, &encoded.data);
+    stream.dict.set("Width", encoded.width as i64);
+    stream.dict.set("Height", encoded.height as i64);
 
-    new_stream
-}
\ No newline at end of file
+    let (filter, bits): (&[u8], i64) = match encoded.encoding {
+        ImageEncoding::Jpeg => (b"DCTDecode", 8),
+        ImageEncoding::Flate => (b"FlateDecode", 8),
+        ImageEncoding::Bilevel => (b"FlateDecode", 1),
+    };
+    stream.dict.set("Filter", Object::Name(filter.to_vec()));
+    stream.dict.set("BitsPerComponent", bits);
+    stream.dict.remove(b"DecodeParms");
+
+    let converted_to_bilevel = encoded.encoding == ImageEncoding::Bilevel;
+
+    if converted_to_bilevel || encoded.converted_to_grayscale {
+        stream.dict.set("ColorSpace", Object::Name(b"DeviceGray".to_vec()));
+        // A decode array sized for the old component count no longer applies
+        stream.dict.remove(b"Decode");
+    }
+
+    OptimizedImage {
+        stream,
+        converted_to_grayscale: encoded.converted_to_grayscale,
+        converted_to_bilevel,
+        metadata_bytes_removed: 0,
+        recompression_skipped: None,
+        ssim: encoded.ssim,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    #[test]
+    fn huge_dimensions_are_not_below_the_pixel_threshold() {
+        let stream = Stream::new(dictionary! {"Width" => i64::MAX, "Height" => i64::MAX}, vec![0; 1 << 16]);
+        let settings = ImageSettings { min_image_bytes: 0, ..ImageSettings::default() };
+        assert_eq!(below_size_threshold(&stream, &settings), None);
+    }
+
+    /// A photo-like image: smooth gradients with some noise
+    fn photo(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+            let noise = ((x * 7919 + y * 104729) % 31) as u8;
+            image::Rgb([(x % 256) as u8 ^ noise, (y % 256) as u8, ((x + y) % 256) as u8])
+        }))
+    }
+
+    /// The start of frame marker of a JPEG and each component's sampling factors
+    fn frame_header(jpeg: &[u8]) -> (u8, Vec<(u8, u8)>) {
+        let mut pos = 2;
+        loop {
+            let marker = jpeg[pos + 1];
+            let length = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+            if (0xC0..=0xC2).contains(&marker) {
+                let segment = &jpeg[pos + 4..pos + 2 + length];
+                let factors = segment[6..].chunks(3).map(|component| (component[1] >> 4, component[1] & 0x0F)).collect();
+                return (marker, factors);
+            }
+            pos += 2 + length;
+        }
+    }
+
+    #[test]
+    fn chroma_subsampling_is_written_to_the_frame_header() {
+        let img = photo(320, 240);
+        let encode = |chroma_subsampling| {
+            let settings = ImageSettings { chroma_subsampling, ..ImageSettings::default() };
+            encode_jpeg(&img, &settings).unwrap()
+        };
+        let full = encode(ChromaSubsampling::Yuv444);
+        let half = encode(ChromaSubsampling::Yuv422);
+        let quarter = encode(ChromaSubsampling::Yuv420);
+        // Luma is sampled relative to chroma, which keeps one sample per block
+        assert_eq!(frame_header(&full).1, [(1, 1), (1, 1), (1, 1)]);
+        assert_eq!(frame_header(&half).1, [(2, 1), (1, 1), (1, 1)]);
+        assert_eq!(frame_header(&quarter).1, [(2, 2), (1, 1), (1, 1)]);
+        assert!(quarter.len() < full.len(), "4:2:0 is {} bytes, 4:4:4 {}", quarter.len(), full.len());
+    }
+
+    #[test]
+    fn progressive_scans_are_marked_in_the_frame_header() {
+        let img = photo(320, 240);
+        let encode = |progressive_jpeg| {
+            let settings = ImageSettings { progressive_jpeg, ..ImageSettings::default() };
+            encode_jpeg(&img, &settings).unwrap()
+        };
+        // SOF2 for a progressive frame, SOF0 for a baseline one
+        assert_eq!(frame_header(&encode(true)).0, 0xC2);
+        assert_eq!(frame_header(&encode(false)).0, 0xC0);
+        let preset = |preset| create_image_settings_for_preset(&preset, 80).progressive_jpeg;
+        assert!(preset(crate::cli::Preset::Web) && preset(crate::cli::Preset::Maximum));
+        assert!(!preset(crate::cli::Preset::Print));
+    }
+
+    /// An uncompressed DeviceRGB image stream holding `img`
+    fn raw_stream(img: &DynamicImage) -> Stream {
+        let dict = dictionary! {
+            "Type" => "XObject", "Subtype" => "Image",
+            "Width" => img.width() as i64, "Height" => img.height() as i64,
+            "ColorSpace" => "DeviceRGB", "BitsPerComponent" => 8,
+        };
+        Stream::new(dict, img.to_rgb8().into_raw())
+    }
+
+    fn filter_after(stream: &Stream, settings: &ImageSettings) -> Vec<u8> {
+        match optimize_image_stream(stream, settings, false).unwrap() {
+            ImageOutcome::Optimized(optimized) => optimized.stream.dict.get(b"Filter").unwrap().as_name().unwrap().to_vec(),
+            _ => panic!("the image was not optimized"),
+        }
+    }
+
+    #[test]
+    fn diagrams_stay_lossless_and_photos_become_jpeg() {
+        // Flat boxes in three colors on white
+        let diagram = DynamicImage::ImageRgb8(image::RgbImage::from_fn(400, 300, |x, y| match (x / 100, y / 100) {
+            (0, 0) | (2, 1) => image::Rgb([200, 30, 30]),
+            (1, 2) | (3, 0) => image::Rgb([30, 30, 200]),
+            _ if x % 100 == 0 || y % 100 == 0 => image::Rgb([0, 0, 0]),
+            _ => image::Rgb([255, 255, 255]),
+        }));
+        let settings = ImageSettings::default();
+        assert_eq!(filter_after(&raw_stream(&diagram), &settings), b"FlateDecode");
+        assert_eq!(filter_after(&raw_stream(&photo(400, 300)), &settings), b"DCTDecode");
+
+        // Unless lossless images must stay lossless
+        let settings = ImageSettings { lossy_conversion: false, ..ImageSettings::default() };
+        assert_eq!(filter_after(&raw_stream(&photo(400, 300)), &settings), b"FlateDecode");
+    }
+
+    #[test]
+    fn scaled_sides_keep_at_least_a_pixel() {
+        assert_eq!(fit_within(4000, 3000, 1000), (1000, 750));
+        assert_eq!(fit_within(3000, 4000, 1000), (750, 1000));
+        // A side scaled below a pixel would be an empty image
+        assert_eq!(fit_within(100_000, 1, 1000), (1000, 1));
+        assert_eq!(fit_within(1, 100_000, 1000), (1, 1000));
+        assert_eq!(fit_within(u32::MAX, 3, 1024), (1024, 1));
+        assert_eq!(fit_within(u32::MAX, u32::MAX, 1024), (1024, 1024));
+        assert_eq!(fit_within(5, 3, 0), (1, 1));
+
+        let settings = ImageSettings { max_dimension: Some(64), ..ImageSettings::default() };
+        let resized = resize_image_if_needed(DynamicImage::new_rgb8(20_000, 2), &settings);
+        assert_eq!(resized.dimensions(), (64, 1));
+        let resized = resize_image_if_needed(DynamicImage::new_luma8(3, 50_000), &settings);
+        assert_eq!(resized.dimensions(), (1, 64));
+    }
+
+    #[test]
+    fn encodes_below_the_minimum_ssim_are_rejected() {
+        let img = photo(320, 240);
+        let coarse = ImageSettings { jpeg_quality: 5, ..ImageSettings::default() };
+        let plain = encode_jpeg(&img, &coarse).unwrap();
+        let reference = img.to_luma8();
+        let plain_ssim = crate::quality::ssim(&reference, &image::load_from_memory(&plain).unwrap().to_luma8());
+        assert!(plain_ssim < 0.95, "quality 5 already has an SSIM of {}", plain_ssim);
+
+        // The quality is raised until the encode is similar enough
+        let settings = ImageSettings { min_ssim: Some(0.95), ..coarse.clone() };
+        let checked = encode_jpeg_checked(&img, &settings).unwrap().unwrap();
+        assert!(checked.ssim.unwrap() >= 0.95);
+        assert!(checked.data.len() > plain.len());
+
+        // And the original image is kept when no quality is
+        let mut stream = raw_stream(&img);
+        stream.dict.set("Filter", "DCTDecode");
+        stream.set_content(encode_jpeg(&img, &ImageSettings { jpeg_quality: 95, ..ImageSettings::default() }).unwrap());
+        let settings = ImageSettings { min_ssim: Some(1.0), ..coarse };
+        match optimize_image_stream(&stream, &settings, false).unwrap() {
+            ImageOutcome::Skipped(reason) => assert_eq!(reason, "no JPEG quality reaches the minimum SSIM of 1"),
+            _ => panic!("an encode below the minimum SSIM was kept"),
+        }
+    }
+
+    fn image_of(width: i64, height: i64) -> Stream {
+        Stream::new(dictionary! {"Width" => width, "Height" => height}, Vec::new())
+    }
+
+    #[test]
+    fn concurrent_reservations_stay_within_the_budget() {
+        let budget = Arc::new(MemoryBudget::new(40 << 20));
+        let settings = ImageSettings { memory_budget: Some(budget.clone()), ..ImageSettings::default() };
+        // 16 MB each, so two fit at once but not three
+        let image = image_of(2048, 2048);
+        let held = AtomicU64::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..4 {
+                        let reservation = reserve_memory(&image, &settings).unwrap();
+                        let now = held.fetch_add(reservation.bytes, Ordering::SeqCst) + reservation.bytes;
+                        assert!(now <= budget.limit(), "{} bytes held at once", now);
+                        std::thread::sleep(std::time::Duration::from_millis(2));
+                        held.fetch_sub(reservation.bytes, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+        assert!(budget.peak() <= budget.limit(), "peak {} over the limit", budget.peak());
+        assert_eq!(budget.peak(), 32 << 20);
+        assert_eq!(*budget.reserved.lock().unwrap(), 0);
+
+        // An image larger than the whole budget waits to have it to itself
+        let reservation = reserve_memory(&image_of(10_000, 10_000), &settings).unwrap();
+        assert_eq!(reservation.bytes, budget.limit());
+        drop(reservation);
+        assert!(budget.peak() <= budget.limit());
+    }
+
+    #[test]
+    fn small_images_bypass_the_budget() {
+        let budget = Arc::new(MemoryBudget::new(1 << 20));
+        let settings = ImageSettings { memory_budget: Some(budget.clone()), ..ImageSettings::default() };
+        // Just under the floor, and missing dimensions
+        let held: Vec<_> = (0..16).map(|_| reserve_memory(&image_of(1023, 1024), &settings)).collect();
+        assert!(held.iter().all(Option::is_none));
+        assert!(reserve_memory(&Stream::new(dictionary! {}, Vec::new()), &settings).is_none());
+        assert_eq!(budget.peak(), 0);
+        // Without a budget nothing is reserved at all
+        assert!(reserve_memory(&image_of(10_000, 10_000), &ImageSettings::default()).is_none());
+    }
+}
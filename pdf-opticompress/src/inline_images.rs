@@ -0,0 +1,679 @@
+use anyhow::Result;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::image_optimizer::{optimize_image_stream, record_outcome, ImageOutcome, ImageSettings, ImageSource, ImageStats};
+
+/// Optimized inline images larger than this are moved into image XObjects
+/// (the PDF spec recommends inline images only up to 4 KB)
+const HOIST_THRESHOLD: usize = 4096;
+
+/// An inline image (`BI ... ID ... EI`) found in a content stream
+pub struct InlineImage {
+    pub span: Range<usize>, // From `BI` to just past `EI`
+    pub dict: Dictionary,   // Abbreviated keys and values expanded to their full names
+    pub data: Range<usize>,
+}
+
+/// Optimize the inline images of every page's content streams
+pub fn optimize_inline_images(doc: &mut Document, settings: &ImageSettings, stats: &mut ImageStats) -> Result<()> {
+    let mut hoisted: HashMap<ObjectId, Vec<(String, ObjectId)>> = HashMap::new();
+
+    let pages = doc.get_pages();
+    for (&page_number, &page_id) in &pages {
+        if settings.pages.as_ref().is_some_and(|selected| !selected.contains(&page_number)) {
+            continue;
+        }
+        for content_id in doc.get_page_contents(page_id) {
+            if let Entry::Vacant(entry) = hoisted.entry(content_id) {
+                entry.insert(optimize_content_stream(doc, content_id, page_number, settings, stats)?);
+            }
+        }
+    }
+    // Content streams may be shared, so every page using one needs the resources, including
+    // pages that weren't selected
+    for &page_id in pages.values() {
+        for content_id in doc.get_page_contents(page_id) {
+            let Some(images) = hoisted.get(&content_id).cloned() else { continue };
+            for (name, image_id) in images {
+                add_xobject_resource(doc, page_id, &name, image_id)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrite the inline images of one content stream, returning the XObjects it now uses
+fn optimize_content_stream(
+    doc: &mut Document,
+    content_id: ObjectId,
+    page_number: u32,
+    settings: &ImageSettings,
+    stats: &mut ImageStats,
+) -> Result<Vec<(String, ObjectId)>> {
+    let Ok(Object::Stream(stream)) = doc.get_object(content_id) else {
+        return Ok(Vec::new());
+    };
+    let Ok(content) = crate::filters::decode_stream(stream) else {
+        return Ok(Vec::new()); // Filters we can't undo
+    };
+    let images = find_inline_images(&content);
+    if images.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut output = Vec::with_capacity(content.len());
+    let mut hoisted = Vec::new();
+    let mut last = 0;
+    for image in images {
+        let mut dict = image.dict.clone();
+        dict.set("Type", Object::Name(b"XObject".to_vec()));
+        dict.set("Subtype", Object::Name(b"Image".to_vec()));
+        let is_mask = matches!(dict.get(b"ImageMask"), Ok(Object::Boolean(true)));
+        let original = Stream::new(dict, content[image.data.clone()].to_vec());
+
+        // Only rewrite the content stream when the image actually shrinks
+        let outcome = match optimize_image_stream(&original, settings, is_mask)? {
+            ImageOutcome::Optimized(optimized) if optimized.stream.content.len() >= image.data.len() => {
+                ImageOutcome::Unchanged("re-encoding would not be smaller".to_string())
+            }
+            outcome => outcome,
+        };
+        let source = ImageSource::Inline { page: page_number };
+        let Some(optimized) = record_outcome(stats, source, &original, outcome) else {
+            continue;
+        };
+
+        output.extend_from_slice(&content[last..image.span.start]);
+        if optimized.content.len() > HOIST_THRESHOLD {
+            let image_id = doc.add_object(optimized);
+            let name = format!("OptInl{}", image_id.0);
+            output.extend_from_slice(format!("/{} Do", name).as_bytes());
+            hoisted.push((name, image_id));
+        } else {
+            write_inline_image(&mut output, &optimized);
+        }
+        last = image.span.end;
+    }
+    output.extend_from_slice(&content[last..]);
+
+    if last > 0 {
+        let Ok(Object::Stream(original)) = doc.get_object(content_id) else {
+            return Ok(hoisted);
+        };
+        let mut stream = Stream::new(original.dict.clone(), Vec::new());
+        stream.dict.remove(b"DecodeParms");
+        stream.dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+        stream.set_content(crate::filters::flate_encode(&output, 9)?);
+        doc.objects.insert(content_id, Object::Stream(stream));
+    }
+    Ok(hoisted)
+}
+
+/// Register an image XObject under `name` in a page's resources
+fn add_xobject_resource(doc: &mut Document, page_id: ObjectId, name: &str, image_id: ObjectId) -> Result<()> {
+    // Inherited resources are copied onto the page so the addition stays local to it
+    let page = doc.get_dictionary(page_id)?;
+    let resources_id = match page.get(b"Resources") {
+        Ok(Object::Reference(id)) => Some(*id),
+        Ok(Object::Dictionary(_)) => None,
+        _ => {
+            let inherited = inherited_resources(doc, page_id).unwrap_or_default();
+            doc.get_dictionary_mut(page_id)?.set("Resources", inherited);
+            None
+        }
+    };
+    let resources = match resources_id {
+        Some(id) => doc.get_dictionary_mut(id)?,
+        None => doc.get_dictionary_mut(page_id)?.get_mut(b"Resources")?.as_dict_mut()?,
+    };
+
+    let xobjects_id = match resources.get(b"XObject") {
+        Ok(Object::Reference(id)) => Some(*id),
+        Ok(Object::Dictionary(_)) => None,
+        _ => {
+            resources.set("XObject", Dictionary::new());
+            None
+        }
+    };
+    let xobjects = match xobjects_id {
+        Some(id) => doc.get_dictionary_mut(id)?,
+        None => resources.get_mut(b"XObject")?.as_dict_mut()?,
+    };
+    xobjects.set(name, image_id);
+    Ok(())
+}
+
+/// A page's resources: its own, or else those it inherits from the page tree
+pub fn page_resources(doc: &Document, page_id: ObjectId) -> Option<Dictionary> {
+    match doc.get_dictionary(page_id).ok()?.get(b"Resources") {
+        Ok(resources) => crate::objects::resolve(doc, resources).as_dict().ok().cloned(),
+        Err(_) => inherited_resources(doc, page_id),
+    }
+}
+
+/// The resources a page inherits from its ancestors in the page tree
+pub fn inherited_resources(doc: &Document, page_id: ObjectId) -> Option<Dictionary> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    loop {
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+        if let Ok(resources) = node.get(b"Resources") {
+            return crate::objects::resolve(doc, resources).as_dict().ok().cloned();
+        }
+    }
+}
+
+/// Count the inline images in the content streams of all pages
+pub fn count_inline_images(doc: &Document) -> usize {
+    let mut count = 0;
+    for (_, page_id) in doc.get_pages() {
+        for content_id in doc.get_page_contents(page_id) {
+            if let Ok(Object::Stream(stream)) = doc.get_object(content_id) {
+                if let Ok(content) = crate::filters::decode_stream(stream) {
+                    count += find_inline_images(&content).len();
+                }
+            }
+        }
+    }
+    count
+}
+
+/// Locate the inline images in decoded content stream data
+pub fn find_inline_images(content: &[u8]) -> Vec<InlineImage> {
+    let mut images = Vec::new();
+    let mut pos = 0;
+
+    while pos < content.len() {
+        match content[pos] {
+            b'%' => skip_comment(content, &mut pos),
+            b'(' => skip_literal_string(content, &mut pos),
+            b'<' if content.get(pos + 1) != Some(&b'<') => skip_hex_string(content, &mut pos),
+            b'/' => {
+                pos += 1;
+                read_token(content, &mut pos);
+            }
+            c if is_regular(c) => {
+                let start = pos;
+                let token = read_token(content, &mut pos);
+                if token == b"BI" {
+                    match parse_inline_image(content, start, &mut pos) {
+                        Some(image) => images.push(image),
+                        None => break, // Malformed; don't guess where the content resumes
+                    }
+                }
+            }
+            _ => pos += 1,
+        }
+    }
+
+    images
+}
+
+/// Walk the operators of decoded content stream data, passing each one with its operands.
+/// Inline images are skipped whole; returns false if the content couldn't be parsed
+pub fn for_each_operation(content: &[u8], mut visit: impl FnMut(&[u8], &[Object])) -> bool {
+    let mut operands = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        skip_whitespace(content, &mut pos);
+        let Some(&c) = content.get(pos) else { return true };
+        if is_regular(c) && !c.is_ascii_digit() && !matches!(c, b'+' | b'-' | b'.') {
+            let start = pos;
+            match read_token(content, &mut pos) {
+                b"true" => operands.push(Object::Boolean(true)),
+                b"false" => operands.push(Object::Boolean(false)),
+                b"null" => operands.push(Object::Null),
+                b"BI" => {
+                    if parse_inline_image(content, start, &mut pos).is_none() {
+                        return false;
+                    }
+                    operands.clear();
+                }
+                operator => {
+                    visit(operator, &operands);
+                    operands.clear();
+                }
+            }
+            continue;
+        }
+        match parse_object(content, &mut pos) {
+            Some(operand) => operands.push(operand),
+            None => return false,
+        }
+    }
+}
+
+/// One operator of a content stream with the source text of its operands
+pub struct RawOperation<'a> {
+    pub operator: &'a [u8],
+    pub operands: Vec<&'a [u8]>,
+}
+
+/// Split decoded content stream data into operations, keeping the source text of every
+/// operand. An inline image becomes a `BI` operation whose single operand is the whole
+/// `BI ... EI` text. Comments are dropped; returns `None` if the content couldn't be parsed
+/// or ends with operands that no operator uses.
+pub fn split_operations(content: &[u8]) -> Option<Vec<RawOperation<'_>>> {
+    let mut operations = Vec::new();
+    let mut operands = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        skip_whitespace(content, &mut pos);
+        let Some(&c) = content.get(pos) else {
+            return operands.is_empty().then_some(operations);
+        };
+        let start = pos;
+        if is_regular(c) && !c.is_ascii_digit() && !matches!(c, b'+' | b'-' | b'.') {
+            match read_token(content, &mut pos) {
+                b"true" | b"false" | b"null" => operands.push(&content[start..pos]),
+                b"BI" => {
+                    if !operands.is_empty() {
+                        return None;
+                    }
+                    let image = parse_inline_image(content, start, &mut pos)?;
+                    operations.push(RawOperation {
+                        operator: b"BI",
+                        operands: vec![&content[image.span]],
+                    });
+                }
+                operator => operations.push(RawOperation {
+                    operator,
+                    operands: std::mem::take(&mut operands),
+                }),
+            }
+            continue;
+        }
+        parse_object(content, &mut pos)?;
+        operands.push(&content[start..pos]);
+    }
+}
+
+fn parse_inline_image(content: &[u8], start: usize, pos: &mut usize) -> Option<InlineImage> {
+    let mut dict = Dictionary::new();
+    loop {
+        skip_whitespace(content, pos);
+        if content.get(*pos..*pos + 2) == Some(b"ID") && !content.get(*pos + 2).copied().is_some_and(is_regular) {
+            *pos += 2;
+            break;
+        }
+        let Object::Name(key) = parse_object(content, pos)? else {
+            return None;
+        };
+        skip_whitespace(content, pos);
+        let value = parse_object(content, pos)?;
+        dict.set(expand_key(&key), expand_value(value));
+    }
+
+    // A single whitespace byte separates `ID` from the data
+    *pos += 1;
+    let data_start = *pos;
+    let data_end = match data_length(&dict) {
+        Some(length) if ends_with_ei(content, data_start + length) => data_start + length,
+        _ => find_ei(content, data_start)?,
+    };
+
+    let mut end = data_end;
+    skip_spaces(content, &mut end);
+    *pos = end + 2;
+    Some(InlineImage {
+        span: start..end + 2,
+        dict,
+        data: data_start..data_end,
+    })
+}
+
+/// The data length, when it's declared or can be derived from unfiltered samples
+fn data_length(dict: &Dictionary) -> Option<usize> {
+    if let Ok(length) = dict.get(b"Length").and_then(Object::as_i64) {
+        return Some(length as usize);
+    }
+    if dict.has(b"Filter") {
+        return None;
+    }
+
+    let int = |key: &[u8]| dict.get(key).and_then(Object::as_i64).ok();
+    let image_mask = matches!(dict.get(b"ImageMask"), Ok(Object::Boolean(true)));
+    let bits = if image_mask { 1 } else { int(b"BitsPerComponent")? };
+    let components = match dict.get(b"ColorSpace") {
+        _ if image_mask => 1,
+        Ok(Object::Name(name)) if name == b"DeviceGray" => 1,
+        Ok(Object::Name(name)) if name == b"DeviceRGB" => 3,
+        Ok(Object::Name(name)) if name == b"DeviceCMYK" => 4,
+        Ok(Object::Array(items)) if matches!(items.first(), Some(Object::Name(n)) if n == b"Indexed") => 1,
+        _ => return None,
+    };
+    let row = (int(b"Width")? * components * bits + 7) / 8;
+    Some((row * int(b"Height")?) as usize)
+}
+
+/// Whether `EI` (after optional whitespace) follows at `pos`
+fn ends_with_ei(content: &[u8], mut pos: usize) -> bool {
+    skip_spaces(content, &mut pos);
+    content.get(pos..pos + 2) == Some(b"EI") && !content.get(pos + 2).copied().is_some_and(is_regular)
+}
+
+/// Search for the first whitespace-delimited `EI` after the data start,
+/// returning the offset of the whitespace that ends the data
+fn find_ei(content: &[u8], from: usize) -> Option<usize> {
+    (from + 1..content.len().saturating_sub(1))
+        .find(|&i| is_whitespace(content[i - 1]) && ends_with_ei(content, i))
+        .map(|i| i - 1)
+}
+
+/// Serialize an image stream as an inline image, using the abbreviated names
+fn write_inline_image(output: &mut Vec<u8>, stream: &Stream) {
+    output.extend_from_slice(b"BI");
+    for (key, value) in stream.dict.iter() {
+        if matches!(key.as_slice(), b"Type" | b"Subtype" | b"Length") {
+            continue;
+        }
+        output.push(b' ');
+        write_object(output, &Object::Name(abbreviate_key(key).to_vec()));
+        output.push(b' ');
+        write_object(output, &abbreviate_value(value.clone()));
+    }
+    output.extend_from_slice(format!(" /L {} ID ", stream.content.len()).as_bytes());
+    output.extend_from_slice(&stream.content);
+    output.extend_from_slice(b"\nEI");
+}
+
+const KEY_ABBREVIATIONS: &[(&[u8], &[u8])] = &[
+    (b"BPC", b"BitsPerComponent"),
+    (b"CS", b"ColorSpace"),
+    (b"D", b"Decode"),
+    (b"DP", b"DecodeParms"),
+    (b"F", b"Filter"),
+    (b"H", b"Height"),
+    (b"IM", b"ImageMask"),
+    (b"I", b"Interpolate"),
+    (b"L", b"Length"),
+    (b"W", b"Width"),
+];
+
+const VALUE_ABBREVIATIONS: &[(&[u8], &[u8])] = &[
+    (b"G", b"DeviceGray"),
+    (b"RGB", b"DeviceRGB"),
+    (b"CMYK", b"DeviceCMYK"),
+    (b"I", b"Indexed"),
+    (b"AHx", b"ASCIIHexDecode"),
+    (b"A85", b"ASCII85Decode"),
+    (b"LZW", b"LZWDecode"),
+    (b"Fl", b"FlateDecode"),
+    (b"RL", b"RunLengthDecode"),
+    (b"CCF", b"CCITTFaxDecode"),
+    (b"DCT", b"DCTDecode"),
+];
+
+fn expand_key(key: &[u8]) -> Vec<u8> {
+    KEY_ABBREVIATIONS
+        .iter()
+        .find(|(short, _)| *short == key)
+        .map_or(key, |(_, full)| full)
+        .to_vec()
+}
+
+fn abbreviate_key(key: &[u8]) -> &[u8] {
+    KEY_ABBREVIATIONS.iter().find(|(_, full)| *full == key).map_or(key, |(short, _)| short)
+}
+
+fn expand_value(value: Object) -> Object {
+    map_names(value, |name| VALUE_ABBREVIATIONS.iter().find(|(short, _)| *short == name).map(|(_, full)| *full))
+}
+
+fn abbreviate_value(value: Object) -> Object {
+    map_names(value, |name| VALUE_ABBREVIATIONS.iter().find(|(_, full)| *full == name).map(|(short, _)| *short))
+}
+
+/// Rename names in a value and (for color space and filter arrays) its elements
+fn map_names(value: Object, rename: impl Fn(&[u8]) -> Option<&'static [u8]> + Copy) -> Object {
+    match value {
+        Object::Name(name) => Object::Name(rename(&name).map_or(name, <[u8]>::to_vec)),
+        Object::Array(items) => Object::Array(items.into_iter().map(|item| map_names(item, rename)).collect()),
+        other => other,
+    }
+}
+
+/// Parse a direct object of the kinds allowed in inline image dictionaries
+fn parse_object(content: &[u8], pos: &mut usize) -> Option<Object> {
+    skip_whitespace(content, pos);
+    match *content.get(*pos)? {
+        b'/' => {
+            *pos += 1;
+            Some(Object::Name(decode_name(read_token(content, pos))))
+        }
+        b'[' => {
+            *pos += 1;
+            let mut items = Vec::new();
+            loop {
+                skip_whitespace(content, pos);
+                if *content.get(*pos)? == b']' {
+                    *pos += 1;
+                    return Some(Object::Array(items));
+                }
+                items.push(parse_object(content, pos)?);
+            }
+        }
+        b'<' if content.get(*pos + 1) == Some(&b'<') => {
+            *pos += 2;
+            let mut dict = Dictionary::new();
+            loop {
+                skip_whitespace(content, pos);
+                if content.get(*pos..*pos + 2)? == b">>" {
+                    *pos += 2;
+                    return Some(Object::Dictionary(dict));
+                }
+                let Object::Name(key) = parse_object(content, pos)? else {
+                    return None;
+                };
+                let value = parse_object(content, pos)?;
+                dict.set(key, value);
+            }
+        }
+        b'<' => {
+            let start = *pos + 1;
+            skip_hex_string(content, pos);
+            let hex: Vec<u8> = content[start..*pos - 1].iter().copied().filter(u8::is_ascii_hexdigit).collect();
+            let bytes = hex
+                .chunks(2)
+                .map(|pair| {
+                    let digit = |c: u8| (c as char).to_digit(16).unwrap_or(0) as u8;
+                    digit(pair[0]) << 4 | pair.get(1).map_or(0, |&c| digit(c))
+                })
+                .collect();
+            Some(Object::String(bytes, lopdf::StringFormat::Hexadecimal))
+        }
+        b'(' => {
+            let start = *pos + 1;
+            skip_literal_string(content, pos);
+            Some(Object::String(unescape_literal(&content[start..*pos - 1]), lopdf::StringFormat::Literal))
+        }
+        _ => {
+            let token = read_token(content, pos);
+            match token {
+                b"true" => Some(Object::Boolean(true)),
+                b"false" => Some(Object::Boolean(false)),
+                b"null" => Some(Object::Null),
+                _ => {
+                    let text = std::str::from_utf8(token).ok()?;
+                    if let Ok(int) = text.parse::<i64>() {
+                        Some(Object::Integer(int))
+                    } else {
+                        text.parse::<f32>().ok().map(Object::Real)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Write the object kinds produced by `parse_object` in PDF syntax
+fn write_object(output: &mut Vec<u8>, object: &Object) {
+    match object {
+        Object::Name(name) => {
+            output.push(b'/');
+            for &byte in name {
+                if is_regular(byte) && byte.is_ascii_graphic() && byte != b'#' {
+                    output.push(byte);
+                } else {
+                    output.extend_from_slice(format!("#{:02X}", byte).as_bytes());
+                }
+            }
+        }
+        Object::Array(items) => {
+            output.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    output.push(b' ');
+                }
+                write_object(output, item);
+            }
+            output.push(b']');
+        }
+        Object::Dictionary(dict) => {
+            output.extend_from_slice(b"<<");
+            for (key, value) in dict.iter() {
+                write_object(output, &Object::Name(key.clone()));
+                output.push(b' ');
+                write_object(output, value);
+            }
+            output.extend_from_slice(b">>");
+        }
+        Object::String(bytes, _) => {
+            output.push(b'<');
+            for byte in bytes {
+                output.extend_from_slice(format!("{:02X}", byte).as_bytes());
+            }
+            output.push(b'>');
+        }
+        Object::Boolean(value) => output.extend_from_slice(value.to_string().as_bytes()),
+        Object::Integer(value) => output.extend_from_slice(value.to_string().as_bytes()),
+        Object::Real(value) => output.extend_from_slice(value.to_string().as_bytes()),
+        _ => output.extend_from_slice(b"null"),
+    }
+}
+
+/// Resolve the backslash escapes of a literal string
+fn unescape_literal(raw: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] != b'\\' || i + 1 == raw.len() {
+            bytes.push(raw[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match raw[i] {
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b't' => bytes.push(b'\t'),
+            b'b' => bytes.push(0x08),
+            b'f' => bytes.push(0x0C),
+            b'\r' | b'\n' => {} // Line continuation
+            b'0'..=b'7' => {
+                let digits = raw[i..].iter().take(3).take_while(|c| (b'0'..=b'7').contains(c)).count();
+                let value = raw[i..i + digits].iter().fold(0u32, |acc, &c| acc * 8 + (c - b'0') as u32);
+                bytes.push(value as u8);
+                i += digits;
+                continue;
+            }
+            other => bytes.push(other),
+        }
+        i += 1;
+    }
+    bytes
+}
+
+/// Resolve `#xx` escapes in a name
+fn decode_name(raw: &[u8]) -> Vec<u8> {
+    let mut name = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        let escaped = (raw[i] == b'#')
+            .then(|| raw.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                name.push(byte);
+                i += 3;
+            }
+            None => {
+                name.push(raw[i]);
+                i += 1;
+            }
+        }
+    }
+    name
+}
+
+fn is_whitespace(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\r' | b'\n' | b'\x0C' | b'\0')
+}
+
+fn is_regular(c: u8) -> bool {
+    !is_whitespace(c) && !b"()<>[]{}/%".contains(&c)
+}
+
+fn skip_whitespace(content: &[u8], pos: &mut usize) {
+    while *pos < content.len() {
+        match content[*pos] {
+            b'%' => skip_comment(content, pos),
+            c if is_whitespace(c) => *pos += 1,
+            _ => break,
+        }
+    }
+}
+
+/// Skip whitespace only; comments can't appear around inline image data
+fn skip_spaces(content: &[u8], pos: &mut usize) {
+    while *pos < content.len() && is_whitespace(content[*pos]) {
+        *pos += 1;
+    }
+}
+
+fn skip_comment(content: &[u8], pos: &mut usize) {
+    while *pos < content.len() && !matches!(content[*pos], b'\r' | b'\n') {
+        *pos += 1;
+    }
+}
+
+fn skip_literal_string(content: &[u8], pos: &mut usize) {
+    let mut depth = 0;
+    while *pos < content.len() {
+        match content[*pos] {
+            b'\\' => *pos += 1,
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    *pos += 1;
+                    return;
+                }
+            }
+            _ => {}
+        }
+        *pos += 1;
+    }
+}
+
+fn skip_hex_string(content: &[u8], pos: &mut usize) {
+    while *pos < content.len() && content[*pos] != b'>' {
+        *pos += 1;
+    }
+    *pos = (*pos + 1).min(content.len());
+}
+
+fn read_token<'a>(content: &'a [u8], pos: &mut usize) -> &'a [u8] {
+    let start = *pos;
+    while *pos < content.len() && is_regular(content[*pos]) {
+        *pos += 1;
+    }
+    &content[start..*pos]
+}
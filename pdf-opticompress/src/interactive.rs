@@ -0,0 +1,306 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, Input, Select};
+use std::ffi::OsString;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli::Preset;
+use crate::config::Config;
+
+const ACTIONS: [&str; 4] = ["Optimize a PDF", "Analyze a PDF", "Optimize several PDFs (batch)", "Quit"];
+
+/// Ask what to do until told to quit, running each action as the command it amounts to, so
+/// that it's checked and carried out as on the command line. A failed action is reported and
+/// the menu shown again.
+pub fn run(sniff: bool, config: &Config) -> Result<()> {
+    let prompter = Prompter::new();
+    println!("Interactive mode for pdf-opticompress");
+    loop {
+        println!();
+        let args = match prompter.select("What do you want to do?", &ACTIONS, 0)? {
+            Some(0) => optimize_args(&prompter, config, sniff),
+            Some(1) => analyze_args(&prompter, sniff),
+            Some(2) => batch_args(&prompter, config, sniff),
+            _ => return Ok(()),
+        };
+        let outcome = args.and_then(|mut args| {
+            if !sniff {
+                args.push("--no-sniff".into());
+            }
+            let command = config.try_parse_from(args)?.command.context("No command to run")?;
+            crate::run(command, sniff, config)
+        });
+        if let Err(e) = outcome {
+            eprintln!("Error: {:#}", e);
+        }
+    }
+}
+
+fn optimize_args(prompter: &Prompter, config: &Config, sniff: bool) -> Result<Vec<OsString>> {
+    let input = prompter.input("Input PDF (URL or local path)", None, |text| check_input(text, sniff))?;
+    let default_output = Path::new(&input).with_extension("optimized.pdf");
+    let default_output = (!crate::utils::is_url(&input)).then(|| default_output.display().to_string());
+    let (output, force) = loop {
+        let output = prompter.input("Output PDF", default_output.clone(), parse_output)?;
+        if !output.exists() {
+            break (output, false);
+        }
+        if prompter.confirm(&format!("{} already exists. Replace it?", output.display()), false)? {
+            break (output, true);
+        }
+    };
+    let (preset, quality) = settings(prompter, config)?;
+    let mut args = command_args("optimize", &preset, quality);
+    args.extend([input.into(), output.into()]);
+    if force {
+        args.push("--force".into());
+    }
+    Ok(args)
+}
+
+fn analyze_args(prompter: &Prompter, sniff: bool) -> Result<Vec<OsString>> {
+    let input = prompter.input("Input PDF (URL or local path)", None, |text| check_input(text, sniff))?;
+    Ok(vec!["pdf-opticompress".into(), "analyze".into(), input.into()])
+}
+
+fn batch_args(prompter: &Prompter, config: &Config, sniff: bool) -> Result<Vec<OsString>> {
+    let files = prompter.input("Input PDFs (URLs or local paths, space separated)", None, |text| parse_inputs(text, sniff))?;
+    let output_dir = prompter.input("Output directory (empty to write each output next to its input)", Some(String::new()), |text| {
+        Ok::<_, String>(Some(PathBuf::from(text.trim())).filter(|directory| !directory.as_os_str().is_empty()))
+    })?;
+    let (preset, quality) = settings(prompter, config)?;
+    let threads = prompter.input("Threads", default(config, "threads"), parse_threads)?;
+
+    let naming = crate::batch::Naming { template: None, preset: &preset, quality };
+    let outputs = crate::batch::output_paths(&files, &vec![None; files.len()], output_dir.as_deref(), false, &naming)?;
+    let existing = outputs.iter().filter(|output| output.exists()).count();
+    let overwrite = existing > 0
+        && prompter.confirm(&format!("{} of the outputs already exist. Replace them (or else keep them)?", existing), false)?;
+
+    let mut args = command_args("batch", &preset, quality);
+    args.extend(files.into_iter().map(PathBuf::into_os_string));
+    if let Some(directory) = output_dir {
+        args.extend(["--output-dir".into(), directory.into_os_string()]);
+    }
+    args.push(format!("--threads={}", threads).into());
+    match (existing, overwrite) {
+        (0, _) => {}
+        (_, true) => args.push("--overwrite".into()),
+        (_, false) => args.push("--skip-existing".into()),
+    }
+    Ok(args)
+}
+
+/// The preset, from a menu, and the quality, offering those the commands default to
+fn settings(prompter: &Prompter, config: &Config) -> Result<(Preset, u8)> {
+    let presets = Preset::value_variants();
+    let names: Vec<String> = presets.iter().map(preset_name).collect();
+    let preset = default(config, "preset").and_then(|preset| names.iter().position(|name| *name == preset)).unwrap_or(0);
+    let preset = match prompter.select("Preset", &names, preset)? {
+        Some(index) => presets[index],
+        None => anyhow::bail!("No preset chosen"),
+    };
+    let quality = prompter.input("Quality (0-100)", default(config, "quality"), parse_quality)?;
+    Ok((preset, quality))
+}
+
+/// What the commands take for a key when no flag sets it
+fn default(config: &Config, key: &str) -> Option<String> {
+    config.effective(key).and_then(|(value, _)| value.into_iter().next())
+}
+
+fn preset_name(preset: &Preset) -> String {
+    preset.to_possible_value().map_or(String::new(), |value| value.get_name().to_string())
+}
+
+fn command_args(command: &str, preset: &Preset, quality: u8) -> Vec<OsString> {
+    vec![
+        "pdf-opticompress".into(),
+        command.into(),
+        format!("--preset={}", preset_name(preset)).into(),
+        format!("--quality={}", quality).into(),
+    ]
+}
+
+/// A URL, or a file that can be optimized; returned as given
+fn check_input(text: &str, sniff: bool) -> Result<String, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("Give a file or a URL".to_string());
+    }
+    if !crate::utils::is_url(text) {
+        crate::utils::validate_input_file(Path::new(text), sniff).map_err(|e| e.to_string())?;
+    }
+    Ok(text.to_string())
+}
+
+/// Whitespace-separated inputs, each a URL or a file that can be optimized
+fn parse_inputs(text: &str, sniff: bool) -> Result<Vec<PathBuf>, String> {
+    let files: Vec<PathBuf> = text.split_whitespace().map(|file| check_input(file, sniff).map(PathBuf::from)).collect::<Result<_, _>>()?;
+    if files.is_empty() {
+        return Err("Give at least one file or URL".to_string());
+    }
+    Ok(files)
+}
+
+fn parse_output(text: &str) -> Result<PathBuf, String> {
+    match text.trim() {
+        "" => Err("Give the file to write".to_string()),
+        output if Path::new(output).is_dir() => Err(format!("{} is a directory; give a file in it", output)),
+        output => Ok(PathBuf::from(output)),
+    }
+}
+
+fn parse_quality(text: &str) -> Result<u8, String> {
+    match text.trim().parse::<u8>() {
+        Ok(quality) if quality <= 100 => Ok(quality),
+        _ => Err(format!("{:?} is not a quality from 0 to 100", text.trim())),
+    }
+}
+
+fn parse_threads(text: &str) -> Result<usize, String> {
+    match text.trim().parse::<usize>() {
+        Ok(threads) if threads > 0 => Ok(threads),
+        _ => Err(format!("{:?} is not a number of threads (1 or more)", text.trim())),
+    }
+}
+
+/// Menus and prompts on a terminal, or, when stdin or stdout isn't one, plain numbered
+/// questions answered one line each, so that a session can be scripted through a pipe. At
+/// the end of piped input, menus return `None` and other prompts fail.
+struct Prompter {
+    theme: Option<ColorfulTheme>,
+}
+
+impl Prompter {
+    fn new() -> Self {
+        let terminal = io::stdin().is_terminal() && io::stdout().is_terminal();
+        Prompter { theme: terminal.then(ColorfulTheme::default) }
+    }
+
+    /// The index of the item chosen, or `None` when the menu was left (Esc or q)
+    fn select<T: ToString>(&self, prompt: &str, items: &[T], default: usize) -> Result<Option<usize>> {
+        if let Some(theme) = &self.theme {
+            return Ok(Select::with_theme(theme).with_prompt(prompt).items(items).default(default).interact_opt()?);
+        }
+        println!("{}", prompt);
+        for (index, item) in items.iter().enumerate() {
+            println!("  {}) {}", index + 1, item.to_string());
+        }
+        loop {
+            let Some(line) = read_line(&format!("Choice [{}]", default + 1))? else {
+                return Ok(None);
+            };
+            match line.parse::<usize>() {
+                _ if line.is_empty() => return Ok(Some(default)),
+                Ok(choice) if (1..=items.len()).contains(&choice) => return Ok(Some(choice - 1)),
+                _ => println!("  Enter a number from 1 to {}", items.len()),
+            }
+        }
+    }
+
+    /// Ask until `parse` accepts the answer; an empty answer stands for `default`, when given
+    fn input<T>(&self, prompt: &str, default: Option<String>, parse: impl Fn(&str) -> Result<T, String>) -> Result<T> {
+        if let Some(theme) = &self.theme {
+            let mut input = Input::<String>::with_theme(theme).with_prompt(prompt).allow_empty(default.is_some());
+            if let Some(default) = default.filter(|default| !default.is_empty()) {
+                input = input.default(default);
+            }
+            let answer = input.validate_with(|text: &String| parse(text).map(|_| ())).interact_text()?;
+            return parse(&answer).map_err(anyhow::Error::msg);
+        }
+        loop {
+            let label = match &default {
+                Some(default) if !default.is_empty() => format!("{} [{}]", prompt, default),
+                _ => prompt.to_string(),
+            };
+            let line = read_line(&label)?.context("No more input")?;
+            let answer = match &default {
+                Some(default) if line.is_empty() => default.as_str(),
+                _ => &line,
+            };
+            match parse(answer) {
+                Ok(value) => return Ok(value),
+                Err(e) => println!("  {}", e),
+            }
+        }
+    }
+
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool> {
+        if let Some(theme) = &self.theme {
+            return Ok(Confirm::with_theme(theme).with_prompt(prompt).default(default).interact()?);
+        }
+        loop {
+            let line = read_line(&format!("{} [{}]", prompt, if default { "Y/n" } else { "y/N" }))?.context("No more input")?;
+            match line.to_ascii_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => println!("  Answer y or n"),
+            }
+        }
+    }
+}
+
+/// A line from stdin, trimmed, or `None` at its end
+fn read_line(prompt: &str) -> Result<Option<String>> {
+    print!("{}: ", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        println!();
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_must_be_urls_or_pdf_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let pdf = dir.path().join("report.pdf");
+        std::fs::write(&pdf, "%PDF-1.7\n").unwrap();
+        let text = dir.path().join("notes.pdf");
+        std::fs::write(&text, "meeting notes\n").unwrap();
+        let pdf = pdf.to_string_lossy();
+        let text = text.to_string_lossy();
+
+        assert_eq!(check_input(&format!("  {} ", pdf), true), Ok(pdf.to_string()));
+        assert_eq!(check_input("https://example.com/report.pdf", true), Ok("https://example.com/report.pdf".to_string()));
+        assert_eq!(check_input("  ", true), Err("Give a file or a URL".to_string()));
+        assert!(check_input(&text, true).unwrap_err().contains("is not a PDF"));
+        assert_eq!(check_input(&text, false), Ok(text.to_string()));
+        assert!(check_input(&dir.path().join("missing.pdf").to_string_lossy(), true).unwrap_err().starts_with("File not found"));
+
+        let files = parse_inputs(&format!("{}\t https://example.com/a.pdf  ", pdf), true).unwrap();
+        assert_eq!(files, [PathBuf::from(pdf.as_ref()), PathBuf::from("https://example.com/a.pdf")]);
+        assert_eq!(parse_inputs(" ", true), Err("Give at least one file or URL".to_string()));
+        assert!(parse_inputs(&format!("{} {}", pdf, text), true).unwrap_err().contains("is not a PDF"));
+    }
+
+    #[test]
+    fn outputs_must_be_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(parse_output(" out.pdf "), Ok(PathBuf::from("out.pdf")));
+        assert_eq!(parse_output(""), Err("Give the file to write".to_string()));
+        let directory = dir.path().to_string_lossy();
+        assert_eq!(parse_output(&directory), Err(format!("{} is a directory; give a file in it", directory)));
+    }
+
+    #[test]
+    fn quality_and_threads_are_checked() {
+        assert_eq!(parse_quality("0"), Ok(0));
+        assert_eq!(parse_quality(" 100 "), Ok(100));
+        assert_eq!(parse_quality("101"), Err("\"101\" is not a quality from 0 to 100".to_string()));
+        assert!(parse_quality("-1").is_err());
+        assert!(parse_quality("high").is_err());
+        assert_eq!(parse_threads("8"), Ok(8));
+        assert_eq!(parse_threads("0"), Err("\"0\" is not a number of threads (1 or more)".to_string()));
+        assert!(parse_threads("").is_err());
+    }
+}
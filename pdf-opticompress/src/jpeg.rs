@@ -0,0 +1,262 @@
+//! Lossless manipulation of JPEG (DCTDecode) byte streams
+
+const SOI: u8 = 0xD8;
+const SOS: u8 = 0xDA;
+const DQT: u8 = 0xDB;
+const APP0: u8 = 0xE0;
+const APP2: u8 = 0xE2;
+const APP14: u8 = 0xEE;
+const APP15: u8 = 0xEF;
+const COM: u8 = 0xFE;
+
+/// Remove metadata segments (EXIF, XMP, thumbnails, comments, ...) from a JPEG without
+/// touching the compressed image data. The JFIF header and Adobe segment are kept since
+/// decoders rely on them for color interpretation, as are ICC profiles unless `strip_icc`.
+///
+/// Returns `None` when nothing was removed or the stream can't be parsed.
+pub fn strip_app_segments(data: &[u8], strip_icc: bool) -> Option<Vec<u8>> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != SOI {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    let mut removed = false;
+
+    loop {
+        // Skip fill bytes preceding the marker
+        if data.get(pos) != Some(&0xFF) {
+            return None;
+        }
+        while data.get(pos) == Some(&0xFF) {
+            pos += 1;
+        }
+        let marker = *data.get(pos)?;
+        pos += 1;
+
+        // Standalone markers carry no length
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            output.extend_from_slice(&[0xFF, marker]);
+            continue;
+        }
+
+        let length = u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+        if length < 2 || pos + length > data.len() {
+            return None;
+        }
+        let payload = &data[pos + 2..pos + length];
+
+        if marker == SOS {
+            // Entropy-coded data follows; copy the rest verbatim
+            output.extend_from_slice(&[0xFF, marker]);
+            output.extend_from_slice(&data[pos..]);
+            break;
+        }
+
+        if is_metadata_segment(marker, payload, strip_icc) {
+            removed = true;
+        } else {
+            output.extend_from_slice(&[0xFF, marker]);
+            output.extend_from_slice(&data[pos..pos + length]);
+        }
+        pos += length;
+    }
+
+    removed.then_some(output)
+}
+
+/// Whether a segment only carries metadata that doesn't affect decoding
+fn is_metadata_segment(marker: u8, payload: &[u8], strip_icc: bool) -> bool {
+    match marker {
+        APP0 => !payload.starts_with(b"JFIF\0"), // JFXX thumbnails and friends
+        APP2 if payload.starts_with(b"ICC_PROFILE\0") => strip_icc,
+        APP14 => false, // Adobe color transform
+        APP0..=APP15 | COM => true,
+        _ => false,
+    }
+}
+
+/// IJG reference luminance quantization table (quality 50)
+const STD_LUMINANCE_TABLE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56, 14, 17, 22, 29, 51,
+    87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Estimate the IJG quality setting a JPEG was saved with from its luminance
+/// quantization table. Returns `None` when no table can be found.
+pub fn estimate_quality(data: &[u8]) -> Option<u8> {
+    let mut table = luminance_table(data)?;
+
+    // Invert the IJG table scaling: q < 50 scales by 5000/q, q >= 50 by 200 - 2q. Entries
+    // clamped to the 8-bit maximum at low qualities would hide how far the table was scaled,
+    // so they're left out. Scaling keeps the entries' order, which pairs them up without
+    // undoing the zigzag order the table is stored in.
+    table.sort_unstable();
+    let mut reference = STD_LUMINANCE_TABLE;
+    reference.sort_unstable();
+    let (sum, reference) = table
+        .iter()
+        .zip(reference)
+        .filter(|&(&value, _)| value < 255)
+        .fold((0u64, 0u64), |(sum, total), (&value, base)| (sum + value as u64, total + base as u64));
+    if reference == 0 {
+        // Every entry is clamped: as coarse as an 8-bit table gets
+        return Some(1);
+    }
+    let scale = (sum * 100 + reference / 2) / reference;
+    let quality = if scale <= 100 {
+        (200 - scale) / 2
+    } else {
+        5000 / scale
+    };
+    Some(quality.clamp(1, 100) as u8)
+}
+
+/// The quantization table with id 0 (used for luminance by virtually every encoder)
+fn luminance_table(data: &[u8]) -> Option<Vec<u16>> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != SOI {
+        return None;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == SOS {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if length < 2 || pos + 2 + length > data.len() {
+            return None;
+        }
+
+        if marker == DQT {
+            // A DQT segment may define several tables
+            let mut segment = &data[pos + 4..pos + 2 + length];
+            while let Some((&info, rest)) = segment.split_first() {
+                let wide = info >> 4 == 1;
+                let size = if wide { 128 } else { 64 };
+                if rest.len() < size {
+                    return None;
+                }
+                if info & 0x0F == 0 {
+                    let values = if wide {
+                        rest[..size].chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect()
+                    } else {
+                        rest[..size].iter().map(|&v| v as u16).collect()
+                    };
+                    return Some(values);
+                }
+                segment = &rest[size..];
+            }
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+
+    /// A JPEG of a noisy gradient at `quality`, with only the JFIF header before the frame
+    fn jpeg(quality: u8) -> Vec<u8> {
+        let img = image::RgbImage::from_fn(256, 192, |x, y| {
+            let noise = ((x * 7919 + y * 104729) % 31) as u8;
+            image::Rgb([(x % 256) as u8 ^ noise, (y % 256) as u8, ((x + y) % 256) as u8])
+        });
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, quality).encode_image(&img).unwrap();
+        data
+    }
+
+    /// Where the JFIF header that follows the start of image ends
+    fn jfif_end(jpeg: &[u8]) -> usize {
+        4 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize
+    }
+
+    /// `jpeg` with `segments` added after its JFIF header
+    fn with_segments(jpeg: &[u8], segments: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let jfif_end = jfif_end(jpeg);
+        let mut output = jpeg[..jfif_end].to_vec();
+        for (marker, payload) in segments {
+            output.extend_from_slice(&[0xFF, *marker]);
+            output.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+            output.extend_from_slice(payload);
+        }
+        output.extend_from_slice(&jpeg[jfif_end..]);
+        output
+    }
+
+    /// The markers of the segments before the scan data
+    fn markers(jpeg: &[u8]) -> Vec<u8> {
+        let mut markers = Vec::new();
+        let mut pos = 2;
+        while jpeg[pos + 1] != SOS {
+            markers.push(jpeg[pos + 1]);
+            pos += 2 + u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        }
+        markers
+    }
+
+    fn payload(signature: &[u8], size: usize) -> Vec<u8> {
+        let mut payload = signature.to_vec();
+        payload.resize(size, 0x5A);
+        payload
+    }
+
+    #[test]
+    fn metadata_segments_are_removed_and_the_image_kept() {
+        let original = jpeg(90);
+        let tagged = with_segments(
+            &original,
+            &[
+                (0xE1, payload(b"Exif\0\0", 60_000)),
+                (0xE1, payload(b"http://ns.adobe.com/xap/1.0/\0", 4_000)),
+                (APP2, payload(b"ICC_PROFILE\0", 3_000)),
+                (APP14, payload(b"Adobe", 12)),
+                (COM, payload(b"Made with a camera", 18)),
+            ],
+        );
+
+        let stripped = strip_app_segments(&tagged, false).unwrap();
+        let kept: Vec<u8> = markers(&stripped).into_iter().filter(|marker| (APP0..=APP15).contains(marker) || *marker == COM).collect();
+        assert_eq!(kept, [APP0, APP2, APP14]);
+        // The ICC profile and Adobe segments, with their markers and lengths, are all that's added
+        assert_eq!(stripped.len(), original.len() + 3_004 + 16);
+        // The tables, frame and scan data are untouched
+        assert!(stripped.ends_with(&original[jfif_end(&original)..]));
+
+        // The ICC profile goes too when asked
+        let stripped = strip_app_segments(&tagged, true).unwrap();
+        assert!(markers(&stripped).starts_with(&[APP0, APP14]) && !markers(&stripped).contains(&APP2));
+        assert_eq!(stripped.len(), original.len() + 16);
+    }
+
+    #[test]
+    fn a_jpeg_without_metadata_is_left_alone() {
+        assert_eq!(strip_app_segments(&jpeg(90), false), None);
+        assert_eq!(strip_app_segments(b"not a jpeg", false), None);
+    }
+
+    #[test]
+    fn the_quality_is_estimated_from_the_quantization_table() {
+        for quality in [5, 10, 25, 50, 75, 85, 95] {
+            let estimated = estimate_quality(&jpeg(quality)).unwrap();
+            assert!(estimated.abs_diff(quality) <= 2, "quality {} estimated as {}", quality, estimated);
+        }
+        // Metadata before the tables doesn't get in the way
+        let tagged = with_segments(&jpeg(60), &[(0xE1, payload(b"Exif\0\0", 1_000))]);
+        assert!(estimate_quality(&tagged).unwrap().abs_diff(60) <= 2);
+        assert_eq!(estimate_quality(b"not a jpeg"), None);
+    }
+}
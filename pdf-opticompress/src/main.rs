@@ -3,414 +3,605 @@ mod optimizer;
 mod pdf_reader;
 mod pdf_writer;
 mod analyzer;
+mod annotations;
+mod batch;
+mod batch_state;
+mod checker;
+mod comparison;
+mod config;
+mod content_streams;
+mod dedup;
+mod encryption;
+mod filters;
+mod fonts;
+mod forms;
+mod icc;
 mod image_optimizer;
+mod inline_images;
+mod interactive;
+mod jpeg;
+mod marker;
+mod metadata;
+mod navigation;
+mod objects;
+mod pages;
+mod pdfa;
+mod placement;
+mod quality;
+mod report;
+mod resources;
+mod sanitize;
+mod standard_fonts;
+mod streams;
+mod structure_tree;
+mod summary;
+mod truetype;
 mod utils;
+mod verification;
+mod xmp;
 
-use anyhow::Result;
-use clap::Parser;
-use cli::Cli;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
 use rayon::prelude::*;
 use std::io::{self, Write};
 use std::path::PathBuf;
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    // The configuration file gives the commands their defaults, so it's read first
+    let args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    let config = crate::config::Config::load(&args)?;
+    let cli = config.try_parse_from(&args).unwrap_or_else(|e| e.exit());
+    let sniff = !cli.no_sniff;
 
-    match cli.command {
-        Some(cli::Commands::Optimize { input, output, quality, preset }) => {
-            // Resolve input
-            let input_path = crate::utils::resolve_input_path(&input.to_str().unwrap())?;
-            // Validate input file
-            crate::utils::validate_input_file(&input_path)?;
-
-            // Perform optimization
-            let result = crate::optimizer::optimize_pdf(&input_path, &output, quality, &preset, true)?;
-
-            // Print results
-            crate::optimizer::print_optimization_results(&result);
+    // `pdf-opticompress FILE...` is run as the optimize or batch command it stands for
+    let command = match cli.command {
+        Some(_) if !cli.files.is_empty() => {
+            anyhow::bail!("{} was given before the command; give the files after it", cli.files[0].display())
         }
-        Some(cli::Commands::Analyze { input, show_savings }) => {
-            // Resolve input
-            let input_path = crate::utils::resolve_input_path(&input.to_str().unwrap())?;
-            // Validate input file
-            crate::utils::validate_input_file(&input_path)?;
-
-            // Load and analyze PDF
-            let doc = crate::pdf_reader::load_pdf(&input_path)?;
-            crate::pdf_reader::validate_pdf(&doc)?;
-
-            let analysis = crate::analyzer::analyze_pdf(&doc)?;
-            crate::analyzer::print_analysis(&analysis, show_savings);
-
-            // Show file size
-            let file_size = crate::utils::get_file_size(&input_path)?;
-            println!("File size: {}", crate::utils::format_bytes(file_size));
+        Some(command) => Some(command),
+        None => cli.shorthand(&config),
+    };
+    let status = match command {
+        Some(command) => run(command, sniff, &config)?,
+        None => {
+            crate::interactive::run(sniff, &config)?;
+            0
         }
-        Some(cli::Commands::Batch { files, output_dir, threads }) => {
-            if files.is_empty() {
-                eprintln!("Error: No input files specified");
-                std::process::exit(1);
-            }
+    };
+    if status != 0 {
+        std::process::exit(status);
+    }
+    Ok(())
+}
 
-            // Resolve and validate all input files
-            let resolved_files: Vec<PathBuf> = files.iter().map(|f| crate::utils::resolve_input_path(&f.to_str().unwrap())).collect::<Result<Vec<_>>>()?;
-            for (original, resolved) in files.iter().zip(&resolved_files) {
-                if let Err(e) = crate::utils::validate_input_file(resolved) {
-                    eprintln!("Error with {}: {}", original.display(), e);
-                    std::process::exit(1);
+/// Run a command, returning the exit status it ends with
+fn run(command: cli::Commands, sniff: bool, config: &crate::config::Config) -> Result<i32> {
+    match command {
+        cli::Commands::Optimize { input, output, suffix, create_dirs, force, name_template, in_place, backup, no_backup, optimize, target_size, pages, verbose, format, deterministic, password, keep_encryption, encrypt_owner, encrypt_user, permissions, cipher, info } => {
+            let named = |directory: &std::path::Path| -> Result<PathBuf> {
+                if crate::utils::is_std_stream(&input) {
+                    anyhow::bail!("An input read from stdin has no name to give its output; give the output file, or - for stdout");
                 }
+                let stem = input.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+                Ok(directory.join(format!("{}{}.pdf", stem, suffix.as_deref().unwrap_or(".optimized"))))
+            };
+            let directory_given = output.as_ref().is_some_and(|output| {
+                !crate::utils::is_std_stream(output) && (output.is_dir() || output.to_string_lossy().ends_with(std::path::is_separator))
+            });
+            let output = match (output, name_template) {
+                (Some(output), _) if in_place => {
+                    if !crate::batch::same_file(&input, &output) {
+                        anyhow::bail!("--in-place replaces the input; the output can only be left out or be the input itself");
+                    }
+                    input.clone()
+                }
+                (Some(output), _) if directory_given => named(&output)?,
+                (Some(output), _) => output,
+                (None, _) if in_place => input.clone(),
+                (None, Some(template)) => {
+                    let template = crate::batch::NameTemplate::parse(&template)?;
+                    input.with_file_name(template.expand(&input, 1, &optimize.preset, optimize.quality)?)
+                }
+                (None, None) => named(input.parent().unwrap_or(std::path::Path::new("")))?,
+            };
+            // Writing over the input directly would lose it if verification failed
+            if !in_place && crate::batch::same_file(&input, &output) {
+                anyhow::bail!("{} is the input itself (use --in-place to replace it safely)", output.display());
             }
+            if !in_place && !force && !crate::utils::is_std_stream(&output) && output.exists() {
+                anyhow::bail!("{} already exists (use --force to replace it)", output.display());
+            }
+            if !crate::utils::is_std_stream(&output) && !in_place && !optimize.dry_run {
+                match output.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    Some(parent) if create_dirs => {
+                        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?
+                    }
+                    Some(parent) if !parent.is_dir() => {
+                        anyhow::bail!("The output's directory {} doesn't exist (use --create-dirs to create it)", parent.display())
+                    }
+                    _ => {}
+                }
+            }
+            if in_place && (crate::utils::is_url(&input.to_string_lossy()) || crate::utils::is_std_stream(&input)) {
+                anyhow::bail!("--in-place needs an input file to replace, not {}", input.display());
+            }
+            // Resolve and validate the input, reading stdin for -
+            let input_file = crate::utils::open_input(&input, sniff)?;
+            let input_path = input_file.path();
 
-            println!("Batch processing {} files with {} threads", resolved_files.len(), threads);
-
-            // Set up rayon thread pool
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(threads)
-                .build_global()
-                .unwrap_or_else(|_| {
-                    eprintln!("Warning: Failed to set thread count, using default");
-                });
+            let options = crate::optimizer::OptimizeOptions {
+                target_size,
+                pages,
+                verbose,
+                password,
+                keep_encryption,
+                encrypt: encrypt_owner.map(|owner_password| crate::encryption::EncryptionSettings {
+                    user_password: encrypt_user.unwrap_or_default(),
+                    owner_password,
+                    permissions,
+                    cipher,
+                }),
+                deterministic,
+                document_info: info.into(),
+                in_place: in_place.then(|| crate::optimizer::InPlace::new(backup, no_backup, force)),
+                ..crate::optimizer::OptimizeOptions::from_args(&optimize)
+            };
 
-            // Prepare work items
-            let work_items: Vec<_> = resolved_files.iter().enumerate().map(|(i, input_file)| {
-                let output_file = if let Some(ref dir) = output_dir {
-                    dir.join(files[i].file_name().unwrap())
-                } else {
-                    files[i].with_extension("optimized.pdf")
-                };
-                (i, input_file.clone(), output_file)
-            }).collect();
+            // Perform optimization
+            let result = crate::optimizer::optimize_pdf(input_path, &output, &options, true)?;
 
-            // Process files in parallel
-            let results: Vec<_> = work_items.into_par_iter().map(|(i, input_file, output_file)| {
-                println!("Processing file {}/{}: {}", i + 1, resolved_files.len(), files[i].display());
-
-                match crate::optimizer::optimize_pdf(&input_file, &output_file, 80, &cli::Preset::Web, false) {
-                    Ok(result) => {
-                        println!("  ✓ Saved {:.1}% ({})",
-                                result.compression_ratio,
-                                crate::utils::format_bytes(result.original_size - result.optimized_size));
-                        Ok(result)
+            // Print results, on stderr when stdout holds the PDF
+            let mut out: Box<dyn Write> = if crate::utils::is_std_stream(&output) { Box::new(io::stderr()) } else { Box::new(io::stdout()) };
+            match format {
+                cli::OutputFormat::Text => {
+                    if options.verbose || options.dry_run {
+                        crate::optimizer::print_image_records(&mut out, &result)?;
                     }
-                    Err(e) => {
-                        eprintln!("  ✗ Failed: {}", e);
-                        Err(e)
-                    }
-                }
-            }).collect();
-
-            // Calculate totals
-            let mut total_original = 0u64;
-            let mut total_optimized = 0u64;
-            let mut total_images = 0usize;
-            let mut successful_files = 0;
-
-            for result in results {
-                if let Ok(ref res) = result {
-                    total_original += res.original_size;
-                    total_optimized += res.optimized_size;
-                    total_images += res.images_optimized;
-                    successful_files += 1;
+                    crate::optimizer::print_optimization_results(&mut out, &result)?;
                 }
+                cli::OutputFormat::Json => writeln!(out, "{}", crate::optimizer::optimization_results_json(&input, &output, &options, &result))?,
+            }
+        }
+        cli::Commands::Analyze { inputs, compare: true, format, .. } => {
+            let [input, other] = inputs.as_slice() else {
+                anyhow::bail!("--compare takes two PDFs, the input and the one to compare it with");
+            };
+            if crate::utils::is_std_stream(input) && crate::utils::is_std_stream(other) {
+                anyhow::bail!("stdin can only be read once; give - as one of the PDFs at most");
+            }
+            let mut sides = Vec::new();
+            for path in [input, other] {
+                let input_file = crate::utils::open_input(path, sniff)?;
+                let resolved = input_file.path();
+                let doc = crate::pdf_reader::load_pdf(resolved, false)?;
+                crate::pdf_reader::validate_pdf(&doc)?;
+                let analysis = crate::analyzer::analyze_pdf(&doc)?;
+                let snapshot = crate::verification::content_snapshot(&doc);
+                sides.push((analysis, crate::utils::get_file_size(resolved)?, snapshot));
             }
+            let side = |index: usize, path| crate::comparison::Side { path, file_size: sides[index].1, analysis: &sides[index].0 };
+            let (before, after) = (side(0, input), side(1, other));
+            let rows = crate::comparison::compare(&before, &after);
+            let problems = crate::verification::compare_content(&sides[0].2, &sides[1].2);
+            match format {
+                cli::OutputFormat::Text => crate::comparison::print_comparison(&before, &after, &rows, &problems),
+                cli::OutputFormat::Json => println!("{}", crate::comparison::comparison_json(&before, &after, &rows, &problems)),
+            }
+            if !problems.is_empty() {
+                return Ok(1);
+            }
+        }
+        cli::Commands::Analyze { inputs, show_savings, top, deep, preset, quality, simulate_presets, format, .. } => {
+            if inputs.iter().filter(|input| crate::utils::is_std_stream(input)).count() > 1 {
+                anyhow::bail!("stdin can only be read once; give - as one of the PDFs at most");
+            }
+            let analyze = |input: &PathBuf| -> Result<(u64, crate::analyzer::PdfAnalysis)> {
+                // Resolve and validate the input, reading stdin for -
+                let input_file = crate::utils::open_input(input, sniff)?;
+                let input_path = input_file.path();
 
-            let total_ratio = if total_original > 0 {
-                crate::utils::calculate_compression_ratio(total_original, total_optimized)
-            } else {
-                0.0
+                // Load and analyze PDF
+                let doc = crate::pdf_reader::load_pdf(input_path, false)?;
+                crate::pdf_reader::validate_pdf(&doc)?;
+
+                let mut analysis = crate::analyzer::analyze_pdf(&doc)?;
+                if deep {
+                    let image_settings = crate::image_optimizer::create_image_settings_for_preset(&preset, quality);
+                    let save_options = crate::pdf_writer::create_save_options_for_preset(&preset);
+                    let preset_name = preset.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+                    analysis.measured_savings =
+                        Some(crate::analyzer::measure_savings(&doc, &analysis, &preset_name, &image_settings, &save_options)?);
+                }
+                if simulate_presets {
+                    analysis.preset_simulations = crate::analyzer::simulate_presets(&doc, &analysis, quality)?;
+                }
+                Ok((crate::utils::get_file_size(input_path)?, analysis))
             };
 
-            println!("\nBatch Summary:");
-            println!("==============");
-            println!("Files processed: {}/{}", successful_files, resolved_files.len());
-            println!("Total original size: {}", crate::utils::format_bytes(total_original));
-            println!("Total optimized size: {}", crate::utils::format_bytes(total_optimized));
-            println!("Total space saved: {:.1}%", total_ratio);
-            println!("Total images optimized: {}", total_images);
+            if let [input] = inputs.as_slice() {
+                let (file_size, analysis) = analyze(input)?;
+                match format {
+                    cli::OutputFormat::Text => crate::analyzer::print_analysis(&analysis, file_size, show_savings || deep, top.unwrap_or(0)),
+                    cli::OutputFormat::Json => println!("{}", crate::analyzer::analysis_json(input, file_size, &analysis, top.unwrap_or(0))),
+                }
+            } else {
+                // A file that can't be analyzed is recorded and the others carry on
+                let files: Vec<_> = inputs
+                    .par_iter()
+                    .map(|input| crate::summary::FileAnalysis { path: input.clone(), result: analyze(input) })
+                    .collect();
+                let summary = crate::summary::summarize(&files);
+                match format {
+                    cli::OutputFormat::Text => crate::summary::print_summary(&files, &summary),
+                    cli::OutputFormat::Json => println!("{}", crate::summary::summary_json(&files, &summary, top.unwrap_or(0))),
+                }
+            }
         }
-        None => {
-            interactive_mode()?;
+        cli::Commands::Info { input, format, password } => {
+            let input_file = crate::utils::open_input(&input, sniff)?;
+            let input_path = input_file.path();
+
+            let info = crate::pdf_reader::read_pdf_info(input_path, password.as_deref().unwrap_or(""))?;
+            match format {
+                cli::OutputFormat::Text => crate::pdf_reader::print_pdf_info(&input, &info),
+                cli::OutputFormat::Json => println!("{}", crate::pdf_reader::pdf_info_json(&input, &info)),
+            }
         }
-    }
+        cli::Commands::Decompress { input, output, password } => {
+            let input_file = crate::utils::open_input(&input, sniff)?;
+            let input_path = input_file.path();
 
-    Ok(())
-}
+            let (mut doc, encryption) = crate::pdf_reader::load_decrypted(input_path, password.as_deref())?;
+            let stats = crate::streams::decompress_streams(&mut doc);
 
-fn interactive_mode() -> Result<()> {
-            for file in &files {
-                if let Err(e) = crate::utils::validate_input_file(file) {
-                    eprintln!("Error with {}: {}", file.display(), e);
-                    std::process::exit(1);
-                }
+            let save_options = crate::pdf_writer::SaveOptions { enable_compression: false, readable: true, ..Default::default() };
+            crate::pdf_writer::save_pdf(&mut doc, &output, &save_options)?;
+
+            let streams = |count: usize| format!("{} stream{}", count, if count == 1 { "" } else { "s" });
+            println!("Decoded {}", streams(stats.decoded));
+            if stats.left_encoded > 0 {
+                println!("Left {} encoded (image codecs and filters that can't be decoded)", streams(stats.left_encoded));
+            }
+            if stats.failed > 0 {
+                println!("Couldn't decode {}: the data is corrupt", streams(stats.failed));
+            }
+            if encryption.is_some() {
+                println!("The output isn't encrypted");
             }
+            println!("Wrote {} ({})", output.display(), crate::utils::format_bytes(crate::utils::get_file_size(&output)?));
+        }
+        cli::Commands::Split { input, ranges, every, out_dir, optimize, preset, quality, password } => {
+            let input_file = crate::utils::open_input(&input, sniff)?;
+            let input_path = input_file.path();
 
-            println!("Batch processing {} files with {} threads", files.len(), threads);
+            let (doc, encryption) = crate::pdf_reader::load_decrypted(input_path, password.as_deref())?;
+            crate::pdf_reader::validate_pdf(&doc)?;
+            let page_count = doc.get_pages().len() as u32;
+            let ranges = match every {
+                Some(size) => crate::pages::chunk_ranges(page_count, size),
+                None => crate::pages::parse_page_ranges(ranges.as_deref().unwrap_or_default(), page_count)?,
+            };
+            if encryption.is_some() {
+                eprintln!("Note: {} is encrypted; the output files won't be", input.display());
+            }
+            if crate::pdf_reader::signature_info(&doc).is_signed() {
+                eprintln!("Warning: {} is digitally signed; the signatures won't be valid in the output files", input.display());
+            }
 
-            // Set up rayon thread pool
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(threads)
-                .build_global()
-                .unwrap_or_else(|_| {
-                    eprintln!("Warning: Failed to set thread count, using default");
-                });
+            let out_dir = out_dir.unwrap_or_else(|| match input.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            });
+            std::fs::create_dir_all(&out_dir).with_context(|| format!("Failed to create {}", out_dir.display()))?;
+            let stem = input.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "part".to_string());
+            let options = crate::optimizer::OptimizeOptions { quality, preset, force_signed: true, ..Default::default() };
+            // Parts are only copied, so their streams stay as they are
+            let save_options = crate::pdf_writer::SaveOptions { enable_compression: false, ..Default::default() };
 
-            // Prepare work items
-            let work_items: Vec<_> = files.iter().enumerate().map(|(i, input_file)| {
-                let output_file = if let Some(ref dir) = output_dir {
-                    dir.join(input_file.file_name().unwrap())
+            for range in &ranges {
+                let numbers: Vec<u32> = range.clone().collect();
+                let mut part = crate::pages::extract_pages(&doc, &numbers);
+                let output = out_dir.join(crate::pages::part_file_name(&stem, range, page_count));
+                let pages = format!("{} page{}", numbers.len(), if numbers.len() == 1 { "" } else { "s" });
+                crate::pdf_writer::save_pdf(&mut part, &output, &save_options)?;
+                if optimize {
+                    let unoptimized_size = crate::utils::get_file_size(&output)?;
+                    let result = crate::optimizer::optimize_pdf(&output, &output, &options, false)
+                        .with_context(|| format!("Failed to optimize {}", output.display()))?;
+                    println!(
+                        "Wrote {} ({}, {}, {:.1}% smaller)",
+                        output.display(),
+                        pages,
+                        crate::utils::format_bytes(result.optimized_size),
+                        crate::utils::calculate_compression_ratio(unoptimized_size, result.optimized_size)
+                    );
                 } else {
-                    input_file.with_extension("optimized.pdf")
-                };
-                (i, input_file.clone(), output_file)
-            }).collect();
+                    println!("Wrote {} ({}, {})", output.display(), pages, crate::utils::format_bytes(crate::utils::get_file_size(&output)?));
+                }
+            }
+        }
+        cli::Commands::Merge { files, output, optimize, preset, quality, password } => {
+            let mut sources = Vec::new();
+            for file in &files {
+                let input_file = crate::utils::open_input(file, sniff)?;
+                let input_path = input_file.path();
 
-            // Process files in parallel
-            let results: Vec<_> = work_items.into_par_iter().map(|(i, input_file, output_file)| {
-                println!("Processing file {}/{}: {}", i + 1, files.len(), input_file.display());
-
-                match crate::optimizer::optimize_pdf(&input_file, &output_file, 80, &cli::Preset::Web, false) {
-                    Ok(result) => {
-                        println!("  ✓ Saved {:.1}% ({})",
-                                result.compression_ratio,
-                                crate::utils::format_bytes(result.original_size - result.optimized_size));
-                        Ok(result)
-                    }
-                    Err(e) => {
-                        eprintln!("  ✗ Failed: {}", e);
-                        Err(e)
-                    }
+                let mut doc = crate::pdf_reader::load_pdf(input_path, false)?;
+                if doc.trailer.get(b"Encrypt").is_ok() && password.is_none() {
+                    anyhow::bail!("{} is encrypted; give its password with --password", file.display());
                 }
-            }).collect();
-
-            // Calculate totals
-            let mut total_original = 0u64;
-            let mut total_optimized = 0u64;
-            let mut total_images = 0usize;
-            let mut successful_files = 0;
-
-            for result in results {
-                if let Ok(ref res) = result {
-                    total_original += res.original_size;
-                    total_optimized += res.optimized_size;
-                    total_images += res.images_optimized;
-                    successful_files += 1;
+                crate::encryption::decrypt_document(&mut doc, password.as_deref().unwrap_or(""))
+                    .with_context(|| format!("Failed to decrypt {}", file.display()))?;
+                crate::pdf_reader::validate_pdf(&doc)?;
+                if crate::pdf_reader::signature_info(&doc).is_signed() {
+                    eprintln!("Warning: {} is digitally signed; the signature won't be valid in the merged file", file.display());
                 }
+                let label = file.file_name().unwrap_or(file.as_os_str()).to_string_lossy().into_owned();
+                sources.push((label, doc));
             }
 
-            // Print batch summary
-            let total_ratio = if total_original > 0 {
-                crate::utils::calculate_compression_ratio(total_original, total_optimized)
+            let mut merged = crate::pages::merge_documents(sources);
+            // Documents made with the same tools often embed the same fonts and logos
+            let fonts = crate::dedup::deduplicate_fonts(&mut merged);
+            let images = crate::dedup::deduplicate_images(&mut merged);
+            let page_count = merged.get_pages().len();
+
+            let save_options = crate::pdf_writer::SaveOptions { enable_compression: false, ..Default::default() };
+            crate::pdf_writer::save_pdf(&mut merged, &output, &save_options)?;
+            let size = if optimize {
+                // Signatures were already warned about and can't survive merging anyway
+                let options = crate::optimizer::OptimizeOptions { quality, preset, force_signed: true, ..Default::default() };
+                crate::optimizer::optimize_pdf(&output, &output, &options, true)?.optimized_size
             } else {
-                0.0
+                crate::utils::get_file_size(&output)?
             };
 
-            println!("\nBatch Summary:");
-            println!("==============");
-            println!("Files processed: {}/{}", successful_files, files.len());
-            println!("Total original size: {}", crate::utils::format_bytes(total_original));
-            println!("Total optimized size: {}", crate::utils::format_bytes(total_optimized));
-            println!("Total space saved: {:.1}%", total_ratio);
-            println!("Total images optimized: {}", total_images);
+            println!("Merged {} files ({} pages)", files.len(), page_count);
+            let mut duplicates = Vec::new();
+            if fonts.merged > 0 {
+                duplicates.push(format!("{} duplicate font program{}", fonts.merged, if fonts.merged == 1 { "" } else { "s" }));
+            }
+            if images.merged > 0 {
+                duplicates.push(format!("{} duplicate image{}", images.merged, if images.merged == 1 { "" } else { "s" }));
+            }
+            if !duplicates.is_empty() {
+                println!(
+                    "Stored {} once ({} saved)",
+                    duplicates.join(" and "),
+                    crate::utils::format_bytes(fonts.bytes_saved + images.bytes_saved)
+                );
+            }
+            println!("Wrote {} ({})", output.display(), crate::utils::format_bytes(size));
         }
-    }
+        cli::Commands::DeletePages { input, output, pages, remap_links, password } => {
+            let input_file = crate::utils::open_input(&input, sniff)?;
+            let input_path = input_file.path();
 
-    Ok(())
-}
-
-fn interactive_mode() -> Result<()> {
-    println!("Interactive mode for pdf-opticompress");
-    print!("Choose command (1: Optimize, 2: Analyze, 3: Batch): ");
-    io::stdout().flush().unwrap();
-    let mut choice = String::new();
-    io::stdin().read_line(&mut choice).unwrap();
-    match choice.trim() {
-        "1" => {
-            print!("Input PDF (URL or local path): ");
-            io::stdout().flush().unwrap();
-            let mut input_str = String::new();
-            io::stdin().read_line(&mut input_str).unwrap();
-            let input = crate::utils::resolve_input_path(input_str.trim())?;
-            crate::utils::validate_input_file(&input)?;
-            print!("Output PDF: ");
-            io::stdout().flush().unwrap();
-            let mut output_str = String::new();
-            io::stdin().read_line(&mut output_str).unwrap();
-            let output = PathBuf::from(output_str.trim());
-            print!("Quality (0-100, default 80): ");
-            io::stdout().flush().unwrap();
-            let mut quality_str = String::new();
-            io::stdin().read_line(&mut quality_str).unwrap();
-            let quality = quality_str.trim().parse().unwrap_or(80);
-            print!("Preset (web/print/archive/maximum, default web): ");
-            io::stdout().flush().unwrap();
-            let mut preset_str = String::new();
-            io::stdin().read_line(&mut preset_str).unwrap();
-            let preset = match preset_str.trim() {
-                "print" => cli::Preset::Print,
-                "archive" => cli::Preset::Archive,
-                "maximum" => cli::Preset::Maximum,
-                _ => cli::Preset::Web,
-            };
-            let result = crate::optimizer::optimize_pdf(&input, &output, quality, &preset, true)?;
-            crate::optimizer::print_optimization_results(&result);
-        }
-        "2" => {
-            print!("Input PDF (URL or local path): ");
-            io::stdout().flush().unwrap();
-            let mut input_str = String::new();
-            io::stdin().read_line(&mut input_str).unwrap();
-            let input = crate::utils::resolve_input_path(input_str.trim())?;
-            crate::utils::validate_input_file(&input)?;
-            let doc = crate::pdf_reader::load_pdf(&input)?;
+            let (mut doc, encryption) = crate::pdf_reader::load_decrypted(input_path, password.as_deref())?;
             crate::pdf_reader::validate_pdf(&doc)?;
-            let analysis = crate::analyzer::analyze_pdf(&doc)?;
-            crate::analyzer::print_analysis(&analysis, true);
-            let file_size = crate::utils::get_file_size(&input)?;
-            println!("File size: {}", crate::utils::format_bytes(file_size));
+            let page_count = doc.get_pages().len() as u32;
+            let deleted: std::collections::BTreeSet<u32> =
+                crate::pages::parse_page_ranges(&pages, page_count)?.into_iter().flatten().collect();
+            if deleted.len() as u32 == page_count {
+                match page_count {
+                    1 => anyhow::bail!("Can't delete the only page of {}; a PDF needs at least one", input.display()),
+                    _ => anyhow::bail!("Can't delete all {} pages of {}; a PDF needs at least one", page_count, input.display()),
+                }
+            }
+            if crate::pdf_reader::signature_info(&doc).is_signed() {
+                eprintln!("Warning: {} is digitally signed; the signatures won't be valid in the output", input.display());
+            }
+
+            let stats = crate::pages::delete_pages(&mut doc, &deleted, remap_links);
+            let save_options = crate::pdf_writer::SaveOptions { enable_compression: false, encryption, ..Default::default() };
+            crate::pdf_writer::save_pdf(&mut doc, &output, &save_options)?;
+
+            let plural = |count: usize| if count == 1 { "" } else { "s" };
+            let remaining = page_count as usize - deleted.len();
+            println!("Deleted {} page{} ({} remain{})", deleted.len(), plural(deleted.len()), remaining, if remaining == 1 { "s" } else { "" });
+            let mut removed = Vec::new();
+            if stats.bookmarks_removed > 0 {
+                removed.push(format!("{} bookmark{}", stats.bookmarks_removed, plural(stats.bookmarks_removed)));
+            }
+            if stats.links_removed > 0 {
+                removed.push(format!("{} link{}", stats.links_removed, plural(stats.links_removed)));
+            }
+            if stats.named_destinations_removed > 0 {
+                removed.push(format!("{} named destination{}", stats.named_destinations_removed, plural(stats.named_destinations_removed)));
+            }
+            if let Some(last) = removed.pop() {
+                match removed.is_empty() {
+                    true => println!("Removed {} leading to deleted pages", last),
+                    false => println!("Removed {} and {} leading to deleted pages", removed.join(", "), last),
+                }
+            }
+            if stats.destinations_remapped > 0 {
+                println!(
+                    "Pointed {} destination{} at the nearest remaining page",
+                    stats.destinations_remapped,
+                    plural(stats.destinations_remapped)
+                );
+            }
+            if stats.objects_removed > 0 {
+                println!("Removed {} object{} only the deleted pages used", stats.objects_removed, plural(stats.objects_removed));
+            }
+            println!("Wrote {} ({})", output.display(), crate::utils::format_bytes(crate::utils::get_file_size(&output)?));
         }
-        "3" => {
-            print!("Input PDFs (URLs or local paths, space separated): ");
-            io::stdout().flush().unwrap();
-            let mut files_str = String::new();
-            io::stdin().read_line(&mut files_str).unwrap();
-            let files: Vec<PathBuf> = files_str.trim().split_whitespace().map(|s| crate::utils::resolve_input_path(s)).collect::<Result<Vec<_>>>()?;
-            if files.is_empty() {
-                eprintln!("No input files specified");
-                return Ok(());
+        cli::Commands::Metadata { action } => match action {
+            cli::MetadataAction::Show { input, password } => {
+                let input_file = crate::utils::open_input(&input, sniff)?;
+                let input_path = input_file.path();
+
+                let (doc, _) = crate::pdf_reader::load_decrypted(input_path, password.as_deref())?;
+                crate::metadata::print_metadata(&doc);
             }
-            for file in &files {
-                crate::utils::validate_input_file(&file)?;
-            }
-            print!("Output directory (optional): ");
-            io::stdout().flush().unwrap();
-            let mut outdir_str = String::new();
-            io::stdin().read_line(&mut outdir_str).unwrap();
-            let output_dir = if outdir_str.trim().is_empty() { None } else { Some(PathBuf::from(outdir_str.trim())) };
-            print!("Threads (default 4): ");
-            io::stdout().flush().unwrap();
-            let mut threads_str = String::new();
-            io::stdin().read_line(&mut threads_str).unwrap();
-            let threads = threads_str.trim().parse().unwrap_or(4);
-            println!("Batch processing {} files with {} threads", files.len(), threads);
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(threads)
-                .build_global()
-                .unwrap_or_else(|_| eprintln!("Warning: Failed to set thread count, using default"));
-            let work_items: Vec<_> = files.iter().enumerate().map(|(i, input_file)| {
-                let output_file = if let Some(ref dir) = output_dir {
-                    dir.join(format!("optimized_{}.pdf", i))
-                } else {
-                    PathBuf::from(format!("optimized_{}.pdf", i))
-                };
-                (i, input_file.clone(), output_file)
-            }).collect();
-            let results: Vec<_> = work_items.into_par_iter().map(|(i, input_file, output_file)| {
-                println!("Processing file {}/{}: {}", i + 1, files.len(), i);
-                match crate::optimizer::optimize_pdf(&input_file, &output_file, 80, &cli::Preset::Web, false) {
-                    Ok(result) => {
-                        println!("  ✓ Saved {:.1}% ({})", result.compression_ratio, crate::utils::format_bytes(result.original_size - result.optimized_size));
-                        Ok(result)
-                    }
-                    Err(e) => {
-                        eprintln!("  ✗ Failed: {}", e);
-                        Err(e)
-                    }
+            cli::MetadataAction::Set { input, output, title, author, subject, keywords, password } => {
+                let input_file = crate::utils::open_input(&input, sniff)?;
+                let input_path = input_file.path();
+                let info = crate::pdf_writer::DocumentInfo { title, author, subject, keywords, modified: None };
+                if info.is_empty() {
+                    anyhow::bail!("Nothing to set: give at least one of --title, --author, --subject and --keywords");
+                }
+
+                let (mut doc, encryption) = crate::pdf_reader::load_decrypted(input_path, password.as_deref())?;
+                let info = crate::pdf_writer::DocumentInfo { modified: Some(crate::xmp::pdf_date_now()), ..info };
+                crate::pdf_writer::set_document_info(&mut doc, &info);
+                let save_options = crate::pdf_writer::SaveOptions { enable_compression: false, encryption, ..Default::default() };
+                crate::pdf_writer::save_pdf(&mut doc, &output, &save_options)?;
+
+                let xmp = if crate::xmp::catalog_metadata_id(&doc).is_some() { " and XMP metadata" } else { "" };
+                println!("Updated the document information{} of {}", xmp, input.display());
+                println!("Wrote {}", output.display());
+            }
+            cli::MetadataAction::Strip { input, output, password } => {
+                let input_file = crate::utils::open_input(&input, sniff)?;
+                let input_path = input_file.path();
+
+                let (mut doc, encryption) = crate::pdf_reader::load_decrypted(input_path, password.as_deref())?;
+                let stats = crate::metadata::strip_metadata(&mut doc);
+                let save_options = crate::pdf_writer::SaveOptions { enable_compression: false, encryption, ..Default::default() };
+                crate::pdf_writer::save_pdf(&mut doc, &output, &save_options)?;
+
+                let mut removed = Vec::new();
+                if stats.info_removed {
+                    removed.push("the document information".to_string());
+                }
+                if stats.metadata_streams_removed > 0 {
+                    removed.push(format!(
+                        "{} XMP metadata stream{} ({})",
+                        stats.metadata_streams_removed,
+                        if stats.metadata_streams_removed == 1 { "" } else { "s" },
+                        crate::utils::format_bytes(stats.bytes_removed)
+                    ));
                 }
-            }).collect();
-            let mut total_original = 0u64;
-            let mut total_optimized = 0u64;
-            let mut total_images = 0usize;
-            let mut successful_files = 0;
-            for result in results {
-                if let Ok(ref res) = result {
-                    total_original += res.original_size;
-                    total_optimized += res.optimized_size;
-                    total_images += res.images_optimized;
-                    successful_files += 1;
+                match removed.is_empty() {
+                    true => println!("{} has no metadata", input.display()),
+                    false => println!("Removed {}", removed.join(" and ")),
                 }
+                println!("Wrote {}", output.display());
+            }
+        },
+        cli::Commands::Config { action: cli::ConfigAction::Show } => config.print_effective(),
+        cli::Commands::Check { input, format, password } => {
+            let input_file = crate::utils::open_input(&input, sniff)?;
+            let input_path = input_file.path();
+
+            let findings = crate::checker::check_pdf(input_path, password.as_deref().unwrap_or(""))?;
+            match format {
+                cli::OutputFormat::Text => crate::checker::print_findings(&input, &findings),
+                cli::OutputFormat::Json => println!("{}", crate::checker::findings_json(&input, &findings)),
+            }
+            if findings.iter().any(|finding| finding.severity == crate::checker::Severity::Error) {
+                return Ok(1);
             }
-            let total_ratio = if total_original > 0 { crate::utils::calculate_compression_ratio(total_original, total_optimized) } else { 0.0 };
-            println!("\nBatch Summary:\n==============\nFiles processed: {}/{}\nTotal original size: {}\nTotal optimized size: {}\nTotal space saved: {:.1}%\nTotal images optimized: {}", successful_files, files.len(), crate::utils::format_bytes(total_original), crate::utils::format_bytes(total_optimized), total_ratio, total_images);
         }
-        "2" => {
-            print!("Input PDF: ");
-            io::stdout().flush().unwrap();
-            let mut input_str = String::new();
-            io::stdin().read_line(&mut input_str).unwrap();
-            let input = PathBuf::from(input_str.trim());
-            crate::utils::validate_input_file(&input)?;
-            let doc = crate::pdf_reader::load_pdf(&input)?;
+        cli::Commands::Repair { input, output, password } => {
+            let input_file = crate::utils::open_input(&input, sniff)?;
+            let input_path = input_file.path();
+
+            let (mut doc, repair) = crate::pdf_reader::load_repaired(input_path)?;
+            let encryption = crate::encryption::decrypt_document(&mut doc, password.as_deref().unwrap_or(""))
+                .with_context(|| format!("Failed to decrypt {}", input_path.display()))?;
             crate::pdf_reader::validate_pdf(&doc)?;
-            let analysis = crate::analyzer::analyze_pdf(&doc)?;
-            crate::analyzer::print_analysis(&analysis, true);
-            let file_size = crate::utils::get_file_size(&input)?;
-            println!("File size: {}", crate::utils::format_bytes(file_size));
+
+            // Only the file structure is rewritten: streams stay as they are
+            let save_options = crate::pdf_writer::SaveOptions { enable_compression: false, encryption, ..Default::default() };
+            crate::pdf_writer::save_pdf(&mut doc, &output, &save_options)?;
+
+            match repair {
+                Some(repair) => println!("Repaired {}: {}", input.display(), repair),
+                None => println!("{} isn't damaged; rewrote it as it is", input.display()),
+            }
+            println!("Wrote {} ({} pages)", output.display(), doc.get_pages().len());
         }
-        "3" => {
-            print!("Input PDFs (space separated): ");
-            io::stdout().flush().unwrap();
-            let mut files_str = String::new();
-            io::stdin().read_line(&mut files_str).unwrap();
-            let files: Vec<PathBuf> = files_str.trim().split_whitespace().map(PathBuf::from).collect();
+        cli::Commands::Batch { mut files, files_from, null, output_dir, flatten, name_template, in_place, backup, no_backup, skip_existing, skip_if_newer, overwrite, state_file, retry_failed, skip_optimized, threads, timeout, max_memory, no_progress, deterministic, report, optimize } => {
+            let mut explicit_outputs = vec![None; files.len()];
+            if let Some(manifest) = &files_from {
+                for entry in crate::batch::read_manifest(manifest, null)? {
+                    files.push(entry.input);
+                    explicit_outputs.push(entry.output);
+                }
+            }
             if files.is_empty() {
-                eprintln!("No input files specified");
-                return Ok(());
+                eprintln!("Error: No input files specified");
+                return Ok(1);
             }
-            for file in &files {
-                crate::utils::validate_input_file(file)?;
-            }
-            print!("Output directory (optional): ");
-            io::stdout().flush().unwrap();
-            let mut outdir_str = String::new();
-            io::stdin().read_line(&mut outdir_str).unwrap();
-            let output_dir = if outdir_str.trim().is_empty() { None } else { Some(PathBuf::from(outdir_str.trim())) };
-            print!("Threads (default 4): ");
-            io::stdout().flush().unwrap();
-            let mut threads_str = String::new();
-            io::stdin().read_line(&mut threads_str).unwrap();
-            let threads = threads_str.trim().parse().unwrap_or(4);
-            println!("Batch processing {} files with {} threads", files.len(), threads);
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(threads)
-                .build_global()
-                .unwrap_or_else(|_| eprintln!("Warning: Failed to set thread count, using default"));
-            let work_items: Vec<_> = files.iter().enumerate().map(|(i, input_file)| {
-                let output_file = if let Some(ref dir) = output_dir {
-                    dir.join(input_file.file_name().unwrap())
-                } else {
-                    input_file.with_extension("optimized.pdf")
-                };
-                (i, input_file.clone(), output_file)
-            }).collect();
-            let results: Vec<_> = work_items.into_par_iter().map(|(i, input_file, output_file)| {
-                println!("Processing file {}/{}: {}", i + 1, files.len(), input_file.display());
-                match crate::optimizer::optimize_pdf(&input_file, &output_file, 80, &cli::Preset::Web, false) {
-                    Ok(result) => {
-                        println!("  ✓ Saved {:.1}% ({})", result.compression_ratio, crate::utils::format_bytes(result.original_size - result.optimized_size));
-                        Ok(result)
-                    }
-                    Err(e) => {
-                        eprintln!("  ✗ Failed: {}", e);
-                        Err(e)
-                    }
+            if in_place {
+                if let Some(input) = files.iter().find(|input| crate::utils::is_url(&input.to_string_lossy())) {
+                    anyhow::bail!("--in-place can't replace a download: {}", input.display());
+                }
+                if explicit_outputs.iter().any(Option::is_some) {
+                    anyhow::bail!("--in-place replaces the inputs, so --files-from can't give them outputs");
                 }
-            }).collect();
-            let mut total_original = 0u64;
-            let mut total_optimized = 0u64;
-            let mut total_images = 0usize;
-            let mut successful_files = 0;
-            for result in results {
-                if let Ok(ref res) = result {
-                    total_original += res.original_size;
-                    total_optimized += res.optimized_size;
-                    total_images += res.images_optimized;
-                    successful_files += 1;
+            }
+            // Two threads writing the same output would corrupt it
+            let (files, explicit_outputs) = crate::batch::dedupe_inputs(files, explicit_outputs);
+
+            // A file that can't be read fails on its own instead of stopping the batch
+            let resolved_files = crate::batch::resolve_inputs(&files, sniff);
+
+            // Check the report's name before spending hours on the batch
+            let report_format = report.as_deref().map(crate::report::ReportFormat::from_path).transpose()?;
+
+            let mut options = crate::optimizer::OptimizeOptions { deterministic, ..crate::optimizer::OptimizeOptions::from_args(&optimize) };
+            // Downscaling hundreds of scans with Lanczos3 dominates batch runtime
+            options.resize_filter.get_or_insert(cli::ResizeFilter::CatmullRom);
+            options.in_place = in_place.then(|| crate::optimizer::InPlace::new(backup, no_backup, overwrite));
+            options.memory_budget = max_memory.map(|megabytes| std::sync::Arc::new(crate::image_optimizer::MemoryBudget::new(megabytes << 20)));
+
+            let name_template = name_template.as_deref().map(crate::batch::NameTemplate::parse).transpose()?;
+            let naming = crate::batch::Naming { template: name_template.as_ref(), preset: &options.preset, quality: options.quality };
+            let output_files = if in_place {
+                files.clone()
+            } else {
+                crate::batch::output_paths(&files, &explicit_outputs, output_dir.as_deref(), flatten, &naming)?
+            };
+            match &options.in_place {
+                Some(in_place) => crate::batch::refuse_existing_backups(&resolved_files, in_place)?,
+                None => crate::batch::refuse_outputs_over_inputs(&resolved_files, &output_files)?,
+            }
+            let existing = match (overwrite || in_place, skip_existing, skip_if_newer) {
+                (true, _, _) => crate::batch::ExistingOutputs::Overwrite,
+                (_, true, _) => crate::batch::ExistingOutputs::Skip,
+                (_, _, true) => crate::batch::ExistingOutputs::SkipIfNewer,
+                _ => crate::batch::ExistingOutputs::Refuse,
+            };
+            let state = state_file.as_deref().map(|path| crate::batch_state::BatchState::load(path, retry_failed)).transpose()?;
+            if let (Some(state), Some(path)) = (&state, &state_file) {
+                if state.recorded() > 0 {
+                    println!("Resuming from {} ({} files recorded)", path.display(), state.recorded());
                 }
             }
-            let total_ratio = if total_original > 0 { crate::utils::calculate_compression_ratio(total_original, total_optimized) } else { 0.0 };
-            println!("\nBatch Summary:\n==============\nFiles processed: {}/{}\nTotal original size: {}\nTotal optimized size: {}\nTotal space saved: {:.1}%\nTotal images optimized: {}", successful_files, files.len(), crate::utils::format_bytes(total_original), crate::utils::format_bytes(total_optimized), total_ratio, total_images);
+            let work_items = crate::batch::work_items(&files, resolved_files, output_files, existing, state.as_ref())?;
+            if !options.dry_run {
+                crate::batch::create_output_dirs(&work_items)?;
+            }
+
+            let dry_run = if options.dry_run { " (dry run, nothing is written)" } else { "" };
+            println!("Batch processing {} files with {} threads{}", work_items.len(), threads, dry_run);
+
+            // Process files in parallel
+            let settings = crate::batch::RunSettings {
+                threads,
+                timeout: timeout.map(std::time::Duration::from_secs),
+                // Each run replaces the inputs; only go on with those asked to be more aggressive
+                skip_optimized: skip_optimized || in_place,
+                show_progress: !no_progress,
+            };
+            let records = crate::batch::run(work_items, &options, &settings, state.as_ref())?;
+
+            crate::batch::print_summary(&records);
+            if let Some(budget) = &options.memory_budget {
+                println!("Most memory reserved for decoding at once: {} of {}", crate::utils::format_bytes(budget.peak()), crate::utils::format_bytes(budget.limit()));
+            }
+            if let (Some(report), Some(format)) = (&report, report_format) {
+                let rows: Vec<_> = records.iter().map(crate::batch::FileRecord::report_row).collect();
+                crate::report::write_report(report, format, &rows)?;
+                println!("Report written to {}", report.display());
+            }
+            return Ok(crate::batch::exit_code(&records));
         }
-        _ => println!("Invalid choice"),
     }
-    Ok(())
+
+    Ok(0)
 }
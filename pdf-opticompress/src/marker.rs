@@ -0,0 +1,97 @@
+use clap::ValueEnum;
+use lopdf::{Document, Object};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Preset;
+use crate::image_optimizer::create_image_settings_for_preset;
+
+/// The document information entry the optimizer stamps its outputs with
+const MARKER_KEY: &str = "PdfOpticompress";
+
+/// Which version of the optimizer wrote a file, and with which settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Marker {
+    pub version: String,
+    pub preset: Preset,
+    pub quality: u8,
+}
+
+impl Marker {
+    /// The marker for an output of this version
+    pub fn new(preset: Preset, quality: u8) -> Self {
+        Marker { version: env!("CARGO_PKG_VERSION").to_string(), preset, quality }
+    }
+
+    /// As stored, e.g. {"version":"0.1.0","preset":"web","quality":80}
+    fn to_text(&self) -> String {
+        serde_json::to_string(self).expect("a marker serializes")
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        serde_json::from_str(text).ok()
+    }
+
+    /// Whether the file was optimized at least as aggressively as `preset` and `quality`
+    /// would: with a preset further along archive, print, web, scan and maximum, or the same
+    /// one at a JPEG quality (as the preset limits it) no higher
+    pub fn covers(&self, preset: Preset, quality: u8) -> bool {
+        let effective = |preset: Preset, quality: u8| create_image_settings_for_preset(&preset, quality).jpeg_quality;
+        match aggressiveness(self.preset).cmp(&aggressiveness(preset)) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Equal => effective(self.preset, self.quality) <= effective(preset, quality),
+            std::cmp::Ordering::Less => false,
+        }
+    }
+
+    /// For people: "pdf-opticompress 0.1.0 (preset web, quality 80)"
+    pub fn describe(&self) -> String {
+        format!("pdf-opticompress {} (preset {}, quality {})", self.version, preset_name(self.preset), self.quality)
+    }
+}
+
+fn preset_name(preset: Preset) -> String {
+    preset.to_possible_value().map_or(String::new(), |value| value.get_name().to_string())
+}
+
+/// How far each preset goes, from the most faithful
+fn aggressiveness(preset: Preset) -> u8 {
+    match preset {
+        Preset::Archive => 0,
+        Preset::Print => 1,
+        Preset::Web => 2,
+        Preset::Scan => 3,
+        Preset::Maximum => 4,
+    }
+}
+
+/// Record the marker in the document information dictionary, creating it if there is none
+pub fn stamp(doc: &mut Document, marker: &Marker) {
+    let value = crate::pdf_writer::text_string(&marker.to_text());
+    match doc.trailer.get(b"Info") {
+        Ok(Object::Reference(id)) => {
+            let id = *id;
+            if let Ok(info) = doc.get_dictionary_mut(id) {
+                info.set(MARKER_KEY, value);
+                return;
+            }
+        }
+        Ok(Object::Dictionary(_)) => {
+            if let Ok(Object::Dictionary(info)) = doc.trailer.get_mut(b"Info") {
+                info.set(MARKER_KEY, value);
+                return;
+            }
+        }
+        _ => {}
+    }
+    let id = doc.add_object(lopdf::dictionary! { MARKER_KEY => value });
+    doc.trailer.set("Info", id);
+}
+
+/// The marker a document was stamped with, if any
+pub fn read_marker(doc: &Document) -> Option<Marker> {
+    let info = doc.trailer.get(b"Info").ok().and_then(|info| crate::objects::resolve(doc, info).as_dict().ok())?;
+    let Ok(Object::String(bytes, _)) = info.get(MARKER_KEY.as_bytes()).map(|value| crate::objects::resolve(doc, value)) else {
+        return None;
+    };
+    Marker::parse(&crate::pdf_writer::decode_text_string(bytes))
+}
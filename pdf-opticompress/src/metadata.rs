@@ -0,0 +1,175 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::HashSet;
+
+use crate::objects::{for_each_reference, referenced_ids};
+
+/// Results of the metadata removal pass
+#[derive(Debug, Default, Clone)]
+pub struct MetadataStats {
+    pub info_removed: bool,
+    pub metadata_streams_removed: usize,
+    pub bytes_removed: u64,
+}
+
+/// Results of the private application data removal pass
+#[derive(Debug, Default, Clone)]
+pub struct PrivateDataStats {
+    pub entries_removed: usize,
+    pub bytes_removed: u64,
+}
+
+/// Remove the document information dictionary (`/Info`) and every XMP `/Metadata`
+/// stream, whether attached to the catalog, a page, an image or any other object
+pub fn strip_metadata(doc: &mut Document) -> MetadataStats {
+    let mut stats = MetadataStats::default();
+
+    let info = doc.trailer.remove(b"Info");
+    stats.info_removed = info.is_some();
+
+    let mut candidates = Vec::new();
+    if let Some(Object::Reference(id)) = info {
+        candidates.push(id);
+    }
+    for object in doc.objects.values_mut() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        if let Some(Object::Reference(id)) = dict.remove(b"Metadata") {
+            candidates.push(id);
+        }
+    }
+    // Metadata streams nothing points at anymore
+    candidates.extend(
+        doc.objects
+            .iter()
+            .filter(|(_, object)| object.as_stream().is_ok_and(is_metadata_stream))
+            .map(|(id, _)| *id),
+    );
+
+    let referenced = referenced_ids(doc);
+    for id in candidates {
+        if referenced.contains(&id) {
+            continue;
+        }
+        if let Some(Object::Stream(stream)) = doc.objects.remove(&id) {
+            stats.metadata_streams_removed += 1;
+            stats.bytes_removed += stream.content.len() as u64;
+        }
+    }
+
+    stats
+}
+
+fn is_metadata_stream(stream: &Stream) -> bool {
+    matches!(stream.dict.get(b"Type"), Ok(Object::Name(name)) if name == b"Metadata")
+}
+
+/// Remove private application data: `/PieceInfo` (Illustrator and InDesign keep their
+/// editable source there), `/Alternates` of images, and `/Metadata` on anything but the
+/// catalog. The data itself is left for the unreachable-object pass, which is what
+/// `bytes_removed` (the size of the streams only these entries reach) assumes.
+pub fn strip_private_data(doc: &mut Document) -> PrivateDataStats {
+    let mut stats = PrivateDataStats {
+        bytes_removed: private_data_size(doc),
+        ..Default::default()
+    };
+    let catalog_id = catalog_id(doc);
+    for (id, object) in doc.objects.iter_mut() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        for key in private_keys(dict, Some(*id) == catalog_id) {
+            dict.remove(key);
+            stats.entries_removed += 1;
+        }
+    }
+    stats
+}
+
+/// Bytes of the streams that only private application data entries lead to
+pub fn private_data_size(doc: &Document) -> u64 {
+    let all = reachable_ids(doc, false);
+    let without_private = reachable_ids(doc, true);
+    all.difference(&without_private)
+        .filter_map(|id| doc.objects.get(id)?.as_stream().ok())
+        .map(|stream| stream.content.len() as u64)
+        .sum()
+}
+
+/// Objects reachable from the trailer, optionally not following private data entries
+fn reachable_ids(doc: &Document, skip_private: bool) -> HashSet<ObjectId> {
+    let catalog_id = catalog_id(doc);
+    let mut reachable = HashSet::new();
+    let mut pending = Vec::new();
+    for (_, value) in doc.trailer.iter() {
+        for_each_reference(value, &mut |id| pending.push(id));
+    }
+    while let Some(id) = pending.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        let Some(object) = doc.objects.get(&id) else { continue };
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &stream.dict,
+            other => {
+                for_each_reference(other, &mut |child| pending.push(child));
+                continue;
+            }
+        };
+        let skipped = if skip_private { private_keys(dict, Some(id) == catalog_id) } else { Vec::new() };
+        for (key, value) in dict.iter() {
+            if !skipped.contains(&key.as_slice()) {
+                for_each_reference(value, &mut |child| pending.push(child));
+            }
+        }
+    }
+    reachable
+}
+
+/// The private data entries present in a dictionary
+fn private_keys(dict: &Dictionary, is_catalog: bool) -> Vec<&'static [u8]> {
+    let mut keys: Vec<&'static [u8]> = Vec::new();
+    if dict.has(b"PieceInfo") {
+        keys.push(b"PieceInfo");
+    }
+    if dict.has(b"Alternates") && matches!(dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Image") {
+        keys.push(b"Alternates");
+    }
+    if dict.has(b"Metadata") && !is_catalog {
+        keys.push(b"Metadata");
+    }
+    keys
+}
+
+fn catalog_id(doc: &Document) -> Option<ObjectId> {
+    doc.trailer.get(b"Root").and_then(Object::as_reference).ok()
+}
+
+/// Print the document information dictionary and the catalog's XMP metadata
+pub fn print_metadata(doc: &Document) {
+    println!("Document information:");
+    let info = crate::pdf_reader::document_info(doc);
+    if info.is_empty() {
+        println!("  (none)");
+    }
+    for (key, value) in info {
+        println!("  {}: {}", key, value);
+    }
+
+    println!("XMP metadata:");
+    let properties = crate::xmp::catalog_metadata_id(doc)
+        .and_then(|id| crate::xmp::read_packet(doc, id))
+        .map(|packet| crate::xmp::read_xmp(&packet))
+        .unwrap_or_default();
+    if properties.is_empty() {
+        println!("  (none)");
+    }
+    for (property, value) in properties {
+        println!("  {}: {}", property.name, value);
+    }
+}
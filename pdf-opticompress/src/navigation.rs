@@ -0,0 +1,321 @@
+use anyhow::Result;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{HashMap, HashSet};
+
+use crate::objects::resolve;
+use crate::pdf_writer::decode_text_string;
+
+/// Where a destination leads
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// A page of this document, by index in the page tree
+    Page(usize),
+    /// A page of another document (remote destinations use page numbers)
+    External,
+    /// A page object that isn't in the page tree, or a name that isn't defined
+    Missing,
+}
+
+/// Every bookmark, named destination and internal link with the page it leads to,
+/// keyed by a description that stays the same across optimization
+#[derive(Debug, Default)]
+pub struct NavigationSnapshot {
+    pub destinations: Vec<(String, Target)>,
+}
+
+/// Nesting deeper than this is treated as a cycle
+const MAX_DEPTH: usize = 64;
+
+pub fn navigation_snapshot(doc: &Document) -> NavigationSnapshot {
+    let pages: HashMap<ObjectId, usize> = doc
+        .get_pages()
+        .into_values()
+        .enumerate()
+        .map(|(index, id)| (id, index))
+        .collect();
+    let named = NamedDestinations::new(doc);
+    let target = |dest: &Object| destination_target(doc, dest, &pages, &named);
+    let mut destinations = Vec::new();
+
+    for (name, dest) in &named.by_name {
+        destinations.push((format!("named destination /{}", String::from_utf8_lossy(name)), target(dest)));
+    }
+    for (name, dest) in &named.by_string {
+        destinations.push((format!("named destination ({})", String::from_utf8_lossy(name)), target(dest)));
+    }
+
+    let outlines = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| resolve(doc, outlines).as_dict().ok());
+    if let Some(outlines) = outlines {
+        let mut visited = HashSet::new();
+        collect_outline(doc, outlines, "", &mut visited, &mut |label, item| {
+            if let Some(dest) = item_destination(doc, item) {
+                destinations.push((format!("bookmark {}", label), target(dest)));
+            }
+        });
+    }
+
+    for (page_index, page_id) in doc.get_pages().into_values().enumerate() {
+        let Ok(page) = doc.get_dictionary(page_id) else { continue };
+        let Ok(Object::Array(annotations)) = page.get(b"Annots").map(|annots| resolve(doc, annots)) else { continue };
+        let links = annotations
+            .iter()
+            .filter_map(|annotation| resolve(doc, annotation).as_dict().ok())
+            .filter(|annotation| matches!(annotation.get(b"Subtype"), Ok(Object::Name(name)) if name == b"Link"));
+        for (link_index, link) in links.enumerate() {
+            if let Some(dest) = item_destination(doc, link) {
+                destinations.push((format!("link {} on page {}", link_index + 1, page_index + 1), target(dest)));
+            }
+        }
+    }
+
+    NavigationSnapshot { destinations }
+}
+
+/// Check that every destination of `before` still exists in `after` and leads to the
+/// same page. Links are allowed to disappear when `links_removed` (they were stripped).
+/// Returns the number of destinations checked.
+pub fn verify_navigation(before: &NavigationSnapshot, after: &NavigationSnapshot, links_removed: bool) -> Result<usize> {
+    let after: HashMap<&str, Target> = after
+        .destinations
+        .iter()
+        .map(|(source, target)| (source.as_str(), *target))
+        .collect();
+    let mut problems = Vec::new();
+    let mut checked = 0;
+    for (source, target) in &before.destinations {
+        checked += 1;
+        match after.get(source.as_str()) {
+            None if links_removed && source.starts_with("link ") => checked -= 1,
+            None => problems.push(format!("{} disappeared", source)),
+            Some(new) if new != target => problems.push(format!(
+                "{} leads to {} instead of {}",
+                source,
+                describe(*new),
+                describe(*target)
+            )),
+            Some(_) => {}
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(checked);
+    }
+    let shown: Vec<&str> = problems.iter().take(5).map(String::as_str).collect();
+    anyhow::bail!(
+        "{} of {} bookmarks, named destinations and links broke during optimization: {}{}",
+        problems.len(),
+        checked,
+        shown.join("; "),
+        if problems.len() > shown.len() { "; ..." } else { "" }
+    )
+}
+
+fn describe(target: Target) -> String {
+    match target {
+        Target::Page(index) => format!("page {}", index + 1),
+        Target::External => "another document".to_string(),
+        Target::Missing => "nothing".to_string(),
+    }
+}
+
+/// Replace the named destinations of bookmarks, links and GoTo actions with the explicit
+/// destinations they stand for, so they keep working without the catalog's name tables
+pub fn resolve_named_destinations(doc: &mut Document) {
+    let named = NamedDestinations::new(doc);
+    let explicit = |dest: &Object| -> Option<Object> {
+        let mut dest = named.lookup(resolve(doc, dest)).map(|dest| resolve(doc, dest))?;
+        if let Object::Dictionary(dict) = dest {
+            dest = resolve(doc, dict.get(b"D").ok()?);
+        }
+        matches!(dest, Object::Array(_)).then(|| dest.clone())
+    };
+
+    // Bookmarks and links hold a `/Dest` or a GoTo action, which may also stand on its own
+    let mut replacements: Vec<(ObjectId, bool, &[u8], Object)> = Vec::new();
+    for (id, object) in &doc.objects {
+        let Object::Dictionary(dict) = object else { continue };
+        if let Some(dest) = dict.get(b"Dest").ok().and_then(explicit) {
+            replacements.push((*id, false, b"Dest", dest));
+        }
+        if is_goto(dict) {
+            if let Some(dest) = dict.get(b"D").ok().and_then(explicit) {
+                replacements.push((*id, false, b"D", dest));
+            }
+        }
+        if let Ok(Object::Dictionary(action)) = dict.get(b"A") {
+            if let Some(dest) = action.get(b"D").ok().filter(|_| is_goto(action)).and_then(explicit) {
+                replacements.push((*id, true, b"D", dest));
+            }
+        }
+    }
+
+    for (id, in_action, key, dest) in replacements {
+        let Ok(dict) = doc.get_dictionary_mut(id) else { continue };
+        match in_action {
+            true => {
+                if let Ok(Object::Dictionary(action)) = dict.get_mut(b"A") {
+                    action.set(key, dest);
+                }
+            }
+            false => dict.set(key, dest),
+        }
+    }
+}
+
+fn is_goto(action: &Dictionary) -> bool {
+    matches!(action.get(b"S"), Ok(Object::Name(kind)) if kind == b"GoTo")
+}
+
+/// The catalog's `/Dests` dictionary (names) and `/Names /Dests` tree (strings)
+pub struct NamedDestinations {
+    by_name: Vec<(Vec<u8>, Object)>,
+    by_string: Vec<(Vec<u8>, Object)>,
+}
+
+impl NamedDestinations {
+    pub fn new(doc: &Document) -> Self {
+        let catalog = doc.catalog().ok();
+        let by_name = catalog
+            .and_then(|catalog| catalog.get(b"Dests").ok())
+            .and_then(|dests| resolve(doc, dests).as_dict().ok())
+            .map(|dests| dests.iter().map(|(name, dest)| (name.clone(), dest.clone())).collect())
+            .unwrap_or_default();
+        let mut by_string = Vec::new();
+        let tree = catalog
+            .and_then(|catalog| catalog.get(b"Names").ok())
+            .and_then(|names| resolve(doc, names).as_dict().ok())
+            .and_then(|names| names.get(b"Dests").ok());
+        if let Some(tree) = tree {
+            name_tree_entries(doc, tree, 0, &mut by_string);
+        }
+        Self { by_name, by_string }
+    }
+
+    fn lookup(&self, name: &Object) -> Option<&Object> {
+        let (entries, key) = match name {
+            Object::Name(key) => (&self.by_name, key),
+            Object::String(key, _) => (&self.by_string, key),
+            _ => return None,
+        };
+        entries.iter().find(|(name, _)| name == key).map(|(_, dest)| dest)
+    }
+
+    /// The explicit destination a destination stands for
+    fn explicit<'a>(&'a self, doc: &'a Document, dest: &'a Object) -> &'a Object {
+        let mut dest = resolve(doc, dest);
+        // Named destinations lead to an explicit one, possibly wrapped in a `/D` dictionary
+        for _ in 0..2 {
+            if let Some(explicit) = self.lookup(dest) {
+                dest = resolve(doc, explicit);
+            }
+            if let Object::Dictionary(dict) = dest {
+                dest = dict.get(b"D").map(|d| resolve(doc, d)).unwrap_or(dest);
+            }
+        }
+        dest
+    }
+
+    /// The page object a destination leads to, if it's a page of this document
+    pub fn page(&self, doc: &Document, dest: &Object) -> Option<ObjectId> {
+        match self.explicit(doc, dest) {
+            Object::Array(items) => items.first().and_then(|page| page.as_reference().ok()),
+            _ => None,
+        }
+    }
+}
+
+/// The keys and values of a name tree, in order
+pub fn name_tree(doc: &Document, tree: &Object) -> Vec<(Vec<u8>, Object)> {
+    let mut entries = Vec::new();
+    name_tree_entries(doc, tree, 0, &mut entries);
+    entries
+}
+
+fn name_tree_entries(doc: &Document, node: &Object, depth: usize, entries: &mut Vec<(Vec<u8>, Object)>) {
+    let Ok(node) = resolve(doc, node).as_dict() else { return };
+    if depth > MAX_DEPTH {
+        return;
+    }
+    if let Ok(Object::Array(names)) = node.get(b"Names").map(|names| resolve(doc, names)) {
+        for pair in names.chunks_exact(2) {
+            if let Object::String(key, _) = &pair[0] {
+                entries.push((key.clone(), pair[1].clone()));
+            }
+        }
+    }
+    if let Ok(Object::Array(kids)) = node.get(b"Kids").map(|kids| resolve(doc, kids)) {
+        for kid in kids {
+            name_tree_entries(doc, kid, depth + 1, entries);
+        }
+    }
+}
+
+/// The destination of an outline item or link: `/Dest`, or `/D` of a GoTo action
+pub fn item_destination<'a>(doc: &'a Document, item: &'a Dictionary) -> Option<&'a Object> {
+    if let Ok(dest) = item.get(b"Dest") {
+        return Some(dest);
+    }
+    let action = resolve(doc, item.get(b"A").ok()?).as_dict().ok()?;
+    match action.get(b"S") {
+        Ok(Object::Name(kind)) if kind == b"GoTo" => action.get(b"D").ok(),
+        _ => None,
+    }
+}
+
+fn destination_target(doc: &Document, dest: &Object, pages: &HashMap<ObjectId, usize>, named: &NamedDestinations) -> Target {
+    match named.explicit(doc, dest) {
+        Object::Array(items) => match items.first() {
+            Some(Object::Reference(page)) => pages.get(page).map_or(Target::Missing, |index| Target::Page(*index)),
+            Some(Object::Integer(_)) => Target::External,
+            _ => Target::Missing,
+        },
+        _ => Target::Missing,
+    }
+}
+
+/// The number of items in the document outline, at every level
+pub fn outline_len(doc: &Document) -> usize {
+    let outlines = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| resolve(doc, outlines).as_dict().ok());
+    let mut count = 0;
+    if let Some(outlines) = outlines {
+        collect_outline(doc, outlines, "", &mut HashSet::new(), &mut |_, _| count += 1);
+    }
+    count
+}
+
+/// Visit the outline items depth-first, labelled by their position (`2.1`) and title
+fn collect_outline(
+    doc: &Document,
+    parent: &Dictionary,
+    prefix: &str,
+    visited: &mut HashSet<ObjectId>,
+    visit: &mut dyn FnMut(&str, &Dictionary),
+) {
+    let mut next = parent.get(b"First").and_then(Object::as_reference).ok();
+    let mut position = 0;
+    while let Some(id) = next {
+        if !visited.insert(id) || visited.len() > 100_000 {
+            return;
+        }
+        let Ok(item) = doc.get_dictionary(id) else { return };
+        position += 1;
+        let title = match item.get(b"Title").map(|title| resolve(doc, title)) {
+            Ok(Object::String(title, _)) => decode_text_string(title),
+            _ => String::new(),
+        };
+        let number = if prefix.is_empty() { position.to_string() } else { format!("{}.{}", prefix, position) };
+        visit(&format!("{} '{}'", number, title), item);
+        if prefix.split('.').count() < MAX_DEPTH {
+            collect_outline(doc, item, &number, visited, visit);
+        }
+        next = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+}
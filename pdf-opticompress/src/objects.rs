@@ -0,0 +1,86 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{HashMap, HashSet};
+
+/// Visit every indirect reference contained in an object, recursing into arrays,
+/// dictionaries and stream dictionaries
+pub fn for_each_reference(object: &Object, visit: &mut impl FnMut(ObjectId)) {
+    match object {
+        Object::Reference(id) => visit(*id),
+        Object::Array(items) => items.iter().for_each(|item| for_each_reference(item, visit)),
+        Object::Dictionary(dict) => for_each_reference_in_dict(dict, visit),
+        Object::Stream(stream) => for_each_reference_in_dict(&stream.dict, visit),
+        _ => {}
+    }
+}
+
+fn for_each_reference_in_dict(dict: &Dictionary, visit: &mut impl FnMut(ObjectId)) {
+    for (_, value) in dict.iter() {
+        for_each_reference(value, visit);
+    }
+}
+
+/// Ids referenced from any object or from the trailer
+pub fn referenced_ids(doc: &Document) -> HashSet<ObjectId> {
+    let mut ids = HashSet::new();
+    for object in doc.objects.values() {
+        for_each_reference(object, &mut |id| {
+            ids.insert(id);
+        });
+    }
+    for_each_reference_in_dict(&doc.trailer, &mut |id| {
+        ids.insert(id);
+    });
+    ids
+}
+
+/// Rewrite references according to `replacements` everywhere in the document
+pub fn replace_references(doc: &mut Document, replacements: &HashMap<ObjectId, ObjectId>) {
+    if replacements.is_empty() {
+        return;
+    }
+    for object in doc.objects.values_mut() {
+        replace_in_object(object, replacements);
+    }
+    for (_, value) in doc.trailer.iter_mut() {
+        replace_in_object(value, replacements);
+    }
+}
+
+fn replace_in_object(object: &mut Object, replacements: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(new_id) = replacements.get(id) {
+                *id = *new_id;
+            }
+        }
+        Object::Array(items) => items.iter_mut().for_each(|item| replace_in_object(item, replacements)),
+        Object::Dictionary(dict) => {
+            for (_, value) in dict.iter_mut() {
+                replace_in_object(value, replacements);
+            }
+        }
+        Object::Stream(stream) => {
+            for (_, value) in stream.dict.iter_mut() {
+                replace_in_object(value, replacements);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Ids of the page thumbnail images (`/Thumb`)
+pub fn thumbnail_ids(doc: &Document) -> HashSet<ObjectId> {
+    doc.get_pages()
+        .values()
+        .filter_map(|page_id| doc.get_dictionary(*page_id).ok())
+        .filter_map(|page| page.get(b"Thumb").and_then(Object::as_reference).ok())
+        .collect()
+}
+
+/// Follow a reference to the object it points to; direct objects are returned as-is
+pub fn resolve<'a>(doc: &'a Document, object: &'a Object) -> &'a Object {
+    match object {
+        Object::Reference(id) => doc.get_object(*id).unwrap_or(object),
+        _ => object,
+    }
+}
@@ -1,38 +1,505 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
 use indicatif::{ProgressBar, ProgressStyle};
 use lopdf::Document;
-use std::path::Path;
-use std::time::Instant;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::analyzer::analyze_pdf;
-use crate::cli::Preset;
-use crate::image_optimizer::{optimize_images_in_pdf, create_image_settings_for_preset};
-use crate::pdf_reader::{load_pdf, validate_pdf};
-use crate::pdf_writer::{save_pdf, create_save_options_for_preset};
-use crate::utils::{get_file_size, calculate_compression_ratio, format_bytes};
+use crate::annotations::{flatten_forms, strip_annotations, AnnotationStats};
+use crate::cli::{ChromaSubsampling, ImageArgs, JpegBackend, OptimizeArgs, Preset, ResizeFilter, StageArgs, StructureArgs};
+use crate::content_streams::{optimize_content_streams, ContentStats};
+use crate::dedup::{deduplicate_fonts, DedupStats};
+use crate::fonts::{subset_fonts, unembed_standard_fonts, FontStats};
+use crate::forms::{form_snapshot, verify_form};
+use crate::icc::{process_icc_profiles, IccStats};
+use crate::navigation::{navigation_snapshot, verify_navigation, NavigationSnapshot};
+use crate::pages::parse_page_ranges;
+use crate::pdfa::{add_pdfa_requirements, check_pdfa, reject_forbidden_content, FindingStatus, PdfaReport, PDFA_LEVEL};
+use crate::metadata::{strip_metadata, strip_private_data, MetadataStats, PrivateDataStats};
+use crate::resources::{prune_page_resources, ResourceStats};
+use crate::sanitize::{sanitize, SanitizeStats};
+use crate::streams::{recompress_lzw_streams, strip_transport_filters, StreamStats};
+use crate::structure_tree::{strip_structure_tree, StructureTreeStats};
+use crate::image_optimizer::{
+    optimize_images_in_pdf, create_image_settings_for_preset, selected_images, DecodeCache, ImageAction, MemoryBudget, ImageOptimizationRecord,
+    ImageSettings, ImageStats,
+};
+use crate::encryption::{decrypt_document, new_encryption, required_version, EncryptionSettings};
+use crate::pdf_reader::{load_pdf, signature_info, validate_pdf, SignedDocumentError};
+use crate::pdf_writer::{
+    save_pdf, create_save_options_for_preset, parse_version, serialize_pdf, serialized_size, set_document_info,
+    version_drops_xref_stream, DocumentInfo, SaveOptions,
+};
+use crate::utils::{get_file_size, calculate_compression_ratio, format_bytes, is_std_stream};
+use crate::verification::{compare_content, content_snapshot, ContentSnapshot, VerificationError};
 
-/// Optimization results
+/// Which optimization stages run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stages {
+    #[default]
+    All,
+    ImagesOnly,
+    StructureOnly,
+}
+
+impl Stages {
+    pub fn from_args(args: &StageArgs) -> Self {
+        if args.images_only {
+            Stages::ImagesOnly
+        } else if args.structure_only {
+            Stages::StructureOnly
+        } else {
+            Stages::All
+        }
+    }
+
+    fn images(self) -> bool {
+        self != Stages::StructureOnly
+    }
+
+    fn structure(self) -> bool {
+        self != Stages::ImagesOnly
+    }
+}
+
+/// Options for a single optimization run
+#[derive(Clone)]
+pub struct OptimizeOptions {
+    pub quality: u8,
+    pub preset: Preset,
+    pub chroma_subsampling: Option<ChromaSubsampling>,
+    pub grayscale: bool,
+    pub bilevel: bool,
+    pub force_bilevel: bool,
+    pub jpeg_encoder: JpegBackend,
+    pub png_level: Option<u8>,
+    pub lossy_conversion: bool,
+    pub min_ssim: Option<f64>,
+    pub lossless: bool,
+    pub strip_icc: Option<bool>,
+    pub max_dimension: Option<u32>,
+    pub max_dpi: Option<u32>,
+    pub no_resize: bool,
+    pub resize_filter: Option<ResizeFilter>,
+    pub min_image_bytes: Option<u64>,
+    pub min_image_pixels: Option<u64>,
+    pub max_decode_pixels: Option<u64>,
+    pub max_decode_bytes: Option<u64>,
+    pub stages: Stages,
+    pub prune_resources: bool,
+    pub subset_fonts: bool,
+    pub unembed_standard_fonts: bool,
+    pub strip_thumbnails: bool,
+    pub recompress_lzw: bool,
+    pub flate_level: Option<u32>,
+    pub recompress_streams: bool, // Flate compress unfiltered streams and re-deflate Flate ones when saving
+    pub optimize_content_streams: bool,
+    pub content_precision: u32,
+    pub strip_metadata: bool,
+    pub strip_private_data: bool,
+    pub strip_structure: bool,
+    pub sanitize: bool,
+    pub flatten_forms: bool,
+    pub strip_annotations: Vec<String>, // Annotation subtypes to remove
+    pub document_info: DocumentInfo,
+    pub force_signed: bool, // Optimize digitally signed documents, breaking the signature
+    pub pdf_version: Option<String>, // Declared output version; None keeps the input's as far as possible
+    pub pdfa: bool, // Keep to what PDF/A allows and add what it requires
+    pub password: Option<String>, // User password of an encrypted input
+    pub keep_encryption: bool, // Encrypt the output as the input was
+    pub encrypt: Option<EncryptionSettings>, // Protect the output with new passwords
+    pub repair: bool, // Rebuild a damaged input's cross-reference table instead of failing
+    pub verify: bool, // Re-open the output and check its pages, text, bookmarks, destinations and links
+    pub keep_failed: bool, // Keep output that fails verification as <output>.failed
+    pub min_savings: Option<f64>, // Percentage below which the optimized output isn't kept
+    pub copy_below_threshold: bool, // Replace such output with a copy of the input instead of deleting it
+    pub deterministic: bool, // Same input and options, same bytes: no random file identifier
+    pub target_size: Option<u64>, // Search for the highest quality that fits in this many bytes
+    pub dry_run: bool, // Go through every stage in memory and estimate the output's size instead of writing it
+    pub in_place: Option<InPlace>, // Replace the input instead of writing the output
+    pub preserve_times: bool, // Give the output the input's modification and access times
+    pub preserve_mode: bool, // Give the output the input's permissions
+    pub pages: Option<String>, // Page ranges whose images are optimized, e.g. "180-420"; None = all
+    pub deadline: Option<Deadline>, // Give up with `TimedOutError` once past it
+    pub memory_budget: Option<Arc<MemoryBudget>>, // Memory for decoded images, shared across a batch
+    pub verbose: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            quality: 80,
+            preset: Preset::Web,
+            chroma_subsampling: None,
+            grayscale: false,
+            bilevel: false,
+            force_bilevel: false,
+            jpeg_encoder: JpegBackend::default(),
+            png_level: None,
+            lossy_conversion: true,
+            min_ssim: None,
+            lossless: false,
+            strip_icc: None,
+            max_dimension: None,
+            max_dpi: None,
+            no_resize: false,
+            resize_filter: None,
+            min_image_bytes: None,
+            min_image_pixels: None,
+            max_decode_pixels: None,
+            max_decode_bytes: None,
+            stages: Stages::All,
+            prune_resources: false,
+            subset_fonts: false,
+            unembed_standard_fonts: false,
+            strip_thumbnails: false,
+            recompress_lzw: false,
+            flate_level: None,
+            recompress_streams: true,
+            optimize_content_streams: false,
+            content_precision: 2,
+            strip_metadata: false,
+            strip_private_data: false,
+            strip_structure: false,
+            sanitize: false,
+            flatten_forms: false,
+            strip_annotations: Vec::new(),
+            document_info: DocumentInfo::default(),
+            force_signed: false,
+            pdf_version: None,
+            pdfa: false,
+            password: None,
+            keep_encryption: false,
+            encrypt: None,
+            repair: false,
+            verify: true,
+            keep_failed: false,
+            min_savings: None,
+            copy_below_threshold: false,
+            deterministic: false,
+            target_size: None,
+            dry_run: false,
+            in_place: None,
+            preserve_times: false,
+            preserve_mode: false,
+            pages: None,
+            deadline: None,
+            memory_budget: None,
+            verbose: false,
+        }
+    }
+}
+
+impl OptimizeOptions {
+    /// The options set by the flags shared by the Optimize and Batch commands; the
+    /// others keep their defaults
+    pub fn from_args(args: &OptimizeArgs) -> Self {
+        let mut options = OptimizeOptions {
+            quality: args.quality,
+            preset: args.preset,
+            chroma_subsampling: args.chroma,
+            strip_metadata: args.strip_metadata,
+            strip_private_data: args.strip_private_data,
+            strip_structure: args.strip_structure,
+            sanitize: args.sanitize,
+            flatten_forms: args.flatten_forms,
+            strip_annotations: args.strip_annotations.clone(),
+            force_signed: args.force_signed,
+            pdf_version: args.pdf_version.clone(),
+            pdfa: args.pdfa,
+            repair: args.repair,
+            verify: !args.no_verify,
+            keep_failed: args.keep_failed,
+            min_savings: args.min_savings,
+            copy_below_threshold: args.copy_below_threshold,
+            dry_run: args.dry_run,
+            preserve_times: args.preserve_times,
+            preserve_mode: args.preserve_mode,
+            stages: Stages::from_args(&args.stages),
+            ..Default::default()
+        };
+        options.apply_image_args(&args.image);
+        options.apply_structure_args(&args.structure);
+        options
+    }
+
+    /// Apply the document structure flags
+    fn apply_structure_args(&mut self, args: &StructureArgs) {
+        self.prune_resources = args.prune_resources;
+        self.subset_fonts = args.subset_fonts;
+        self.unembed_standard_fonts = args.unembed_standard_fonts;
+        self.strip_thumbnails = args.strip_thumbnails;
+        self.recompress_lzw = args.recompress_lzw;
+        self.flate_level = args.flate_level;
+        self.recompress_streams = !args.no_recompress_streams;
+        self.optimize_content_streams = args.optimize_content_streams;
+        self.content_precision = args.content_precision;
+    }
+
+    /// Apply the image flags
+    fn apply_image_args(&mut self, args: &ImageArgs) {
+        self.grayscale = args.grayscale;
+        self.bilevel = args.bilevel;
+        self.force_bilevel = args.force_bilevel;
+        self.png_level = args.png_level;
+        self.lossy_conversion = !args.no_lossy_conversion;
+        self.min_ssim = args.min_ssim;
+        self.lossless = args.lossless;
+        self.max_dimension = args.max_dimension;
+        self.max_dpi = args.max_dpi;
+        self.no_resize = args.no_resize;
+        if args.resize_filter.is_some() {
+            self.resize_filter = args.resize_filter;
+        }
+        self.min_image_bytes = args.min_image_size;
+        self.min_image_pixels = args.min_image_pixels;
+        self.max_decode_pixels = args.max_decode_pixels;
+        self.max_decode_bytes = args.max_decode_memory;
+        if args.strip_icc {
+            self.strip_icc = Some(true);
+        } else if args.keep_icc {
+            self.strip_icc = Some(false);
+        }
+        if let Some(backend) = args.jpeg_encoder {
+            if backend == JpegBackend::Mozjpeg && !cfg!(feature = "mozjpeg") {
+                eprintln!("Warning: mozjpeg support is not compiled in, using the built-in JPEG encoder");
+            }
+            self.jpeg_encoder = backend;
+        }
+    }
+
+    /// Image settings for the preset with the explicit overrides applied
+    pub fn image_settings(&self) -> ImageSettings {
+        let mut settings = create_image_settings_for_preset(&self.preset, self.quality);
+        if let Some(chroma) = self.chroma_subsampling {
+            settings.chroma_subsampling = chroma;
+        }
+        // The flags add to what the preset converts; they can't turn conversion off
+        settings.convert_to_grayscale |= self.grayscale;
+        settings.bilevel |= self.bilevel || self.force_bilevel;
+        settings.force_bilevel = self.force_bilevel;
+        settings.jpeg_encoder = self.jpeg_encoder;
+        if let Some(level) = self.png_level {
+            settings.png_level = level;
+        }
+        settings.lossy_conversion = self.lossy_conversion;
+        settings.min_ssim = self.min_ssim;
+        if let Some(strip) = self.strip_icc {
+            settings.strip_icc_profiles = strip;
+        }
+        if self.no_resize {
+            settings.max_dimension = None;
+            settings.max_dpi = None;
+        } else {
+            if let Some(max_dim) = self.max_dimension {
+                settings.max_dimension = Some(max_dim);
+            }
+            if let Some(max_dpi) = self.max_dpi {
+                settings.max_dpi = Some(max_dpi);
+            }
+        }
+        if let Some(filter) = self.resize_filter {
+            settings.resize_filter = filter;
+        }
+        if let Some(bytes) = self.min_image_bytes {
+            settings.min_image_bytes = bytes;
+        }
+        if let Some(pixels) = self.min_image_pixels {
+            settings.min_image_pixels = pixels;
+        }
+        if let Some(pixels) = self.max_decode_pixels {
+            settings.max_decode_pixels = pixels;
+        }
+        if let Some(bytes) = self.max_decode_bytes {
+            settings.max_decode_bytes = bytes;
+        }
+        if self.pdfa {
+            // Device color spaces only mean the same under a matching output intent
+            settings.strip_icc_profiles = false;
+        }
+        if self.lossless || self.pdfa {
+            // Only lossless steps remain, whatever the preset asked for
+            settings.lossless = true;
+            settings.max_dimension = None;
+            settings.max_dpi = None;
+            settings.convert_to_grayscale = false;
+            settings.bilevel = false;
+            settings.force_bilevel = false;
+            settings.lossy_conversion = false;
+            settings.min_ssim = None;
+        }
+        settings.deadline = self.deadline;
+        settings.memory_budget = self.memory_budget.clone();
+        settings
+    }
+}
+
+/// When to give up on a file. Checked between stages and between images, so a single
+/// stage or image that takes long runs to its end first.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    at: Instant,
+    limit: Duration,
+}
+
+impl Deadline {
+    pub fn after(limit: Duration) -> Self {
+        Deadline { at: Instant::now() + limit, limit }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if Instant::now() >= self.at {
+            return Err(TimedOutError { limit: self.limit }.into());
+        }
+        Ok(())
+    }
+}
+
+/// Returned when a file isn't done by its `Deadline`
 #[derive(Debug)]
+pub struct TimedOutError {
+    pub limit: Duration,
+}
+
+impl fmt::Display for TimedOutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out after {}s", self.limit.as_secs_f64())
+    }
+}
+
+impl std::error::Error for TimedOutError {}
+
+/// `Deadline::check`, for when there may be none
+pub fn check_deadline(deadline: Option<Deadline>) -> Result<()> {
+    deadline.as_ref().map_or(Ok(()), Deadline::check)
+}
+
+/// How an input optimized in place is replaced
+#[derive(Debug, Clone, Default)]
+pub struct InPlace {
+    pub backup_suffix: Option<String>, // Keep the original as <NAME><SUFFIX>, e.g. file.pdf.bak
+    pub replace_backup: bool,          // Replace a backup left by an earlier run instead of failing
+}
+
+impl InPlace {
+    /// From `--backup` and `--no-backup`: a backup named with the suffix, `.bak` by default
+    pub fn new(backup: Option<String>, no_backup: bool, replace_backup: bool) -> Self {
+        InPlace { backup_suffix: (!no_backup).then(|| backup.unwrap_or_else(|| ".bak".to_string())), replace_backup }
+    }
+
+    /// Where the original of `path` is kept, if it is
+    pub fn backup_path(&self, path: &Path) -> Option<PathBuf> {
+        self.backup_suffix.as_ref().map(|suffix| {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(suffix);
+            PathBuf::from(backup)
+        })
+    }
+
+    /// The backup of `path` an earlier run left, which this one would replace without
+    /// `replace_backup`; it may be the only copy of the real original
+    pub fn existing_backup(&self, path: &Path) -> Option<PathBuf> {
+        self.backup_path(path).filter(|backup| !self.replace_backup && backup.symlink_metadata().is_ok())
+    }
+}
+
+/// What became of an output that saved less than `--min-savings`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BelowThreshold {
+    Deleted,
+    OriginalCopied,
+    /// Optimizing in place, the input was left as it was
+    OriginalKept,
+}
+
+/// Optimization results
+#[derive(Debug, Serialize)]
 pub struct OptimizationResult {
     pub original_size: u64,
-    pub optimized_size: u64,
+    pub optimized_size: u64, // Estimated in a dry run
+    #[serde(serialize_with = "serialize_percentage")]
     pub compression_ratio: f64,
+    pub below_threshold: Option<BelowThreshold>, // Set when the output wasn't kept, or wouldn't be in a dry run
+    pub dry_run: bool, // Nothing was written
     pub images_optimized: usize,
+    pub images_skipped: usize,
+    pub images_resized: usize,
+    pub image_bytes_before: u64,
+    pub image_bytes_after: u64,
+    pub stream_bytes_before: u64,
+    pub stream_bytes_after: u64,
+    pub inline_images_optimized: usize,
+    pub duplicate_images_merged: usize,
+    pub duplicate_image_bytes_saved: u64,
+    pub duplicate_fonts_merged: usize,
+    pub duplicate_font_bytes_saved: u64,
+    pub resource_entries_pruned: usize,
+    pub fonts_subset: usize,
+    pub font_subset_bytes_saved: u64,
+    pub metadata_removed: bool,
+    pub metadata_updated: bool,
+    pub active_content_removed: SanitizeStats,
+    pub private_data_bytes_removed: u64,
+    pub structure_tree_removed: bool,
+    pub form_fields_flattened: usize,
+    pub form_fields_without_appearance: usize,
+    pub annotations_removed: usize,
+    pub pages_verified: Option<usize>,
+    pub destinations_verified: Option<usize>,
+    pub transport_filters_removed: usize,
+    pub transport_bytes_saved: i64,
+    pub lzw_streams_recompressed: usize,
+    pub content_streams_optimized: usize,
+    pub content_operators_removed: usize,
+    pub content_numbers_rounded: usize,
+    pub lzw_bytes_saved: i64,
+    #[serde(rename = "input_pdf_version")]
+    pub input_version: String,
+    #[serde(rename = "output_pdf_version")]
+    pub output_version: String,
+    pub fonts_unembedded: usize,
+    pub unembedded_font_bytes_saved: u64,
+    #[serde(skip)] // Only listed when asked for
+    pub image_records: Vec<ImageOptimizationRecord>,
+    pub images_converted_to_grayscale: usize,
+    pub images_converted_to_bilevel: usize,
+    pub jpeg_metadata_bytes_removed: u64,
+    pub icc_profiles_stripped: usize,
+    pub icc_profiles_deduplicated: usize,
+    pub icc_bytes_removed: u64,
+    pub lossless: bool,
+    #[serde(rename = "pdfa")]
+    pub pdfa_report: Option<PdfaReport>,
+    pub stages: Stages,
+    #[serde(rename = "processing_time_seconds", serialize_with = "serialize_seconds")]
     pub processing_time: std::time::Duration,
 }
 
+/// To two decimals, as it's printed
+fn serialize_percentage<S: Serializer>(percentage: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64((percentage * 100.0).round() / 100.0)
+}
+
+/// In seconds, to the millisecond
+fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_millis() as f64 / 1000.0)
+}
+
 /// Optimize a single PDF file
 pub fn optimize_pdf(
     input_path: &Path,
     output_path: &Path,
-    quality: u8,
-    preset: &Preset,
+    options: &OptimizeOptions,
     show_progress: bool,
 ) -> Result<OptimizationResult> {
-    let start_time = Instant::now();
-
     // Set up progress bar
-    let pb = if show_progress {
+    let pb = show_progress.then(|| {
         let pb = ProgressBar::new(100);
         pb.set_style(
             ProgressStyle::default_bar()
@@ -40,77 +507,953 @@ pub fn optimize_pdf(
                 .unwrap()
                 .progress_chars("#>-"),
         );
+        pb
+    });
+    let result = optimize_pdf_with_progress(input_path, output_path, options, pb.as_ref());
+    if let (Some(pb), Ok(_)) = (&pb, &result) {
+        pb.finish_with_message("Optimization complete!");
+    }
+    result
+}
+
+/// Optimize a single PDF file, showing each stage on `pb`, a bar of length 100 the caller
+/// owns and finishes. With `options.in_place`, the input is replaced and `output_path`
+/// isn't used.
+pub fn optimize_pdf_with_progress(
+    input_path: &Path,
+    output_path: &Path,
+    options: &OptimizeOptions,
+    pb: Option<&ProgressBar>,
+) -> Result<OptimizationResult> {
+    match &options.in_place {
+        Some(in_place) => optimize_in_place(input_path, options, in_place, pb),
+        None => optimize_to(input_path, output_path, options, pb),
+    }
+}
+
+/// Optimize a file where it sits: the output is written to a temporary file next to it and
+/// verified, and only then renamed over it, after the original is copied to its backup. When
+/// anything fails or the output saves less than `--min-savings`, the original is untouched.
+fn optimize_in_place(path: &Path, options: &OptimizeOptions, in_place: &InPlace, pb: Option<&ProgressBar>) -> Result<OptimizationResult> {
+    // Replace the file a symbolic link points to, not the link
+    let path = &std::fs::canonicalize(path).with_context(|| format!("Failed to resolve {}", path.display()))?;
+    if let Some(backup) = in_place.existing_backup(path) {
+        anyhow::bail!("The backup {} already exists (use --force to replace it)", backup.display());
+    }
+    if options.dry_run {
+        return optimize_to(path, path, options, pb);
+    }
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let prefix = format!(".{}.", path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default());
+    let temp = tempfile::Builder::new()
+        .prefix(&prefix)
+        .suffix(".tmp")
+        .tempfile_in(directory)
+        .with_context(|| format!("Failed to create a temporary file in {}", directory.display()))?
+        .into_temp_path();
+    // A copy of the input over itself would be pointless; the input is simply kept. The
+    // replacement looks like the original apart from its content.
+    let options = &OptimizeOptions { copy_below_threshold: false, preserve_times: true, preserve_mode: true, ..options.clone() };
+    let mut result = optimize_to(path, &temp, options, pb)?;
+    if result.below_threshold.is_some() {
+        result.below_threshold = Some(BelowThreshold::OriginalKept);
+        return Ok(result);
+    }
+
+    if let Some(backup) = in_place.backup_path(path) {
+        std::fs::copy(path, &backup)
+            .and_then(|_| std::fs::File::open(&backup)?.sync_all())
+            .with_context(|| format!("Failed to back up {} to {}", path.display(), backup.display()))?;
+    }
+    temp.persist(path).map_err(|e| e.error).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(result)
+}
+
+fn optimize_to(
+    input_path: &Path,
+    output_path: &Path,
+    options: &OptimizeOptions,
+    pb: Option<&ProgressBar>,
+) -> Result<OptimizationResult> {
+    let start_time = Instant::now();
+    if options.target_size.is_some() && (options.lossless || !options.stages.images()) {
+        anyhow::bail!("--target-size needs lossy image recompression and can't be combined with --lossless or --structure-only");
+    }
+    if options.strip_annotations.iter().any(|subtype| subtype.eq_ignore_ascii_case("Widget")) {
+        anyhow::bail!("Form field widgets can't be stripped on their own; use --flatten-forms");
+    }
+    if options.pdfa {
+        check_pdfa_options(options)?;
+    }
+    if options.deterministic && (options.keep_encryption || options.encrypt.is_some()) {
+        anyhow::bail!("Encryption uses random keys and salts, so --deterministic can't be combined with --keep-encryption or --encrypt-owner");
+    }
+
+    if let Some(pb) = pb {
         pb.set_message("Loading PDF...");
-        Some(pb)
-    } else {
-        None
-    };
+    }
+
+    // Taken before reading the input changes its access time
+    let input_metadata = (options.preserve_times || options.preserve_mode)
+        .then(|| std::fs::metadata(input_path))
+        .transpose()
+        .with_context(|| format!("Failed to read {}", input_path.display()))?;
 
     // Load and validate PDF
-    let mut doc = load_pdf(input_path)?;
+    let mut doc = load_pdf(input_path, options.repair)?;
+    let password = options.password.as_deref().unwrap_or("");
+    let encryption = decrypt_document(&mut doc, password)
+        .with_context(|| format!("Failed to decrypt {}", input_path.display()))?;
     validate_pdf(&doc)?;
+    check_deadline(options.deadline)?;
+    match (&encryption, options.keep_encryption) {
+        (Some(_), false) if options.encrypt.is_none() => eprintln!(
+            "Note: {} is encrypted; the output won't be (use --keep-encryption to keep its passwords and permissions)",
+            input_path.display()
+        ),
+        (None, true) => eprintln!("Warning: {} isn't encrypted; --keep-encryption has nothing to keep", input_path.display()),
+        _ => {}
+    }
+
+    // Rewriting the file changes the byte ranges a signature covers
+    if signature_info(&doc).is_signed() {
+        if !options.force_signed {
+            return Err(SignedDocumentError.into());
+        }
+        eprintln!("Warning: {} is digitally signed; its signature will no longer be valid", input_path.display());
+    }
+    // Fillable forms must come through intact unless they are being flattened
+    let form_before = if options.flatten_forms { None } else { form_snapshot(&doc) };
+    // Verification re-opens the written file, so a dry run has nothing to verify
+    let snapshots_before = (options.verify && !options.dry_run).then(|| (content_snapshot(&doc), navigation_snapshot(&doc)));
 
-    if let Some(ref pb) = pb {
+    if let Some(pb) = pb {
         pb.set_message("Analyzing content...");
         pb.inc(10);
     }
 
-    // Analyze the PDF
-    let analysis = analyze_pdf(&doc)?;
+    let stream_bytes_before = total_stream_bytes(&doc);
+    check_deadline(options.deadline)?;
 
-    if let Some(ref pb) = pb {
+    if let Some(pb) = pb {
         pb.set_message("Optimizing images...");
         pb.inc(20);
     }
 
     // Create optimization settings
-    let image_settings = create_image_settings_for_preset(preset, quality);
-    let save_options = create_save_options_for_preset(preset);
+    let mut image_settings = options.image_settings();
+    if let Some(pages) = &options.pages {
+        let ranges = parse_page_ranges(pages, doc.get_pages().len() as u32)?;
+        image_settings.pages = Some(ranges.into_iter().flatten().collect());
+    }
+    let mut save_options = create_save_options_for_preset(&options.preset);
+    save_options.strip_thumbnails |= options.strip_thumbnails;
+    save_options.recompress_lzw |= options.recompress_lzw;
+    if let Some(level) = options.flate_level {
+        save_options.flate_level = level;
+        save_options.zopfli = false;
+    }
+    save_options.enable_compression &= options.recompress_streams;
+    save_options.version = options.pdf_version.clone();
+    save_options.deterministic = options.deterministic;
+    if options.keep_encryption {
+        save_options.encryption = encryption;
+    }
+    if let Some(settings) = &options.encrypt {
+        save_options.encryption = Some(new_encryption(&mut doc, settings)?);
+    }
+    if let (Some(encryption), Some(version)) = (&save_options.encryption, &options.pdf_version) {
+        let required = required_version(encryption);
+        if parse_version(version).is_some_and(|version| version < required) {
+            anyhow::bail!("PDF {} can't hold this encryption; it needs PDF {}.{} or later", version, required.0, required.1);
+        }
+    }
+    let input_version = doc.version.clone();
+    if let Some(version) = &options.pdf_version {
+        if version_drops_xref_stream(&doc, version) {
+            eprintln!("Warning: PDF {} has no cross-reference streams; writing a cross-reference table instead", version);
+        }
+        if parse_version(version) < parse_version(&input_version) {
+            eprintln!(
+                "Warning: the input declares PDF {}; features newer than PDF {} that it uses are kept as they are",
+                input_version, version
+            );
+        }
+    }
+    if !options.stages.structure() {
+        save_options.enable_compression = false;
+        save_options.remove_unused_objects = false;
+        save_options.strip_thumbnails = false;
+    }
+
+    // Font programs embedded once per merged source document
+    let font_stats = if options.stages.structure() {
+        deduplicate_fonts(&mut doc)
+    } else {
+        DedupStats::default()
+    };
+    let resource_stats = if options.stages.structure() && options.prune_resources {
+        // Pruned entries only shrink the file once their objects are dropped
+        save_options.remove_unused_objects = true;
+        prune_page_resources(&mut doc)
+    } else {
+        ResourceStats::default()
+    };
+    let metadata_stats = if options.strip_metadata {
+        strip_metadata(&mut doc)
+    } else {
+        MetadataStats::default()
+    };
+    let private_data_stats = if options.strip_private_data {
+        save_options.remove_unused_objects = true;
+        strip_private_data(&mut doc)
+    } else {
+        PrivateDataStats::default()
+    };
+    let form_stats = if options.flatten_forms {
+        save_options.remove_unused_objects = true;
+        flatten_forms(&mut doc)
+    } else {
+        AnnotationStats::default()
+    };
+    let annotation_stats = if !options.strip_annotations.is_empty() {
+        save_options.remove_unused_objects = true;
+        strip_annotations(&mut doc, &options.strip_annotations)
+    } else {
+        AnnotationStats::default()
+    };
+    let structure_tree_stats = if options.strip_structure {
+        eprintln!(
+            "Warning: --strip-structure removes the document's tags; screen readers, reflow and \
+             PDF/UA conformance will no longer work on the output"
+        );
+        save_options.remove_unused_objects = true;
+        strip_structure_tree(&mut doc)
+    } else {
+        StructureTreeStats::default()
+    };
+    let sanitize_stats = if options.sanitize {
+        // Scripts and attachments only leave the file once nothing references them
+        save_options.remove_unused_objects = true;
+        sanitize(&mut doc)
+    } else {
+        SanitizeStats::default()
+    };
+    // After stripping, so `--strip-metadata` with `--set-title` leaves only the title
+    set_document_info(&mut doc, &options.document_info);
+    // After sanitizing, which removes what PDF/A forbids, and after the document
+    // information is final, so the XMP packet matches it
+    let pdfa_findings = if options.pdfa {
+        reject_forbidden_content(&doc)?;
+        save_options.recompress_lzw = true;
+        add_pdfa_requirements(&mut doc, options.deterministic)?
+    } else {
+        Vec::new()
+    };
+
+    check_deadline(options.deadline)?;
+    // Unembedding first, so standard fonts aren't subset only to be dropped
+    let unembed_stats = if options.stages.structure() && options.unembed_standard_fonts {
+        // The programs only leave the file once nothing references them
+        save_options.remove_unused_objects = true;
+        unembed_standard_fonts(&mut doc)
+    } else {
+        FontStats::default()
+    };
+    let subset_stats = if options.stages.structure() && options.subset_fonts {
+        subset_fonts(&mut doc, &save_options)
+    } else {
+        FontStats::default()
+    };
+
+    // Before the LZW pass and the image pass, which then see the binary filter underneath
+    let transport_stats = if options.stages.structure() {
+        strip_transport_filters(&mut doc, &save_options)
+    } else {
+        StreamStats::default()
+    };
+    let lzw_stats = if options.stages.structure() && save_options.recompress_lzw {
+        recompress_lzw_streams(&mut doc, &save_options)
+    } else {
+        StreamStats::default()
+    };
+
+    let content_stats = if options.stages.structure() && options.optimize_content_streams {
+        optimize_content_streams(&mut doc, options.content_precision, &save_options)
+    } else {
+        ContentStats::default()
+    };
 
-    // Optimize images
-    let images_optimized = optimize_images_in_pdf(&mut doc, &image_settings)?;
+    check_deadline(options.deadline)?;
+    // Optimize images, searching for the settings that meet the target size if there is one
+    let (image_stats, icc_stats) = match options.target_size {
+        _ if !options.stages.images() => (ImageStats::default(), IccStats::default()),
+        Some(target) => {
+            let attempt = optimize_to_target_size(&doc, &image_settings, &save_options, target, pb)?;
+            doc = attempt.doc;
+            (attempt.image_stats, attempt.icc_stats)
+        }
+        None => optimize_document(&mut doc, &image_settings)?,
+    };
 
-    if let Some(ref pb) = pb {
+    if let Some(pb) = pb {
         pb.set_message("Compressing structure...");
         pb.inc(30);
     }
 
-    // Save optimized PDF
-    save_pdf(&mut doc, output_path, &save_options)?;
+    // The marker lives in the document information, which --strip-metadata removes
+    if !options.strip_metadata {
+        crate::marker::stamp(&mut doc, &crate::marker::Marker::new(options.preset, options.quality));
+    }
 
-    if let Some(ref pb) = pb {
+    // Save optimized PDF; past this point the file is finished even if it runs late
+    check_deadline(options.deadline)?;
+    // The output, when it's kept in memory: in a dry run, to measure it, and for `-`, to be
+    // written to stdout once it's verified
+    let to_stdout = is_std_stream(output_path);
+    let mut buffer = if options.dry_run {
+        // Serialized with zlib, as the target size search does: zopfli would take as long as
+        // the real run and only makes the file smaller than estimated
+        let save_options = SaveOptions { zopfli: false, ..save_options.clone() };
+        Some(serialize_pdf(&mut doc, &save_options)?)
+    } else if to_stdout {
+        Some(serialize_pdf(&mut doc, &save_options)?)
+    } else {
+        save_pdf(&mut doc, output_path, &save_options)?;
+        None
+    };
+    if let Some(before) = &form_before {
+        if let Err(e) = verify_form(before, form_snapshot(&doc).as_ref()) {
+            // Don't leave a file with a broken form behind
+            if buffer.is_none() {
+                let _ = std::fs::remove_file(output_path);
+            }
+            return Err(e);
+        }
+    }
+    let pdfa_report = options.pdfa.then(|| check_pdfa(&doc, pdfa_findings));
+    let (pages_verified, destinations_verified) = match &snapshots_before {
+        Some((content_before, navigation_before)) => {
+            let password = options.encrypt.as_ref().map_or(password, |settings| settings.user_password.as_str());
+            let links_removed = options.strip_annotations.iter().any(|subtype| subtype.eq_ignore_ascii_case("Link"));
+            let written = match &buffer {
+                Some(bytes) => Document::load_mem(bytes).context("Failed to load the output"),
+                None => load_pdf(output_path, false),
+            };
+            match verify_output(written, password, content_before, navigation_before, links_removed) {
+                Ok(destinations) => (Some(content_before.pages.len()), Some(destinations)),
+                Err(problems) if buffer.is_some() => return Err(VerificationError { problems, kept_as: None }.into()),
+                Err(problems) => return Err(discard_failed_output(output_path, problems, options.keep_failed).into()),
+            }
+        }
+        None => (None, None),
+    };
+
+    if let Some(pb) = pb {
         pb.set_message("Finalizing...");
         pb.inc(30);
-        pb.finish_with_message("Optimization complete!");
     }
 
     // Calculate results
+    let stream_bytes_after = total_stream_bytes(&doc);
     let original_size = get_file_size(input_path)?;
-    let optimized_size = get_file_size(output_path)?;
+    let optimized_size = match &buffer {
+        Some(bytes) => bytes.len() as u64,
+        None => get_file_size(output_path)?,
+    };
     let compression_ratio = calculate_compression_ratio(original_size, optimized_size);
+    let below_threshold = match options.min_savings {
+        Some(min_savings) if compression_ratio < min_savings && options.dry_run => Some(if options.copy_below_threshold {
+            BelowThreshold::OriginalCopied
+        } else {
+            BelowThreshold::Deleted
+        }),
+        Some(min_savings) if compression_ratio < min_savings && options.copy_below_threshold => {
+            if to_stdout {
+                buffer = Some(std::fs::read(input_path).with_context(|| format!("Failed to read {}", input_path.display()))?);
+            } else {
+                std::fs::copy(input_path, output_path)
+                    .with_context(|| format!("Failed to copy {} to {}", input_path.display(), output_path.display()))?;
+            }
+            Some(BelowThreshold::OriginalCopied)
+        }
+        Some(min_savings) if compression_ratio < min_savings => {
+            if to_stdout {
+                buffer = None;
+            } else {
+                std::fs::remove_file(output_path).with_context(|| format!("Failed to delete {}", output_path.display()))?;
+            }
+            Some(BelowThreshold::Deleted)
+        }
+        _ => None,
+    };
+    if let Some(bytes) = buffer.filter(|_| to_stdout && !options.dry_run) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&bytes).and_then(|_| stdout.flush()).context("Failed to write the output to stdout")?;
+    }
+    let written = !to_stdout && !options.dry_run && below_threshold != Some(BelowThreshold::Deleted);
+    if let Some(metadata) = input_metadata.filter(|_| written) {
+        preserve_metadata(&metadata, output_path, options.preserve_times, options.preserve_mode)
+            .with_context(|| format!("Failed to give {} the times and permissions of {}", output_path.display(), input_path.display()))?;
+    }
     let processing_time = start_time.elapsed();
 
     Ok(OptimizationResult {
         original_size,
         optimized_size,
         compression_ratio,
-        images_optimized,
+        below_threshold,
+        dry_run: options.dry_run,
+        images_optimized: image_stats.optimized,
+        images_skipped: image_stats.skipped,
+        images_resized: image_stats
+            .records
+            .iter()
+            .filter(|record| matches!(record.action, ImageAction::Resized { .. }))
+            .count(),
+        image_bytes_before: image_stats.records.iter().map(|record| record.original_size).sum(),
+        image_bytes_after: image_stats.records.iter().map(|record| record.new_size).sum(),
+        stream_bytes_before,
+        stream_bytes_after,
+        inline_images_optimized: image_stats.inline_optimized,
+        duplicate_images_merged: image_stats.duplicates_merged,
+        duplicate_image_bytes_saved: image_stats.duplicate_bytes_saved,
+        duplicate_fonts_merged: font_stats.merged,
+        duplicate_font_bytes_saved: font_stats.bytes_saved,
+        resource_entries_pruned: resource_stats.entries_removed,
+        fonts_subset: subset_stats.fonts,
+        font_subset_bytes_saved: subset_stats.bytes_saved,
+        metadata_removed: metadata_stats.info_removed || metadata_stats.metadata_streams_removed > 0,
+        metadata_updated: !options.document_info.is_empty(),
+        active_content_removed: sanitize_stats,
+        private_data_bytes_removed: private_data_stats.bytes_removed,
+        structure_tree_removed: structure_tree_stats.tree_removed,
+        form_fields_flattened: form_stats.fields_flattened,
+        form_fields_without_appearance: form_stats.fields_without_appearance,
+        annotations_removed: annotation_stats.annotations_removed,
+        pages_verified,
+        destinations_verified,
+        transport_filters_removed: transport_stats.streams,
+        transport_bytes_saved: transport_stats.bytes_saved,
+        lzw_streams_recompressed: lzw_stats.streams,
+        content_streams_optimized: content_stats.streams,
+        content_operators_removed: content_stats.operators_removed,
+        content_numbers_rounded: content_stats.numbers_rounded,
+        lzw_bytes_saved: lzw_stats.bytes_saved,
+        input_version,
+        output_version: doc.version.clone(),
+        fonts_unembedded: unembed_stats.fonts,
+        unembedded_font_bytes_saved: unembed_stats.bytes_saved,
+        image_records: image_stats.records,
+        images_converted_to_grayscale: image_stats.converted_to_grayscale,
+        images_converted_to_bilevel: image_stats.converted_to_bilevel,
+        jpeg_metadata_bytes_removed: image_stats.jpeg_metadata_bytes_removed,
+        icc_profiles_stripped: icc_stats.profiles_removed,
+        icc_profiles_deduplicated: icc_stats.profiles_deduplicated,
+        icc_bytes_removed: icc_stats.bytes_removed,
+        lossless: options.lossless || options.pdfa,
+        pdfa_report,
+        stages: options.stages,
         processing_time,
     })
 }
 
-/// Print optimization results
-pub fn print_optimization_results(result: &OptimizationResult) {
-    println!("\nOptimization Results:");
-    println!("===================");
-    println!("Original size: {}", format_bytes(result.original_size));
-    println!("Optimized size: {}", format_bytes(result.optimized_size));
-    println!("Space saved: {:.1}%", result.compression_ratio);
-    println!("Images optimized: {}", result.images_optimized);
-    println!("Processing time: {:.2}s", result.processing_time.as_secs_f64());
+/// Compare the output, loaded back from the file or memory, with the input: it must load and pass
+/// `validate_pdf`, and keep its page count, the text of every page, and where its
+/// bookmarks, named destinations and links lead. Returns the number of destinations checked.
+fn verify_output(
+    written: Result<Document>,
+    password: &str,
+    content_before: &ContentSnapshot,
+    navigation_before: &NavigationSnapshot,
+    links_removed: bool,
+) -> std::result::Result<usize, Vec<String>> {
+    let mut written = written.map_err(|e| vec![format!("{:#}", e)])?;
+    decrypt_document(&mut written, password).map_err(|e| vec![format!("it can't be decrypted: {:#}", e)])?;
+    validate_pdf(&written).map_err(|e| vec![e.to_string()])?;
+
+    let mut problems = compare_content(content_before, &content_snapshot(&written));
+    let destinations = verify_navigation(navigation_before, &navigation_snapshot(&written), links_removed)
+        .unwrap_or_else(|e| {
+            problems.push(e.to_string());
+            0
+        });
+    if problems.is_empty() {
+        Ok(destinations)
+    } else {
+        Err(problems)
+    }
+}
+
+/// Copy the input's modification and access times and its permissions onto the output.
+/// The times go first: a read-only output couldn't be opened to set them afterwards.
+fn preserve_metadata(metadata: &std::fs::Metadata, output: &Path, times: bool, mode: bool) -> std::io::Result<()> {
+    if times {
+        let mut file_times = std::fs::FileTimes::new().set_modified(metadata.modified()?);
+        if let Ok(accessed) = metadata.accessed() {
+            file_times = file_times.set_accessed(accessed);
+        }
+        std::fs::File::options().write(true).open(output)?.set_times(file_times)?;
+    }
+    if mode {
+        std::fs::set_permissions(output, metadata.permissions())?;
+    }
+    Ok(())
+}
+
+/// Delete output that failed verification, or keep it with a `.failed` suffix
+fn discard_failed_output(path: &Path, problems: Vec<String>, keep: bool) -> VerificationError {
+    let kept_as = if keep {
+        let mut failed = path.as_os_str().to_owned();
+        failed.push(".failed");
+        let failed = std::path::PathBuf::from(failed);
+        std::fs::rename(path, &failed).ok().map(|_| failed)
+    } else {
+        let _ = std::fs::remove_file(path);
+        None
+    };
+    VerificationError { problems, kept_as }
+}
+
+/// Refuse options that would produce something PDF/A forbids
+fn check_pdfa_options(options: &OptimizeOptions) -> Result<()> {
+    if options.keep_encryption || options.encrypt.is_some() {
+        anyhow::bail!("PDF/A forbids encryption; --pdfa can't be combined with --keep-encryption or --encrypt-owner");
+    }
+    if options.unembed_standard_fonts {
+        anyhow::bail!("PDF/A requires every font to be embedded; --pdfa can't be combined with --unembed-standard-fonts");
+    }
+    if options.strip_metadata {
+        anyhow::bail!("PDF/A requires XMP metadata; --pdfa can't be combined with --strip-metadata");
+    }
+    if options.target_size.is_some() {
+        anyhow::bail!("--pdfa only recompresses images losslessly, so it can't be combined with --target-size");
+    }
+    if options.pdf_version.as_deref().and_then(parse_version).is_some_and(|version| version > (1, 7)) {
+        anyhow::bail!("{} is based on PDF 1.7; --pdfa can't be combined with --pdf-version 2.0", PDFA_LEVEL);
+    }
+    Ok(())
+}
+
+/// Run the image and ICC profile passes over a document
+fn optimize_document(doc: &mut Document, settings: &ImageSettings) -> Result<(ImageStats, IccStats)> {
+    let image_stats = optimize_images_in_pdf(doc, settings)?;
+    let selected = selected_images(doc, settings);
+    let icc_stats = process_icc_profiles(doc, settings.strip_icc_profiles, selected.as_ref());
+    Ok((image_stats, icc_stats))
+}
+
+/// A fully optimized in-memory copy of the document and its serialized size
+struct SizedAttempt {
+    doc: Document,
+    image_stats: ImageStats,
+    icc_stats: IccStats,
+    size: u64,
+}
+
+/// Optimize a copy of the document with the given settings and measure the result
+fn run_sized_attempt(doc: &Document, settings: &ImageSettings, save_options: &SaveOptions) -> Result<SizedAttempt> {
+    let mut attempt = doc.clone();
+    let (image_stats, icc_stats) = optimize_document(&mut attempt, settings)?;
+    let size = serialized_size(&mut attempt, save_options)?;
+    Ok(SizedAttempt {
+        doc: attempt,
+        image_stats,
+        icc_stats,
+        size,
+    })
+}
+
+/// Find the highest JPEG quality (lowering the size limit for images if even the lowest
+/// quality isn't enough) whose output fits in `target` bytes
+fn optimize_to_target_size(
+    doc: &Document,
+    settings: &ImageSettings,
+    save_options: &SaveOptions,
+    target: u64,
+    pb: Option<&ProgressBar>,
+) -> Result<SizedAttempt> {
+    const MIN_QUALITY: u8 = 10;
+    const DIMENSION_STEPS: [u32; 6] = [2400, 1600, 1200, 1000, 800, 600];
+
+    // Every attempt decodes the same images; do it once
+    let mut settings = ImageSettings {
+        decode_cache: Some(Arc::new(DecodeCache::default())),
+        ..settings.clone()
+    };
+    // Sizes are estimated with zlib; zopfli on every attempt would take far too long and
+    // only makes the final file smaller than estimated
+    let save_options = &SaveOptions {
+        zopfli: false,
+        ..save_options.clone()
+    };
+    let mut dimensions = vec![settings.max_dimension];
+    let current_limit = settings.max_dimension.unwrap_or(u32::MAX);
+    dimensions.extend(DIMENSION_STEPS.iter().filter(|&&d| d < current_limit).map(|&d| Some(d)));
+
+    let max_quality = settings.jpeg_quality.max(MIN_QUALITY);
+    let mut smallest: Option<(u64, u8, Option<u32>)> = None;
+    let mut attempts = 0;
+
+    for max_dimension in dimensions {
+        settings.max_dimension = max_dimension;
+        let (mut low, mut high) = (MIN_QUALITY, max_quality);
+        let mut best: Option<SizedAttempt> = None;
+
+        while low <= high {
+            let quality = low + (high - low) / 2;
+            settings.jpeg_quality = quality;
+            attempts += 1;
+            if let Some(pb) = pb {
+                pb.set_message(format!("Searching for target size (attempt {}, quality {})...", attempts, quality));
+            }
+
+            let attempt = run_sized_attempt(doc, &settings, save_options)?;
+            if attempt.size <= target {
+                low = quality + 1;
+                best = Some(attempt);
+            } else {
+                if smallest.is_none_or(|(size, _, _)| attempt.size < size) {
+                    smallest = Some((attempt.size, quality, max_dimension));
+                }
+                high = quality - 1;
+            }
+        }
+
+        if let Some(best) = best {
+            return Ok(best);
+        }
+    }
+
+    let (size, quality, max_dimension) = smallest.unwrap_or((0, MIN_QUALITY, None));
+    anyhow::bail!(
+        "Cannot reach the target size of {}: the smallest achievable output is {} (quality {}, max dimension {})",
+        format_bytes(target),
+        format_bytes(size),
+        quality,
+        max_dimension.map_or("unlimited".to_string(), |d| format!("{}px", d))
+    )
+}
+
+/// Total size of all stream data in the document as it would be written
+fn total_stream_bytes(doc: &Document) -> u64 {
+    doc.objects
+        .values()
+        .filter_map(|object| object.as_stream().ok())
+        .map(|stream| stream.content.len() as u64)
+        .sum()
+}
+
+impl OptimizationResult {
+    /// Saved bytes attributed to each stage; negative values mean that stage grew the file
+    pub fn savings_breakdown(&self) -> Vec<(&'static str, i64)> {
+        let resizing: i64 = self
+            .image_records
+            .iter()
+            .filter(|record| matches!(record.action, ImageAction::Resized { .. }))
+            .map(|record| record.original_size as i64 - record.new_size as i64)
+            .sum();
+        let images = self.image_bytes_before as i64 - self.image_bytes_after as i64;
+        let duplicates = self.duplicate_image_bytes_saved as i64;
+        let fonts = self.duplicate_font_bytes_saved as i64;
+        let subsetting = self.font_subset_bytes_saved as i64;
+        let unembedding = self.unembedded_font_bytes_saved as i64;
+        let streams = self.stream_bytes_before as i64 - self.stream_bytes_after as i64;
+        // Dropping ASCII transport encodings is a structural change, even though it
+        // shrinks stream data
+        let transport = self.transport_bytes_saved;
+        let total = self.original_size as i64 - self.optimized_size as i64;
+
+        vec![
+            ("Image recompression", images - resizing),
+            ("Image resizing", resizing),
+            ("Duplicate images", duplicates),
+            ("Duplicate fonts", fonts),
+            ("Font subsetting", subsetting),
+            ("Font unembedding", unembedding),
+            ("Other streams", streams - images - duplicates - fonts - subsetting - transport),
+            ("File structure", total - streams + transport),
+        ]
+    }
+}
+
+/// Print optimization results to `out`
+pub fn print_optimization_results(out: &mut dyn Write, result: &OptimizationResult) -> std::io::Result<()> {
+    if result.dry_run {
+        writeln!(out, "\nOptimization Plan (dry run, nothing written):")?;
+        writeln!(out, "============================================")?;
+    } else {
+        writeln!(out, "\nOptimization Results:")?;
+        writeln!(out, "===================")?;
+    }
+    if result.lossless {
+        writeln!(out, "Mode: lossless (no image pixels re-encoded)")?;
+    }
+    match result.stages {
+        Stages::All => {}
+        Stages::ImagesOnly => writeln!(out, "Stages: images only (structural compression skipped)")?,
+        Stages::StructureOnly => writeln!(out, "Stages: structure only (image optimization skipped)")?,
+    }
+    writeln!(out, "Original size: {}", format_bytes(result.original_size))?;
+    if result.dry_run {
+        writeln!(out, "Estimated size: {}", format_bytes(result.optimized_size))?;
+    } else {
+        writeln!(out, "Optimized size: {}", format_bytes(result.optimized_size))?;
+    }
+    writeln!(out, "Space saved: {:.1}%", result.compression_ratio)?;
+    match (result.below_threshold, result.dry_run) {
+        (Some(BelowThreshold::Deleted), false) => writeln!(out, "Below --min-savings: the output was deleted")?,
+        (Some(BelowThreshold::Deleted), true) => writeln!(out, "Below --min-savings: no output would be kept")?,
+        (Some(BelowThreshold::OriginalCopied), false) => writeln!(out, "Below --min-savings: the output is a copy of the input")?,
+        (Some(BelowThreshold::OriginalCopied), true) => writeln!(out, "Below --min-savings: the output would be a copy of the input")?,
+        (Some(BelowThreshold::OriginalKept), false) => writeln!(out, "Below --min-savings: the original was left as it was")?,
+        (Some(BelowThreshold::OriginalKept), true) => writeln!(out, "Below --min-savings: the original would be left as it was")?,
+        (None, _) => {}
+    }
+    writeln!(out, "Images optimized: {}", result.images_optimized)?;
+    if result.inline_images_optimized > 0 {
+        writeln!(out, "Inline images optimized: {}", result.inline_images_optimized)?;
+    }
+    if result.images_resized > 0 {
+        writeln!(out, "Images resized: {}", result.images_resized)?;
+    }
+    if result.images_skipped > 0 {
+        writeln!(out, "Images skipped: {}", result.images_skipped)?;
+    }
+    if result.duplicate_images_merged > 0 {
+        writeln!(out, 
+            "{} duplicate images merged, saving {}",
+            result.duplicate_images_merged,
+            format_bytes(result.duplicate_image_bytes_saved)
+        )?;
+    }
+    if result.duplicate_fonts_merged > 0 {
+        writeln!(out, 
+            "{} duplicate fonts merged, saving {}",
+            result.duplicate_fonts_merged,
+            format_bytes(result.duplicate_font_bytes_saved)
+        )?;
+    }
+    if result.fonts_subset > 0 {
+        writeln!(out, 
+            "Fonts subset: {} (saving {})",
+            result.fonts_subset,
+            format_bytes(result.font_subset_bytes_saved)
+        )?;
+    }
+    if result.metadata_removed {
+        writeln!(out, "Metadata removed")?;
+    }
+    if result.private_data_bytes_removed > 0 {
+        writeln!(out, "Private application data removed: {}", format_bytes(result.private_data_bytes_removed))?;
+    }
+    if result.form_fields_flattened > 0 {
+        writeln!(out, "Form fields flattened: {}", result.form_fields_flattened)?;
+    }
+    if result.form_fields_without_appearance > 0 {
+        writeln!(out, 
+            "Warning: {} form fields had no appearance stream, so their values were not drawn",
+            result.form_fields_without_appearance
+        )?;
+    }
+    if result.annotations_removed > 0 {
+        writeln!(out, "Annotations removed: {}", result.annotations_removed)?;
+    }
+    if result.transport_filters_removed > 0 {
+        writeln!(out, 
+            "ASCII-encoded streams converted to binary: {} (saving {})",
+            result.transport_filters_removed,
+            format_bytes(result.transport_bytes_saved.max(0) as u64)
+        )?;
+    }
+    if result.lzw_streams_recompressed > 0 {
+        writeln!(out, 
+            "LZW streams recompressed as Flate: {} (saving {})",
+            result.lzw_streams_recompressed,
+            format_bytes(result.lzw_bytes_saved.max(0) as u64)
+        )?;
+    }
+    if result.content_streams_optimized > 0 {
+        writeln!(out, 
+            "Content streams cleaned up: {} ({} redundant operators removed, {} numbers rounded)",
+            result.content_streams_optimized, result.content_operators_removed, result.content_numbers_rounded
+        )?;
+    }
+    if result.output_version != result.input_version {
+        writeln!(out, "PDF version: {} -> {}", result.input_version, result.output_version)?;
+    }
+    if let (Some(pages), Some(destinations)) = (result.pages_verified, result.destinations_verified) {
+        writeln!(out, 
+            "Verified: {} pages with unchanged text; {} bookmarks, named destinations and links lead to the same pages",
+            pages, destinations
+        )?;
+    }
+    if result.structure_tree_removed {
+        writeln!(out, "Structure tree removed: the document is no longer tagged for accessibility")?;
+    }
+    if result.metadata_updated {
+        writeln!(out, "Document information updated")?;
+    }
+    let removed = &result.active_content_removed;
+    if removed.total() > 0 {
+        let parts: Vec<String> = [
+            (removed.javascript, "document JavaScript"),
+            (removed.embedded_files, "embedded files"),
+            (removed.open_actions, "open action"),
+            (removed.additional_actions, "additional actions"),
+            (removed.risky_actions, "risky link/field actions"),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, label)| format!("{} {}", count, label))
+        .collect();
+        writeln!(out, "Active content removed: {}", parts.join(", "))?;
+    }
+    if result.fonts_unembedded > 0 {
+        writeln!(out, 
+            "Standard fonts unembedded: {} (saving {})",
+            result.fonts_unembedded,
+            format_bytes(result.unembedded_font_bytes_saved)
+        )?;
+    }
+    if result.resource_entries_pruned > 0 {
+        writeln!(out, "Unused resource entries removed: {}", result.resource_entries_pruned)?;
+    }
+    if result.images_converted_to_grayscale > 0 {
+        writeln!(out, "Images converted to grayscale: {}", result.images_converted_to_grayscale)?;
+    }
+    if result.images_converted_to_bilevel > 0 {
+        writeln!(out, "Images converted to bilevel: {}", result.images_converted_to_bilevel)?;
+    }
+    if result.jpeg_metadata_bytes_removed > 0 {
+        writeln!(out, "JPEG metadata removed: {}", format_bytes(result.jpeg_metadata_bytes_removed))?;
+    }
+    if result.icc_profiles_stripped > 0 {
+        writeln!(out, "ICC profiles stripped: {}", result.icc_profiles_stripped)?;
+    }
+    if result.icc_profiles_deduplicated > 0 {
+        writeln!(out, "Duplicate ICC profiles removed: {}", result.icc_profiles_deduplicated)?;
+    }
+    if result.icc_bytes_removed > 0 {
+        writeln!(out, "ICC profile bytes removed: {}", format_bytes(result.icc_bytes_removed))?;
+    }
+    if let Some(report) = &result.pdfa_report {
+        writeln!(out, "{} check:", PDFA_LEVEL)?;
+        for finding in &report.findings {
+            let status = match finding.status {
+                FindingStatus::Ok => "ok",
+                FindingStatus::Fixed => "fixed",
+                FindingStatus::Failed => "FAILED",
+            };
+            writeln!(out, "  {:<7}{}", status, finding.message)?;
+        }
+        if report.failures() > 0 {
+            writeln!(out, "  The output is not {} compliant; see the failed checks above", PDFA_LEVEL)?;
+        }
+    }
+    writeln!(out, "Processing time: {:.2}s", result.processing_time.as_secs_f64())?;
 
     if result.compression_ratio > 0.0 {
         let saved_bytes = result.original_size - result.optimized_size;
-        println!("Bytes saved: {}", format_bytes(saved_bytes));
+        writeln!(out, "Bytes saved: {}", format_bytes(saved_bytes))?;
+    }
+
+    writeln!(out, "\nSavings by stage:")?;
+    for (stage, saved) in result.savings_breakdown() {
+        let sign = if saved < 0 { "-" } else { "" };
+        writeln!(out, "  {:<20} {:>10}", stage, format!("{}{}", sign, format_bytes(saved.unsigned_abs())))?;
+    }
+    Ok(())
+}
+/// The sizes in the results JSON, each followed by the same size formatted as `<NAME>_formatted`
+const JSON_SIZES: [&str; 17] = [
+    "original_size",
+    "optimized_size",
+    "bytes_saved",
+    "image_bytes_before",
+    "image_bytes_after",
+    "stream_bytes_before",
+    "stream_bytes_after",
+    "jpeg_metadata_bytes_removed",
+    "duplicate_image_bytes_saved",
+    "duplicate_font_bytes_saved",
+    "font_subset_bytes_saved",
+    "unembedded_font_bytes_saved",
+    "transport_bytes_saved",
+    "lzw_bytes_saved",
+    "icc_bytes_removed",
+    "private_data_bytes_removed",
+    "bytes",
+];
+
+/// What `--format json` prints: the results, with what was optimized and how
+#[derive(Serialize)]
+struct ResultsJson<'a> {
+    input: String,
+    output: String,
+    version: &'static str,
+    preset: String,
+    quality: u8,
+    #[serde(flatten)]
+    result: &'a OptimizationResult,
+    bytes_saved: i64,
+    savings_by_stage: Vec<StageSavings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    images: Option<&'a [ImageOptimizationRecord]>,
+}
+
+#[derive(Serialize)]
+struct StageSavings {
+    stage: &'static str,
+    bytes: i64,
+}
+
+/// The results as a JSON object, with sizes in bytes (and formatted, under `..._formatted`).
+/// The per-image records are only included when `options.verbose`, or in a dry run, where
+/// they are the plan.
+pub fn optimization_results_json(input: &Path, output: &Path, options: &OptimizeOptions, result: &OptimizationResult) -> String {
+    let json = ResultsJson {
+        input: input.display().to_string(),
+        output: output.display().to_string(),
+        version: env!("CARGO_PKG_VERSION"),
+        preset: options.preset.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default(),
+        quality: options.quality,
+        result,
+        bytes_saved: result.original_size as i64 - result.optimized_size as i64,
+        savings_by_stage: result.savings_breakdown().into_iter().map(|(stage, bytes)| StageSavings { stage, bytes }).collect(),
+        images: (options.verbose || result.dry_run).then_some(&result.image_records),
+    };
+    let mut value = serde_json::to_value(json).expect("the results serialize to JSON");
+    with_formatted_sizes(&mut value);
+    serde_json::to_string_pretty(&value).expect("JSON values serialize")
+}
+
+/// Follow each of the `JSON_SIZES` in the object, and in the objects of its arrays, with its
+/// formatted counterpart
+fn with_formatted_sizes(value: &mut serde_json::Value) {
+    let serde_json::Value::Object(object) = value else {
+        return;
+    };
+    let mut formatted = serde_json::Map::new();
+    for (key, mut value) in std::mem::take(object) {
+        if let serde_json::Value::Array(items) = &mut value {
+            items.iter_mut().for_each(with_formatted_sizes);
+        }
+        let size = value.as_i64().filter(|_| JSON_SIZES.contains(&key.as_str()));
+        formatted.insert(key.clone(), value);
+        if let Some(size) = size {
+            let sign = if size < 0 { "-" } else { "" };
+            formatted.insert(format!("{}_formatted", key), format!("{}{}", sign, format_bytes(size.unsigned_abs())).into());
+        }
     }
-}
\ No newline at end of file
+    *object = formatted;
+}
+
+/// Print one line per image describing what was done to it, or would be in a dry run
+pub fn print_image_records(out: &mut dyn Write, result: &OptimizationResult) -> std::io::Result<()> {
+    writeln!(out, "\n{}:", if result.dry_run { "Planned image actions" } else { "Images" })?;
+    for record in &result.image_records {
+        writeln!(out, "  {}", record)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,761 @@
+use anyhow::Result;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use crate::navigation::{item_destination, resolve_named_destinations, NamedDestinations};
+use crate::objects::resolve;
+use crate::pdf_writer::{parse_version, remove_unreachable_objects, text_string};
+
+/// Page attributes a page may inherit from its ancestors in the page tree
+const INHERITABLE_KEYS: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Catalog entries that describe the document as a whole and still apply to a part of it
+const CATALOG_KEYS: [&[u8]; 3] = [b"Lang", b"ViewerPreferences", b"OutputIntents"];
+
+/// Parse page ranges such as `1-10,11-20,25,30-`, checking them against the page count.
+/// A range without an end runs to the last page.
+pub fn parse_page_ranges(spec: &str, page_count: u32) -> Result<Vec<RangeInclusive<u32>>> {
+    let mut ranges = Vec::new();
+    for part in spec.split(',').map(str::trim) {
+        if part.is_empty() {
+            anyhow::bail!("Empty page range in '{}'", spec);
+        }
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let number = |value: &str| value.trim().parse::<u32>().ok();
+        let (Some(first), Some(last)) = (number(first), if last.trim().is_empty() { Some(page_count) } else { number(last) }) else {
+            anyhow::bail!("'{}' isn't a page range: use N or N-M, e.g. 1-10,11-20", part);
+        };
+        if first == 0 {
+            anyhow::bail!("Page range '{}' starts at page 0; pages are numbered from 1", part);
+        }
+        if first > last {
+            anyhow::bail!("Page range '{}' ends before it starts", part);
+        }
+        if last > page_count {
+            let valid = if page_count == 1 { "the only page is 1".to_string() } else { format!("valid pages are 1-{}", page_count) };
+            anyhow::bail!("Page range '{}' goes past the last page: {}", part, valid);
+        }
+        ranges.push(first..=last);
+    }
+    Ok(ranges)
+}
+
+/// Consecutive ranges of `size` pages covering the whole document (the last may be shorter)
+pub fn chunk_ranges(page_count: u32, size: u32) -> Vec<RangeInclusive<u32>> {
+    (1..=page_count)
+        .step_by(size.max(1) as usize)
+        .map(|first| first..=(first + size - 1).min(page_count))
+        .collect()
+}
+
+/// A new document holding the given pages (numbered from 1) and everything they use:
+/// resources, fonts, images, annotations. References to pages that aren't copied and to
+/// the old page tree become null, and links leading to those pages are dropped, so the
+/// new document never refers to an object outside its own table.
+pub fn extract_pages(doc: &Document, page_numbers: &[u32]) -> Document {
+    let pages = doc.get_pages();
+    let selected: Vec<ObjectId> = page_numbers.iter().filter_map(|number| pages.get(number).copied()).collect();
+    let mut excluded = page_tree_nodes(doc);
+    excluded.extend(pages.values().copied().filter(|id| !selected.contains(id)));
+
+    let mut part = Document::with_version(doc.version.clone());
+    let pages_id = part.new_object_id();
+    let mut copier = Copier::new(doc, &mut part, excluded);
+    let kids = copier.copy_pages(&selected, pages_id);
+    let info = copier.copy_info();
+    let catalog_entries = copier.copy_catalog_entries();
+
+    finish_document(&mut part, pages_id, kids, catalog_entries, info);
+    part
+}
+
+/// Combine documents into one, their pages one after another. Each document gets a
+/// bookmark labelled `label` leading to its first page, with its own bookmarks below it.
+/// Named destinations are replaced with the explicit ones they stand for, and the form
+/// fields of all documents go into a single form with the first form's defaults. The
+/// document information and catalog settings of the first document are kept.
+pub fn merge_documents(sources: Vec<(String, Document)>) -> Document {
+    let version = sources
+        .iter()
+        .map(|(_, doc)| doc.version.clone())
+        .max_by_key(|version| parse_version(version))
+        .unwrap_or_else(|| "1.7".to_string());
+    let mut merged = Document::with_version(version);
+    let pages_id = merged.new_object_id();
+    let outlines_id = merged.new_object_id();
+    let mut kids = Vec::new();
+    // Each document's bookmark and how many bookmarks it shows when open
+    let mut bookmarks: Vec<(ObjectId, Dictionary, i64)> = Vec::new();
+    let mut fields = Vec::new();
+    let mut form: Option<Dictionary> = None;
+    let mut info = None;
+    let mut catalog_entries = Vec::new();
+
+    for (index, (label, mut doc)) in sources.into_iter().enumerate() {
+        resolve_named_destinations(&mut doc);
+        let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        let outline_root = doc
+            .catalog()
+            .ok()
+            .and_then(|catalog| catalog.get(b"Outlines").ok())
+            .and_then(|outlines| outlines.as_reference().ok());
+        let mut copier = Copier::new(&doc, &mut merged, page_tree_nodes(&doc));
+        // The document's bookmarks hang below its own bookmark instead of its outline root
+        let bookmark_id = copier.target.new_object_id();
+        if let Some(root) = outline_root {
+            copier.ids.insert(root, bookmark_id);
+        }
+        let source_kids = copier.copy_pages(&pages, pages_id);
+
+        let mut bookmark = dictionary! { "Title" => text_string(&label), "Parent" => outlines_id };
+        if let Some(first_page) = source_kids.first() {
+            bookmark.set("Dest", vec![first_page.clone(), Object::Name(b"Fit".to_vec())]);
+        }
+        let mut open = 0;
+        if let Some(root) = outline_root.and_then(|id| doc.get_dictionary(id).ok()) {
+            for key in [b"First".as_slice(), b"Last"] {
+                if let Ok(item) = root.get(key) {
+                    let item = copier.rewrite(item);
+                    bookmark.set(key, item);
+                }
+            }
+            copier.copy_pending();
+            if let Ok(Object::Integer(count)) = root.get(b"Count") {
+                open = (*count).max(0);
+            }
+        }
+        if open > 0 {
+            bookmark.set("Count", open);
+        }
+
+        let source_form = doc
+            .catalog()
+            .ok()
+            .and_then(|catalog| catalog.get(b"AcroForm").ok())
+            .and_then(|form| resolve(&doc, form).as_dict().ok());
+        if let Some(source_form) = source_form {
+            if let Ok(Object::Array(source_fields)) = source_form.get(b"Fields").map(|fields| resolve(&doc, fields)) {
+                fields.extend(source_fields.iter().map(|field| copier.rewrite(field)).filter(|field| !matches!(field, Object::Null)));
+            }
+            if form.is_none() {
+                let mut defaults = source_form.clone();
+                defaults.remove(b"Fields");
+                form = Some(copier.rewrite_dictionary(&defaults));
+            }
+            copier.copy_pending();
+        }
+        if index == 0 {
+            info = copier.copy_info();
+            catalog_entries = copier.copy_catalog_entries();
+        }
+        kids.extend(source_kids);
+        bookmarks.push((bookmark_id, bookmark, open));
+    }
+
+    let ids: Vec<ObjectId> = bookmarks.iter().map(|(id, _, _)| *id).collect();
+    let mut outlines = dictionary! { "Type" => "Outlines" };
+    if let (Some(first), Some(last)) = (ids.first(), ids.last()) {
+        outlines.set("First", *first);
+        outlines.set("Last", *last);
+    }
+    outlines.set("Count", bookmarks.iter().map(|(_, _, open)| 1 + open).sum::<i64>());
+    for (index, (id, mut bookmark, _)) in bookmarks.into_iter().enumerate() {
+        if index > 0 {
+            bookmark.set("Prev", ids[index - 1]);
+        }
+        if let Some(next) = ids.get(index + 1) {
+            bookmark.set("Next", *next);
+        }
+        merged.objects.insert(id, Object::Dictionary(bookmark));
+    }
+    merged.objects.insert(outlines_id, Object::Dictionary(outlines));
+
+    let catalog_id = finish_document(&mut merged, pages_id, kids, catalog_entries, info);
+    if let Ok(catalog) = merged.get_dictionary_mut(catalog_id) {
+        catalog.set("Outlines", outlines_id);
+        catalog.set("PageMode", "UseOutlines");
+        if let Some(mut form) = form.filter(|_| !fields.is_empty()) {
+            form.set("Fields", fields);
+            catalog.set("AcroForm", form);
+        }
+    }
+    merged
+}
+
+/// Give a document assembled from copied pages its page tree, catalog and information
+/// dictionary, returning the catalog
+fn finish_document(
+    doc: &mut Document,
+    pages_id: ObjectId,
+    kids: Vec<Object>,
+    catalog_entries: Vec<(&[u8], Object)>,
+    info: Option<ObjectId>,
+) -> ObjectId {
+    let count = kids.len() as i64;
+    doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => count }));
+    let mut catalog = dictionary! { "Type" => "Catalog", "Pages" => pages_id };
+    for (key, value) in catalog_entries {
+        catalog.set(key, value);
+    }
+    let catalog_id = doc.add_object(catalog);
+    doc.trailer.set("Root", catalog_id);
+    if let Some(info_id) = info {
+        doc.trailer.set("Info", info_id);
+    }
+    catalog_id
+}
+
+/// Copies objects from one document into another under new ids, following references
+struct Copier<'a> {
+    source: &'a Document,
+    target: &'a mut Document,
+    /// New id of every source object copied or about to be
+    ids: HashMap<ObjectId, ObjectId>,
+    /// Source objects given an id but not copied yet
+    pending: Vec<ObjectId>,
+    /// Source objects that are never copied; references to them become null
+    excluded: HashSet<ObjectId>,
+}
+
+impl<'a> Copier<'a> {
+    fn new(source: &'a Document, target: &'a mut Document, excluded: HashSet<ObjectId>) -> Self {
+        Self {
+            source,
+            target,
+            ids: HashMap::new(),
+            pending: Vec::new(),
+            excluded,
+        }
+    }
+
+    /// Copy pages under the page tree node `pages_id`, with the attributes they inherit,
+    /// returning the references to them. Links to excluded pages are dropped.
+    fn copy_pages(&mut self, pages: &[ObjectId], pages_id: ObjectId) -> Vec<Object> {
+        let doc = self.source;
+        // Pages get their new ids first, so links between them keep working
+        let kids: Vec<Object> = pages.iter().map(|id| self.map(*id)).collect();
+        self.pending.clear();
+
+        for &page_id in pages {
+            let Ok(page) = doc.get_dictionary(page_id) else { continue };
+            let mut page = page.clone();
+            for key in INHERITABLE_KEYS {
+                if page.get(key).is_err() {
+                    if let Some(value) = inherited_attribute(doc, page_id, key) {
+                        page.set(key, value);
+                    }
+                }
+            }
+            // Article beads chain pages of the whole document together
+            page.remove(b"B");
+            page.remove(b"Parent");
+            if let Ok(annotations) = page.get(b"Annots") {
+                let kept: Vec<Object> = match resolve(doc, annotations) {
+                    Object::Array(annotations) => annotations
+                        .iter()
+                        .filter(|annotation| !leads_to_excluded_page(doc, annotation, &self.excluded))
+                        .cloned()
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                page.set("Annots", kept);
+            }
+            let mut page = self.rewrite(&Object::Dictionary(page));
+            if let Object::Dictionary(ref mut page) = page {
+                page.set("Parent", pages_id);
+            }
+            let Some(new_id) = self.ids.get(&page_id).copied() else { continue };
+            self.target.objects.insert(new_id, page);
+            self.copy_pending();
+        }
+        kids
+    }
+
+    /// Copy the document information dictionary, returning its new id
+    fn copy_info(&mut self) -> Option<ObjectId> {
+        let info = self.source.trailer.get(b"Info").ok()?.clone();
+        let info = self.rewrite(&info);
+        self.copy_pending();
+        info.as_reference().ok()
+    }
+
+    /// Copy the catalog entries that still apply to the new document
+    fn copy_catalog_entries(&mut self) -> Vec<(&'static [u8], Object)> {
+        let Ok(catalog) = self.source.catalog() else { return Vec::new() };
+        let entries: Vec<(&'static [u8], Object)> = CATALOG_KEYS
+            .iter()
+            .filter_map(|key| catalog.get(key).ok().map(|value| (*key, value.clone())))
+            .collect();
+        let entries = entries.into_iter().map(|(key, value)| (key, self.rewrite(&value))).collect();
+        self.copy_pending();
+        entries
+    }
+
+    /// The reference to use for a source object in the target, scheduling it for copying
+    fn map(&mut self, id: ObjectId) -> Object {
+        if self.excluded.contains(&id) || !self.source.objects.contains_key(&id) {
+            return Object::Null;
+        }
+        let new_id = match self.ids.get(&id) {
+            Some(new_id) => *new_id,
+            None => {
+                let new_id = self.target.new_object_id();
+                self.ids.insert(id, new_id);
+                self.pending.push(id);
+                new_id
+            }
+        };
+        Object::Reference(new_id)
+    }
+
+    fn rewrite(&mut self, object: &Object) -> Object {
+        match object {
+            Object::Reference(id) => self.map(*id),
+            Object::Array(items) => Object::Array(items.iter().map(|item| self.rewrite(item)).collect()),
+            Object::Dictionary(dict) => Object::Dictionary(self.rewrite_dictionary(dict)),
+            Object::Stream(stream) => {
+                let mut stream = stream.clone();
+                stream.dict = self.rewrite_dictionary(&stream.dict);
+                Object::Stream(stream)
+            }
+            _ => object.clone(),
+        }
+    }
+
+    fn rewrite_dictionary(&mut self, dict: &Dictionary) -> Dictionary {
+        let mut rewritten = Dictionary::new();
+        for (key, value) in dict.iter() {
+            rewritten.set(key.clone(), self.rewrite(value));
+        }
+        rewritten
+    }
+
+    /// Copy every scheduled object, and the ones they refer to in turn
+    fn copy_pending(&mut self) {
+        while let Some(id) = self.pending.pop() {
+            let Some(object) = self.source.objects.get(&id) else { continue };
+            let object = self.rewrite(object);
+            self.target.objects.insert(self.ids[&id], object);
+        }
+    }
+}
+
+/// Ids of the intermediate nodes of the page tree
+fn page_tree_nodes(doc: &Document) -> HashSet<ObjectId> {
+    doc.objects
+        .iter()
+        .filter(|(_, object)| matches!(object, Object::Dictionary(dict) if matches!(dict.get(b"Type"), Ok(Object::Name(name)) if name == b"Pages")))
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+/// The value of `key` from the nearest ancestor of a page that has it
+fn inherited_attribute(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    let mut visited = HashSet::new();
+    loop {
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        if !visited.insert(parent) {
+            return None;
+        }
+        node = doc.get_dictionary(parent).ok()?;
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+    }
+}
+
+/// Whether an annotation is a link to one of the `excluded` pages
+fn leads_to_excluded_page(doc: &Document, annotation: &Object, excluded: &HashSet<ObjectId>) -> bool {
+    let Ok(annotation) = resolve(doc, annotation).as_dict() else { return false };
+    match item_destination(doc, annotation).map(|dest| resolve(doc, dest)) {
+        Some(Object::Array(dest)) => matches!(dest.first(), Some(Object::Reference(page)) if excluded.contains(page)),
+        _ => false,
+    }
+}
+
+/// What deleting pages removed or changed besides the pages
+#[derive(Debug, Default)]
+pub struct DeleteStats {
+    pub bookmarks_removed: usize,
+    pub links_removed: usize,
+    pub named_destinations_removed: usize,
+    pub destinations_remapped: usize,
+    /// Objects only the deleted pages used: annotations, resources, images, fonts
+    pub objects_removed: usize,
+}
+
+/// Remove pages (numbered from 1) from the document. Bookmarks, links and named
+/// destinations leading to them are removed too, or, with `remap_links`, pointed at the
+/// nearest remaining page. Objects nothing else uses are dropped.
+pub fn delete_pages(doc: &mut Document, page_numbers: &BTreeSet<u32>, remap_links: bool) -> DeleteStats {
+    let pages = doc.get_pages();
+    let deleted: HashSet<ObjectId> = pages
+        .iter()
+        .filter(|(number, _)| page_numbers.contains(number))
+        .map(|(_, id)| *id)
+        .collect();
+    let mut stats = DeleteStats::default();
+    if remap_links {
+        stats.destinations_remapped = remap_destinations(doc, &nearest_remaining_pages(&pages, page_numbers));
+    } else {
+        remove_destinations(doc, &deleted, &mut stats);
+    }
+
+    // Annotations go with their page, unless a remaining page shows them too
+    let remaining_annotations: HashSet<ObjectId> = pages
+        .values()
+        .filter(|id| !deleted.contains(id))
+        .flat_map(|id| annotation_ids(doc, *id))
+        .collect();
+    let mut removed = deleted.clone();
+    removed.extend(
+        deleted
+            .iter()
+            .flat_map(|id| annotation_ids(doc, *id))
+            .filter(|id| !remaining_annotations.contains(id)),
+    );
+    for id in &removed {
+        doc.objects.remove(id);
+    }
+    for object in doc.objects.values_mut() {
+        drop_references(object, &removed);
+    }
+    if let Ok(root) = doc.catalog().and_then(|catalog| catalog.get(b"Pages")).and_then(Object::as_reference) {
+        recount_pages(doc, root, &mut HashSet::new());
+    }
+
+    stats.objects_removed = removed.len() - deleted.len() + remove_unreachable_objects(doc);
+    stats
+}
+
+/// For each deleted page, the remaining page closest to it (the following one on a tie)
+fn nearest_remaining_pages(pages: &BTreeMap<u32, ObjectId>, deleted: &BTreeSet<u32>) -> HashMap<ObjectId, ObjectId> {
+    let remaining: Vec<u32> = pages.keys().copied().filter(|number| !deleted.contains(number)).collect();
+    let mut nearest = HashMap::new();
+    for number in deleted {
+        let following = remaining.iter().find(|page| *page > number);
+        let preceding = remaining.iter().rev().find(|page| *page < number);
+        let closest = match (preceding, following) {
+            (Some(before), Some(after)) if number - before < after - number => Some(before),
+            (_, Some(after)) => Some(after),
+            (before, None) => before,
+        };
+        if let (Some(page), Some(closest)) = (pages.get(number), closest.and_then(|number| pages.get(number))) {
+            nearest.insert(*page, *closest);
+        }
+    }
+    nearest
+}
+
+/// Point explicit destinations (`[page /Fit]` and the like) at other pages, returning how
+/// many changed. Named destinations are explicit ones in the name tables, so they follow.
+fn remap_destinations(doc: &mut Document, pages: &HashMap<ObjectId, ObjectId>) -> usize {
+    fn remap(object: &mut Object, pages: &HashMap<ObjectId, ObjectId>, count: &mut usize) {
+        match object {
+            Object::Array(items) => {
+                if let [Object::Reference(page), Object::Name(_), ..] = items.as_mut_slice() {
+                    if let Some(new_page) = pages.get(page) {
+                        *page = *new_page;
+                        *count += 1;
+                    }
+                }
+                items.iter_mut().for_each(|item| remap(item, pages, count));
+            }
+            Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, value)| remap(value, pages, count)),
+            Object::Stream(stream) => stream.dict.iter_mut().for_each(|(_, value)| remap(value, pages, count)),
+            _ => {}
+        }
+    }
+    let mut count = 0;
+    for object in doc.objects.values_mut() {
+        remap(object, pages, &mut count);
+    }
+    count
+}
+
+/// Remove the links, bookmarks, named destinations and open action leading to `deleted` pages
+fn remove_destinations(doc: &mut Document, deleted: &HashSet<ObjectId>, stats: &mut DeleteStats) {
+    let named = NamedDestinations::new(doc);
+    let leads_to_deleted = |doc: &Document, dest: &Object| named.page(doc, dest).is_some_and(|page| deleted.contains(&page));
+    let leads_away = |doc: &Document, item: &Object| {
+        let Ok(item) = resolve(doc, item).as_dict() else { return false };
+        item_destination(doc, item).is_some_and(|dest| leads_to_deleted(doc, dest))
+    };
+
+    for page_id in doc.get_pages().into_values().filter(|id| !deleted.contains(id)) {
+        let Some(annotations) = doc.get_dictionary(page_id).ok().and_then(|page| page.get(b"Annots").ok()).cloned() else { continue };
+        let Object::Array(items) = resolve(doc, &annotations) else { continue };
+        let is_link = |item: &Object| {
+            matches!(resolve(doc, item).as_dict().map(|a| a.get(b"Subtype")), Ok(Ok(Object::Name(subtype))) if subtype == b"Link")
+        };
+        let kept: Vec<Object> = items.iter().filter(|item| !(is_link(item) && leads_away(doc, item))).cloned().collect();
+        if kept.len() == items.len() {
+            continue;
+        }
+        stats.links_removed += items.len() - kept.len();
+        match annotations {
+            Object::Reference(id) => {
+                doc.objects.insert(id, Object::Array(kept));
+            }
+            _ => {
+                if let Ok(page) = doc.get_dictionary_mut(page_id) {
+                    page.set("Annots", kept);
+                }
+            }
+        }
+    }
+
+    let outline_root = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| outlines.as_reference().ok());
+    if let Some(root) = outline_root {
+        prune_outline(doc, root, &leads_away, &mut HashSet::new(), stats);
+        count_outline(doc, root, 0);
+    }
+
+    let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) else { return };
+    let open_action = doc.get_dictionary(catalog_id).ok().and_then(|catalog| catalog.get(b"OpenAction").ok());
+    // The open action is a destination or an action; only GoTo actions lead to a page
+    let opens_deleted_page = open_action.is_some_and(|action| match resolve(doc, action) {
+        Object::Dictionary(action) => {
+            matches!(action.get(b"S"), Ok(Object::Name(kind)) if kind == b"GoTo")
+                && action.get(b"D").is_ok_and(|dest| leads_to_deleted(doc, dest))
+        }
+        dest => leads_to_deleted(doc, dest),
+    });
+    if opens_deleted_page {
+        if let Ok(catalog) = doc.get_dictionary_mut(catalog_id) {
+            catalog.remove(b"OpenAction");
+        }
+    }
+
+    // `/Dests` maps names to destinations; the `/Names /Dests` tree maps strings
+    if let Some(dests) = indirect_entry(doc, catalog_id, b"Dests") {
+        let names: Vec<Vec<u8>> = doc
+            .get_dictionary(dests)
+            .map(|dests| dests.iter().filter(|(_, dest)| leads_to_deleted(doc, dest)).map(|(name, _)| name.clone()).collect())
+            .unwrap_or_default();
+        stats.named_destinations_removed += names.len();
+        if let Ok(dests) = doc.get_dictionary_mut(dests) {
+            for name in &names {
+                dests.remove(name);
+            }
+        }
+    }
+    let tree = indirect_entry(doc, catalog_id, b"Names").and_then(|names| indirect_entry(doc, names, b"Dests"));
+    let mut nodes = Vec::from_iter(tree);
+    let mut visited = HashSet::new();
+    while let Some(id) = nodes.pop() {
+        if !visited.insert(id) {
+            continue;
+        }
+        let Ok(node) = doc.get_dictionary(id) else { continue };
+        if let Ok(Object::Array(kids)) = node.get(b"Kids").map(|kids| resolve(doc, kids)) {
+            nodes.extend(kids.iter().filter_map(|kid| kid.as_reference().ok()));
+        }
+        let Ok(Object::Array(entries)) = node.get(b"Names").map(|names| resolve(doc, names)) else { continue };
+        let kept: Vec<Object> = entries
+            .chunks(2)
+            .filter(|pair| !pair.get(1).is_some_and(|dest| leads_to_deleted(doc, dest)))
+            .flatten()
+            .cloned()
+            .collect();
+        if kept.len() < entries.len() {
+            stats.named_destinations_removed += (entries.len() - kept.len()) / 2;
+            if let Ok(node) = doc.get_dictionary_mut(id) {
+                node.set("Names", kept);
+            }
+        }
+    }
+}
+
+/// The id of the dictionary under `key`, moving it into an object of its own if it's
+/// held directly so it can be changed in place
+fn indirect_entry(doc: &mut Document, dict_id: ObjectId, key: &[u8]) -> Option<ObjectId> {
+    match doc.get_dictionary(dict_id).ok()?.get(key).ok()? {
+        Object::Reference(id) => Some(*id),
+        Object::Dictionary(dict) => {
+            let id = doc.add_object(dict.clone());
+            doc.get_dictionary_mut(dict_id).ok()?.set(key, id);
+            Some(id)
+        }
+        _ => None,
+    }
+}
+
+/// Remove the outline items below `parent` that `leads_away` approves of, depth-first.
+/// Items that still have children only lose their destination.
+fn prune_outline(
+    doc: &mut Document,
+    parent: ObjectId,
+    leads_away: &dyn Fn(&Document, &Object) -> bool,
+    visited: &mut HashSet<ObjectId>,
+    stats: &mut DeleteStats,
+) {
+    let mut kept = Vec::new();
+    for item in outline_children(doc, parent, visited) {
+        prune_outline(doc, item, leads_away, visited, stats);
+        if !leads_away(doc, &Object::Reference(item)) {
+            kept.push(item);
+        } else if doc.get_dictionary(item).is_ok_and(|dict| dict.has(b"First")) {
+            if let Ok(dict) = doc.get_dictionary_mut(item) {
+                dict.remove(b"Dest");
+                dict.remove(b"A");
+            }
+            kept.push(item);
+        } else {
+            doc.objects.remove(&item);
+            stats.bookmarks_removed += 1;
+        }
+    }
+    link_outline_children(doc, parent, &kept);
+}
+
+/// The items directly below an outline item (or the outline root), in order
+fn outline_children(doc: &Document, parent: ObjectId, visited: &mut HashSet<ObjectId>) -> Vec<ObjectId> {
+    let mut children = Vec::new();
+    let mut next = doc.get_dictionary(parent).and_then(|parent| parent.get(b"First")).and_then(Object::as_reference).ok();
+    while let Some(id) = next {
+        if !visited.insert(id) {
+            break;
+        }
+        children.push(id);
+        next = doc.get_dictionary(id).and_then(|item| item.get(b"Next")).and_then(Object::as_reference).ok();
+    }
+    children
+}
+
+/// Chain `children` below `parent` with `/First`, `/Last`, `/Prev` and `/Next`
+fn link_outline_children(doc: &mut Document, parent: ObjectId, children: &[ObjectId]) {
+    for (index, id) in children.iter().enumerate() {
+        let Ok(item) = doc.get_dictionary_mut(*id) else { continue };
+        match index.checked_sub(1).map(|previous| children[previous]) {
+            Some(previous) => item.set("Prev", previous),
+            None => {
+                item.remove(b"Prev");
+            }
+        }
+        match children.get(index + 1) {
+            Some(next) => item.set("Next", *next),
+            None => {
+                item.remove(b"Next");
+            }
+        }
+    }
+    let Ok(parent) = doc.get_dictionary_mut(parent) else { return };
+    match (children.first(), children.last()) {
+        (Some(first), Some(last)) => {
+            parent.set("First", *first);
+            parent.set("Last", *last);
+        }
+        _ => {
+            parent.remove(b"First");
+            parent.remove(b"Last");
+            parent.remove(b"Count");
+        }
+    }
+}
+
+/// Set `/Count` of an outline item and its descendants: the number of descendants shown
+/// when it's open, negative when it's closed. Returns how many it shows to its parent.
+fn count_outline(doc: &mut Document, id: ObjectId, depth: usize) -> i64 {
+    if depth > 64 {
+        return 0;
+    }
+    let children = outline_children(doc, id, &mut HashSet::new());
+    let shown: i64 = children.iter().map(|child| 1 + count_outline(doc, *child, depth + 1)).sum();
+    let Ok(item) = doc.get_dictionary_mut(id) else { return 0 };
+    let closed = matches!(item.get(b"Count"), Ok(Object::Integer(count)) if *count < 0);
+    if children.is_empty() {
+        item.remove(b"Count");
+        0
+    } else if closed {
+        item.set("Count", -shown);
+        0
+    } else {
+        item.set("Count", shown);
+        shown
+    }
+}
+
+/// The annotations of a page that are objects of their own
+fn annotation_ids(doc: &Document, page_id: ObjectId) -> Vec<ObjectId> {
+    match doc.get_dictionary(page_id).and_then(|page| page.get(b"Annots")).map(|annots| resolve(doc, annots)) {
+        Ok(Object::Array(annotations)) => annotations.iter().filter_map(|annotation| annotation.as_reference().ok()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Remove references to `removed` objects: dictionary entries go, as do the elements of
+/// page tree, annotation and form field lists; elsewhere in arrays they become null
+fn drop_references(object: &mut Object, removed: &HashSet<ObjectId>) {
+    let is_removed = |object: &Object| matches!(object, Object::Reference(id) if removed.contains(id));
+    let dict = match object {
+        Object::Dictionary(dict) => dict,
+        Object::Stream(stream) => &mut stream.dict,
+        Object::Array(items) => {
+            for item in items.iter_mut() {
+                match is_removed(item) {
+                    true => *item = Object::Null,
+                    false => drop_references(item, removed),
+                }
+            }
+            return;
+        }
+        _ => return,
+    };
+    let keys: Vec<Vec<u8>> = dict.iter().filter(|(_, value)| is_removed(value)).map(|(key, _)| key.clone()).collect();
+    for key in &keys {
+        dict.remove(key);
+    }
+    for (key, value) in dict.iter_mut() {
+        if let (b"Kids" | b"Annots" | b"Fields", Object::Array(items)) = (key.as_slice(), &mut *value) {
+            items.retain(|item| !is_removed(item));
+        }
+        drop_references(value, removed);
+    }
+}
+
+/// Set `/Count` throughout the page tree below `node`, dropping intermediate nodes left
+/// without pages. Returns the number of pages below `node`.
+fn recount_pages(doc: &mut Document, node: ObjectId, visited: &mut HashSet<ObjectId>) -> i64 {
+    if !visited.insert(node) {
+        return 0;
+    }
+    let Ok(dict) = doc.get_dictionary(node) else { return 0 };
+    if !matches!(dict.get(b"Type"), Ok(Object::Name(kind)) if kind == b"Pages") {
+        return 1;
+    }
+    let kids: Vec<ObjectId> = match dict.get(b"Kids").map(|kids| resolve(doc, kids)) {
+        Ok(Object::Array(kids)) => kids.iter().filter_map(|kid| kid.as_reference().ok()).collect(),
+        _ => Vec::new(),
+    };
+    let mut count = 0;
+    let mut kept: Vec<Object> = Vec::new();
+    for kid in kids {
+        let pages = recount_pages(doc, kid, visited);
+        if pages > 0 {
+            count += pages;
+            kept.push(kid.into());
+        }
+    }
+    if let Ok(dict) = doc.get_dictionary_mut(node) {
+        dict.set("Kids", kept);
+        dict.set("Count", count);
+    }
+    count
+}
+
+/// File name of the part holding `range`, e.g. `report-01-10.pdf`; page numbers are padded
+/// to the width of the page count so the parts sort in order
+pub fn part_file_name(stem: &str, range: &RangeInclusive<u32>, page_count: u32) -> String {
+    let width = page_count.to_string().len();
+    match range.start() == range.end() {
+        true => format!("{}-{:0width$}.pdf", stem, range.start()),
+        false => format!("{}-{:0width$}-{:0width$}.pdf", stem, range.start(), range.end()),
+    }
+}
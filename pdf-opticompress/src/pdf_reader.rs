@@ -1,11 +1,338 @@
 use anyhow::{Context, Result};
-use lopdf::Document;
+use lopdf::xref::XrefEntry;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::Path;
 
-/// Load a PDF document from file
-pub fn load_pdf(path: &Path) -> Result<Document> {
-    Document::load(path)
-        .with_context(|| format!("Failed to load PDF: {}", path.display()))
+/// Load a PDF document from file. `tolerant` repairs damaged files (see `load_repaired`),
+/// warning about what was wrong.
+pub fn load_pdf(path: &Path, tolerant: bool) -> Result<Document> {
+    if !tolerant {
+        return Document::load(path).with_context(|| format!("Failed to load PDF: {}", path.display()));
+    }
+    let (doc, repair) = load_repaired(path)?;
+    if let Some(repair) = repair {
+        eprintln!("Warning: {} is damaged: {}", path.display(), repair);
+    }
+    Ok(doc)
+}
+
+/// Load a PDF document and decrypt it with `password` (the empty password when `None`).
+/// Returns the encryption, to write the output with.
+pub fn load_decrypted(path: &Path, password: Option<&str>) -> Result<(Document, Option<crate::encryption::Encryption>)> {
+    let mut doc = load_pdf(path, false)?;
+    let encryption = crate::encryption::decrypt_document(&mut doc, password.unwrap_or(""))
+        .with_context(|| format!("Failed to decrypt {}", path.display()))?;
+    Ok((doc, encryption))
+}
+
+/// What was wrong with a damaged file and how it was read anyway
+#[derive(Debug)]
+pub struct Repair {
+    /// Why the file couldn't be read as it is
+    pub problem: String,
+    /// Objects found by scanning the file, when its cross-reference table was rebuilt
+    pub objects_found: Option<usize>,
+    /// Streams read up to their `endstream` because their `/Length` was wrong
+    pub streams_recovered: Vec<ObjectId>,
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.problem)?;
+        if let Some(objects) = self.objects_found {
+            write!(f, "; rebuilt the cross-reference table from {} objects found in the file", objects)?;
+        }
+        if !self.streams_recovered.is_empty() {
+            write!(f, "; recovered {} streams with a wrong /Length", self.streams_recovered.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Load a PDF document, repairing it when its cross-reference table is missing, truncated
+/// or points to the wrong places (the table is rebuilt by scanning the file for
+/// `obj ... endobj`), or when stream lengths are wrong. Returns what was repaired, if
+/// anything.
+pub fn load_repaired(path: &Path) -> Result<(Document, Option<Repair>)> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let problem = match Document::load_mem(&data) {
+        Ok(mut doc) => {
+            let offsets = normal_offsets(&doc);
+            let missing = offsets.keys().filter(|id| !doc.objects.contains_key(id)).count();
+            match validate_pdf(&doc) {
+                Ok(()) if missing == 0 => {
+                    let streams_recovered = recover_streams(&mut doc, &data, &offsets);
+                    let repair = (!streams_recovered.is_empty()).then(|| Repair {
+                        problem: "some stream lengths are wrong".to_string(),
+                        objects_found: None,
+                        streams_recovered,
+                    });
+                    return Ok((doc, repair));
+                }
+                Ok(()) => format!("{} objects can't be read where the cross-reference table puts them", missing),
+                Err(e) => e.to_string(),
+            }
+        }
+        Err(e) => format!("it can't be read as it is ({})", e),
+    };
+
+    let (mut doc, offsets) = rebuild(&data).with_context(|| format!("Failed to repair {} ({})", path.display(), problem))?;
+    let streams_recovered = recover_streams(&mut doc, &data, &offsets);
+    let repair = Repair { problem, objects_found: Some(offsets.len()), streams_recovered };
+    Ok((doc, Some(repair)))
+}
+
+/// Offsets of the objects stored directly in the file, as its cross-reference table lists them
+fn normal_offsets(doc: &Document) -> BTreeMap<ObjectId, usize> {
+    doc.reference_table
+        .entries
+        .iter()
+        .filter_map(|(&id, entry)| match *entry {
+            XrefEntry::Normal { offset, generation } => Some(((id, generation), offset as usize)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Read the objects found by scanning the file, through a new cross-reference table
+/// appended to it, and take the trailer from the file's last `trailer` dictionary or
+/// cross-reference stream (or, failing those, point it at the catalog). Returns the
+/// objects' offsets in the file.
+fn rebuild(data: &[u8]) -> Result<(Document, BTreeMap<ObjectId, usize>)> {
+    // Anything before the header (mail or download headers) is dropped
+    let start = find(data, 0, b"%PDF-").context("No PDF header found")?;
+    let data = &data[start..];
+    let offsets = scan_objects(data);
+    if offsets.is_empty() {
+        anyhow::bail!("no objects found");
+    }
+
+    // The last `trailer` dictionary is parsed as an extra object
+    let max_id = offsets.keys().map(|id| id.0).max().unwrap_or(0);
+    let trailer_id = (max_id + 1, 0);
+    let mut buffer = data.to_vec();
+    let mut entries = offsets.clone();
+    if let Some(trailer) = last_trailer(data) {
+        entries.insert(trailer_id, buffer.len() + 1);
+        buffer.extend(format!("\n{} 0 obj\n", trailer_id.0).as_bytes());
+        buffer.extend(trailer);
+        buffer.extend(b"\nendobj\n");
+    }
+
+    let xref_start = buffer.len() + 1;
+    let size = trailer_id.0 + 1;
+    buffer.extend(format!("\nxref\n0 {}\n0000000000 65535 f \n", size).as_bytes());
+    let by_number: BTreeMap<u32, (u16, usize)> = entries.iter().map(|(id, offset)| (id.0, (id.1, *offset))).collect();
+    for number in 1..size {
+        match by_number.get(&number) {
+            Some((generation, offset)) => buffer.extend(format!("{:010} {:05} n \n", offset, generation).as_bytes()),
+            None => buffer.extend(b"0000000000 65535 f \n"),
+        }
+    }
+    buffer.extend(format!("trailer\n<</Size {}>>\nstartxref\n{}\n%%EOF\n", size, xref_start).as_bytes());
+
+    let mut doc = Document::load_mem(&buffer).context("the rebuilt file can't be read")?;
+    doc.reference_table.entries.remove(&trailer_id.0);
+    doc.max_id = max_id;
+
+    // Cross-reference streams describe the damaged layout, but hold the trailer entries
+    // of files that use them
+    let mut trailers: Vec<Dictionary> = Vec::new();
+    let xref_streams: Vec<ObjectId> = offsets
+        .keys()
+        .filter(|id| matches!(doc.objects.get(id), Some(Object::Stream(stream)) if stream.dict.type_is(b"XRef")))
+        .copied()
+        .collect();
+    let mut offsets = offsets;
+    let mut by_offset = xref_streams;
+    by_offset.sort_by_key(|id| offsets[id]);
+    for id in by_offset {
+        offsets.remove(&id);
+        if let Some(Object::Stream(stream)) = doc.objects.remove(&id) {
+            trailers.push(stream.dict);
+        }
+    }
+    if let Some(Object::Dictionary(dict)) = doc.objects.remove(&trailer_id) {
+        trailers.push(dict);
+    }
+
+    let mut trailer = Dictionary::new();
+    for dict in &trailers {
+        for key in [b"Root".as_slice(), b"Info", b"ID", b"Encrypt"] {
+            if let Ok(value) = dict.get(key) {
+                trailer.set(key, value.clone());
+            }
+        }
+    }
+    let has_catalog = |doc: &Document, trailer: &Dictionary| {
+        trailer
+            .get(b"Root")
+            .and_then(Object::as_reference)
+            .and_then(|id| doc.get_dictionary(id))
+            .is_ok_and(|catalog| catalog.type_is(b"Catalog"))
+    };
+    if !has_catalog(&doc, &trailer) {
+        let catalog = offsets
+            .iter()
+            .filter(|(id, _)| matches!(doc.objects.get(id), Some(Object::Dictionary(dict)) if dict.type_is(b"Catalog")))
+            .max_by_key(|(_, offset)| **offset)
+            .map(|(id, _)| *id)
+            .context("no document catalog found")?;
+        trailer.set("Root", catalog);
+    }
+    if !trailer.has(b"Encrypt") {
+        let encryption = offsets.keys().find(|id| {
+            matches!(doc.objects.get(id), Some(Object::Dictionary(dict))
+                if dict.get(b"Filter").and_then(Object::as_name).is_ok_and(|filter| filter == b"Standard") && dict.has(b"O") && dict.has(b"U"))
+        });
+        if let Some(id) = encryption {
+            trailer.set("Encrypt", *id);
+        }
+    }
+    if trailer.has(b"Encrypt") && !trailer.has(b"ID") {
+        anyhow::bail!("the file is encrypted, and the file identifier needed to decrypt it was lost with its trailer");
+    }
+    trailer.set("Size", i64::from(max_id + 1));
+    doc.trailer = trailer;
+    Ok((doc, offsets.into_iter().map(|(id, offset)| (id, start + offset)).collect()))
+}
+
+/// Find the `N G obj` headers in a file, skipping stream data. Later definitions of an
+/// object (incremental updates) replace earlier ones.
+fn scan_objects(data: &[u8]) -> BTreeMap<ObjectId, usize> {
+    let mut found: BTreeMap<u32, (u16, usize)> = BTreeMap::new();
+    let mut pos = 0;
+    while let Some(keyword) = find(data, pos, b"obj") {
+        pos = keyword + 3;
+        if data.get(pos).is_some_and(|&byte| !is_delimiter(byte)) {
+            continue;
+        }
+        let Some((id, offset)) = object_header(data, keyword) else { continue };
+        found.insert(id.0, (id.1, offset));
+        pos = object_end(data, pos);
+    }
+    found.into_iter().map(|(number, (generation, offset))| ((number, generation), offset)).collect()
+}
+
+/// The object number, generation and offset of the `N G` before an `obj` keyword
+fn object_header(data: &[u8], keyword: usize) -> Option<(ObjectId, usize)> {
+    let mut pos = keyword;
+    let digits_before = |end: usize| {
+        let start = data[..end].iter().rposition(|byte| !byte.is_ascii_digit()).map_or(0, |p| p + 1);
+        (start < end).then(|| (start, std::str::from_utf8(&data[start..end]).ok()))
+    };
+    let whitespace_before = |end: usize| {
+        let start = data[..end].iter().rposition(|&byte| !is_whitespace(byte)).map_or(0, |p| p + 1);
+        (start < end).then_some(start)
+    };
+
+    pos = whitespace_before(pos)?;
+    let (generation_start, generation) = digits_before(pos)?;
+    pos = whitespace_before(generation_start)?;
+    let (number_start, number) = digits_before(pos)?;
+    if number_start > 0 && !is_delimiter(data[number_start - 1]) {
+        return None;
+    }
+    let id = (number?.parse().ok()?, generation?.parse().ok()?);
+    Some((id, number_start))
+}
+
+/// Where scanning resumes after the object whose `obj` keyword ends at `pos`: past its
+/// `endobj`, and past its stream data, which may contain anything
+fn object_end(data: &[u8], pos: usize) -> usize {
+    let Some(end) = find(data, pos, b"endobj") else { return pos };
+    match stream_keyword(data, pos, end) {
+        Some(stream) => find(data, stream, b"endstream")
+            .and_then(|end_stream| find(data, end_stream, b"endobj"))
+            .map_or(pos, |end| end + 6),
+        None => end + 6,
+    }
+}
+
+/// The offset of the `stream` keyword between `pos` and `end`, if the object has one
+fn stream_keyword(data: &[u8], pos: usize, end: usize) -> Option<usize> {
+    let mut from = pos;
+    while let Some(keyword) = find(&data[..end], from, b"stream") {
+        from = keyword + 6;
+        let before = data[pos..keyword].iter().rposition(|&byte| !is_whitespace(byte)).map(|p| pos + p);
+        if before.is_some_and(|before| before > pos && &data[before - 1..=before] == b">>") {
+            return Some(keyword);
+        }
+    }
+    None
+}
+
+/// Give back the data of streams that were read as bare dictionaries because their
+/// `/Length` was wrong: everything between `stream` and `endstream`
+fn recover_streams(doc: &mut Document, data: &[u8], offsets: &BTreeMap<ObjectId, usize>) -> Vec<ObjectId> {
+    let mut recovered = Vec::new();
+    for (id, &offset) in offsets {
+        let Some(Object::Dictionary(dict)) = doc.objects.get(id) else { continue };
+        let Some(end) = find(data, offset, b"endobj") else { continue };
+        let Some(keyword) = stream_keyword(data, offset, end) else { continue };
+        let mut content_start = keyword + 6;
+        if data.get(content_start) == Some(&b'\r') {
+            content_start += 1;
+        }
+        if data.get(content_start) == Some(&b'\n') {
+            content_start += 1;
+        }
+        let Some(mut content_end) = find(data, content_start, b"endstream") else { continue };
+        if data[..content_end].ends_with(b"\r\n") {
+            content_end -= 2;
+        } else if data[..content_end].ends_with(b"\n") || data[..content_end].ends_with(b"\r") {
+            content_end -= 1;
+        }
+        let content = data[content_start..content_end.max(content_start)].to_vec();
+        doc.objects.insert(*id, Object::Stream(Stream::new(dict.clone(), content)));
+        recovered.push(*id);
+    }
+    recovered
+}
+
+fn find(data: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    data.get(from..)?.windows(needle.len()).position(|window| window == needle).map(|p| from + p)
+}
+
+/// The last `trailer` keyword's dictionary, brackets matched
+fn last_trailer(data: &[u8]) -> Option<&[u8]> {
+    let mut keyword = None;
+    let mut from = 0;
+    while let Some(found) = find(data, from, b"trailer") {
+        keyword = Some(found);
+        from = found + 7;
+    }
+    let start = find(data, keyword? + 7, b"<<")?;
+    let mut depth = 0;
+    let mut pos = start;
+    while pos + 1 < data.len() {
+        match &data[pos..pos + 2] {
+            b"<<" => {
+                depth += 1;
+                pos += 2;
+            }
+            b">>" => {
+                depth -= 1;
+                pos += 2;
+                if depth == 0 {
+                    return Some(&data[start..pos]);
+                }
+            }
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\r' | b'\n' | b'\x0C' | b'\0')
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    is_whitespace(byte) || b"()<>[]{}/%".contains(&byte)
 }
 
 /// Validate that the loaded document is valid
@@ -16,18 +343,86 @@ pub fn validate_pdf(doc: &Document) -> Result<()> {
     }
 
     // Check if document has a root catalog
-    if let Err(_) = doc.trailer.get(b"Root") {
+    if doc.trailer.get(b"Root").is_err() {
         return Err(anyhow::anyhow!("PDF document is missing root catalog"));
     }
 
     Ok(())
 }
 
+/// Digital signatures found in a document
+#[derive(Debug, Default, Clone)]
+pub struct SignatureInfo {
+    /// Signature fields holding a signature
+    pub signed_fields: usize,
+    /// Signature fields waiting to be signed, which optimizing doesn't affect
+    pub empty_fields: usize,
+    /// A DocMDP certification or UR3 usage-rights signature in the catalog's `/Perms`
+    pub permissions_signed: bool,
+}
+
+impl SignatureInfo {
+    pub fn is_signed(&self) -> bool {
+        self.signed_fields > 0 || self.permissions_signed
+    }
+}
+
+/// Find signature fields (`/FT /Sig`) and `/Perms` signatures
+pub fn signature_info(doc: &Document) -> SignatureInfo {
+    let mut info = SignatureInfo::default();
+    for object in doc.objects.values() {
+        let Object::Dictionary(dict) = object else { continue };
+        if matches!(dict.get(b"FT"), Ok(Object::Name(name)) if name == b"Sig") {
+            match dict.get(b"V") {
+                Ok(Object::Null) | Err(_) => info.empty_fields += 1,
+                Ok(_) => info.signed_fields += 1,
+            }
+        }
+    }
+
+    let perms = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Perms").ok())
+        .and_then(|perms| crate::objects::resolve(doc, perms).as_dict().ok());
+    info.permissions_signed = perms.is_some_and(|perms| perms.has(b"DocMDP") || perms.has(b"UR3"));
+    info
+}
+
+/// Returned when asked to optimize a signed document without `--force-signed`
+#[derive(Debug)]
+pub struct SignedDocumentError;
+
+impl fmt::Display for SignedDocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the document is digitally signed and optimizing it would invalidate the signature \
+             (use --force-signed to optimize it anyway)"
+        )
+    }
+}
+
+impl std::error::Error for SignedDocumentError {}
+
+/// The document information dictionary entries `info` shows, in order
+const INFO_FIELDS: [&str; 8] = ["Title", "Author", "Subject", "Keywords", "Creator", "Producer", "CreationDate", "ModDate"];
+
 /// Get basic document information
 pub struct PdfInfo {
     pub page_count: usize,
     pub version: String,
     pub has_encryption: bool,
+    /// Distinct page sizes (width and height in points, from the `/MediaBox`) and how many
+    /// pages have each, in the order they first appear
+    pub page_sizes: Vec<((f32, f32), usize)>,
+    /// The information dictionary's entries, dates as ISO 8601; `None` while they're encrypted
+    pub document_info: Option<Vec<(&'static str, String)>>,
+    pub linearized: bool,
+    /// Whether the catalog's `/MarkInfo` says the document is tagged
+    pub tagged: bool,
+    /// The stamp of an earlier optimization; `None` while the information is encrypted
+    pub optimized_by: Option<crate::marker::Marker>,
 }
 
 pub fn get_pdf_info(doc: &Document) -> PdfInfo {
@@ -35,9 +430,169 @@ pub fn get_pdf_info(doc: &Document) -> PdfInfo {
     let version = doc.version.clone();
     let has_encryption = doc.is_encrypted();
 
+    let mut page_sizes: Vec<((f32, f32), usize)> = Vec::new();
+    for page_id in doc.get_pages().into_values() {
+        let Some([x0, y0, x1, y1]) = crate::placement::media_box(doc, page_id) else { continue };
+        // Sizes a hundredth of a point apart are the same size
+        let size = (((x1 - x0).abs() * 100.0).round() / 100.0, ((y1 - y0).abs() * 100.0).round() / 100.0);
+        match page_sizes.iter_mut().find(|(known, _)| *known == size) {
+            Some((_, count)) => *count += 1,
+            None => page_sizes.push((size, 1)),
+        }
+    }
+
+    let document_info = (!has_encryption).then(|| document_info(doc));
+    let optimized_by = (!has_encryption).then(|| crate::marker::read_marker(doc)).flatten();
+    // The linearization parameter dictionary is the first object in a linearized file
+    let linearized = doc.objects.values().any(|object| matches!(object, Object::Dictionary(dict) if dict.has(b"Linearized")));
+    let tagged = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"MarkInfo").ok())
+        .and_then(|mark_info| crate::objects::resolve(doc, mark_info).as_dict().ok())
+        .and_then(|mark_info| mark_info.get(b"Marked").and_then(Object::as_bool).ok())
+        .unwrap_or(false);
+
     PdfInfo {
         page_count,
         version,
         has_encryption,
+        page_sizes,
+        document_info,
+        linearized,
+        tagged,
+        optimized_by,
+    }
+}
+
+/// The document information dictionary's entries, dates as ISO 8601
+pub fn document_info(doc: &Document) -> Vec<(&'static str, String)> {
+    let Some(info) = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|info| crate::objects::resolve(doc, info).as_dict().ok())
+    else {
+        return Vec::new();
+    };
+    INFO_FIELDS
+        .into_iter()
+        .filter_map(|key| {
+            let Ok(Object::String(bytes, _)) = info.get(key.as_bytes()).map(|value| crate::objects::resolve(doc, value)) else {
+                return None;
+            };
+            let value = match key {
+                "CreationDate" | "ModDate" => crate::xmp::xmp_date(bytes),
+                _ => None,
+            };
+            Some((key, value.unwrap_or_else(|| crate::pdf_writer::decode_text_string(bytes))))
+        })
+        .collect()
+}
+
+/// Read a document's information without its stream data, so that even huge files are
+/// read quickly: stream objects are kept as bare dictionaries. The information dictionary
+/// of an encrypted file is decrypted with `password` when it opens the file.
+pub fn read_pdf_info(path: &Path, password: &str) -> Result<PdfInfo> {
+    let mut doc = Document::load_filtered(path, without_stream_data)
+        .with_context(|| format!("Failed to load PDF: {}", path.display()))?;
+    let has_encryption = doc.is_encrypted();
+    if has_encryption && crate::encryption::decrypt_document(&mut doc, password).is_err() {
+        return Ok(get_pdf_info(&doc));
+    }
+    Ok(PdfInfo { has_encryption, ..get_pdf_info(&doc) })
+}
+
+/// Print the information `info` shows
+pub fn print_pdf_info(path: &Path, info: &PdfInfo) {
+    println!("File: {}", path.display());
+    println!("PDF version: {}", info.version);
+    println!("Pages: {}", info.page_count);
+    for ((width, height), count) in &info.page_sizes {
+        let paper = paper_name(*width, *height).map(|name| format!(" ({})", name)).unwrap_or_default();
+        println!("  {} x {} pt{}: {} page{}", width, height, paper, count, if *count == 1 { "" } else { "s" });
+    }
+    println!("Encrypted: {}", if info.has_encryption { "yes" } else { "no" });
+    println!("Linearized: {}", if info.linearized { "yes" } else { "no" });
+    println!("Tagged: {}", if info.tagged { "yes" } else { "no" });
+    if let Some(marker) = &info.optimized_by {
+        println!("Optimized by: {}", marker.describe());
+    }
+    match &info.document_info {
+        Some(fields) => {
+            for (key, value) in fields {
+                println!("{}: {}", key, value);
+            }
+        }
+        None => println!("Document info: encrypted (give the user password with --password)"),
+    }
+}
+
+/// What `info --format json` prints
+#[derive(Serialize)]
+struct InfoJson<'a> {
+    file: String,
+    version: &'a str,
+    pages: usize,
+    page_sizes: Vec<PageSizeJson>,
+    encrypted: bool,
+    linearized: bool,
+    tagged: bool,
+    optimized_by: Option<&'a crate::marker::Marker>,
+    /// The information dictionary's entries in the order `info` shows them
+    info: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Serialize)]
+struct PageSizeJson {
+    width: f32,
+    height: f32,
+    pages: usize,
+}
+
+/// The information as a JSON object
+pub fn pdf_info_json(path: &Path, info: &PdfInfo) -> String {
+    let json = InfoJson {
+        file: path.display().to_string(),
+        version: &info.version,
+        pages: info.page_count,
+        page_sizes: info.page_sizes.iter().map(|&((width, height), pages)| PageSizeJson { width, height, pages }).collect(),
+        encrypted: info.has_encryption,
+        linearized: info.linearized,
+        tagged: info.tagged,
+        optimized_by: info.optimized_by.as_ref(),
+        info: info
+            .document_info
+            .as_ref()
+            .map(|fields| fields.iter().map(|(key, value)| (key.to_string(), serde_json::Value::from(value.as_str()))).collect()),
+    };
+    serde_json::to_string_pretty(&json).expect("the information serializes to JSON")
+}
+
+/// The common paper size a page size is, in either orientation
+fn paper_name(width: f32, height: f32) -> Option<&'static str> {
+    const PAPER_SIZES: [(&str, f32, f32); 7] = [
+        ("A3", 841.89, 1190.55),
+        ("A4", 595.28, 841.89),
+        ("A5", 419.53, 595.28),
+        ("Letter", 612.0, 792.0),
+        ("Legal", 612.0, 1008.0),
+        ("Tabloid", 792.0, 1224.0),
+        ("Executive", 522.0, 756.0),
+    ];
+    let (short, long) = (width.min(height), width.max(height));
+    PAPER_SIZES
+        .iter()
+        .find(|(_, paper_short, paper_long)| (short - paper_short).abs() <= 1.5 && (long - paper_long).abs() <= 1.5)
+        .map(|(name, _, _)| *name)
+}
+
+/// Replace a stream by its dictionary. Object streams are kept: the loader unpacks them.
+fn without_stream_data(id: ObjectId, object: &mut Object) -> Option<(ObjectId, Object)> {
+    if let Object::Stream(stream) = object {
+        if !stream.dict.type_is(b"ObjStm") {
+            *object = Object::Dictionary(std::mem::take(&mut stream.dict));
+        }
     }
+    Some((id, object.clone()))
 }
\ No newline at end of file
@@ -1,47 +1,839 @@
 use anyhow::{Context, Result};
-use lopdf::Document;
+use lopdf::xref::XrefType;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 
+use crate::objects::{for_each_reference, referenced_ids, thumbnail_ids};
+use crate::xmp::{catalog_metadata_id, info_object, read_packet, read_xmp, update_xmp, xmp_packet};
+
 /// Save options for PDF optimization
 #[derive(Clone)]
 pub struct SaveOptions {
     pub enable_compression: bool,
+    /// Drop objects that can't be reached from the trailer (old thumbnails,
+    /// leftovers of incremental updates, streams replaced during optimization)
+    pub remove_unused_objects: bool,
+    /// Remove the `/Thumb` preview images of pages
+    pub strip_thumbnails: bool,
+    /// Re-encode legacy `/LZWDecode` streams with Flate (run before image optimization,
+    /// so LZW images get optimized too)
+    pub recompress_lzw: bool,
+    /// Flate level (1-9) for the non-image streams the optimizer (re)compresses
+    pub flate_level: u32,
+    /// Compress those streams with zopfli instead (needs the `zopfli` feature)
+    pub zopfli: bool,
+    /// PDF version to declare (e.g. "1.4"); features the version lacks are not written.
+    /// `None` keeps the input's version, raised as far as the output needs.
+    pub version: Option<String>,
+    /// Encrypt the output as the input was encrypted; `None` writes it unencrypted
+    pub encryption: Option<crate::encryption::Encryption>,
+    /// Number the objects in the order they're reached from the trailer and derive the
+    /// file identifier from the content, so the same document always gives the same bytes
+    pub deterministic: bool,
+    /// Spread dictionaries and arrays over indented lines and write a classic
+    /// cross-reference table, so the file can be read in a text editor
+    pub readable: bool,
 }
 
 impl Default for SaveOptions {
     fn default() -> Self {
         Self {
             enable_compression: true,
+            remove_unused_objects: false,
+            strip_thumbnails: false,
+            recompress_lzw: false,
+            flate_level: 9,
+            zopfli: false,
+            version: None,
+            encryption: None,
+            deterministic: false,
+            readable: false,
+        }
+    }
+}
+
+/// zlib's default level, which higher-effort settings must never do worse than
+const DEFAULT_FLATE_LEVEL: u32 = 6;
+
+impl SaveOptions {
+    /// Deflate data with the configured encoder. Higher levels aren't strictly better in
+    /// zlib, so higher-effort encoders also try the default level and keep the smaller result.
+    pub fn deflate(&self, data: &[u8]) -> Result<Vec<u8>> {
+        #[cfg(feature = "zopfli")]
+        let encoded = if self.zopfli {
+            crate::filters::zopfli_encode(data)?
+        } else {
+            crate::filters::flate_encode(data, self.flate_level)?
+        };
+        #[cfg(not(feature = "zopfli"))]
+        let encoded = crate::filters::flate_encode(data, self.flate_level)?;
+
+        if self.flate_level <= DEFAULT_FLATE_LEVEL && !self.zopfli {
+            return Ok(encoded);
+        }
+        let default = crate::filters::flate_encode(data, DEFAULT_FLATE_LEVEL)?;
+        Ok(if default.len() < encoded.len() { default } else { encoded })
+    }
+}
+
+/// Document information entries to write into the output; `None` leaves an entry as it is
+#[derive(Clone, Default)]
+pub struct DocumentInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    /// A PDF date to record as the modification date
+    pub modified: Option<String>,
+}
+
+impl DocumentInfo {
+    pub fn is_empty(&self) -> bool {
+        self.entries().next().is_none()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&'static str, &String)> {
+        [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Subject", &self.subject),
+            ("Keywords", &self.keywords),
+            ("ModDate", &self.modified),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_ref().map(|value| (key, value)))
+    }
+}
+
+impl From<crate::cli::DocumentInfoArgs> for DocumentInfo {
+    fn from(args: crate::cli::DocumentInfoArgs) -> Self {
+        Self {
+            title: args.set_title,
+            author: args.set_author,
+            subject: args.set_subject,
+            keywords: args.set_keywords,
+            modified: None,
         }
     }
 }
 
 /// Save a PDF document with optimization options
 pub fn save_pdf(doc: &mut Document, path: &Path, options: &SaveOptions) -> Result<()> {
+    prepare_for_save(doc, options);
+    if options.deterministic {
+        derive_file_id(doc, options.readable)?;
+    }
+
+    write_document(doc, options, |doc| {
+        write_atomically(path, |writer| serialize(doc, writer, options.readable)).with_context(|| format!("Failed to save PDF: {}", path.display()))
+    })
+}
+
+/// Write to a temporary file next to `path` and rename it over `path` once it's complete
+/// and synced, so an interrupted or failed save never leaves a truncated output behind
+/// (or clobbers an existing file). The temporary file is removed when anything fails.
+fn write_atomically(path: &Path, write: impl FnOnce(&mut BufWriter<&mut File>) -> Result<()>) -> Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = format!(".{}.", path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default());
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(&prefix).suffix(".tmp");
+    // Temporary files are private; give the output the permissions of the file it replaces,
+    // or those a plainly created file would get
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+    let mut temp = builder.tempfile_in(directory)?;
+    if let Ok(existing) = std::fs::metadata(path) {
+        temp.as_file().set_permissions(existing.permissions())?;
+    }
+
+    {
+        let mut writer = BufWriter::new(temp.as_file_mut());
+        write(&mut writer)?;
+        writer.flush()?;
+    }
+    temp.as_file().sync_all()?;
+    temp.persist(path).map_err(|e| e.error)?;
+
+    // Make the rename itself durable; not every platform can sync a directory
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+/// The document as `save_pdf` would write it, in memory
+pub fn serialize_pdf(doc: &mut Document, options: &SaveOptions) -> Result<Vec<u8>> {
+    prepare_for_save(doc, options);
+    if options.deterministic {
+        derive_file_id(doc, options.readable)?;
+    }
+
+    let mut buffer = Vec::new();
+    write_document(doc, options, |doc| serialize(doc, &mut buffer, options.readable).context("Failed to serialize PDF"))?;
+    Ok(buffer)
+}
+
+/// Size of the document as `save_pdf` would write it, without touching the disk
+pub fn serialized_size(doc: &mut Document, options: &SaveOptions) -> Result<u64> {
+    Ok(serialize_pdf(doc, options)?.len() as u64)
+}
+
+fn serialize<W: Write>(doc: &mut Document, target: &mut W, readable: bool) -> Result<()> {
+    if readable {
+        write_readable(doc, target)?;
+    } else {
+        doc.save_to(target)?;
+    }
+    Ok(())
+}
+
+/// Counts what's written, for the cross-reference table
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    written: usize,
+}
+
+impl Write for CountingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Write the document as lopdf would, but with every dictionary and array that holds a
+/// dictionary spread over indented lines. Like lopdf, object and cross-reference streams
+/// (whose objects were read into the document) are left out, and so is the linearization
+/// dictionary, which no longer describes the file.
+fn write_readable(doc: &Document, target: &mut dyn Write) -> std::io::Result<()> {
+    let mut out = CountingWriter { inner: target, written: 0 };
+    writeln!(out, "%PDF-{}", doc.version)?;
+    out.write_all(b"%\xE2\xE3\xCF\xD3\n")?;
+
+    let mut offsets = BTreeMap::new();
+    for (&(number, generation), object) in &doc.objects {
+        let skipped = match object {
+            Object::Stream(stream) => stream.dict.type_is(b"XRef") || stream.dict.type_is(b"ObjStm"),
+            Object::Dictionary(dict) => dict.has(b"Linearized"),
+            _ => false,
+        };
+        if skipped {
+            continue;
+        }
+        offsets.insert(number, (out.written, generation));
+        writeln!(out, "{} {} obj", number, generation)?;
+        match object {
+            Object::Stream(stream) => {
+                write_readable_dictionary(&mut out, &stream.dict, 0)?;
+                out.write_all(b"\nstream\n")?;
+                out.write_all(&stream.content)?;
+                out.write_all(b"\nendstream")?;
+            }
+            object => write_readable_object(&mut out, object, 0)?,
+        }
+        out.write_all(b"\nendobj\n")?;
+    }
+
+    let size = offsets.keys().next_back().map_or(1, |last| last + 1);
+    let xref_offset = out.written;
+    write!(out, "xref\n0 {}\n", size)?;
+    // Free entries form a list starting at object 0
+    let mut free = (1..size).filter(|number| !offsets.contains_key(number)).peekable();
+    writeln!(out, "{:010} 65535 f ", free.peek().copied().unwrap_or(0))?;
+    for number in 1..size {
+        match offsets.get(&number) {
+            Some((offset, generation)) => writeln!(out, "{:010} {:05} n ", offset, generation)?,
+            None => {
+                free.next();
+                writeln!(out, "{:010} 00000 f ", free.peek().copied().unwrap_or(0))?;
+            }
+        }
+    }
+
+    let mut trailer = doc.trailer.clone();
+    for key in XREF_STREAM_KEYS {
+        trailer.remove(key);
+    }
+    trailer.set("Size", size as i64);
+    out.write_all(b"trailer\n")?;
+    write_readable_dictionary(&mut out, &trailer, 0)?;
+    write!(out, "\nstartxref\n{}\n%%EOF\n", xref_offset)
+}
+
+fn write_readable_dictionary(out: &mut dyn Write, dict: &Dictionary, indent: usize) -> std::io::Result<()> {
+    if dict.is_empty() {
+        return out.write_all(b"<< >>");
+    }
+    out.write_all(b"<<\n")?;
+    for (key, value) in dict.iter() {
+        write!(out, "{:1$}", "", (indent + 1) * 2)?;
+        write_name(out, key)?;
+        out.write_all(b" ")?;
+        write_readable_object(out, value, indent + 1)?;
+        out.write_all(b"\n")?;
+    }
+    write!(out, "{:1$}>>", "", indent * 2)
+}
+
+fn write_readable_object(out: &mut dyn Write, object: &Object, indent: usize) -> std::io::Result<()> {
+    match object {
+        Object::Null => out.write_all(b"null"),
+        Object::Boolean(value) => write!(out, "{}", value),
+        Object::Integer(value) => write!(out, "{}", value),
+        Object::Real(value) => write!(out, "{}", value),
+        Object::Name(name) => write_name(out, name),
+        Object::String(bytes, StringFormat::Hexadecimal) => {
+            out.write_all(b"<")?;
+            for byte in bytes {
+                write!(out, "{:02X}", byte)?;
+            }
+            out.write_all(b">")
+        }
+        Object::String(bytes, StringFormat::Literal) => {
+            out.write_all(b"(")?;
+            for &byte in bytes {
+                match byte {
+                    b'(' | b')' | b'\\' => out.write_all(&[b'\\', byte])?,
+                    b'\n' => out.write_all(b"\\n")?,
+                    b'\r' => out.write_all(b"\\r")?,
+                    b'\t' => out.write_all(b"\\t")?,
+                    0x20..=0x7E => out.write_all(&[byte])?,
+                    _ => write!(out, "\\{:03o}", byte)?,
+                }
+            }
+            out.write_all(b")")
+        }
+        Object::Array(items) if items.iter().any(spans_lines) => {
+            out.write_all(b"[\n")?;
+            for item in items {
+                write!(out, "{:1$}", "", (indent + 1) * 2)?;
+                write_readable_object(out, item, indent + 1)?;
+                out.write_all(b"\n")?;
+            }
+            write!(out, "{:1$}]", "", indent * 2)
+        }
+        Object::Array(items) => {
+            out.write_all(b"[")?;
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.write_all(b" ")?;
+                }
+                write_readable_object(out, item, indent)?;
+            }
+            out.write_all(b"]")
+        }
+        Object::Dictionary(dict) => write_readable_dictionary(out, dict, indent),
+        // Streams are always indirect objects, written by `write_readable`
+        Object::Stream(stream) => write_readable_dictionary(out, &stream.dict, indent),
+        Object::Reference((number, generation)) => write!(out, "{} {} R", number, generation),
+    }
+}
+
+fn spans_lines(object: &Object) -> bool {
+    match object {
+        Object::Dictionary(dict) => !dict.is_empty(),
+        Object::Array(items) => items.iter().any(spans_lines),
+        _ => false,
+    }
+}
+
+/// A name with delimiters, whitespace, `#` and bytes outside printable ASCII written as
+/// `#xx` escapes
+fn write_name(out: &mut dyn Write, name: &[u8]) -> std::io::Result<()> {
+    out.write_all(b"/")?;
+    for &byte in name {
+        if b"()<>[]{}/%#".contains(&byte) || !(0x21..=0x7E).contains(&byte) {
+            write!(out, "#{:02X}", byte)?;
+        } else {
+            out.write_all(&[byte])?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the prepared document, encrypted (as the last stage, after compression) when
+/// the options keep the input's encryption
+fn write_document(doc: &mut Document, options: &SaveOptions, write: impl FnOnce(&mut Document) -> Result<()>) -> Result<()> {
+    match &options.encryption {
+        Some(encryption) => crate::encryption::with_encryption(doc, encryption, write),
+        None => write(doc),
+    }
+}
+
+fn prepare_for_save(doc: &mut Document, options: &SaveOptions) {
+    // First, so length objects it leaves unreferenced are pruned below
+    direct_stream_lengths(doc);
+    if options.readable {
+        doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+    }
+    if options.strip_thumbnails {
+        strip_thumbnails(doc);
+    }
+    if options.remove_unused_objects {
+        remove_unreachable_objects(doc);
+    }
     // Apply compression if enabled
     if options.enable_compression {
-        doc.compress();
+        compress_streams(doc, options);
+    }
+    apply_version(doc, options.version.as_deref());
+    if options.deterministic {
+        renumber_in_reading_order(doc);
+    }
+}
+
+/// Give every stream a direct `/Length` matching its content (streams rewritten later get
+/// one from lopdf). An indirect length object may be shared with other streams or left
+/// stale by a rewrite, and strict parsers reject a wrong length.
+fn direct_stream_lengths(doc: &mut Document) {
+    for object in doc.objects.values_mut() {
+        let Object::Stream(stream) = object else { continue };
+        let length = stream.content.len() as i64;
+        if !matches!(stream.dict.get(b"Length"), Ok(Object::Integer(value)) if *value == length) {
+            stream.dict.set("Length", length);
+        }
+    }
+}
+
+/// Flate compress every stream without a filter, as `Document::compress` does but with
+/// the configured encoder, and re-deflate plain Flate streams other than images when
+/// that makes them smaller (images are the image optimizer's business). Streams with
+/// other filters, cross-reference and object streams, and streams whose data lives in an
+/// external file are left alone.
+fn compress_streams(doc: &mut Document, options: &SaveOptions) {
+    for object in doc.objects.values_mut() {
+        let Object::Stream(stream) = object else { continue };
+        let Some(encoded) = compressed_content(stream, options) else { continue };
+        if crate::filters::stream_filters(stream).is_empty() {
+            stream.dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+            // Parameters without a filter describe nothing, but would apply to Flate
+            stream.dict.remove(b"DecodeParms");
+        }
+        stream.set_content(encoded);
+    }
+}
+
+/// The Flate data `compress_streams` would store in place of a stream's content, when it
+/// is worth it
+pub fn compressed_content(stream: &Stream, options: &SaveOptions) -> Option<Vec<u8>> {
+    // Roughly the size of the `/Filter /FlateDecode` entry the compressed stream needs
+    const FILTER_ENTRY: usize = 19;
+
+    if !stream.allows_compression {
+        return None;
+    }
+    let is = |key: &[u8], value: &[u8]| matches!(stream.dict.get(key), Ok(Object::Name(name)) if name == value);
+    if is(b"Type", b"XRef") || is(b"Type", b"ObjStm") || stream.dict.has(b"F") {
+        return None;
+    }
+
+    let filters = crate::filters::stream_filters(stream);
+    if filters.is_empty() {
+        let encoded = options.deflate(&stream.content).ok()?;
+        (encoded.len() + FILTER_ENTRY < stream.content.len()).then_some(encoded)
+    } else if filters == [b"FlateDecode"] && !stream.dict.has(b"DecodeParms") && !is(b"Subtype", b"Image") {
+        let data = crate::filters::flate_decode(&stream.content).ok()?;
+        let encoded = options.deflate(&data).ok()?;
+        (encoded.len() < stream.content.len()).then_some(encoded)
+    } else {
+        None
+    }
+}
+
+/// The oldest version that can hold cross-reference streams
+const XREF_STREAM_VERSION: (u8, u8) = (1, 5);
+
+/// Trailer entries describing a cross-reference stream that was read
+const XREF_STREAM_KEYS: [&[u8]; 8] = [b"Type", b"W", b"Index", b"Filter", b"DecodeParms", b"Length", b"Prev", b"XRefStm"];
+
+/// The version the document will declare when saved with `requested`
+pub fn output_version(doc: &Document, requested: Option<&str>) -> String {
+    if let Some(requested) = requested {
+        return requested.to_string();
+    }
+    match parse_version(&doc.version) {
+        Some(version) if uses_xref_stream(doc) && version < XREF_STREAM_VERSION => "1.5".to_string(),
+        _ => doc.version.clone(),
+    }
+}
+
+/// Whether saving as `requested` means giving up the input's cross-reference stream
+pub fn version_drops_xref_stream(doc: &Document, requested: &str) -> bool {
+    uses_xref_stream(doc)
+        && parse_version(requested).is_some_and(|version| version < XREF_STREAM_VERSION)
+}
+
+/// Declare the output version, writing a classic cross-reference table for versions
+/// before 1.5 and dropping a catalog `/Version` newer than a forced one
+fn apply_version(doc: &mut Document, requested: Option<&str>) {
+    let version = output_version(doc, requested);
+    if let Some(requested) = requested {
+        if version_drops_xref_stream(doc, requested) {
+            doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+            for key in XREF_STREAM_KEYS {
+                doc.trailer.remove(key);
+            }
+        }
+        let catalog_version = doc
+            .catalog()
+            .ok()
+            .and_then(|catalog| catalog.get(b"Version").and_then(Object::as_name).ok())
+            .and_then(|name| parse_version(&String::from_utf8_lossy(name)));
+        if catalog_version.is_some_and(|catalog| Some(catalog) > parse_version(requested)) {
+            if let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) {
+                if let Ok(catalog) = doc.get_dictionary_mut(catalog_id) {
+                    catalog.remove(b"Version");
+                }
+            }
+        }
+    }
+    doc.version = version;
+}
+
+fn uses_xref_stream(doc: &Document) -> bool {
+    matches!(doc.reference_table.cross_reference_type, XrefType::CrossReferenceStream)
+}
+
+/// Parse a version such as "1.4" into comparable numbers
+pub fn parse_version(version: &str) -> Option<(u8, u8)> {
+    let (major, minor) = version.trim().split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Remove page thumbnails, dropping the images themselves when nothing else uses them.
+/// Returns the number of pages whose thumbnail was removed.
+pub fn strip_thumbnails(doc: &mut Document) -> usize {
+    let thumbnails = thumbnail_ids(doc);
+    let mut stripped = 0;
+    for page_id in doc.get_pages().into_values() {
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            if page.remove(b"Thumb").is_some() {
+                stripped += 1;
+            }
+        }
+    }
+
+    let referenced = referenced_ids(doc);
+    doc.objects.retain(|id, _| !thumbnails.contains(id) || referenced.contains(id));
+    stripped
+}
+
+/// Remove every object not reachable from the trailer (`/Root`, `/Info`, `/Encrypt`, ...),
+/// returning how many were dropped
+pub fn remove_unreachable_objects(doc: &mut Document) -> usize {
+    let reachable = reachable_objects(doc);
+    let before = doc.objects.len();
+    doc.objects.retain(|id, _| reachable.contains(id));
+    before - doc.objects.len()
+}
+
+/// Renumber the objects 1, 2, 3, ... in the order a breadth-first walk from the trailer
+/// reaches them, then the unreachable ones in their current order, so the numbering no
+/// longer depends on the order the passes added objects in
+fn renumber_in_reading_order(doc: &mut Document) {
+    let mut order: Vec<ObjectId> = Vec::with_capacity(doc.objects.len());
+    let mut seen: HashSet<ObjectId> = HashSet::new();
+    let mut visit = |id: ObjectId, order: &mut Vec<ObjectId>| {
+        if doc.objects.contains_key(&id) && seen.insert(id) {
+            order.push(id);
+        }
+    };
+    for (_, value) in doc.trailer.iter() {
+        for_each_reference(value, &mut |id| visit(id, &mut order));
+    }
+    let mut next = 0;
+    while let Some(&id) = order.get(next) {
+        for_each_reference(&doc.objects[&id], &mut |child| visit(child, &mut order));
+        next += 1;
+    }
+    for &id in doc.objects.keys() {
+        visit(id, &mut order);
+    }
+
+    let numbers: HashMap<ObjectId, ObjectId> = order.iter().enumerate().map(|(index, &id)| (id, (index as u32 + 1, 0))).collect();
+    let mut objects = std::mem::take(&mut doc.objects);
+    doc.objects = order
+        .iter()
+        .map(|id| {
+            let mut object = objects.remove(id).unwrap();
+            renumber_references(&mut object, &numbers);
+            (numbers[id], object)
+        })
+        .collect();
+    for (_, value) in doc.trailer.iter_mut() {
+        renumber_references(value, &numbers);
+    }
+    doc.max_id = order.len() as u32;
+}
+
+fn renumber_references(object: &mut Object, numbers: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            // A reference to a missing object stays as dangling as it was
+            if let Some(number) = numbers.get(id) {
+                *id = *number;
+            }
+        }
+        Object::Array(items) => items.iter_mut().for_each(|item| renumber_references(item, numbers)),
+        Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, value)| renumber_references(value, numbers)),
+        Object::Stream(stream) => stream.dict.iter_mut().for_each(|(_, value)| renumber_references(value, numbers)),
+        _ => {}
     }
+}
 
-    let _file = doc.save(path)
-        .with_context(|| format!("Failed to save PDF: {}", path.display()))?;
+/// Set the file identifier to the MD5 of the document written without one, keeping the
+/// permanent first half of an identifier the input had
+fn derive_file_id(doc: &mut Document, readable: bool) -> Result<()> {
+    let permanent = doc.trailer.get(b"ID").and_then(Object::as_array).ok().and_then(|id| id.first().cloned());
+    doc.trailer.remove(b"ID");
+    let mut buffer = Vec::new();
+    serialize(doc, &mut buffer, readable).context("Failed to serialize PDF")?;
+    let id = Object::String(openssl::hash::hash(openssl::hash::MessageDigest::md5(), &buffer)?.to_vec(), StringFormat::Hexadecimal);
+    doc.trailer.set("ID", vec![permanent.unwrap_or_else(|| id.clone()), id]);
     Ok(())
 }
 
+/// Ids of the objects that can be reached from the trailer
+pub fn reachable_objects(doc: &Document) -> HashSet<ObjectId> {
+    let mut reachable: HashSet<ObjectId> = HashSet::new();
+    let mut pending: Vec<ObjectId> = Vec::new();
+    for (_, value) in doc.trailer.iter() {
+        for_each_reference(value, &mut |id| pending.push(id));
+    }
+
+    while let Some(id) = pending.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        if let Some(object) = doc.objects.get(&id) {
+            for_each_reference(object, &mut |child| {
+                if !reachable.contains(&child) {
+                    pending.push(child);
+                }
+            });
+        }
+    }
+    reachable
+}
+
+/// Write `info` into the document information dictionary, creating it if there is none
+/// and keeping the entries not being set. An XMP packet on the catalog is regenerated
+/// from the resulting dictionary so viewers preferring XMP show the same values.
+pub fn set_document_info(doc: &mut Document, info: &DocumentInfo) {
+    if info.is_empty() {
+        return;
+    }
+
+    let existing = match doc.trailer.get(b"Info") {
+        Ok(Object::Reference(id)) => doc.get_dictionary(*id).ok().map(|dict| (Some(*id), dict.clone())),
+        Ok(Object::Dictionary(dict)) => Some((None, dict.clone())),
+        _ => None,
+    };
+    let (info_id, mut dict) = existing.unwrap_or_default();
+    let catalog_metadata = catalog_metadata_id(doc);
+    let packet = catalog_metadata.and_then(|id| read_packet(doc, id));
+    // What only the XMP says is kept by copying it into the dictionary first
+    if let Some(packet) = &packet {
+        for (property, value) in read_xmp(packet) {
+            if dict.has(property.info_key.as_bytes()) {
+                continue;
+            }
+            if let Some(object) = info_object(property, &value) {
+                dict.set(property.info_key, object);
+            }
+        }
+    }
+    for (key, value) in info.entries() {
+        dict.set(key, text_string(value));
+    }
+    let xmp = packet.and_then(|packet| update_xmp(&packet, &dict)).unwrap_or_else(|| xmp_packet(&dict));
+
+    match info_id {
+        Some(id) => {
+            doc.objects.insert(id, Object::Dictionary(dict));
+        }
+        None => {
+            let id = doc.add_object(dict);
+            doc.trailer.set("Info", id);
+        }
+    }
+
+    if let Some(id) = catalog_metadata {
+        let mut stream_dict = Dictionary::new();
+        stream_dict.set("Type", Object::Name(b"Metadata".to_vec()));
+        stream_dict.set("Subtype", Object::Name(b"XML".to_vec()));
+        // XMP is left uncompressed so tools that scan for the packet can find it
+        doc.objects.insert(id, Object::Stream(Stream::new(stream_dict, xmp.into_bytes()).with_compression(false)));
+    }
+}
+
+/// Give the document a file identifier if it has none: two random 16-byte strings, or
+/// (`deterministic`) the MD5 of the document as it stands, so the same input always
+/// gets the same identifier. Returns whether one was added.
+pub fn ensure_file_id(doc: &mut Document, deterministic: bool) -> Result<bool> {
+    if doc.trailer.has(b"ID") {
+        return Ok(false);
+    }
+    let id = if deterministic {
+        let mut buffer = Vec::new();
+        doc.save_to(&mut buffer).context("Failed to serialize PDF")?;
+        openssl::hash::hash(openssl::hash::MessageDigest::md5(), &buffer)?.to_vec()
+    } else {
+        let mut id = vec![0; 16];
+        openssl::rand::rand_bytes(&mut id)?;
+        id
+    };
+    let id = Object::String(id, StringFormat::Hexadecimal);
+    doc.trailer.set("ID", vec![id.clone(), id]);
+    Ok(true)
+}
+
+/// A PDF text string: PDFDocEncoding for ASCII, UTF-16BE with a byte order mark otherwise
+pub fn text_string(value: &str) -> Object {
+    if value.is_ascii() {
+        return Object::String(value.as_bytes().to_vec(), StringFormat::Literal);
+    }
+    let mut bytes = vec![0xFE, 0xFF];
+    bytes.extend(value.encode_utf16().flat_map(u16::to_be_bytes));
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Decode a PDF text string (UTF-16BE with a byte order mark, or PDFDocEncoding,
+/// approximated as Latin-1)
+pub fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
 /// Create optimized save options based on preset
 pub fn create_save_options_for_preset(preset: &crate::cli::Preset) -> SaveOptions {
     match preset {
         crate::cli::Preset::Web => SaveOptions {
             enable_compression: true,
+            remove_unused_objects: false,
+            strip_thumbnails: true,
+            recompress_lzw: false,
+            flate_level: 6,
+            zopfli: false,
+            version: None,
+            encryption: None,
+            deterministic: false,
+            readable: false,
         },
         crate::cli::Preset::Print => SaveOptions {
             enable_compression: true,
+            remove_unused_objects: false,
+            strip_thumbnails: false,
+            recompress_lzw: false,
+            flate_level: 9,
+            zopfli: false,
+            version: None,
+            encryption: None,
+            deterministic: false,
+            readable: false,
         },
         crate::cli::Preset::Archive => SaveOptions {
             enable_compression: true,
+            remove_unused_objects: true,
+            strip_thumbnails: false,
+            recompress_lzw: true,
+            flate_level: 9,
+            zopfli: cfg!(feature = "zopfli"),
+            version: None,
+            encryption: None,
+            deterministic: false,
+            readable: false,
         },
         crate::cli::Preset::Maximum => SaveOptions {
             enable_compression: true,
+            remove_unused_objects: true,
+            strip_thumbnails: true,
+            recompress_lzw: true,
+            flate_level: 9,
+            zopfli: cfg!(feature = "zopfli"),
+            version: None,
+            encryption: None,
+            deterministic: false,
+            readable: false,
+        },
+        crate::cli::Preset::Scan => SaveOptions {
+            enable_compression: true,
+            remove_unused_objects: true,
+            strip_thumbnails: true,
+            recompress_lzw: true, // Older scanning software often wrote LZW
+            flate_level: 9,
+            zopfli: false, // The page images are nearly all of the file
+            version: None,
+            encryption: None,
+            deterministic: false,
+            readable: false,
         },
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The names in `directory`
+    fn listing(directory: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(directory).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_destination_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.pdf");
+        std::fs::write(&path, "original").unwrap();
+        let result = write_atomically(&path, |writer| {
+            writer.write_all(b"%PDF-1.7\n% half of a document")?;
+            anyhow::bail!("No space left on device")
+        });
+        assert_eq!(result.unwrap_err().to_string(), "No space left on device");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(listing(dir.path()), ["out.pdf"]);
+    }
+
+    #[test]
+    fn a_failed_rename_leaves_no_temporary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        // A file can't be renamed over a directory that has something in it
+        let path = dir.path().join("out.pdf");
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("kept"), "kept").unwrap();
+        assert!(write_atomically(&path, |writer| Ok(writer.write_all(b"%PDF-1.7\n")?)).is_err());
+        assert_eq!(std::fs::read_to_string(path.join("kept")).unwrap(), "kept");
+        assert_eq!(listing(dir.path()), ["out.pdf"]);
+    }
+
+    #[test]
+    fn a_complete_write_replaces_the_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.pdf");
+        std::fs::write(&path, "original").unwrap();
+        #[cfg(unix)]
+        std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o640)).unwrap();
+        write_atomically(&path, |writer| Ok(writer.write_all(b"%PDF-1.7\n")?)).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "%PDF-1.7\n");
+        assert_eq!(listing(dir.path()), ["out.pdf"]);
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions()) & 0o777, 0o640);
+    }
+}
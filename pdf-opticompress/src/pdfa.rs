@@ -0,0 +1,345 @@
+use anyhow::Result;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::collections::BTreeSet;
+
+use crate::filters::{decode_stream, stream_filters};
+use crate::inline_images::for_each_operation;
+use crate::objects::resolve;
+use crate::pdf_writer::{ensure_file_id, parse_version, reachable_objects};
+use crate::xmp::xmp_packet;
+use crate::resources::page_content;
+
+/// The conformance level written into the XMP identification schema. Part 2 is based
+/// on PDF 1.7, so cross-reference and object streams are allowed.
+pub const PDFA_LEVEL: &str = "PDF/A-2b";
+const PART: u8 = 2;
+const CONFORMANCE: &str = "B";
+
+const SRGB: &str = "sRGB IEC61966-2.1";
+
+/// Action types PDF/A forbids that `--sanitize` removes
+const FORBIDDEN_ACTIONS: [&[u8]; 3] = [b"JavaScript", b"Launch", b"ImportData"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingStatus {
+    /// Already as PDF/A requires
+    Ok,
+    /// Changed to what PDF/A requires
+    Fixed,
+    /// Still not as PDF/A requires
+    Failed,
+}
+
+/// One compliance-relevant observation about the output
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Finding {
+    pub status: FindingStatus,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(status: FindingStatus, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+}
+
+/// What `--pdfa` changed and what still stands in the way of conformance. This is no
+/// substitute for a validator: only the requirements the optimizer can affect are checked.
+#[derive(Debug, Default, Clone)]
+pub struct PdfaReport {
+    pub findings: Vec<Finding>,
+}
+
+impl PdfaReport {
+    pub fn failures(&self) -> usize {
+        self.findings.iter().filter(|finding| finding.status == FindingStatus::Failed).count()
+    }
+}
+
+/// The findings, with the level checked against and how many failed
+impl Serialize for PdfaReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut report = serializer.serialize_struct("PdfaReport", 3)?;
+        report.serialize_field("level", PDFA_LEVEL)?;
+        report.serialize_field("failures", &self.failures())?;
+        report.serialize_field("findings", &self.findings)?;
+        report.end()
+    }
+}
+
+/// Refuse documents with content PDF/A forbids that the optimizer won't silently drop
+pub fn reject_forbidden_content(doc: &Document) -> Result<()> {
+    let has_document_javascript = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Names").ok())
+        .and_then(|names| resolve(doc, names).as_dict().ok())
+        .is_some_and(|names| names.has(b"JavaScript"));
+    // Objects nothing refers to anymore (such as actions `--sanitize` unlinked) aren't written
+    let reachable = reachable_objects(doc);
+    let forbidden_action = doc
+        .objects
+        .iter()
+        .filter(|(id, _)| reachable.contains(id))
+        .find_map(|(_, object)| forbidden_action(object));
+
+    if has_document_javascript {
+        anyhow::bail!("The document contains JavaScript, which PDF/A forbids; remove it with --sanitize");
+    }
+    if let Some(action) = forbidden_action {
+        anyhow::bail!("The document contains {} actions, which PDF/A forbids; remove them with --sanitize", action);
+    }
+    Ok(())
+}
+
+/// The first forbidden action type in an object or the dictionaries nested in it
+fn forbidden_action(object: &Object) -> Option<String> {
+    let dict = match object {
+        Object::Dictionary(dict) => dict,
+        Object::Stream(stream) => &stream.dict,
+        Object::Array(items) => return items.iter().find_map(forbidden_action),
+        _ => return None,
+    };
+    if let Ok(action) = dict.get(b"S").and_then(Object::as_name) {
+        if FORBIDDEN_ACTIONS.contains(&action) {
+            return Some(String::from_utf8_lossy(action).into_owned());
+        }
+    }
+    dict.iter().find_map(|(_, value)| forbidden_action(value))
+}
+
+/// Add what PDF/A requires and the input may lack: a file identifier, an output intent
+/// (sRGB, unless one is there) and XMP metadata with the PDF/A identification schema.
+/// `deterministic` derives the identifier from the content instead of randomly.
+pub fn add_pdfa_requirements(doc: &mut Document, deterministic: bool) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) else {
+        anyhow::bail!("The document has no catalog");
+    };
+
+    if ensure_file_id(doc, deterministic)? {
+        findings.push(Finding::new(FindingStatus::Fixed, "File identifier: added"));
+    }
+
+    match pdfa_output_intent(doc) {
+        Some(identifier) => findings.push(Finding::new(FindingStatus::Ok, format!("Output intent: {}", identifier))),
+        None => {
+            let profile = Stream::new(dictionary! { "N" => 3 }, crate::icc::srgb_profile());
+            let profile_id = doc.add_object(profile);
+            let intent = dictionary! {
+                "Type" => "OutputIntent",
+                "S" => "GTS_PDFA1",
+                "OutputConditionIdentifier" => Object::string_literal(SRGB),
+                "Info" => Object::string_literal(SRGB),
+                "RegistryName" => Object::string_literal("http://www.color.org"),
+                "DestOutputProfile" => profile_id,
+            };
+            let intent_id = doc.add_object(intent);
+            let catalog = doc.get_dictionary_mut(catalog_id)?;
+            // Intents for other standards (PDF/X) stay alongside
+            match catalog.get_mut(b"OutputIntents") {
+                Ok(Object::Array(intents)) => intents.push(Object::Reference(intent_id)),
+                _ => catalog.set("OutputIntents", vec![Object::Reference(intent_id)]),
+            }
+            findings.push(Finding::new(FindingStatus::Fixed, format!("Output intent: embedded {}", SRGB)));
+        }
+    }
+
+    findings.push(identify_in_xmp(doc, catalog_id)?);
+    Ok(findings)
+}
+
+/// The identifier of the document's PDF/A output intent, if it has one with a profile
+fn pdfa_output_intent(doc: &Document) -> Option<String> {
+    output_intent_profile(doc)?;
+    let intent = pdfa_intent(doc)?;
+    let identifier = intent.get(b"OutputConditionIdentifier").and_then(Object::as_str).unwrap_or(b"unnamed");
+    Some(String::from_utf8_lossy(identifier).into_owned())
+}
+
+fn pdfa_intent(doc: &Document) -> Option<&Dictionary> {
+    let intents = doc.catalog().ok()?.get(b"OutputIntents").ok()?;
+    resolve(doc, intents).as_array().ok()?.iter().find_map(|intent| {
+        let intent = resolve(doc, intent).as_dict().ok()?;
+        matches!(intent.get(b"S"), Ok(Object::Name(s)) if s == b"GTS_PDFA1").then_some(intent)
+    })
+}
+
+/// The number of components of the PDF/A output intent's profile
+fn output_intent_profile(doc: &Document) -> Option<i64> {
+    let profile = pdfa_intent(doc)?.get(b"DestOutputProfile").ok()?;
+    resolve(doc, profile).as_stream().ok()?.dict.get(b"N").and_then(Object::as_i64).ok()
+}
+
+/// Make the catalog's XMP packet declare PDF/A conformance, creating the packet from the
+/// document information dictionary if there is none. Other XMP properties are kept.
+fn identify_in_xmp(doc: &mut Document, catalog_id: ObjectId) -> Result<Finding> {
+    let metadata_id = doc.get_dictionary(catalog_id)?.get(b"Metadata").and_then(Object::as_reference).ok();
+    let existing = metadata_id
+        .and_then(|id| doc.get_object(id).and_then(Object::as_stream).ok())
+        .and_then(|stream| decode_stream(stream).ok())
+        .and_then(|packet| String::from_utf8(packet).ok());
+
+    let identification = format!(
+        "<rdf:Description rdf:about=\"\" xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">\n\
+         <pdfaid:part>{}</pdfaid:part>\n<pdfaid:conformance>{}</pdfaid:conformance>\n</rdf:Description>\n",
+        PART, CONFORMANCE
+    );
+    let (packet, finding) = match existing {
+        Some(packet) if packet.contains("pdfaid:part") => {
+            return Ok(Finding::new(FindingStatus::Ok, "XMP metadata: already declares a PDF/A part (kept as is)"));
+        }
+        Some(packet) if packet.contains("</rdf:RDF>") => (
+            packet.replacen("</rdf:RDF>", &format!("{}</rdf:RDF>", identification), 1),
+            Finding::new(FindingStatus::Fixed, format!("XMP metadata: identified as {}", PDFA_LEVEL)),
+        ),
+        _ => {
+            let info = match doc.trailer.get(b"Info") {
+                Ok(info) => resolve(doc, info).as_dict().cloned().unwrap_or_default(),
+                Err(_) => Dictionary::new(),
+            };
+            let packet = xmp_packet(&info).replacen("</rdf:RDF>", &format!("{}</rdf:RDF>", identification), 1);
+            let message = format!("XMP metadata: created from the document information, identified as {}", PDFA_LEVEL);
+            (packet, Finding::new(FindingStatus::Fixed, message))
+        }
+    };
+
+    let stream_dict = dictionary! { "Type" => "Metadata", "Subtype" => "XML" };
+    // PDF/A-1 readers expect the packet unfiltered, and tools scan for it
+    let stream = Object::Stream(Stream::new(stream_dict, packet.into_bytes()).with_compression(false));
+    match metadata_id {
+        Some(id) => {
+            doc.objects.insert(id, stream);
+        }
+        None => {
+            let id = doc.add_object(stream);
+            doc.get_dictionary_mut(catalog_id)?.set("Metadata", id);
+        }
+    }
+    Ok(finding)
+}
+
+/// Check the written document for what the optimizer can't fix, after `findings` from
+/// `add_pdfa_requirements`
+pub fn check_pdfa(doc: &Document, findings: Vec<Finding>) -> PdfaReport {
+    let mut report = PdfaReport { findings };
+    let reachable = reachable_objects(doc);
+    let objects = || doc.objects.iter().filter(|(id, _)| reachable.contains(id)).map(|(_, object)| object);
+    let mut push = |status, message: String| report.findings.push(Finding::new(status, message));
+
+    if parse_version(&doc.version).is_some_and(|version| version > (1, 7)) {
+        push(FindingStatus::Failed, format!("PDF version: {} is newer than PDF/A-2 allows (1.7)", doc.version));
+    }
+
+    let missing = fonts_without_programs(doc, objects());
+    if missing.is_empty() {
+        push(FindingStatus::Ok, "Fonts: all embedded".to_string());
+    } else {
+        let names: Vec<&str> = missing.iter().map(String::as_str).collect();
+        push(FindingStatus::Failed, format!("Fonts: {} not embedded ({})", missing.len(), names.join(", ")));
+    }
+
+    let lzw = objects()
+        .filter_map(|object| object.as_stream().ok())
+        .filter(|stream| stream_filters(stream).iter().any(|filter| filter == b"LZWDecode"))
+        .count();
+    if lzw > 0 {
+        push(FindingStatus::Failed, format!("Compression: {} streams use LZW, which PDF/A forbids", lzw));
+    }
+
+    let components = output_intent_profile(doc);
+    for (space, needs) in device_color_spaces(doc, objects()) {
+        if needs != 1 && components != Some(needs) {
+            push(
+                FindingStatus::Failed,
+                format!("Color: {} is used, but the output intent's profile doesn't have {} components", space, needs),
+            );
+        }
+    }
+
+    let has_embedded_files = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Names").ok())
+        .and_then(|names| resolve(doc, names).as_dict().ok())
+        .is_some_and(|names| names.has(b"EmbeddedFiles"));
+    if has_embedded_files {
+        push(
+            FindingStatus::Failed,
+            "Embedded files: PDF/A-2 only allows PDF/A attachments, which isn't checked (remove them with --sanitize)".to_string(),
+        );
+    }
+
+    push(FindingStatus::Ok, "Encryption and JavaScript: none".to_string());
+    report
+}
+
+/// Names of the fonts that have no embedded program (Type 3 fonts are drawn by content
+/// streams and need none)
+fn fonts_without_programs<'a>(doc: &Document, objects: impl Iterator<Item = &'a Object>) -> BTreeSet<String> {
+    let has_program = |font: &Dictionary| {
+        let Ok(descriptor) = font.get(b"FontDescriptor") else { return false };
+        let Ok(descriptor) = resolve(doc, descriptor).as_dict() else { return false };
+        [&b"FontFile"[..], b"FontFile2", b"FontFile3"].iter().any(|key| descriptor.has(key))
+    };
+
+    let mut missing = BTreeSet::new();
+    for object in objects {
+        let Object::Dictionary(font) = object else { continue };
+        if !matches!(font.get(b"Type"), Ok(Object::Name(kind)) if kind == b"Font") {
+            continue;
+        }
+        let embedded = match font.get(b"Subtype").and_then(Object::as_name) {
+            Ok(b"Type3") | Ok(b"CIDFontType0") | Ok(b"CIDFontType2") => continue,
+            // The program belongs to the descendant CID font
+            Ok(b"Type0") => font
+                .get(b"DescendantFonts")
+                .map(|fonts| resolve(doc, fonts))
+                .and_then(Object::as_array)
+                .ok()
+                .and_then(|fonts| fonts.first())
+                .and_then(|descendant| resolve(doc, descendant).as_dict().ok())
+                .is_some_and(has_program),
+            _ => has_program(font),
+        };
+        if !embedded {
+            let name = font.get(b"BaseFont").and_then(Object::as_name).unwrap_or(b"unnamed");
+            missing.insert(String::from_utf8_lossy(name).into_owned());
+        }
+    }
+    missing
+}
+
+/// The device color spaces images and page content use, with their number of components
+fn device_color_spaces<'a>(doc: &Document, objects: impl Iterator<Item = &'a Object>) -> BTreeSet<(&'static str, i64)> {
+    const DEVICE_SPACES: [(&[u8], &str, i64); 3] =
+        [(b"DeviceGray", "DeviceGray", 1), (b"DeviceRGB", "DeviceRGB", 3), (b"DeviceCMYK", "DeviceCMYK", 4)];
+    let device = |name: &[u8]| DEVICE_SPACES.iter().find(|(key, _, _)| *key == name).map(|&(_, space, n)| (space, n));
+
+    let mut spaces = BTreeSet::new();
+    for object in objects {
+        let Object::Stream(stream) = object else { continue };
+        if !matches!(stream.dict.get(b"Subtype"), Ok(Object::Name(subtype)) if subtype == b"Image") {
+            continue;
+        }
+        if let Some(space) = stream.dict.get(b"ColorSpace").and_then(Object::as_name).ok().and_then(device) {
+            spaces.insert(space);
+        }
+    }
+
+    for page_id in doc.get_pages().into_values() {
+        let Some(content) = page_content(doc, page_id) else { continue };
+        for_each_operation(&content, |operator, operands| {
+            let space = match (operator, operands) {
+                (b"rg" | b"RG", _) => device(b"DeviceRGB"),
+                (b"k" | b"K", _) => device(b"DeviceCMYK"),
+                (b"cs" | b"CS", [Object::Name(name)]) => device(name),
+                _ => None,
+            };
+            spaces.extend(space);
+        });
+    }
+    spaces
+}
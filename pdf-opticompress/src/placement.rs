@@ -0,0 +1,241 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::filters::decode_stream;
+use crate::inline_images::{for_each_operation, page_resources};
+use crate::objects::resolve;
+use crate::resources::page_content;
+
+/// Form XObjects nested deeper than this are not followed
+const MAX_DEPTH: usize = 16;
+
+/// How large each image XObject is drawn on the pages, in points
+#[derive(Debug, Default)]
+pub struct DisplaySizes {
+    /// The width and height of every place each image is drawn
+    placements: HashMap<ObjectId, Vec<(f64, f64)>>,
+    /// Images drawn by content that couldn't be followed, at an unknown size
+    unknown: HashSet<ObjectId>,
+    /// For each page, its largest image and the share of the page's area it covers
+    pub page_coverage: Vec<(Option<ObjectId>, f64)>,
+}
+
+impl DisplaySizes {
+    /// The largest size an image is drawn at, if every place it's drawn is known
+    pub fn largest(&self, id: ObjectId) -> Option<(f64, f64)> {
+        self.placements(id)?
+            .iter()
+            .fold(None, |largest, &(width, height)| match largest {
+                Some((largest_width, largest_height)) => Some((width.max(largest_width), height.max(largest_height))),
+                None => Some((width, height)),
+            })
+    }
+
+    /// The size of every place an image is drawn, if they're all known
+    pub fn placements(&self, id: ObjectId) -> Option<&[(f64, f64)]> {
+        if self.unknown.contains(&id) {
+            return None;
+        }
+        self.placements.get(&id).map(Vec::as_slice)
+    }
+
+    fn record(&mut self, id: ObjectId, ctm: &[f64; 6]) {
+        let [a, b, c, d, _, _] = *ctm;
+        // The image fills the unit square, so the matrix's axes are its edges, rotated or not
+        let (width, height) = (a.hypot(b), c.hypot(d));
+        self.placements.entry(id).or_default().push((width, height));
+        let area = (a * d - b * c).abs();
+        if let Some(coverage) = self.page_coverage.last_mut().filter(|(_, largest)| area > *largest) {
+            *coverage = (Some(id), area);
+        }
+    }
+
+    /// Mark every image reachable from `resources` as drawn at an unknown size
+    fn record_unknown(&mut self, doc: &Document, resources: &Dictionary, depth: usize) {
+        resource_images(doc, resources, depth, &mut |id| {
+            self.unknown.insert(id);
+        });
+    }
+}
+
+/// The resolution of an image of `width` by `height` pixels drawn at `size` points: that
+/// of its more coarsely drawn direction, which is the one downsampling has to keep
+pub fn effective_dpi(width: i64, height: i64, size: (f64, f64)) -> f64 {
+    (width as f64 / (size.0 / 72.0)).min(height as f64 / (size.1 / 72.0))
+}
+
+/// Visit every image XObject in `resources`, and in the resources of the Form XObjects
+/// they hold, whether or not the content draws it
+pub fn for_each_resource_image(doc: &Document, resources: &Dictionary, visit: &mut impl FnMut(ObjectId)) {
+    resource_images(doc, resources, 0, visit);
+}
+
+fn resource_images(doc: &Document, resources: &Dictionary, depth: usize, visit: &mut impl FnMut(ObjectId)) {
+    let Some(xobjects) = resources.get(b"XObject").ok().and_then(|x| resolve(doc, x).as_dict().ok()) else { return };
+    for (_, xobject) in xobjects.iter() {
+        let Ok(id) = xobject.as_reference() else { continue };
+        let Ok(Object::Stream(stream)) = doc.get_object(id) else { continue };
+        match stream.dict.get(b"Subtype") {
+            Ok(Object::Name(subtype)) if subtype == b"Image" => visit(id),
+            Ok(Object::Name(subtype)) if subtype == b"Form" && depth < MAX_DEPTH => {
+                let form_resources = stream.dict.get(b"Resources").ok().and_then(|r| resolve(doc, r).as_dict().ok());
+                if let Some(form_resources) = form_resources {
+                    resource_images(doc, form_resources, depth + 1, visit);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The pages (numbered from 1) whose resources hold each image XObject, directly or through
+/// Form XObjects. Soft masks and masks count as used where their image is.
+pub fn image_pages(doc: &Document) -> HashMap<ObjectId, BTreeSet<u32>> {
+    let mut pages: HashMap<ObjectId, BTreeSet<u32>> = HashMap::new();
+    for (page_number, page_id) in doc.get_pages() {
+        let Some(resources) = page_resources(doc, page_id) else { continue };
+        for_each_resource_image(doc, &resources, &mut |id| {
+            pages.entry(id).or_default().insert(page_number);
+            let Ok(Object::Stream(image)) = doc.get_object(id) else { return };
+            for key in [b"SMask".as_slice(), b"Mask"] {
+                if let Ok(mask) = image.dict.get(key).and_then(Object::as_reference) {
+                    pages.entry(mask).or_default().insert(page_number);
+                }
+            }
+        });
+    }
+    pages
+}
+
+/// Follow the current transformation matrix through every page's content, and the Form
+/// XObjects it draws, to find the size each image XObject is drawn at.
+///
+/// Images that are only drawn from elsewhere (annotation appearances, patterns) or used
+/// as soft masks don't appear at all.
+pub fn image_display_sizes(doc: &Document) -> DisplaySizes {
+    let mut sizes = DisplaySizes::default();
+    for page_id in doc.get_pages().into_values() {
+        let Ok(page) = doc.get_dictionary(page_id) else { continue };
+        let resources = page_resources(doc, page_id);
+        let user_unit = page.get(b"UserUnit").and_then(Object::as_float).map_or(1.0, f64::from);
+        let ctm = [user_unit, 0.0, 0.0, user_unit, 0.0, 0.0];
+        sizes.page_coverage.push((None, 0.0));
+
+        if let Some(resources) = resources {
+            match page_content(doc, page_id) {
+                Some(content) => walk(doc, &content, &resources, ctm, &mut Vec::new(), &mut sizes),
+                None => sizes.record_unknown(doc, &resources, 0),
+            }
+        }
+        // Areas are recorded in points, like the page's, scaled by the user unit
+        let page_area = media_box_area(doc, page_id) * user_unit * user_unit;
+        if let Some((_, coverage)) = sizes.page_coverage.last_mut() {
+            *coverage = if page_area > 0.0 { (*coverage / page_area).min(1.0) } else { 0.0 };
+        }
+    }
+    sizes
+}
+
+/// The area of a page's `/MediaBox`
+fn media_box_area(doc: &Document, page_id: ObjectId) -> f64 {
+    let Some([x0, y0, x1, y1]) = media_box(doc, page_id) else { return 0.0 };
+    ((x1 - x0) as f64 * (y1 - y0) as f64).abs()
+}
+
+/// A page's `/MediaBox`, which may be inherited from the page tree
+pub fn media_box(doc: &Document, page_id: ObjectId) -> Option<[f32; 4]> {
+    let mut node = doc.get_dictionary(page_id).ok();
+    for _ in 0..MAX_DEPTH {
+        let dict = node?;
+        if let Ok(media_box) = dict.get(b"MediaBox") {
+            return resolve(doc, media_box)
+                .as_array()
+                .ok()
+                .and_then(|media_box| media_box.iter().map(|n| n.as_float().ok()).collect::<Option<Vec<_>>>())
+                .and_then(|media_box| <[f32; 4]>::try_from(media_box).ok());
+        }
+        node = dict.get(b"Parent").and_then(Object::as_reference).ok().and_then(|id| doc.get_dictionary(id).ok());
+    }
+    None
+}
+
+/// Track `q`, `Q` and `cm` through `content`, recording the images it draws with `Do`
+fn walk(
+    doc: &Document,
+    content: &[u8],
+    resources: &Dictionary,
+    ctm: [f64; 6],
+    stack: &mut Vec<ObjectId>,
+    sizes: &mut DisplaySizes,
+) {
+    let xobjects = resources.get(b"XObject").ok().and_then(|x| resolve(doc, x).as_dict().ok());
+    let mut saved = Vec::new();
+    let mut ctm = ctm;
+    let parsed = for_each_operation(content, |operator, operands| match (operator, operands) {
+        (b"q", _) => saved.push(ctm),
+        (b"Q", _) => ctm = saved.pop().unwrap_or(ctm),
+        (b"cm", _) => {
+            if let Some(matrix) = matrix(operands) {
+                ctm = multiply(&matrix, &ctm);
+            }
+        }
+        (b"Do", [Object::Name(name)]) => {
+            let Some(Ok(Object::Reference(id))) = xobjects.map(|x| x.get(name)) else { return };
+            let Ok(Object::Stream(stream)) = doc.get_object(*id) else { return };
+            match stream.dict.get(b"Subtype") {
+                Ok(Object::Name(subtype)) if subtype == b"Image" => sizes.record(*id, &ctm),
+                Ok(Object::Name(subtype)) if subtype == b"Form" => {
+                    if stack.contains(id) || stack.len() >= MAX_DEPTH {
+                        return;
+                    }
+                    let form_ctm = match stream.dict.get(b"Matrix").ok().and_then(|m| resolve(doc, m).as_array().ok()) {
+                        Some(form_matrix) => matrix(form_matrix).map_or(ctm, |form_matrix| multiply(&form_matrix, &ctm)),
+                        None => ctm,
+                    };
+                    // Forms without resources of their own use their caller's
+                    let form_resources = stream
+                        .dict
+                        .get(b"Resources")
+                        .ok()
+                        .and_then(|r| resolve(doc, r).as_dict().ok())
+                        .unwrap_or(resources);
+                    let Ok(form_content) = decode_stream(stream) else {
+                        sizes.record_unknown(doc, form_resources, stack.len());
+                        return;
+                    };
+                    stack.push(*id);
+                    walk(doc, &form_content, form_resources, form_ctm, stack, sizes);
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    });
+    if !parsed {
+        sizes.record_unknown(doc, resources, stack.len());
+    }
+}
+
+fn matrix(operands: &[Object]) -> Option<[f64; 6]> {
+    if operands.len() != 6 {
+        return None;
+    }
+    let mut matrix = [0.0; 6];
+    for (value, operand) in matrix.iter_mut().zip(operands) {
+        *value = operand.as_float().ok()? as f64;
+    }
+    Some(matrix)
+}
+
+/// The matrix applying `m` first, then `n`
+fn multiply(m: &[f64; 6], n: &[f64; 6]) -> [f64; 6] {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
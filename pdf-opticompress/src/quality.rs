@@ -0,0 +1,49 @@
+use image::GrayImage;
+
+/// Window size for the structural similarity computation
+const WINDOW: u32 = 8;
+
+/// Mean structural similarity (SSIM) of two equally sized grayscale images, averaged
+/// over non-overlapping 8x8 windows. 1.0 means identical.
+pub fn ssim(reference: &GrayImage, candidate: &GrayImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = reference.dimensions();
+    if candidate.dimensions() != (width, height) || width == 0 || height == 0 {
+        return 0.0;
+    }
+
+    let mut total = 0.0;
+    let mut windows = 0u32;
+    for y0 in (0..height).step_by(WINDOW as usize) {
+        for x0 in (0..width).step_by(WINDOW as usize) {
+            let (x1, y1) = ((x0 + WINDOW).min(width), (y0 + WINDOW).min(height));
+            let count = ((x1 - x0) * (y1 - y0)) as f64;
+
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let a = reference.get_pixel(x, y).0[0] as f64;
+                    let b = candidate.get_pixel(x, y).0[0] as f64;
+                    sum_a += a;
+                    sum_b += b;
+                    sum_aa += a * a;
+                    sum_bb += b * b;
+                    sum_ab += a * b;
+                }
+            }
+
+            let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+            let var_a = sum_aa / count - mean_a * mean_a;
+            let var_b = sum_bb / count - mean_b * mean_b;
+            let covariance = sum_ab / count - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    total / windows as f64
+}
@@ -0,0 +1,113 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+/// File formats of the batch report, chosen by the file's extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+impl ReportFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("csv") => Ok(ReportFormat::Csv),
+            Some("json") => Ok(ReportFormat::Json),
+            _ => anyhow::bail!("Can't tell the report format of {}: name it .csv or .json", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Failed,
+    /// Digitally signed and left alone, or its output already exists
+    Skipped,
+    /// Saved less than --min-savings, so the output wasn't kept
+    BelowThreshold,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+            Status::BelowThreshold => "below_threshold",
+        }
+    }
+}
+
+/// What happened to one input of a batch run; a row of the report
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportRow {
+    #[serde(serialize_with = "serialize_path")]
+    pub input: PathBuf,
+    /// Only set when an output was written, or would be in a dry run
+    #[serde(serialize_with = "serialize_optional_path")]
+    pub output: Option<PathBuf>,
+    pub status: Status,
+    pub original_size: Option<u64>,
+    pub optimized_size: Option<u64>,
+    #[serde(serialize_with = "serialize_percentage")]
+    pub percent_saved: Option<f64>,
+    pub images_optimized: Option<usize>,
+    #[serde(rename = "processing_time_seconds", serialize_with = "serialize_seconds")]
+    pub processing_time: Duration,
+    pub error: Option<String>,
+    /// Nothing was written; the optimized size is an estimate
+    pub dry_run: bool,
+}
+
+/// As it's displayed, so that a name that isn't UTF-8 doesn't fail the report
+fn serialize_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&path.display())
+}
+
+/// As `serialize_path`, or null
+pub fn serialize_optional_path<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serialize_path(path, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// To one decimal, as it's printed
+fn serialize_percentage<S: Serializer>(percentage: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    percentage.map(|percentage| (percentage * 10.0).round() / 10.0).serialize(serializer)
+}
+
+/// In seconds, to the millisecond
+fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_millis() as f64 / 1000.0)
+}
+
+/// Write the rows as CSV (with a header line) or as a JSON array of objects
+pub fn write_report(path: &Path, format: ReportFormat, rows: &[ReportRow]) -> Result<()> {
+    let written = match format {
+        ReportFormat::Csv => write_csv(path, rows),
+        ReportFormat::Json => write_json(path, rows),
+    };
+    written.with_context(|| format!("Failed to write the report to {}", path.display()))
+}
+
+fn write_csv(path: &Path, rows: &[ReportRow]) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_json(path: &Path, rows: &[ReportRow]) -> Result<()> {
+    let mut json = serde_json::to_string_pretty(rows)?;
+    json.push('\n');
+    std::fs::write(path, json)?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashSet;
+
+use crate::filters::decode_stream;
+use crate::inline_images::{for_each_operation, page_resources};
+use crate::objects::resolve;
+
+/// Results of the resource pruning pass
+#[derive(Debug, Default, Clone)]
+pub struct ResourceStats {
+    pub entries_removed: usize,
+}
+
+/// Resource categories whose entries are only ever used by name from content streams
+const PRUNABLE: [&[u8]; 3] = [b"XObject", b"Font", b"ExtGState"];
+
+/// Resource names a content stream refers to, per category in `PRUNABLE`
+#[derive(Default)]
+struct UsedNames([HashSet<Vec<u8>>; 3]);
+
+impl UsedNames {
+    fn insert(&mut self, category: usize, name: &[u8]) {
+        self.0[category].insert(name.to_vec());
+    }
+}
+
+/// Remove XObject, Font and ExtGState entries that the content of their page (or Form
+/// XObject) never uses, leaving the objects themselves for the unreachable-object pass.
+///
+/// Pages inheriting resources from the page tree get their own pruned copy; the inherited
+/// dictionaries are dropped once every page has one. Content that can't be parsed keeps
+/// its resources untouched.
+pub fn prune_page_resources(doc: &mut Document) -> ResourceStats {
+    let mut stats = ResourceStats::default();
+    let mut forms = Vec::new();
+    let mut all_pages_local = true;
+
+    for (_, page_id) in doc.get_pages() {
+        let Ok(page) = doc.get_dictionary(page_id) else { continue };
+        let inherited = page.get(b"Resources").is_err();
+        let Some(resources) = page_resources(doc, page_id) else { continue };
+
+        let used = page_content(doc, page_id)
+            .and_then(|content| used_names(doc, &content, &resources, &mut forms, &mut Vec::new()));
+        let Some(used) = used else {
+            all_pages_local &= !inherited;
+            continue;
+        };
+
+        let (pruned, removed) = prune(doc, &resources, &used);
+        if removed > 0 || inherited {
+            if let Ok(page) = doc.get_dictionary_mut(page_id) {
+                page.set("Resources", pruned);
+            }
+            stats.entries_removed += removed;
+        }
+    }
+
+    if all_pages_local {
+        for object in doc.objects.values_mut() {
+            if let Object::Dictionary(dict) = object {
+                if matches!(dict.get(b"Type"), Ok(Object::Name(name)) if name == b"Pages") {
+                    dict.remove(b"Resources");
+                }
+            }
+        }
+    }
+
+    // Form XObjects with their own resources are pruned against their own content, once
+    let mut done = HashSet::new();
+    while let Some(form_id) = forms.pop() {
+        if !done.insert(form_id) {
+            continue;
+        }
+        let Ok(Object::Stream(form)) = doc.get_object(form_id) else { continue };
+        let resources = form.dict.get(b"Resources").ok().and_then(|r| resolve(doc, r).as_dict().ok());
+        let Some(resources) = resources.cloned() else { continue };
+        let Ok(content) = decode_stream(form) else { continue };
+        let Some(used) = used_names(doc, &content, &resources, &mut forms, &mut vec![form_id]) else { continue };
+
+        let (pruned, removed) = prune(doc, &resources, &used);
+        if removed > 0 {
+            if let Ok(Object::Stream(form)) = doc.get_object_mut(form_id) {
+                form.dict.set("Resources", pruned);
+            }
+            stats.entries_removed += removed;
+        }
+    }
+
+    stats
+}
+
+/// Decoded content of all of a page's content streams
+pub fn page_content(doc: &Document, page_id: ObjectId) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    for content_id in doc.get_page_contents(page_id) {
+        let Ok(Object::Stream(stream)) = doc.get_object(content_id) else { return None };
+        content.extend(decode_stream(stream).ok()?);
+        content.push(b'\n');
+    }
+    Some(content)
+}
+
+/// Names used by `content`, resolved against `resources`. Form XObjects without
+/// resources of their own use the caller's, so their names count here too; forms with
+/// their own resources are queued in `forms`. `stack` guards against cyclic forms.
+fn used_names(
+    doc: &Document,
+    content: &[u8],
+    resources: &Dictionary,
+    forms: &mut Vec<ObjectId>,
+    stack: &mut Vec<ObjectId>,
+) -> Option<UsedNames> {
+    let mut used = UsedNames::default();
+    let mut invoked = Vec::new();
+    let parsed = for_each_operation(content, |operator, operands| {
+        let (category, name) = match (operator, operands) {
+            (b"Do", [Object::Name(name), ..]) => (0, name),
+            (b"Tf", [Object::Name(name), ..]) => (1, name),
+            (b"gs", [Object::Name(name), ..]) => (2, name),
+            _ => return,
+        };
+        used.insert(category, name);
+        if category == 0 {
+            invoked.push(name.clone());
+        }
+    });
+    if !parsed {
+        return None;
+    }
+
+    let xobjects = resources.get(b"XObject").ok().and_then(|x| resolve(doc, x).as_dict().ok());
+    for name in invoked {
+        let Some(Ok(Object::Reference(form_id))) = xobjects.map(|x| x.get(&name)) else { continue };
+        let Ok(Object::Stream(form)) = doc.get_object(*form_id) else { continue };
+        if !matches!(form.dict.get(b"Subtype"), Ok(Object::Name(subtype)) if subtype == b"Form") {
+            continue;
+        }
+        if form.dict.get(b"Resources").is_ok() {
+            forms.push(*form_id);
+            continue;
+        }
+        if stack.contains(form_id) {
+            continue;
+        }
+        let form_content = decode_stream(form).ok()?;
+        stack.push(*form_id);
+        let nested = used_names(doc, &form_content, resources, forms, stack);
+        stack.pop();
+        for (set, names) in used.0.iter_mut().zip(nested?.0) {
+            set.extend(names);
+        }
+    }
+
+    Some(used)
+}
+
+/// A copy of `resources` keeping only the used entries of the prunable categories,
+/// and the number of entries dropped
+fn prune(doc: &Document, resources: &Dictionary, used: &UsedNames) -> (Dictionary, usize) {
+    let mut pruned = resources.clone();
+    let mut removed = 0;
+    for (category, key) in PRUNABLE.iter().enumerate() {
+        let Some(entries) = resources.get(key).ok().and_then(|e| resolve(doc, e).as_dict().ok()) else { continue };
+        let mut kept = Dictionary::new();
+        for (name, value) in entries.iter() {
+            if used.0[category].contains(name) {
+                kept.set(name.clone(), value.clone());
+            }
+        }
+        if kept.len() < entries.len() {
+            removed += entries.len() - kept.len();
+            if kept.is_empty() {
+                pruned.remove(key);
+            } else {
+                pruned.set(*key, kept);
+            }
+        }
+    }
+    (pruned, removed)
+}
@@ -0,0 +1,176 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::objects::resolve;
+
+/// Results of the active content removal pass
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SanitizeStats {
+    /// Entries of the document-level `/Names /JavaScript` tree
+    pub javascript: usize,
+    /// Entries of the `/EmbeddedFiles` tree plus file attachment annotations
+    pub embedded_files: usize,
+    pub open_actions: usize,
+    /// `/AA` additional-action dictionaries on the catalog, pages, annotations and fields
+    pub additional_actions: usize,
+    /// JavaScript, Launch, SubmitForm and ImportData actions on links, fields and outlines
+    pub risky_actions: usize,
+}
+
+impl SanitizeStats {
+    pub fn total(&self) -> usize {
+        self.javascript + self.embedded_files + self.open_actions + self.additional_actions + self.risky_actions
+    }
+}
+
+/// Action types that run code, start programs or send data somewhere
+const RISKY_ACTIONS: [&[u8]; 4] = [b"JavaScript", b"Launch", b"SubmitForm", b"ImportData"];
+
+/// Remove active content: document JavaScript, embedded files and attachments, open
+/// actions and additional actions, and every risky action on links, fields and outline
+/// items. Navigation (GoTo, URI, Named actions and plain destinations) is kept. The
+/// removed objects are left for the unreachable-object pass.
+pub fn sanitize(doc: &mut Document) -> SanitizeStats {
+    let mut stats = SanitizeStats::default();
+    let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) else { return stats };
+
+    // Name trees in the catalog's /Names dictionary, which may be direct or indirect
+    let names = doc.get_dictionary(catalog_id).ok().and_then(|catalog| catalog.get(b"Names").ok()).cloned();
+    if let Some(names) = names {
+        if let Ok(dict) = resolve(doc, &names).as_dict() {
+            stats.javascript = dict.get(b"JavaScript").map_or(0, |tree| name_tree_len(doc, tree, 0));
+            stats.embedded_files = dict.get(b"EmbeddedFiles").map_or(0, |tree| name_tree_len(doc, tree, 0));
+        }
+        let dict = match names {
+            Object::Reference(id) => doc.get_dictionary_mut(id).ok(),
+            _ => doc
+                .get_dictionary_mut(catalog_id)
+                .ok()
+                .and_then(|catalog| catalog.get_mut(b"Names").ok())
+                .and_then(|names| names.as_dict_mut().ok()),
+        };
+        let now_empty = dict.is_some_and(|dict| {
+            dict.remove(b"JavaScript");
+            dict.remove(b"EmbeddedFiles");
+            dict.is_empty()
+        });
+        if now_empty {
+            if let Ok(catalog) = doc.get_dictionary_mut(catalog_id) {
+                catalog.remove(b"Names");
+            }
+        }
+    }
+
+    // An open action that only shows a page is harmless; anything else goes
+    let open_action = doc.get_dictionary(catalog_id).ok().and_then(|catalog| catalog.get(b"OpenAction").ok());
+    let remove_open_action = open_action.is_some_and(|action| {
+        matches!(resolve(doc, action), Object::Dictionary(dict) if action_type(dict) != Some(b"GoTo"))
+    });
+    if remove_open_action {
+        if let Ok(catalog) = doc.get_dictionary_mut(catalog_id) {
+            catalog.remove(b"OpenAction");
+            stats.open_actions += 1;
+        }
+    }
+
+    let risky = ids_matching(doc, |dict| action_type(dict).is_some_and(|s| RISKY_ACTIONS.contains(&s)));
+    let attachments = ids_matching(doc, is_file_attachment);
+    for object in doc.objects.values_mut() {
+        strip_actions(object, &risky, &attachments, &mut stats);
+    }
+
+    stats
+}
+
+/// Remove additional actions, risky actions and file attachment annotations from an
+/// object and everything directly nested in it
+fn strip_actions(object: &mut Object, risky: &HashSet<ObjectId>, attachments: &HashSet<ObjectId>, stats: &mut SanitizeStats) {
+    let dict = match object {
+        Object::Dictionary(dict) => dict,
+        Object::Stream(stream) => &mut stream.dict,
+        Object::Array(items) => {
+            for item in items {
+                strip_actions(item, risky, attachments, stats);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    if dict.remove(b"AA").is_some() {
+        stats.additional_actions += 1;
+    }
+    let is_risky = |action: &Object| match action {
+        Object::Reference(id) => risky.contains(id),
+        Object::Dictionary(dict) => action_type(dict).is_some_and(|s| RISKY_ACTIONS.contains(&s)),
+        _ => false,
+    };
+    if dict.get(b"A").is_ok_and(is_risky) {
+        dict.remove(b"A");
+        stats.risky_actions += 1;
+    }
+    // Follow-up actions, a single action or an array of them
+    match dict.get_mut(b"Next") {
+        Ok(Object::Array(actions)) => {
+            let before = actions.len();
+            actions.retain(|action| !is_risky(action));
+            stats.risky_actions += before - actions.len();
+        }
+        Ok(action) if is_risky(action) => {
+            dict.remove(b"Next");
+            stats.risky_actions += 1;
+        }
+        _ => {}
+    }
+    if let Ok(Object::Array(annotations)) = dict.get_mut(b"Annots") {
+        let before = annotations.len();
+        annotations.retain(|annotation| match annotation {
+            Object::Reference(id) => !attachments.contains(id),
+            Object::Dictionary(dict) => !is_file_attachment(dict),
+            _ => true,
+        });
+        stats.embedded_files += before - annotations.len();
+    }
+
+    for (_, value) in dict.iter_mut() {
+        strip_actions(value, risky, attachments, stats);
+    }
+}
+
+/// Ids of the dictionaries satisfying `predicate`
+fn ids_matching(doc: &Document, predicate: impl Fn(&Dictionary) -> bool) -> HashSet<ObjectId> {
+    doc.objects
+        .iter()
+        .filter(|(_, object)| matches!(object, Object::Dictionary(dict) if predicate(dict)))
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+fn action_type(dict: &Dictionary) -> Option<&[u8]> {
+    match dict.get(b"S") {
+        Ok(Object::Name(name)) => Some(name),
+        _ => None,
+    }
+}
+
+fn is_file_attachment(dict: &Dictionary) -> bool {
+    matches!(dict.get(b"Subtype"), Ok(Object::Name(name)) if name == b"FileAttachment")
+}
+
+/// Number of entries in a name tree; `depth` guards against cyclic `/Kids`
+fn name_tree_len(doc: &Document, tree: &Object, depth: usize) -> usize {
+    let Ok(node) = resolve(doc, tree).as_dict() else { return 0 };
+    if depth > 32 {
+        return 0;
+    }
+    let leaves = match node.get(b"Names").map(|names| resolve(doc, names)) {
+        Ok(Object::Array(names)) => names.len() / 2,
+        _ => 0,
+    };
+    let kids = match node.get(b"Kids").map(|kids| resolve(doc, kids)) {
+        Ok(Object::Array(kids)) => kids.iter().map(|kid| name_tree_len(doc, kid, depth + 1)).sum(),
+        _ => 0,
+    };
+    leaves + kids
+}
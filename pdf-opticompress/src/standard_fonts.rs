@@ -0,0 +1,92 @@
+//! Metrics of the standard 14 fonts every PDF viewer provides
+
+/// Names of the standard 14 fonts
+const STANDARD_14: [&[u8]; 14] = [
+    b"Courier",
+    b"Courier-Bold",
+    b"Courier-Oblique",
+    b"Courier-BoldOblique",
+    b"Helvetica",
+    b"Helvetica-Bold",
+    b"Helvetica-Oblique",
+    b"Helvetica-BoldOblique",
+    b"Times-Roman",
+    b"Times-Bold",
+    b"Times-Italic",
+    b"Times-BoldItalic",
+    b"Symbol",
+    b"ZapfDingbats",
+];
+
+/// Advance widths of codes 0x20..=0x7E under StandardEncoding (0x27 is quoteright and
+/// 0x60 quoteleft), from the Adobe Core 14 AFM files
+const HELVETICA: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 222, 333, 333, 389, 584, 278, 333, 278, 278, // 0x20
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, // 0x30
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, // 0x40
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, // 0x50
+    222, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, // 0x60
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, // 0x70
+];
+
+const HELVETICA_BOLD: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 278, 333, 333, 389, 584, 278, 333, 278, 278, // 0x20
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611, // 0x30
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778, // 0x40
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556, // 0x50
+    278, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611, // 0x60
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584, // 0x70
+];
+
+const TIMES_ROMAN: [u16; 95] = [
+    250, 333, 408, 500, 500, 833, 778, 333, 333, 333, 500, 564, 250, 333, 250, 278, // 0x20
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444, // 0x30
+    921, 722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722, // 0x40
+    556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500, // 0x50
+    333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500, // 0x60
+    500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541, // 0x70
+];
+
+/// Widths of quotesingle and grave, which WinAnsi and MacRoman place at 0x27 and 0x60
+const HELVETICA_QUOTES: (u16, u16) = (191, 333);
+const HELVETICA_BOLD_QUOTES: (u16, u16) = (238, 333);
+const TIMES_ROMAN_QUOTES: (u16, u16) = (180, 333);
+
+/// A font's name without a subset tag (`ABCDEF+Helvetica` → `Helvetica`)
+pub fn strip_subset_tag(name: &[u8]) -> &[u8] {
+    if crate::fonts::has_subset_tag(name) {
+        &name[7..]
+    } else {
+        name
+    }
+}
+
+pub fn is_standard_font(name: &[u8]) -> bool {
+    STANDARD_14.contains(&strip_subset_tag(name))
+}
+
+/// The standard width of a code in a standard font, for the fonts and encodings whose
+/// metrics we carry (Courier, and the Helvetica and Times-Roman families over the ASCII
+/// range of StandardEncoding, WinAnsiEncoding and MacRomanEncoding). `None` means unknown.
+pub fn standard_width(name: &[u8], encoding: &[u8], code: u32) -> Option<u16> {
+    let name = strip_subset_tag(name);
+    if name.starts_with(b"Courier") {
+        return Some(600); // Monospaced: every glyph
+    }
+    if !(0x20..=0x7E).contains(&code) {
+        return None;
+    }
+    let (widths, quotes) = match name {
+        b"Helvetica" | b"Helvetica-Oblique" => (&HELVETICA, HELVETICA_QUOTES),
+        b"Helvetica-Bold" | b"Helvetica-BoldOblique" => (&HELVETICA_BOLD, HELVETICA_BOLD_QUOTES),
+        b"Times-Roman" => (&TIMES_ROMAN, TIMES_ROMAN_QUOTES),
+        _ => return None,
+    };
+    match (encoding, code) {
+        (b"StandardEncoding", _) => Some(widths[code as usize - 0x20]),
+        (b"WinAnsiEncoding" | b"MacRomanEncoding", 0x27) => Some(quotes.0),
+        (b"WinAnsiEncoding" | b"MacRomanEncoding", 0x60) => Some(quotes.1),
+        (b"WinAnsiEncoding" | b"MacRomanEncoding", _) => Some(widths[code as usize - 0x20]),
+        _ => None,
+    }
+}
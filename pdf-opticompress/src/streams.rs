@@ -0,0 +1,190 @@
+use lopdf::{Document, Object};
+
+use crate::filters::{ascii85_decode, ascii_hex_decode, decode_stream, stream_filters};
+use crate::pdf_writer::SaveOptions;
+
+/// Results of a stream re-encoding pass
+#[derive(Debug, Default, Clone)]
+pub struct StreamStats {
+    pub streams: usize,
+    /// Encoded bytes before minus after; negative if the new encoding came out larger
+    pub bytes_saved: i64,
+}
+
+/// Re-encode every stream using `/LZWDecode` with Flate, images included (the image
+/// pass then sees an ordinary Flate image). Streams whose other filters can't be
+/// decoded are left alone.
+pub fn recompress_lzw_streams(doc: &mut Document, options: &SaveOptions) -> StreamStats {
+    let mut stats = StreamStats::default();
+    for object in doc.objects.values_mut() {
+        let Object::Stream(stream) = object else { continue };
+        let filters = stream_filters(stream);
+        if !filters.iter().any(|filter| filter == b"LZWDecode" || filter == b"LZW") {
+            continue;
+        }
+        // Per-filter parameter arrays aren't understood by the decoder
+        if matches!(stream.dict.get(b"DecodeParms"), Ok(Object::Array(_))) {
+            continue;
+        }
+        let Ok(data) = decode_stream(stream) else { continue };
+        let Ok(encoded) = options.deflate(&data) else { continue };
+
+        let before = stream.content.len() as i64;
+        stream.dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+        stream.dict.remove(b"DecodeParms");
+        stream.set_content(encoded);
+        stats.streams += 1;
+        stats.bytes_saved += before - stream.content.len() as i64;
+    }
+    stats
+}
+
+/// Decode the ASCIIHex and ASCII85 layers wrapped around streams for 7-bit transport,
+/// keeping the binary filter underneath. Streams left without a filter are Flate
+/// compressed when that makes them smaller.
+pub fn strip_transport_filters(doc: &mut Document, options: &SaveOptions) -> StreamStats {
+    let mut stats = StreamStats::default();
+    for object in doc.objects.values_mut() {
+        let Object::Stream(stream) = object else { continue };
+        let filters = stream_filters(stream);
+        // Only outer layers: an ASCII filter after a binary one would need the binary
+        // data decoded too
+        let layers = filters.iter().take_while(|filter| is_transport_filter(filter)).count();
+        if layers == 0 {
+            continue;
+        }
+        let Ok(mut data) = decode_transport_layers(&stream.content, &filters[..layers]) else { continue };
+
+        let remaining = &filters[layers..];
+        let params = match stream.dict.get(b"DecodeParms") {
+            Ok(Object::Array(params)) => params.get(layers..).map(<[Object]>::to_vec),
+            // A single parameter dictionary belongs to the first filter, an ASCII one
+            // without parameters, but is commonly meant for the filter underneath
+            Ok(params) => Some(vec![params.clone()]),
+            Err(_) => Some(Vec::new()),
+        };
+        let Some(mut params) = params else { continue };
+        params.truncate(remaining.len());
+
+        let before = stream.content.len() as i64;
+        match remaining {
+            [] => {
+                stream.dict.remove(b"Filter");
+                stream.dict.remove(b"DecodeParms");
+                if let Ok(encoded) = options.deflate(&data) {
+                    if encoded.len() < data.len() {
+                        stream.dict.set("Filter", Object::Name(b"FlateDecode".to_vec()));
+                        data = encoded;
+                    }
+                }
+            }
+            [filter] => {
+                stream.dict.set("Filter", Object::Name(filter.clone()));
+                match params.pop() {
+                    Some(Object::Null) | None => {
+                        stream.dict.remove(b"DecodeParms");
+                    }
+                    Some(params) => stream.dict.set("DecodeParms", params),
+                }
+            }
+            _ => {
+                let names = remaining.iter().map(|filter| Object::Name(filter.clone())).collect::<Vec<_>>();
+                stream.dict.set("Filter", names);
+                if params.iter().all(|params| matches!(params, Object::Null)) {
+                    stream.dict.remove(b"DecodeParms");
+                } else {
+                    params.resize(remaining.len(), Object::Null);
+                    stream.dict.set("DecodeParms", params);
+                }
+            }
+        }
+        stream.set_content(data);
+        stats.streams += 1;
+        stats.bytes_saved += before - stream.content.len() as i64;
+    }
+    stats
+}
+
+fn is_transport_filter(filter: &[u8]) -> bool {
+    matches!(filter, b"ASCIIHexDecode" | b"AHx" | b"ASCII85Decode" | b"A85")
+}
+
+/// Results of `decompress_streams`
+#[derive(Debug, Default, Clone)]
+pub struct DecompressStats {
+    pub decoded: usize,
+    /// Streams whose image codec (or a filter that can't be undone) was kept
+    pub left_encoded: usize,
+    /// Streams whose data couldn't be decoded
+    pub failed: usize,
+}
+
+/// Decode every stream whose filters can all be undone (Flate and LZW with their
+/// predictors, ASCIIHex and ASCII85), for reading the document rather than for saving
+/// space. Image codecs are kept, with only the ASCII layers above them decoded. Streams
+/// left encoded, and those whose data is corrupt, get a `/DecompressNote` saying why.
+pub fn decompress_streams(doc: &mut Document) -> DecompressStats {
+    let mut stats = DecompressStats::default();
+    for object in doc.objects.values_mut() {
+        let Object::Stream(stream) = object else { continue };
+        if stream.dict.type_is(b"XRef") || stream.dict.type_is(b"ObjStm") || stream.dict.has(b"F") {
+            continue;
+        }
+        let filters = stream_filters(stream);
+        if filters.is_empty() {
+            continue;
+        }
+        // Per-filter parameter arrays aren't understood by the decoder
+        let per_filter_params = matches!(stream.dict.get(b"DecodeParms"), Ok(Object::Array(_)));
+
+        let decodable = filters.iter().take_while(|filter| is_decodable(filter)).count();
+        let note = if decodable == filters.len() && !per_filter_params {
+            match decode_stream(stream) {
+                Ok(data) => {
+                    stream.dict.remove(b"Filter");
+                    stream.dict.remove(b"DecodeParms");
+                    stream.set_content(data);
+                    stats.decoded += 1;
+                    continue;
+                }
+                Err(e) => {
+                    stats.failed += 1;
+                    format!("not decoded: {}", e)
+                }
+            }
+        } else {
+            let layers = filters.iter().take_while(|filter| is_transport_filter(filter)).count();
+            let mut kept = filters.as_slice();
+            if layers > 0 && !per_filter_params {
+                if let Ok(data) = decode_transport_layers(&stream.content, &filters[..layers]) {
+                    // A single parameter dictionary stays with the filter it's meant for
+                    kept = &filters[layers..];
+                    let mut names: Vec<Object> = kept.iter().map(|filter| Object::Name(filter.clone())).collect();
+                    stream.dict.set("Filter", if names.len() == 1 { names.remove(0) } else { Object::Array(names) });
+                    stream.set_content(data);
+                }
+            }
+            stats.left_encoded += 1;
+            let names: Vec<String> = kept.iter().map(|filter| String::from_utf8_lossy(filter).into_owned()).collect();
+            format!("left encoded with {}", names.join(", "))
+        };
+        stream.dict.set("DecompressNote", Object::string_literal(note));
+    }
+    stats
+}
+
+/// Undo ASCIIHex and ASCII85 layers, outermost first
+fn decode_transport_layers(data: &[u8], filters: &[Vec<u8>]) -> anyhow::Result<Vec<u8>> {
+    let mut data = data.to_vec();
+    for filter in filters {
+        data = match filter.as_slice() {
+            b"ASCIIHexDecode" | b"AHx" => ascii_hex_decode(&data)?,
+            _ => ascii85_decode(&data)?,
+        };
+    }
+    Ok(data)
+}
+
+fn is_decodable(filter: &[u8]) -> bool {
+    is_transport_filter(filter) || matches!(filter, b"FlateDecode" | b"Fl" | b"LZWDecode" | b"LZW")
+}
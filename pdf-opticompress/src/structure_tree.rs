@@ -0,0 +1,42 @@
+use lopdf::{Document, Object};
+
+/// Results of the structure tree removal pass
+#[derive(Debug, Default, Clone)]
+pub struct StructureTreeStats {
+    pub tree_removed: bool,
+    /// `/StructParents` and `/StructParent` keys removed from pages, annotations and XObjects
+    pub struct_parents_removed: usize,
+}
+
+/// Remove the tagged-PDF structure tree (`/StructTreeRoot`, which holds the ParentTree,
+/// RoleMap and ClassMap), `/MarkInfo`, and the `/StructParents` and `/StructParent`
+/// keys that point into it. The elements themselves are left for the unreachable-object
+/// pass. Marked-content operators in the page content stay; without the tree they are inert.
+///
+/// This makes the document inaccessible to screen readers and breaks reflow, so it only
+/// ever runs when asked for explicitly.
+pub fn strip_structure_tree(doc: &mut Document) -> StructureTreeStats {
+    let mut stats = StructureTreeStats::default();
+
+    if let Ok(catalog_id) = doc.trailer.get(b"Root").and_then(Object::as_reference) {
+        if let Ok(catalog) = doc.get_dictionary_mut(catalog_id) {
+            stats.tree_removed = catalog.remove(b"StructTreeRoot").is_some();
+            catalog.remove(b"MarkInfo");
+        }
+    }
+
+    for object in doc.objects.values_mut() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        for key in [b"StructParents".as_slice(), b"StructParent"] {
+            if dict.remove(key).is_some() {
+                stats.struct_parents_removed += 1;
+            }
+        }
+    }
+
+    stats
+}
@@ -0,0 +1,150 @@
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+
+use crate::analyzer::{analysis_value, DocumentKind, PdfAnalysis};
+use crate::utils::{format_bytes, rounded};
+
+/// How many of the largest files the summary lists
+const LARGEST_FILES: usize = 10;
+
+/// One input of a multi-file analysis
+pub struct FileAnalysis {
+    pub path: PathBuf,
+    pub result: anyhow::Result<(u64, PdfAnalysis)>, // File size and analysis
+}
+
+/// Totals over the files that could be analyzed
+#[derive(Debug)]
+pub struct Summary {
+    pub analyzed: usize,
+    pub failed: usize,
+    pub total_size: u64,
+    pub pages: usize,
+    pub images: usize,
+    pub estimated_savings: u64, // Bytes
+    pub kinds: Vec<(DocumentKind, usize)>,
+    pub largest: Vec<(PathBuf, u64)>, // Largest first
+}
+
+impl Summary {
+    pub fn savings_percent(&self) -> f64 {
+        if self.total_size > 0 { self.estimated_savings as f64 / self.total_size as f64 * 100.0 } else { 0.0 }
+    }
+}
+
+/// The savings of one file in percent, measured by --deep where it ran
+fn savings_percent(analysis: &PdfAnalysis) -> f64 {
+    analysis
+        .measured_savings
+        .as_ref()
+        .map_or(analysis.estimated_savings.total_estimated, |measured| measured.total_estimated)
+}
+
+pub fn summarize(files: &[FileAnalysis]) -> Summary {
+    let mut summary = Summary {
+        analyzed: 0,
+        failed: 0,
+        total_size: 0,
+        pages: 0,
+        images: 0,
+        estimated_savings: 0,
+        kinds: [DocumentKind::BornDigital, DocumentKind::Scanned, DocumentKind::Mixed].map(|kind| (kind, 0)).to_vec(),
+        largest: Vec::new(),
+    };
+    for file in files {
+        let Ok((file_size, analysis)) = &file.result else {
+            summary.failed += 1;
+            continue;
+        };
+        summary.analyzed += 1;
+        summary.total_size += file_size;
+        summary.pages += analysis.page_count;
+        summary.images += analysis.image_count;
+        summary.estimated_savings += (*file_size as f64 * savings_percent(analysis) / 100.0).round() as u64;
+        if let Some((_, count)) = summary.kinds.iter_mut().find(|(kind, _)| *kind == analysis.document_kind) {
+            *count += 1;
+        }
+        summary.largest.push((file.path.clone(), *file_size));
+    }
+    // Stable, so files of the same size keep the order they were given in
+    summary.largest.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+    summary.largest.truncate(LARGEST_FILES);
+    summary
+}
+
+/// One line per file, then the totals
+pub fn print_summary(files: &[FileAnalysis], summary: &Summary) {
+    let width = files.iter().map(|file| file.path.display().to_string().chars().count()).max().unwrap_or(0).max(4);
+    println!("  {:<width$} {:>12} {:>6} {:>6}  {:<12} {:>8}", "File", "Size", "Pages", "Images", "Kind", "Savings");
+    for file in files {
+        match &file.result {
+            Ok((file_size, analysis)) => println!(
+                "  {:<width$} {:>12} {:>6} {:>6}  {:<12} {:>7.1}%",
+                file.path.display(),
+                format_bytes(*file_size),
+                analysis.page_count,
+                analysis.image_count,
+                analysis.document_kind.as_str(),
+                savings_percent(analysis)
+            ),
+            Err(e) => println!("  {:<width$} failed: {:#}", file.path.display(), e),
+        }
+    }
+    println!();
+
+    println!(
+        "Summary of {} file{}{}:",
+        summary.analyzed,
+        if summary.analyzed == 1 { "" } else { "s" },
+        if summary.failed > 0 { format!(" ({} more could not be analyzed)", summary.failed) } else { String::new() }
+    );
+    println!("  Total size: {}", format_bytes(summary.total_size));
+    println!("  Pages: {}", summary.pages);
+    println!("  Images: {}", summary.images);
+    println!("  Estimated savings: {} ({:.1}%)", format_bytes(summary.estimated_savings), summary.savings_percent());
+    println!();
+    println!("Document kinds:");
+    for (kind, count) in &summary.kinds {
+        println!("  {:<12} {:>6}", kind.as_str(), count);
+    }
+    if !summary.largest.is_empty() {
+        println!();
+        println!("Largest files:");
+        for (rank, (path, size)) in summary.largest.iter().enumerate() {
+            println!("  {:>2}. {:<width$} {:>12}", rank + 1, path.display(), format_bytes(*size));
+        }
+    }
+}
+
+/// The analysis of every file (or its error) and the totals
+pub fn summary_json(files: &[FileAnalysis], summary: &Summary, top: usize) -> String {
+    let files: Vec<Value> = files
+        .iter()
+        .map(|file| match &file.result {
+            Ok((file_size, analysis)) => analysis_value(&file.path, *file_size, analysis, top),
+            Err(e) => json!({"file": file.path.display().to_string(), "error": format!("{:#}", e)}),
+        })
+        .collect();
+    let kinds: Map<String, Value> = summary.kinds.iter().map(|(kind, count)| (kind.as_str().to_string(), (*count).into())).collect();
+    let largest: Vec<Value> = summary
+        .largest
+        .iter()
+        .map(|(path, size)| json!({"file": path.display().to_string(), "size": size}))
+        .collect();
+    let json = json!({
+        "files": files,
+        "summary": {
+            "files": summary.analyzed + summary.failed,
+            "analyzed": summary.analyzed,
+            "failed": summary.failed,
+            "total_size": summary.total_size,
+            "pages": summary.pages,
+            "images": summary.images,
+            "estimated_savings": summary.estimated_savings,
+            "estimated_savings_percent": rounded(summary.savings_percent(), 1),
+            "document_kinds": kinds,
+            "largest_files": largest,
+        },
+    });
+    serde_json::to_string_pretty(&json).expect("JSON values serialize")
+}
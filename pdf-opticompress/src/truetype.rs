@@ -0,0 +1,264 @@
+//! Reading and glyph-preserving subsetting of TrueType (`glyf`-based sfnt) font programs
+
+use std::collections::BTreeSet;
+
+/// A parsed table directory over the raw font program
+pub struct TrueTypeFont<'a> {
+    data: &'a [u8],
+    tables: Vec<TableRecord>,
+}
+
+struct TableRecord {
+    tag: [u8; 4],
+    offset: usize,
+    length: usize,
+}
+
+const TRUETYPE_VERSION: u32 = 0x0001_0000;
+const APPLE_TRUE: u32 = u32::from_be_bytes(*b"true");
+const CHECKSUM_MAGIC: u32 = 0xB1B0_AFBA;
+
+// Composite glyph flags
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+impl<'a> TrueTypeFont<'a> {
+    /// Parse the table directory; `None` for collections, CFF-based OpenType and
+    /// anything malformed
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let version = read_u32(data, 0)?;
+        if version != TRUETYPE_VERSION && version != APPLE_TRUE {
+            return None;
+        }
+        let num_tables = read_u16(data, 4)? as usize;
+        let mut tables = Vec::with_capacity(num_tables);
+        for i in 0..num_tables {
+            let record = 12 + i * 16;
+            let tag = data.get(record..record + 4)?.try_into().ok()?;
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            if offset.checked_add(length)? > data.len() {
+                return None;
+            }
+            tables.push(TableRecord { tag, offset, length });
+        }
+        Some(Self { data, tables })
+    }
+
+    fn table(&self, tag: &[u8; 4]) -> Option<&'a [u8]> {
+        let record = self.tables.iter().find(|record| &record.tag == tag)?;
+        Some(&self.data[record.offset..record.offset + record.length])
+    }
+
+    pub fn num_glyphs(&self) -> Option<u16> {
+        read_u16(self.table(b"maxp")?, 4)
+    }
+
+    /// The glyph a character code maps to in the given cmap subtable; `None` when the
+    /// subtable is missing or maps the code to `.notdef`
+    pub fn glyph_for(&self, platform: u16, encoding: u16, code: u32) -> Option<u16> {
+        let subtable = self.cmap_subtable(platform, encoding)?;
+        let glyph = match read_u16(subtable, 0)? {
+            0 => *subtable.get(6 + usize::try_from(code).ok().filter(|&c| c < 256)?)? as u16,
+            4 => format4_lookup(subtable, u16::try_from(code).ok()?)?,
+            6 => {
+                let first = read_u16(subtable, 6)? as u32;
+                let count = read_u16(subtable, 8)? as u32;
+                let index = code.checked_sub(first).filter(|&i| i < count)?;
+                read_u16(subtable, 10 + 2 * index as usize)?
+            }
+            12 => {
+                let groups = read_u32(subtable, 12)? as usize;
+                (0..groups).find_map(|i| {
+                    let group = 16 + i * 12;
+                    let start = read_u32(subtable, group)?;
+                    let end = read_u32(subtable, group + 4)?;
+                    let start_glyph = read_u32(subtable, group + 8)?;
+                    (start..=end).contains(&code).then(|| (start_glyph + code - start) as u16)
+                })?
+            }
+            _ => return None,
+        };
+        (glyph != 0).then_some(glyph)
+    }
+
+    fn cmap_subtable(&self, platform: u16, encoding: u16) -> Option<&'a [u8]> {
+        let cmap = self.table(b"cmap")?;
+        let count = read_u16(cmap, 2)? as usize;
+        (0..count).find_map(|i| {
+            let record = 4 + i * 8;
+            if read_u16(cmap, record)? != platform || read_u16(cmap, record + 2)? != encoding {
+                return None;
+            }
+            cmap.get(read_u32(cmap, record + 4)? as usize..)
+        })
+    }
+
+    /// A copy of the font in which every glyph outside `keep` (and the components of
+    /// composite glyphs it contains) is empty. Glyph ids, metrics and every other table
+    /// are unchanged, so the PDF's widths and CID mappings stay valid.
+    pub fn subset(&self, keep: &BTreeSet<u16>) -> Option<Vec<u8>> {
+        let head = self.table(b"head")?;
+        let long_offsets = read_u16(head, 50)? != 0;
+        let num_glyphs = self.num_glyphs()? as usize;
+        let loca = self.table(b"loca")?;
+        let glyf = self.table(b"glyf")?;
+
+        let offsets: Vec<usize> = (0..=num_glyphs)
+            .map(|i| match long_offsets {
+                true => read_u32(loca, i * 4).map(|o| o as usize),
+                false => read_u16(loca, i * 2).map(|o| o as usize * 2),
+            })
+            .collect::<Option<_>>()?;
+        let glyph = |gid: usize| -> Option<&[u8]> {
+            let (start, end) = (offsets[gid], offsets[gid + 1]);
+            if start > end {
+                return None;
+            }
+            glyf.get(start..end)
+        };
+
+        // Close the set over composite glyph components
+        let mut kept = vec![false; num_glyphs];
+        let mut pending: Vec<usize> = keep.iter().map(|&gid| gid as usize).chain([0]).collect();
+        while let Some(gid) = pending.pop() {
+            if gid >= num_glyphs || kept[gid] {
+                continue;
+            }
+            kept[gid] = true;
+            pending.extend(composite_components(glyph(gid)?)?);
+        }
+
+        let mut new_glyf = Vec::new();
+        let mut new_offsets = Vec::with_capacity(num_glyphs + 1);
+        for (gid, &keep) in kept.iter().enumerate() {
+            new_offsets.push(new_glyf.len());
+            if keep {
+                new_glyf.extend_from_slice(glyph(gid)?);
+                new_glyf.resize(new_glyf.len().next_multiple_of(4), 0);
+            }
+        }
+        new_offsets.push(new_glyf.len());
+
+        let mut new_loca = Vec::with_capacity(loca.len());
+        for offset in new_offsets {
+            match long_offsets {
+                true => new_loca.extend_from_slice(&(offset as u32).to_be_bytes()),
+                false => new_loca.extend_from_slice(&((offset / 2) as u16).to_be_bytes()),
+            }
+        }
+
+        let mut new_head = head.to_vec();
+        new_head.get_mut(8..12)?.fill(0);
+
+        Some(self.rebuild(|tag| match tag {
+            b"glyf" => Some(&new_glyf),
+            b"loca" => Some(&new_loca),
+            b"head" => Some(&new_head),
+            _ => None,
+        }))
+    }
+
+    /// Serialize the font with some tables replaced, recomputing the directory checksums
+    fn rebuild<'b>(&'b self, replacement: impl Fn(&[u8; 4]) -> Option<&'b Vec<u8>>) -> Vec<u8> {
+        let directory_len = 12 + self.tables.len() * 16;
+        let mut output = self.data[..12].to_vec();
+        output.resize(directory_len, 0);
+
+        let mut head_offset = None;
+        for (i, record) in self.tables.iter().enumerate() {
+            let table = match replacement(&record.tag) {
+                Some(table) => table.as_slice(),
+                None => &self.data[record.offset..record.offset + record.length],
+            };
+            let offset = output.len();
+            if &record.tag == b"head" {
+                head_offset = Some(offset);
+            }
+            let entry = 12 + i * 16;
+            output[entry..entry + 4].copy_from_slice(&record.tag);
+            output[entry + 4..entry + 8].copy_from_slice(&checksum(table).to_be_bytes());
+            output[entry + 8..entry + 12].copy_from_slice(&(offset as u32).to_be_bytes());
+            output[entry + 12..entry + 16].copy_from_slice(&(table.len() as u32).to_be_bytes());
+            output.extend_from_slice(table);
+            output.resize(output.len().next_multiple_of(4), 0);
+        }
+
+        if let Some(head) = head_offset {
+            let adjustment = CHECKSUM_MAGIC.wrapping_sub(checksum(&output));
+            output[head + 8..head + 12].copy_from_slice(&adjustment.to_be_bytes());
+        }
+        output
+    }
+}
+
+fn format4_lookup(subtable: &[u8], code: u16) -> Option<u16> {
+    let seg_count = read_u16(subtable, 6)? as usize / 2;
+    let end_codes = 14;
+    let start_codes = end_codes + seg_count * 2 + 2;
+    let deltas = start_codes + seg_count * 2;
+    let range_offsets = deltas + seg_count * 2;
+
+    for seg in 0..seg_count {
+        if read_u16(subtable, end_codes + seg * 2)? < code {
+            continue;
+        }
+        let start = read_u16(subtable, start_codes + seg * 2)?;
+        if start > code {
+            return None;
+        }
+        let delta = read_u16(subtable, deltas + seg * 2)?;
+        let range_offset = read_u16(subtable, range_offsets + seg * 2)? as usize;
+        if range_offset == 0 {
+            return Some(code.wrapping_add(delta));
+        }
+        let address = range_offsets + seg * 2 + range_offset + 2 * (code - start) as usize;
+        let glyph = read_u16(subtable, address)?;
+        return Some(if glyph == 0 { 0 } else { glyph.wrapping_add(delta) });
+    }
+    None
+}
+
+/// Glyph ids referenced by a composite glyph (none for simple or empty glyphs)
+fn composite_components(glyph: &[u8]) -> Option<Vec<usize>> {
+    let mut components = Vec::new();
+    if glyph.len() < 10 || (read_u16(glyph, 0)? as i16) >= 0 {
+        return Some(components);
+    }
+    let mut pos = 10;
+    loop {
+        let flags = read_u16(glyph, pos)?;
+        components.push(read_u16(glyph, pos + 2)? as usize);
+        pos += 4;
+        pos += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+        if flags & WE_HAVE_A_SCALE != 0 {
+            pos += 2;
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            pos += 4;
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            pos += 8;
+        }
+        if flags & MORE_COMPONENTS == 0 {
+            return Some(components);
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
@@ -1,30 +1,105 @@
+use std::fmt;
 use std::fs;
+use std::io::Read;
 use std::path::Path;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::path::PathBuf;
-use tempfile;
-
-/// Check if a file exists and is readable
-pub fn validate_input_file(path: &Path) -> std::io::Result<()> {
-    if !path.exists() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("File not found: {}", path.display()),
-        ));
+
+/// How far into a file its `%PDF-` header may start, as PDF readers allow
+const HEADER_WINDOW: usize = 1024;
+
+/// Why an input can't be used, so that callers can tell a missing file from one that
+/// isn't a PDF
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    /// Nothing at the path, or a symbolic link to nothing; `suggestion` is the path with a
+    /// .pdf extension when that exists
+    NotFound { path: PathBuf, link_target: Option<PathBuf>, suggestion: Option<PathBuf> },
+    /// A directory or a device
+    NotAFile { path: PathBuf, directory: bool },
+    Unreadable { path: PathBuf, error: String },
+    Empty(PathBuf),
+    /// No `%PDF-` header, and what the first bytes look like instead, when they're known
+    NotPdf { path: PathBuf, size: u64, looks_like: Option<&'static str> },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::NotFound { path, link_target: Some(target), .. } => {
+                write!(f, "File not found: {} (a symbolic link to {}, which doesn't exist)", path.display(), target.display())
+            }
+            InputError::NotFound { path, suggestion: Some(suggestion), .. } => {
+                write!(f, "File not found: {} (did you mean {}?)", path.display(), suggestion.display())
+            }
+            InputError::NotFound { path, .. } => write!(f, "File not found: {}", path.display()),
+            InputError::NotAFile { path, directory: true } => {
+                write!(f, "Not a file: {} is a directory (give the PDFs in it to batch)", path.display())
+            }
+            InputError::NotAFile { path, .. } => write!(f, "Not a file: {}", path.display()),
+            InputError::Unreadable { path, error } => write!(f, "Cannot read {}: {}", path.display(), error),
+            InputError::Empty(path) => write!(f, "{} is empty (0 B); was it still being written or downloaded?", path.display()),
+            InputError::NotPdf { path, size, looks_like } => {
+                let found = match looks_like {
+                    Some(kind) => format!("it looks like {}", kind),
+                    None => format!("no %PDF- header in its first {}", format_bytes(HEADER_WINDOW as u64)),
+                };
+                write!(f, "{} is not a PDF ({}, {}; use --no-sniff to try loading it anyway)", path.display(), format_bytes(*size), found)
+            }
+        }
     }
+}
+
+impl std::error::Error for InputError {}
 
-    if !path.is_file() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            format!("Not a file: {}", path.display()),
-        ));
+/// Check that a path is a readable file, following symbolic links, and unless `sniff` is
+/// off that it starts like a PDF. An empty file is refused either way.
+pub fn validate_input_file(path: &Path, sniff: bool) -> std::result::Result<(), InputError> {
+    let unreadable = |e: std::io::Error| InputError::Unreadable { path: path.to_path_buf(), error: e.to_string() };
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let link_target = fs::symlink_metadata(path).ok().and_then(|_| fs::read_link(path).ok());
+            let suggestion = Some(path.with_extension("pdf")).filter(|with_pdf| path.extension().is_none() && with_pdf.is_file());
+            return Err(InputError::NotFound { path: path.to_path_buf(), link_target, suggestion });
+        }
+        Err(e) => return Err(unreadable(e)),
+    };
+    if !metadata.is_file() {
+        return Err(InputError::NotAFile { path: path.to_path_buf(), directory: metadata.is_dir() });
     }
 
-    // Try to open the file to check readability
-    fs::File::open(path)?;
+    let mut head = Vec::with_capacity(HEADER_WINDOW);
+    fs::File::open(path).and_then(|file| file.take(HEADER_WINDOW as u64).read_to_end(&mut head)).map_err(unreadable)?;
+    if head.is_empty() {
+        return Err(InputError::Empty(path.to_path_buf()));
+    }
+    if sniff && !head.windows(5).any(|window| window == b"%PDF-") {
+        return Err(InputError::NotPdf { path: path.to_path_buf(), size: metadata.len(), looks_like: file_kind(&head) });
+    }
     Ok(())
 }
 
+/// What a file that isn't a PDF most likely is, from its first bytes
+fn file_kind(head: &[u8]) -> Option<&'static str> {
+    let text = String::from_utf8_lossy(&head[..head.len().min(256)]).trim_start().to_ascii_lowercase();
+    let kind = match head {
+        [0xFF, 0xD8, 0xFF, ..] => "a JPEG image",
+        [0x89, b'P', b'N', b'G', ..] => "a PNG image",
+        [b'G', b'I', b'F', b'8', ..] => "a GIF image",
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => "a TIFF image",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "a WebP image",
+        [b'P', b'K', 0x03, 0x04, ..] => "a ZIP archive (or a Word, Excel or EPUB document)",
+        [0x1F, 0x8B, ..] => "a gzip archive",
+        [0xD0, 0xCF, 0x11, 0xE0, ..] => "an old Word or Excel document",
+        [b'%', b'!', b'P', b'S', ..] => "a PostScript file",
+        _ if text.starts_with("<!doctype html") || text.starts_with("<html") => "a web page, as failed downloads often are",
+        _ if text.starts_with("<?xml") || text.starts_with('<') => "an XML or HTML file",
+        _ => return None,
+    };
+    Some(kind)
+}
+
 /// Get file size in bytes
 pub fn get_file_size(path: &Path) -> std::io::Result<u64> {
     let metadata = fs::metadata(path)?;
@@ -49,6 +124,25 @@ pub fn format_bytes(bytes: u64) -> String {
     }
 }
 
+/// Parse a human readable size such as `512`, `10KB` or `1.5M` into bytes (1 KB = 1024 B)
+pub fn parse_size(input: &str) -> std::result::Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| format!("invalid size: {}", input))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        other => return Err(format!("unknown size unit: {}", other)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
 /// Calculate compression ratio
 pub fn calculate_compression_ratio(original: u64, compressed: u64) -> f64 {
     if original == 0 {
@@ -57,16 +151,66 @@ pub fn calculate_compression_ratio(original: u64, compressed: u64) -> f64 {
     ((original as f64 - compressed as f64) / original as f64) * 100.0
 }
 
-/// Resolve input path: if URL, download to temp file; else return as PathBuf
-pub fn resolve_input_path(input: &str) -> Result<PathBuf> {
-    if input.starts_with("http://") || input.starts_with("https://") {
-        println!("Downloading from URL: {}", input);
-        let response = reqwest::blocking::get(input)?;
-        let temp_file = tempfile::NamedTempFile::new()?;
-        let content = response.bytes()?;
-        std::fs::write(temp_file.path(), content)?;
-        Ok(temp_file.path().to_path_buf())
-    } else {
-        Ok(PathBuf::from(input))
+/// Whether an input is a URL to download rather than a local path
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Whether a path is `-`, which stands for stdin as an input and stdout as an output
+pub fn is_std_stream(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Download a URL to a temporary file
+fn download(url: &str) -> Result<tempfile::NamedTempFile> {
+    eprintln!("Downloading from URL: {}", url);
+    let response = reqwest::blocking::get(url)?;
+    let temp_file = tempfile::NamedTempFile::new()?;
+    let content = response.bytes()?;
+    std::fs::write(temp_file.path(), content)?;
+    Ok(temp_file)
+}
+
+/// An input as a local file: the file given, a download, or what was read from stdin.
+/// Downloads and stdin are kept in temporary files, deleted when this is dropped.
+#[derive(Debug)]
+pub struct InputFile {
+    path: PathBuf,
+    _temp: Option<tempfile::TempPath>,
+}
+
+impl InputFile {
+    /// A file on disk, left alone when this is dropped
+    pub fn local(path: &Path) -> Self {
+        InputFile { path: path.to_path_buf(), _temp: None }
     }
-}
\ No newline at end of file
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Resolve an input that may be a URL, or `-` to read stdin, and check it can be read (and
+/// that it starts like a PDF, when `sniff` is on)
+pub fn open_input(input: &Path, sniff: bool) -> Result<InputFile> {
+    let temp = if is_std_stream(input) {
+        let mut temp_file = tempfile::Builder::new().prefix("stdin-").suffix(".pdf").tempfile()?;
+        let read = std::io::copy(&mut std::io::stdin().lock(), &mut temp_file).context("Failed to read the input from stdin")?;
+        anyhow::ensure!(read > 0, "Nothing to read from stdin");
+        temp_file
+    } else if is_url(&input.to_string_lossy()) {
+        download(&input.to_string_lossy())?
+    } else {
+        validate_input_file(input, sniff)?;
+        return Ok(InputFile::local(input));
+    };
+    let name = if is_std_stream(input) { "stdin".to_string() } else { input.display().to_string() };
+    validate_input_file(temp.path(), sniff).with_context(|| format!("Failed to read {}", name))?;
+    Ok(InputFile { path: temp.path().to_path_buf(), _temp: Some(temp.into_temp_path()) })
+}
+
+/// `value` rounded to `places` decimals, as the JSON shows percentages and resolutions
+pub fn rounded(value: f64, places: i32) -> f64 {
+    let scale = 10f64.powi(places);
+    (value * scale).round() / scale
+}
@@ -0,0 +1,94 @@
+use lopdf::{Document, Object, ObjectId};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::inline_images::for_each_operation;
+use crate::resources::page_content;
+
+/// The page count and the text of every page, which optimization must not change
+#[derive(Debug, Default)]
+pub struct ContentSnapshot {
+    /// Each page's extracted text; `None` when it can't be extracted and isn't compared
+    pub pages: Vec<Option<String>>,
+}
+
+pub fn content_snapshot(doc: &Document) -> ContentSnapshot {
+    let pages = doc.get_pages().into_values().map(|page_id| page_text(doc, page_id)).collect();
+    ContentSnapshot { pages }
+}
+
+/// The strings a page's text operators show, decoded with their fonts' encodings.
+/// Positioning is left out: only the text itself has to survive optimization unchanged.
+fn page_text(doc: &Document, page_id: ObjectId) -> Option<String> {
+    // Our own decoding, which handles every filter the optimizer may rewrite
+    let content = page_content(doc, page_id)?;
+    let encodings: BTreeMap<Vec<u8>, &str> = doc
+        .get_page_fonts(page_id)
+        .into_iter()
+        .map(|(name, font)| (name, font.get_font_encoding()))
+        .collect();
+
+    let mut text = String::new();
+    let mut encoding = None;
+    let parsed = for_each_operation(&content, |operator, operands| match operator {
+        b"Tf" => {
+            encoding = operands.first().and_then(|font| font.as_name().ok()).and_then(|font| encodings.get(font).copied());
+        }
+        b"Tj" | b"TJ" | b"'" | b"\"" => collect_strings(&mut text, encoding, operands),
+        _ => {}
+    });
+    parsed.then_some(text)
+}
+
+fn collect_strings(text: &mut String, encoding: Option<&str>, operands: &[Object]) {
+    for operand in operands {
+        match operand {
+            Object::String(bytes, _) => text.push_str(&Document::decode_text(encoding, bytes)),
+            Object::Array(items) => collect_strings(text, encoding, items),
+            _ => {}
+        }
+    }
+}
+
+/// Compare the output's pages with the input's, returning a description of each difference
+pub fn compare_content(before: &ContentSnapshot, after: &ContentSnapshot) -> Vec<String> {
+    if before.pages.len() != after.pages.len() {
+        return vec![format!("the output has {} pages instead of {}", after.pages.len(), before.pages.len())];
+    }
+    before
+        .pages
+        .iter()
+        .zip(&after.pages)
+        .enumerate()
+        .filter_map(|(index, pair)| match pair {
+            (Some(expected), Some(actual)) if expected != actual => Some(format!("the text of page {} changed", index + 1)),
+            (Some(_), None) => Some(format!("the text of page {} can no longer be extracted", index + 1)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returned when the written output doesn't match the input. The output has been deleted
+/// or, if asked to keep it, renamed.
+#[derive(Debug)]
+pub struct VerificationError {
+    pub problems: Vec<String>,
+    /// Where the failed output was kept
+    pub kept_as: Option<std::path::PathBuf>,
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shown: Vec<&str> = self.problems.iter().take(5).map(String::as_str).collect();
+        write!(f, "the output failed verification: {}", shown.join("; "))?;
+        if self.problems.len() > shown.len() {
+            write!(f, " (and {} more)", self.problems.len() - shown.len())?;
+        }
+        match &self.kept_as {
+            Some(path) => write!(f, "; it was kept as {}", path.display()),
+            None => write!(f, "; it was deleted (use --keep-failed to keep it)"),
+        }
+    }
+}
+
+impl std::error::Error for VerificationError {}
@@ -0,0 +1,338 @@
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::pdf_writer::decode_text_string;
+
+/// How a property's value is written
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// A language alternative; the `x-default` entry is the value
+    LangAlt,
+    /// An ordered list, of which one entry is written
+    Seq,
+    Text,
+    /// An XMP date, a PDF date in the information dictionary
+    Date,
+}
+
+/// An XMP property mirroring a document information entry
+pub struct Property {
+    pub info_key: &'static str,
+    pub name: &'static str,
+    kind: Kind,
+}
+
+/// The properties kept in sync with the information dictionary, in the order they're
+/// written
+pub const PROPERTIES: [Property; 8] = [
+    Property { info_key: "Title", name: "dc:title", kind: Kind::LangAlt },
+    Property { info_key: "Author", name: "dc:creator", kind: Kind::Seq },
+    Property { info_key: "Subject", name: "dc:description", kind: Kind::LangAlt },
+    Property { info_key: "Keywords", name: "pdf:Keywords", kind: Kind::Text },
+    Property { info_key: "Producer", name: "pdf:Producer", kind: Kind::Text },
+    Property { info_key: "Creator", name: "xmp:CreatorTool", kind: Kind::Text },
+    Property { info_key: "CreationDate", name: "xmp:CreateDate", kind: Kind::Date },
+    Property { info_key: "ModDate", name: "xmp:ModifyDate", kind: Kind::Date },
+];
+
+const NAMESPACES: &str = "xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+                          xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"";
+
+/// The catalog's XMP metadata stream
+pub fn catalog_metadata_id(doc: &Document) -> Option<ObjectId> {
+    doc.catalog().ok()?.get(b"Metadata").and_then(Object::as_reference).ok()
+}
+
+/// The XMP packet in a metadata stream
+pub fn read_packet(doc: &Document, id: ObjectId) -> Option<String> {
+    let stream = doc.get_object(id).and_then(Object::as_stream).ok()?;
+    String::from_utf8(crate::filters::decode_stream(stream).ok()?).ok()
+}
+
+/// A minimal XMP packet carrying the text entries and dates of a document information
+/// dictionary
+pub fn xmp_packet(info: &Dictionary) -> String {
+    let properties: String = PROPERTIES
+        .iter()
+        .filter_map(|property| info_value(info, property).map(|value| property_xml(property, &value)))
+        .collect();
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+         <rdf:Description rdf:about=\"\" {}>\n\
+         {}</rdf:Description>\n\
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        NAMESPACES, properties
+    )
+}
+
+/// Write the information dictionary's entries into an existing packet, replacing the
+/// properties that differ and adding those it lacks. Everything else in the packet (lists
+/// of several authors among it) is kept. `None` when the packet has no `rdf:RDF` element
+/// to add to.
+pub fn update_xmp(packet: &str, info: &Dictionary) -> Option<String> {
+    let mut packet = packet.to_string();
+    let mut added = String::new();
+    for property in &PROPERTIES {
+        let Some(value) = info_value(info, property) else { continue };
+        let xml = property_xml(property, &value);
+        if let Some((start, end)) = find_element(&packet, property.name) {
+            if element_value(&packet[start..end], property.kind).as_ref() != Some(&value) {
+                packet.replace_range(start..end, xml.trim_end());
+            }
+            continue;
+        }
+        // A property written as an attribute moves into the new description
+        if let Some((start, end, current)) = find_attribute(&packet, property.name) {
+            if xml_unescape(current) == value {
+                continue;
+            }
+            packet.replace_range(start..end, "");
+        }
+        added += &xml;
+    }
+    if added.is_empty() {
+        return Some(packet);
+    }
+    let end = packet.find("</rdf:RDF>")?;
+    packet.insert_str(end, &format!("<rdf:Description rdf:about=\"\" {}>\n{}</rdf:Description>\n", NAMESPACES, added));
+    Some(packet)
+}
+
+/// The properties of `PROPERTIES` a packet holds, with their values
+pub fn read_xmp(packet: &str) -> Vec<(&'static Property, String)> {
+    PROPERTIES
+        .iter()
+        .filter_map(|property| {
+            let value = match find_element(packet, property.name) {
+                Some((start, end)) => element_value(&packet[start..end], property.kind),
+                None => find_attribute(packet, property.name).map(|(_, _, value)| xml_unescape(value)),
+            };
+            value.filter(|value| !value.is_empty()).map(|value| (property, value))
+        })
+        .collect()
+}
+
+/// The information dictionary value of a property read from XMP: a text string, or a
+/// PDF date
+pub fn info_object(property: &Property, value: &str) -> Option<Object> {
+    match property.kind {
+        Kind::Date => pdf_date_from_xmp(value).map(Object::string_literal),
+        _ => Some(crate::pdf_writer::text_string(value)),
+    }
+}
+
+fn info_value(info: &Dictionary, property: &Property) -> Option<String> {
+    let Ok(Object::String(bytes, _)) = info.get(property.info_key.as_bytes()) else { return None };
+    match property.kind {
+        Kind::Date => xmp_date(bytes),
+        _ => Some(decode_text_string(bytes)),
+    }
+}
+
+fn property_xml(property: &Property, value: &str) -> String {
+    let (name, value) = (property.name, xml_escape(value));
+    match property.kind {
+        Kind::LangAlt => format!("<{0}><rdf:Alt><rdf:li xml:lang=\"x-default\">{1}</rdf:li></rdf:Alt></{0}>\n", name, value),
+        Kind::Seq => format!("<{0}><rdf:Seq><rdf:li>{1}</rdf:li></rdf:Seq></{0}>\n", name, value),
+        Kind::Text | Kind::Date => format!("<{0}>{1}</{0}>\n", name, value),
+    }
+}
+
+/// The byte range of the element `name`, from its start tag through its end tag
+fn find_element(packet: &str, name: &str) -> Option<(usize, usize)> {
+    let open = format!("<{}", name);
+    let mut from = 0;
+    while let Some(found) = packet[from..].find(&open) {
+        let start = from + found;
+        let after = start + open.len();
+        from = after;
+        // Not a longer name sharing the prefix
+        if !packet[after..].starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+        let tag_end = after + packet[after..].find('>')?;
+        if packet[..tag_end].ends_with('/') {
+            return Some((start, tag_end + 1));
+        }
+        let close = format!("</{}>", name);
+        let end = tag_end + packet[tag_end..].find(&close)? + close.len();
+        return Some((start, end));
+    }
+    None
+}
+
+/// The byte range of the attribute `name="value"` (with the whitespace before it) and its
+/// raw value
+fn find_attribute<'a>(packet: &'a str, name: &str) -> Option<(usize, usize, &'a str)> {
+    let mut from = 0;
+    while let Some(found) = packet[from..].find(name) {
+        let start = from + found;
+        from = start + name.len();
+        if !packet[..start].ends_with(|c: char| c.is_whitespace()) {
+            continue;
+        }
+        let rest = packet[from..].trim_start();
+        let Some(rest) = rest.strip_prefix('=') else { continue };
+        let rest = rest.trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else { continue };
+        let value_start = packet.len() - rest.len() + 1;
+        let value_end = value_start + packet[value_start..].find(quote)?;
+        let space = packet[..start].trim_end().len();
+        return Some((space, value_end + 1, &packet[value_start..value_end]));
+    }
+    None
+}
+
+/// The text of an element: the `x-default` (or first) entry of a language alternative,
+/// the entries of a list joined with "; ", or the element's own text
+fn element_value(element: &str, kind: Kind) -> Option<String> {
+    let content = &element[element.find('>')? + 1..];
+    let content = &content[..content.rfind("</").unwrap_or(content.len())];
+    let mut items = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<rdf:li") {
+        let tag_end = start + rest[start..].find('>')?;
+        let tag = &rest[start..tag_end];
+        let end = tag_end + rest[tag_end..].find("</rdf:li>")?;
+        items.push((tag.contains("x-default"), xml_unescape(&rest[tag_end + 1..end])));
+        rest = &rest[end..];
+    }
+    if items.is_empty() {
+        return Some(xml_unescape(content.trim()));
+    }
+    if kind == Kind::LangAlt {
+        let default = items.iter().position(|(is_default, _)| *is_default).unwrap_or(0);
+        return Some(items.swap_remove(default).1);
+    }
+    Some(items.into_iter().map(|(_, item)| item).collect::<Vec<_>>().join("; "))
+}
+
+/// A PDF date (`D:YYYYMMDDHHmmSSOHH'mm`, everything after the year optional) as an XMP
+/// date
+pub fn xmp_date(date: &[u8]) -> Option<String> {
+    let date = std::str::from_utf8(date).ok()?;
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let digits = date.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 || digits % 2 != 0 || digits > 14 {
+        return None;
+    }
+    let field = |start: usize, default: &'static str| date.get(start..start + 2).filter(|_| start + 2 <= digits).unwrap_or(default);
+    let mut xmp = format!(
+        "{}-{}-{}T{}:{}:{}",
+        &date[..4],
+        field(4, "01"),
+        field(6, "01"),
+        field(8, "00"),
+        field(10, "00"),
+        field(12, "00")
+    );
+    let zone = date[digits..].replace('\'', "");
+    match zone.as_bytes() {
+        [b'Z', ..] => xmp.push('Z'),
+        [sign @ (b'+' | b'-'), hours @ ..] if hours.len() >= 2 && hours.iter().all(u8::is_ascii_digit) => {
+            let hours = std::str::from_utf8(hours).ok()?;
+            let minutes = hours.get(2..4).unwrap_or("00");
+            xmp += &format!("{}{}:{}", *sign as char, &hours[..2], minutes);
+        }
+        _ => {}
+    }
+    Some(xmp)
+}
+
+/// An XMP date (`YYYY-MM-DDThh:mm:ssTZD`, everything after the year optional, seconds
+/// possibly fractional) as a PDF date, `D:YYYYMMDDHHmmSSOHH'mm'`
+pub fn pdf_date_from_xmp(date: &str) -> Option<String> {
+    let date = date.trim();
+    let zone_start = date
+        .char_indices()
+        .skip(10)
+        .find(|&(_, c)| c == 'Z' || c == '+' || c == '-')
+        .map_or(date.len(), |(index, _)| index);
+    let (time, zone) = date.split_at(zone_start);
+    // Fractions of a second have no place in a PDF date
+    let time = time.split('.').next()?;
+    let digits: String = time.chars().filter(char::is_ascii_digit).collect();
+    let separators_ok = time.chars().all(|c| c.is_ascii_digit() || matches!(c, '-' | 'T' | ':'));
+    if !separators_ok || digits.len() < 4 || !digits.len().is_multiple_of(2) || digits.len() > 14 {
+        return None;
+    }
+    let mut pdf = format!("D:{}", digits);
+    match zone.as_bytes() {
+        [] => {}
+        [b'Z'] => pdf.push('Z'),
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2] if [h1, h2, m1, m2].iter().all(|c| c.is_ascii_digit()) => {
+            pdf += &format!("{}{}{}'{}{}'", *sign as char, *h1 as char, *h2 as char, *m1 as char, *m2 as char);
+        }
+        _ => return None,
+    }
+    Some(pdf)
+}
+
+/// The current time as a PDF date, in UTC
+pub fn pdf_date_now() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "D:{:04}{:02}{:02}{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else { break };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use std::path::Path;
+use std::process::Output;
+
+/// A good PDF, a file that isn't there and one that only claims to be a PDF
+fn fixtures(dir: &Path) {
+    common::photo_pdf(dir, "good.pdf");
+    std::fs::write(dir.join("corrupt.pdf"), "%PDF-1.4\nnot really a PDF\n").unwrap();
+}
+
+fn batch(dir: &Path, files: &[&str]) -> Output {
+    let mut command = common::command(dir);
+    command.args(["batch", "--output-dir", "out", "--no-progress"]).args(files);
+    command.output().unwrap()
+}
+
+#[test]
+fn no_failures_exit_with_0() {
+    let dir = common::temp_dir();
+    fixtures(dir.path());
+    let output = batch(dir.path(), &["good.pdf"]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.path().join("out/good.pdf").exists());
+}
+
+#[test]
+fn some_failures_exit_with_2_and_are_listed() {
+    let dir = common::temp_dir();
+    fixtures(dir.path());
+    let output = batch(dir.path(), &["good.pdf", "missing.pdf", "corrupt.pdf"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr);
+    assert!(dir.path().join("out/good.pdf").exists());
+    assert!(stdout.contains("Files not found: 1"), "{}", stdout);
+    assert!(stderr.contains("Failed files:") && stderr.contains("  corrupt.pdf: "), "{}", stderr);
+    assert!(!stderr.contains("good.pdf"), "{}", stderr);
+}
+
+#[test]
+fn only_failures_exit_with_1() {
+    let dir = common::temp_dir();
+    fixtures(dir.path());
+    let output = batch(dir.path(), &["missing.pdf", "corrupt.pdf"]);
+    assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!dir.path().join("out/corrupt.pdf").exists());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn names_that_arent_utf8_are_kept() {
+    use std::os::unix::ffi::OsStringExt;
+    let dir = common::temp_dir();
+    let name = std::ffi::OsString::from_vec(b"caf\xe9.pdf".to_vec());
+    common::photo_pdf(dir.path(), "photo.pdf");
+    std::fs::rename(dir.path().join("photo.pdf"), dir.path().join(&name)).unwrap();
+    let output = common::command(dir.path()).args(["batch", "--output-dir", "out", "--no-progress"]).arg(&name).output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.path().join("out").join(&name).exists());
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// The state an earlier run leaves for an input it optimized
+fn done(dir: &Path, input: &str, output: &str) -> Value {
+    let metadata = std::fs::metadata(dir.join(input)).unwrap();
+    let modified = metadata.modified().unwrap().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+    json!({"input": input, "output": output, "status": "ok", "size": metadata.len(), "modified": modified, "sha256": null})
+}
+
+fn recorded(dir: &Path) -> Vec<String> {
+    let state: Value = serde_json::from_str(&std::fs::read_to_string(dir.join("state.json")).unwrap()).unwrap();
+    assert_eq!(state["version"], 1);
+    state["files"].as_array().unwrap().iter().map(|file| file["input"].as_str().unwrap().to_string()).collect()
+}
+
+#[test]
+fn resumes_with_the_inputs_not_yet_done() {
+    let dir = common::temp_dir();
+    for name in ["a.pdf", "b.pdf", "c.pdf"] {
+        common::photo_pdf(dir.path(), name);
+    }
+    // a.pdf was done before the batch was interrupted; its output is a stand-in, which
+    // would be replaced if a.pdf were optimized again
+    std::fs::create_dir(dir.path().join("out")).unwrap();
+    std::fs::write(dir.path().join("out/a.pdf"), "earlier output").unwrap();
+    let state = json!({"version": 1, "files": [done(dir.path(), "a.pdf", "out/a.pdf")]});
+    std::fs::write(dir.path().join("state.json"), state.to_string()).unwrap();
+
+    let stdout = common::run_ok(dir.path(), &["batch", "a.pdf", "b.pdf", "c.pdf", "--output-dir", "out", "--state-file", "state.json", "--no-progress"]);
+    assert!(stdout.contains("Resuming from state.json (1 files recorded)"), "{}", stdout);
+    assert!(stdout.contains("Files processed: 2/3"), "{}", stdout);
+    assert!(stdout.contains("Files skipped (done in an earlier run): 1"), "{}", stdout);
+    assert_eq!(std::fs::read_to_string(dir.path().join("out/a.pdf")).unwrap(), "earlier output");
+    assert!(dir.path().join("out/b.pdf").exists() && dir.path().join("out/c.pdf").exists());
+    assert_eq!(recorded(dir.path()), ["a.pdf", "b.pdf", "c.pdf"]);
+
+    // Everything is done now
+    let stdout = common::run_ok(dir.path(), &["batch", "a.pdf", "b.pdf", "c.pdf", "--output-dir", "out", "--state-file", "state.json", "--no-progress"]);
+    assert!(stdout.contains("Files skipped (done in an earlier run): 3"), "{}", stdout);
+}
+
+#[test]
+fn changed_inputs_are_done_again() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "a.pdf");
+    std::fs::create_dir(dir.path().join("out")).unwrap();
+    std::fs::write(dir.path().join("out/a.pdf"), "earlier output").unwrap();
+    let mut entry = done(dir.path(), "a.pdf", "out/a.pdf");
+    entry["size"] = json!(1);
+    std::fs::write(dir.path().join("state.json"), json!({"version": 1, "files": [entry]}).to_string()).unwrap();
+
+    let stdout = common::run_ok(dir.path(), &["batch", "a.pdf", "--output-dir", "out", "--overwrite", "--state-file", "state.json", "--no-progress"]);
+    assert!(stdout.contains("Files processed: 1/1"), "{}", stdout);
+    assert_ne!(std::fs::read_to_string(dir.path().join("out/a.pdf")).ok().as_deref(), Some("earlier output"));
+}
+
+#[test]
+fn malformed_state_is_an_error() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "a.pdf");
+    std::fs::write(dir.path().join("state.json"), "{\"version\": 1, \"files\": [{\"input\": \"a.pdf\",}]}").unwrap();
+    let output = common::run(dir.path(), &["batch", "a.pdf", "--state-file", "state.json"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to read the state file state.json") && stderr.contains("line 1 column"), "{}", stderr);
+
+    std::fs::write(dir.path().join("state.json"), "{\"version\": 99, \"files\": []}").unwrap();
+    let output = common::run(dir.path(), &["batch", "a.pdf", "--state-file", "state.json"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a state file of this version"));
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::path::Path;
+
+/// A PDF whose large photos take several seconds to recompress in all; the timeout is checked
+/// before each, so a file that runs out of time stops after the first
+fn slow_pdf(dir: &Path) {
+    // Of different sizes, so they aren't merged as duplicates
+    let images = (0..4).map(|i| common::jpeg_stream(&common::photo(1400, 1050 + i), 95)).collect();
+    common::build(&dir.join("slow.pdf"), images, "Slow");
+}
+
+#[test]
+fn a_file_past_the_timeout_fails_and_the_others_go_on() {
+    let dir = common::temp_dir();
+    slow_pdf(dir.path());
+    common::photo_pdf(dir.path(), "a.pdf");
+    common::photo_pdf(dir.path(), "b.pdf");
+
+    // A single thread, which has to move on to the other files once the slow one gives up
+    let output = common::run(
+        dir.path(),
+        &["batch", "slow.pdf", "a.pdf", "b.pdf", "--output-dir", "out", "--timeout", "1", "--threads", "1", "--no-progress"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr);
+    assert!(stderr.contains("  slow.pdf: timed out after 1s"), "{}", stderr);
+    assert!(stdout.contains("Files processed: 2/3"), "{}", stdout);
+
+    // Nothing is left of the slow file's output, not even a temporary file
+    let mut written: Vec<String> =
+        std::fs::read_dir(dir.path().join("out")).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    written.sort();
+    assert_eq!(written, ["a.pdf", "b.pdf"]);
+    for name in written {
+        assert!(std::fs::read(dir.path().join("out").join(name)).unwrap().starts_with(b"%PDF-"));
+    }
+}
+
+#[test]
+fn files_within_the_timeout_are_unaffected() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "a.pdf");
+    let stdout = common::run_ok(dir.path(), &["batch", "a.pdf", "--output-dir", "out", "--timeout", "60", "--no-progress"]);
+    assert!(stdout.contains("Files processed: 1/1"), "{}", stdout);
+    assert!(dir.path().join("out/a.pdf").exists());
+}
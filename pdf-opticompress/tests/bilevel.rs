@@ -0,0 +1,45 @@
+mod common;
+
+use lopdf::{Document, Object, Stream};
+use std::path::Path;
+
+/// The image of the first page
+fn page_image(path: &Path) -> Stream {
+    let doc = Document::load(path).unwrap();
+    let page = *doc.get_pages().values().next().unwrap();
+    let resources = doc.get_dictionary(page).unwrap().get(b"Resources").unwrap().as_dict().unwrap();
+    let xobjects = resources.get(b"XObject").unwrap().as_dict().unwrap();
+    let id = xobjects.get(b"Im1").and_then(Object::as_reference).unwrap();
+    doc.get_object(id).unwrap().as_stream().unwrap().clone()
+}
+
+#[test]
+fn scanned_text_becomes_a_much_smaller_bilevel_image() {
+    let dir = common::temp_dir();
+    let input = dir.path().join("scan.pdf");
+    common::build(&input, vec![common::jpeg_stream(&common::scan(850, 1100), 90)], "Scan");
+
+    let stdout = common::run_ok(dir.path(), &["optimize", "scan.pdf", "out.pdf", "--bilevel"]);
+    assert!(stdout.contains("Images converted to bilevel: 1"), "{}", stdout);
+
+    let before = page_image(&input);
+    let after = page_image(&dir.path().join("out.pdf"));
+    assert_eq!(after.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 1);
+    assert_eq!(after.dict.get(b"ColorSpace").unwrap().as_name().unwrap(), b"DeviceGray");
+    assert!(
+        after.content.len() * 5 <= before.content.len(),
+        "the image went from {} to {} bytes",
+        before.content.len(),
+        after.content.len()
+    );
+}
+
+#[test]
+fn photos_are_left_in_color() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    let stdout = common::run_ok(dir.path(), &["optimize", "photo.pdf", "out.pdf", "--bilevel"]);
+    assert!(!stdout.contains("Images converted to bilevel"), "{}", stdout);
+    let after = page_image(&dir.path().join("out.pdf"));
+    assert_eq!(after.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 8);
+}
@@ -0,0 +1,144 @@
+//! Fixtures and a way to run the binary for the integration tests
+#![allow(dead_code)]
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{Rgb, RgbImage};
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+pub const BINARY: &str = env!("CARGO_BIN_EXE_pdf-opticompress");
+
+/// The binary, run in `dir` with no configuration file or `PDF_OPTICOMPRESS_*` settings from
+/// the environment the tests run in
+pub fn command(dir: &Path) -> Command {
+    isolated(Command::new(BINARY), dir)
+}
+
+/// A shell script run like `command`, with the binary as `$BINARY`
+pub fn shell(dir: &Path, script: &str) -> Output {
+    let mut command = isolated(Command::new("sh"), dir);
+    command.env("BINARY", BINARY).args(["-c", script]).output().expect("failed to run sh")
+}
+
+fn isolated(mut command: Command, dir: &Path) -> Command {
+    command
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join(".config"))
+        .env("RUST_BACKTRACE", "0");
+    for (key, _) in std::env::vars_os() {
+        if key.to_string_lossy().starts_with("PDF_OPTICOMPRESS_") {
+            command.env_remove(key);
+        }
+    }
+    command
+}
+
+pub fn run(dir: &Path, args: &[&str]) -> Output {
+    command(dir).args(args).output().expect("failed to run pdf-opticompress")
+}
+
+/// Run and expect success, returning stdout
+pub fn run_ok(dir: &Path, args: &[&str]) -> String {
+    let output = run(dir, args);
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// A photo-like image: smooth gradients with some noise
+pub fn photo(width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| {
+        let noise = ((x * 7919 + y * 104729) % 31) as u8;
+        Rgb([(x % 256) as u8 ^ noise, (y % 256) as u8, ((x + y) % 256) as u8])
+    })
+}
+
+/// A scanned text page: rows of dark "words" on an off-white background
+pub fn scan(width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| {
+        let line = (y / 20) % 3 == 0 && (x / 9) % 7 != 0 && x > 100 && x < width - 100;
+        if line && (y % 20) > 4 {
+            Rgb([10, 10, 12])
+        } else {
+            Rgb([250, 249, 247])
+        }
+    })
+}
+
+/// An image XObject holding `image` as a JPEG of the given quality
+pub fn jpeg_stream(image: &RgbImage, quality: u8) -> Stream {
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut data, quality).encode_image(image).unwrap();
+    Stream::new(
+        dictionary! {
+            "Type" => "XObject", "Subtype" => "Image",
+            "Width" => image.width() as i64, "Height" => image.height() as i64,
+            "ColorSpace" => "DeviceRGB", "BitsPerComponent" => 8, "Filter" => "DCTDecode",
+        },
+        data,
+    )
+    .with_compression(false)
+}
+
+/// A PDF with a page per image (or a single page without images), each showing `text`, and
+/// document information with a title and an author
+pub fn build(path: &Path, images: Vec<Stream>, text: &str) {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {"Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica"});
+    let pages = images.len().max(1);
+    let mut images = images.into_iter();
+    let mut kids = vec![];
+    for page in 0..pages {
+        let mut xobjects = Dictionary::new();
+        let mut content = format!("BT\n/F1 12 Tf\n50 750 Td\n({} page {}) Tj\nET\n", text, page + 1);
+        if let Some(image) = images.next() {
+            xobjects.set("Im1", doc.add_object(image));
+            content.push_str("q 400 0 0 300 50 300 cm /Im1 Do Q\n");
+        }
+        let contents = doc.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+        let page = doc.add_object(dictionary! {
+            "Type" => "Page", "Parent" => pages_id, "Contents" => contents,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Resources" => dictionary! {"Font" => dictionary! {"F1" => font_id}, "XObject" => xobjects},
+        });
+        kids.push(Object::Reference(page));
+    }
+    let count = kids.len() as i64;
+    doc.objects.insert(pages_id, Object::Dictionary(dictionary! {"Type" => "Pages", "Kids" => kids, "Count" => count}));
+    let catalog = doc.add_object(dictionary! {"Type" => "Catalog", "Pages" => pages_id});
+    doc.trailer.set("Root", catalog);
+    let info = doc.add_object(dictionary! {
+        "Title" => Object::string_literal("Fixture"), "Author" => Object::string_literal("fixture"),
+    });
+    doc.trailer.set("Info", info);
+    doc.save(path).unwrap();
+}
+
+/// A one-page PDF with a photo that recompresses well, written to `dir/name`
+pub fn photo_pdf(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    build(&path, vec![jpeg_stream(&photo(600, 450), 95)], "Photo");
+    path
+}
+
+/// A one-page PDF with only text, written to `dir/name`
+pub fn text_pdf(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    build(&path, vec![], "Hello text");
+    path
+}
+
+pub fn temp_dir() -> tempfile::TempDir {
+    tempfile::tempdir().unwrap()
+}
+
+/// The preset and quality the optimizer stamped an output with, from its marker, e.g.
+/// {"version":"0.1.0","preset":"web","quality":80}
+pub fn marker(path: &Path) -> (String, u64) {
+    let doc = Document::load(path).unwrap();
+    let info = doc.trailer.get(b"Info").and_then(|info| doc.dereference(info)).unwrap().1.as_dict().unwrap();
+    let marker: serde_json::Value = serde_json::from_slice(info.get(b"PdfOpticompress").unwrap().as_str().unwrap()).unwrap();
+    (marker["preset"].as_str().unwrap().to_string(), marker["quality"].as_u64().unwrap())
+}
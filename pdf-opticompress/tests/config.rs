@@ -0,0 +1,65 @@
+mod common;
+
+use serde_json::Value;
+use std::path::Path;
+
+/// The quality and preset `optimize` runs with, from its JSON results
+fn settings(dir: &Path, env: &[(&str, &str)], args: &[&str]) -> (u64, String) {
+    let mut command = common::command(dir);
+    command.args(["optimize", "photo.pdf", "out.pdf", "--dry-run", "--format", "json"]).args(args).envs(env.iter().copied());
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    (json["quality"].as_u64().unwrap(), json["preset"].as_str().unwrap().to_string())
+}
+
+#[test]
+fn flags_win_over_the_environment_over_the_file_over_the_defaults() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    assert_eq!(settings(dir.path(), &[], &[]), (80, "web".to_string()));
+
+    let config = dir.path().join(".config/pdf-opticompress");
+    std::fs::create_dir_all(&config).unwrap();
+    std::fs::write(config.join("config.toml"), "# Smaller files\nquality = 60\npreset = \"print\"\n").unwrap();
+    assert_eq!(settings(dir.path(), &[], &[]), (60, "print".to_string()));
+
+    let env = [("PDF_OPTICOMPRESS_QUALITY", "50")];
+    assert_eq!(settings(dir.path(), &env, &[]), (50, "print".to_string()));
+
+    assert_eq!(settings(dir.path(), &env, &["--quality", "40"]), (40, "print".to_string()));
+    assert_eq!(settings(dir.path(), &env, &["--quality=40", "--preset=archive"]), (40, "archive".to_string()));
+}
+
+#[test]
+fn config_show_names_where_each_setting_comes_from() {
+    let dir = common::temp_dir();
+    std::fs::write(dir.path().join("custom.toml"), "max-dimension = 1600\nstrip_annotations = [\"Link\", \"Popup\"]\n").unwrap();
+    let output = common::command(dir.path()).args(["--config", "custom.toml", "config", "show"]).env("PDF_OPTICOMPRESS_THREADS", "2").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("max_dimension = 1600  # from the configuration file"), "{}", stdout);
+    assert!(stdout.contains("strip_annotations = [\"Link\", \"Popup\"]  # from the configuration file"), "{}", stdout);
+    assert!(stdout.contains("threads = 2  # from PDF_OPTICOMPRESS_THREADS"), "{}", stdout);
+    assert!(stdout.contains("quality = 80  # default"), "{}", stdout);
+}
+
+#[test]
+fn invalid_files_are_reported_with_the_line_and_column() {
+    let dir = common::temp_dir();
+    let error = |content: &str| {
+        std::fs::write(dir.path().join("bad.toml"), content).unwrap();
+        let output = common::run(dir.path(), &["--config", "bad.toml", "config", "show"]);
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    assert!(error("preset = \"web\"\nquality = \n").contains("line 2, column 11"));
+    assert!(error("preset = \"web\"\n\n[batch]\nthreads = 2\n").contains("line 3, column 1: tables aren't supported"));
+    assert!(error("quality = 60\nquality = 70\n").contains("line 2"));
+    assert!(error("threads = 2\nquality = \"high\"\n").contains("line 2, column 11: quality must be an integer"));
+    assert!(error("preset = \"smallest\"\n").contains("line 1, column 10: invalid value 'smallest'"));
+
+    std::fs::write(dir.path().join("unknown.toml"), "colour = true\n").unwrap();
+    let output = common::run(dir.path(), &["--config", "unknown.toml", "config", "show"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown key \"colour\" on line 1"));
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use lopdf::{Document, Object, StringFormat};
+use openssl::sha::sha256;
+use std::path::Path;
+use std::time::Duration;
+
+fn digest(path: &Path) -> [u8; 32] {
+    sha256(&std::fs::read(path).unwrap())
+}
+
+fn file_id(path: &Path) -> Vec<Vec<u8>> {
+    let doc = Document::load(path).unwrap();
+    let id = doc.trailer.get(b"ID").unwrap().as_array().unwrap();
+    id.iter().map(|part| part.as_str().unwrap().to_vec()).collect()
+}
+
+/// Optimize `input` twice, a second apart, and check both outputs are the same bytes
+fn twice(dir: &Path, input: &str, args: &[&str]) {
+    common::run_ok(dir, &[&["optimize", input, "first.pdf"][..], args].concat());
+    // A date taken from the clock would differ from one second to the next
+    std::thread::sleep(Duration::from_millis(1100));
+    common::run_ok(dir, &[&["optimize", input, "second.pdf"][..], args].concat());
+    assert_eq!(digest(&dir.join("first.pdf")), digest(&dir.join("second.pdf")));
+}
+
+#[test]
+fn two_runs_give_the_same_bytes() {
+    let dir = common::temp_dir();
+    // Several images, recompressed in parallel, and a shared font
+    let images = (0..4).map(|page| common::jpeg_stream(&common::photo(300 + page * 40, 240), 95)).collect();
+    common::build(&dir.path().join("album.pdf"), images, "Album");
+    twice(dir.path(), "album.pdf", &["--deterministic", "--preset", "maximum", "--set-title", "Report"]);
+
+    // The identifier the input lacked is derived from the content
+    let id = file_id(&dir.path().join("first.pdf"));
+    assert_eq!(id.len(), 2);
+    assert_eq!(id[0], id[1]);
+    assert_eq!(id[0].len(), 16);
+    // And no date is refreshed
+    let doc = Document::load(dir.path().join("first.pdf")).unwrap();
+    let info = doc.trailer.get(b"Info").and_then(|info| doc.dereference(info)).unwrap().1.as_dict().unwrap();
+    assert!(!info.has(b"ModDate") && !info.has(b"CreationDate"));
+}
+
+#[test]
+fn the_input_identifier_is_kept_and_its_second_half_derived() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    let mut doc = Document::load(dir.path().join("photo.pdf")).unwrap();
+    let permanent = Object::String(b"0123456789abcdef".to_vec(), StringFormat::Hexadecimal);
+    let changing = Object::String(b"fedcba9876543210".to_vec(), StringFormat::Hexadecimal);
+    doc.trailer.set("ID", vec![permanent, changing]);
+    doc.save(dir.path().join("photo.pdf")).unwrap();
+
+    twice(dir.path(), "photo.pdf", &["--deterministic"]);
+    let id = file_id(&dir.path().join("first.pdf"));
+    assert_eq!(id[0], b"0123456789abcdef");
+    assert_ne!(id[1], b"fedcba9876543210");
+}
+
+#[test]
+fn pdfa_output_is_the_same_too() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    // PDF/A adds a file identifier and an XMP packet with the document's dates
+    twice(dir.path(), "photo.pdf", &["--deterministic", "--pdfa"]);
+}
+
+#[test]
+fn file_identifiers_are_random_without_the_flag() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    common::run_ok(dir.path(), &["optimize", "photo.pdf", "first.pdf", "--pdfa"]);
+    common::run_ok(dir.path(), &["optimize", "photo.pdf", "second.pdf", "--pdfa"]);
+    assert_ne!(digest(&dir.path().join("first.pdf")), digest(&dir.path().join("second.pdf")));
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::path::Path;
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn is_pdf(path: &Path) -> bool {
+    std::fs::read(path).unwrap().starts_with(b"%PDF-")
+}
+
+#[test]
+fn existing_outputs_are_replaced_only_with_force() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    std::fs::write(dir.path().join("out.pdf"), "keep me").unwrap();
+
+    let output = common::run(dir.path(), &["optimize", "photo.pdf", "out.pdf"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("out.pdf already exists (use --force to replace it)"), "{}", stderr(&output));
+    assert_eq!(std::fs::read_to_string(dir.path().join("out.pdf")).unwrap(), "keep me");
+
+    common::run_ok(dir.path(), &["optimize", "photo.pdf", "out.pdf", "--force"]);
+    assert!(is_pdf(&dir.path().join("out.pdf")));
+}
+
+#[test]
+fn batch_lists_existing_outputs_unless_overwrite() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "a.pdf");
+    common::photo_pdf(dir.path(), "b.pdf");
+    std::fs::create_dir(dir.path().join("out")).unwrap();
+    std::fs::write(dir.path().join("out/b.pdf"), "keep me").unwrap();
+
+    let output = common::run(dir.path(), &["batch", "a.pdf", "b.pdf", "--output-dir", "out", "--no-progress"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("These outputs already exist") && stderr(&output).contains("out/b.pdf"), "{}", stderr(&output));
+    // Nothing was done
+    assert!(!dir.path().join("out/a.pdf").exists());
+
+    common::run_ok(dir.path(), &["batch", "a.pdf", "b.pdf", "--output-dir", "out", "--no-progress", "--overwrite"]);
+    assert!(is_pdf(&dir.path().join("out/a.pdf")) && is_pdf(&dir.path().join("out/b.pdf")));
+}
+
+#[test]
+fn the_input_is_never_its_own_output() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    let before = std::fs::read(dir.path().join("photo.pdf")).unwrap();
+
+    for output in ["photo.pdf", "./sub/../photo.pdf"] {
+        let result = common::run(dir.path(), &["optimize", "photo.pdf", output, "--force"]);
+        assert!(!result.status.success());
+        assert!(stderr(&result).contains("is the input itself (use --in-place to replace it safely)"), "{}", stderr(&result));
+    }
+    let result = common::run(dir.path(), &["batch", "photo.pdf", "--output-dir", ".", "--overwrite", "--no-progress"]);
+    assert!(!result.status.success());
+    assert!(stderr(&result).contains("These outputs are their inputs (use --in-place to replace the inputs):"), "{}", stderr(&result));
+    assert_eq!(std::fs::read(dir.path().join("photo.pdf")).unwrap(), before);
+}
+
+#[cfg(unix)]
+#[test]
+fn the_input_is_found_through_symlinks() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    std::os::unix::fs::symlink("photo.pdf", dir.path().join("link.pdf")).unwrap();
+    std::os::unix::fs::symlink(".", dir.path().join("here")).unwrap();
+    let before = std::fs::read(dir.path().join("photo.pdf")).unwrap();
+
+    for output in ["link.pdf", "here/photo.pdf"] {
+        let result = common::run(dir.path(), &["optimize", "photo.pdf", output, "--force"]);
+        assert!(stderr(&result).contains("is the input itself"), "{}: {}", output, stderr(&result));
+    }
+    let result = common::run(dir.path(), &["optimize", "link.pdf", "photo.pdf", "--force"]);
+    assert!(stderr(&result).contains("is the input itself"), "{}", stderr(&result));
+
+    std::fs::write(dir.path().join("list.txt"), "photo.pdf\tlink.pdf\n").unwrap();
+    let result = common::run(dir.path(), &["batch", "--files-from", "list.txt", "--overwrite", "--no-progress"]);
+    assert!(stderr(&result).contains("These outputs are their inputs") && stderr(&result).contains("  link.pdf"), "{}", stderr(&result));
+
+    assert_eq!(std::fs::read(dir.path().join("photo.pdf")).unwrap(), before);
+    assert!(std::fs::symlink_metadata(dir.path().join("link.pdf")).unwrap().file_type().is_symlink());
+}
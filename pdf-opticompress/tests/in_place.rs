@@ -0,0 +1,74 @@
+mod common;
+
+use std::path::Path;
+
+/// The files in `dir`, to check that nothing was left behind
+fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn backup_holds_the_original() {
+    let dir = common::temp_dir();
+    let input = common::photo_pdf(dir.path(), "photo.pdf");
+    let original = std::fs::read(&input).unwrap();
+
+    common::run_ok(dir.path(), &["optimize", "--in-place", "photo.pdf"]);
+
+    assert_eq!(std::fs::read(dir.path().join("photo.pdf.bak")).unwrap(), original);
+    assert!(std::fs::read(&input).unwrap().len() < original.len());
+    assert_eq!(names(dir.path()), ["photo.pdf", "photo.pdf.bak"]);
+}
+
+#[test]
+fn existing_backup_is_kept_unless_forced() {
+    let dir = common::temp_dir();
+    let input = common::photo_pdf(dir.path(), "photo.pdf");
+    let original = std::fs::read(&input).unwrap();
+    common::run_ok(dir.path(), &["optimize", "--in-place", "photo.pdf", "--preset", "archive"]);
+    let first_output = std::fs::read(&input).unwrap();
+
+    // Running again would back up the first output over the real original
+    let output = common::run(dir.path(), &["optimize", "--in-place", "photo.pdf", "--preset", "maximum"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    let output = common::run(dir.path(), &["batch", "--in-place", "photo.pdf", "--preset", "maximum"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("These backups already exist"));
+    assert_eq!(std::fs::read(dir.path().join("photo.pdf.bak")).unwrap(), original);
+    assert_eq!(std::fs::read(&input).unwrap(), first_output);
+
+    common::run_ok(dir.path(), &["optimize", "--in-place", "photo.pdf", "--preset", "maximum", "--force"]);
+    assert_eq!(std::fs::read(dir.path().join("photo.pdf.bak")).unwrap(), first_output);
+}
+
+#[test]
+fn failed_backup_leaves_the_original() {
+    let dir = common::temp_dir();
+    let input = common::photo_pdf(dir.path(), "photo.pdf");
+    let original = std::fs::read(&input).unwrap();
+    // The output is written and verified, then copying the original to its backup fails
+    std::fs::create_dir(dir.path().join("photo.pdf.bak")).unwrap();
+
+    let output = common::run(dir.path(), &["optimize", "--in-place", "photo.pdf", "--force"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to back up"));
+    assert_eq!(std::fs::read(&input).unwrap(), original);
+    assert_eq!(names(dir.path()), ["photo.pdf", "photo.pdf.bak"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupted_save_leaves_the_original() {
+    let dir = common::temp_dir();
+    let input = common::photo_pdf(dir.path(), "photo.pdf");
+    let original = std::fs::read(&input).unwrap();
+
+    // Writing past 2 KiB kills the process partway through saving the output
+    let output = common::shell(dir.path(), "ulimit -f 4; exec \"$BINARY\" optimize --in-place photo.pdf");
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read(&input).unwrap(), original);
+    assert!(!dir.path().join("photo.pdf.bak").exists());
+}
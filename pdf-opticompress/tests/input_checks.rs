@@ -0,0 +1,61 @@
+mod common;
+
+use std::path::Path;
+
+fn error(dir: &Path, args: &[&str]) -> String {
+    let output = common::run(dir, args);
+    assert!(!output.status.success(), "{:?} succeeded", args);
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn a_renamed_jpeg_is_not_a_pdf() {
+    let dir = common::temp_dir();
+    common::photo(64, 48).save_with_format(dir.path().join("scan.pdf"), image::ImageFormat::Jpeg).unwrap();
+    let stderr = error(dir.path(), &["optimize", "scan.pdf", "out.pdf"]);
+    assert!(stderr.contains("scan.pdf is not a PDF ("), "{}", stderr);
+    assert!(stderr.contains("it looks like a JPEG image; use --no-sniff to try loading it anyway)"), "{}", stderr);
+
+    // Loading it anyway fails later, as a PDF that can't be parsed
+    let stderr = error(dir.path(), &["optimize", "scan.pdf", "out.pdf", "--no-sniff"]);
+    assert!(!stderr.contains("is not a PDF"), "{}", stderr);
+    assert!(!dir.path().join("out.pdf").exists());
+
+    let output = common::run(dir.path(), &["batch", "scan.pdf", "--output-dir", "out", "--no-progress"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Files that aren't PDFs: 1"));
+}
+
+#[test]
+fn an_empty_file_is_refused_even_without_sniffing() {
+    let dir = common::temp_dir();
+    std::fs::write(dir.path().join("empty.pdf"), "").unwrap();
+    for args in [&["info", "empty.pdf"][..], &["info", "empty.pdf", "--no-sniff"]] {
+        let stderr = error(dir.path(), args);
+        assert!(stderr.contains("empty.pdf is empty (0 B); was it still being written or downloaded?"), "{}", stderr);
+    }
+}
+
+#[test]
+fn missing_inputs_and_directories_are_explained() {
+    let dir = common::temp_dir();
+    common::text_pdf(dir.path(), "report.pdf");
+    assert!(error(dir.path(), &["info", "report"]).contains("File not found: report (did you mean report.pdf?)"));
+    assert!(error(dir.path(), &["info", "missing.pdf"]).contains("File not found: missing.pdf"));
+    std::fs::create_dir(dir.path().join("scans")).unwrap();
+    assert!(error(dir.path(), &["info", "scans"]).contains("Not a file: scans is a directory (give the PDFs in it to batch)"));
+}
+
+#[cfg(unix)]
+#[test]
+fn symbolic_links_are_followed() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    std::os::unix::fs::symlink("photo.pdf", dir.path().join("link.pdf")).unwrap();
+    common::run_ok(dir.path(), &["optimize", "link.pdf", "out.pdf"]);
+    assert!(std::fs::read(dir.path().join("out.pdf")).unwrap().starts_with(b"%PDF-"));
+
+    std::os::unix::fs::symlink("gone.pdf", dir.path().join("broken.pdf")).unwrap();
+    let stderr = error(dir.path(), &["info", "broken.pdf"]);
+    assert!(stderr.contains("File not found: broken.pdf (a symbolic link to gone.pdf, which doesn't exist)"), "{}", stderr);
+}
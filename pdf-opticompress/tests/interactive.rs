@@ -0,0 +1,63 @@
+//! Interactive mode driven through a pipe, a line per answer
+mod common;
+
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+
+/// Stdout of a session given `answers`
+fn session(dir: &Path, answers: &str) -> String {
+    let mut child = common::command(dir).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(answers.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn optimize_one_file() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    // Optimize, a missing input then the right one, the output, preset 2 (print), an
+    // invalid quality then 40, and quit
+    let stdout = session(dir.path(), "1\nmissing.pdf\nphoto.pdf\nsmall.pdf\n2\n150\n40\n4\n");
+    assert!(stdout.contains("  File not found: missing.pdf"), "{}", stdout);
+    assert!(stdout.contains("Output PDF [photo.optimized.pdf]: "), "{}", stdout);
+    assert!(stdout.contains("Quality (0-100) [80]: "), "{}", stdout);
+    assert!(stdout.contains("  \"150\" is not a quality from 0 to 100"), "{}", stdout);
+    assert!(stdout.contains("Optimization Results:"), "{}", stdout);
+    assert_eq!(common::marker(&dir.path().join("small.pdf")), ("print".to_string(), 40));
+
+    // The defaults, and asked before replacing the output
+    let stdout = session(dir.path(), "\nphoto.pdf\nsmall.pdf\ny\n\n\n4\n");
+    assert!(stdout.contains("small.pdf already exists. Replace it? [y/N]: "), "{}", stdout);
+    assert_eq!(common::marker(&dir.path().join("small.pdf")), ("web".to_string(), 80));
+}
+
+#[test]
+fn batch_and_the_end_of_input() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "a.pdf");
+    common::photo_pdf(dir.path(), "b.pdf");
+    // Batch, both inputs into out/, preset 5 (scan), quality 50, 0 threads then 2; the end of
+    // the input quits
+    let stdout = session(dir.path(), "3\na.pdf b.pdf\nout\n5\n50\n0\n2\n");
+    assert!(stdout.contains("  \"0\" is not a number of threads (1 or more)"), "{}", stdout);
+    assert!(stdout.contains("Batch processing 2 files with 2 threads"), "{}", stdout);
+    assert_eq!(common::marker(&dir.path().join("out/b.pdf")), ("scan".to_string(), 50));
+}
+
+#[test]
+fn failed_actions_return_to_the_menu() {
+    let dir = common::temp_dir();
+    std::fs::write(dir.path().join("notes.pdf"), "meeting notes\n").unwrap();
+    // Answers end in the middle of the action, which fails; the menu then ends
+    let mut child = common::command(dir.path()).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"2\nnotes.pdf\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("notes.pdf is not a PDF"), "{}", stdout);
+    assert_eq!(stdout.matches("What do you want to do?").count(), 2, "{}", stdout);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error: No more input"));
+}
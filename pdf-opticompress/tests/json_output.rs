@@ -0,0 +1,51 @@
+mod common;
+
+use serde_json::Value;
+
+#[test]
+fn optimize_prints_its_results_as_json() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    let stdout = common::run_ok(dir.path(), &["optimize", "photo.pdf", "out.pdf", "--format", "json", "--verbose"]);
+
+    // Nothing but the JSON goes to stdout
+    let json: Value = serde_json::from_str(&stdout).expect("stdout is a JSON object");
+    assert_eq!(json["input"], "photo.pdf");
+    assert_eq!(json["output"], "out.pdf");
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["preset"], "web");
+    assert_eq!(json["quality"], 80);
+    assert_eq!(json["dry_run"], false);
+    assert_eq!(json["stages"], "all");
+
+    let original = json["original_size"].as_u64().unwrap();
+    let optimized = json["optimized_size"].as_u64().unwrap();
+    assert_eq!(original, std::fs::metadata(dir.path().join("photo.pdf")).unwrap().len());
+    assert_eq!(optimized, std::fs::metadata(dir.path().join("out.pdf")).unwrap().len());
+    assert_eq!(json["bytes_saved"].as_i64().unwrap(), original as i64 - optimized as i64);
+    assert!(json["optimized_size_formatted"].as_str().unwrap().ends_with("KB"));
+    assert!(json["compression_ratio"].is_f64());
+    assert!(json["processing_time_seconds"].is_f64());
+    for key in ["images_optimized", "image_bytes_before", "image_bytes_after", "stream_bytes_before", "pages_verified"] {
+        assert!(json[key].is_u64(), "{} is {}", key, json[key]);
+    }
+    assert!(json["active_content_removed"]["javascript"].is_u64());
+    assert!(json["savings_by_stage"].as_array().unwrap().iter().all(|stage| stage["stage"].is_string() && stage["bytes"].is_i64()));
+
+    let images = json["images"].as_array().expect("--verbose lists the images");
+    assert_eq!(images.len(), 1);
+    assert!(images[0]["object"].is_array());
+    assert!(images[0]["page"].is_null());
+    assert_eq!(images[0]["filter"], "DCTDecode");
+    assert_eq!(images[0]["action"], "recompressed");
+}
+
+#[test]
+fn images_are_only_listed_when_asked_for() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    let stdout = common::run_ok(dir.path(), &["optimize", "photo.pdf", "out.pdf", "--format", "json"]);
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    assert!(json.get("images").is_none());
+    assert!(json["pdfa"].is_null());
+}
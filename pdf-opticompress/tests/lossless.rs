@@ -0,0 +1,57 @@
+mod common;
+
+use lopdf::{Document, Object};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Every DCTDecode stream of a document, by object number
+fn jpeg_streams(path: &Path) -> BTreeMap<u32, Vec<u8>> {
+    let doc = Document::load(path).unwrap();
+    doc.objects
+        .iter()
+        .filter_map(|(id, object)| {
+            let stream = object.as_stream().ok()?;
+            (stream.dict.get(b"Filter").and_then(Object::as_name).ok()? == b"DCTDecode").then(|| (id.0, stream.content.clone()))
+        })
+        .collect()
+}
+
+/// `jpeg` with a 30 KB EXIF segment after its JFIF header
+fn with_exif(jpeg: &[u8]) -> Vec<u8> {
+    let jfif_end = 4 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+    let mut payload = b"Exif\0\0".to_vec();
+    payload.resize(30_000, 0x5A);
+    let mut tagged = jpeg[..jfif_end].to_vec();
+    tagged.extend_from_slice(&[0xFF, 0xE1]);
+    tagged.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    tagged.extend_from_slice(&payload);
+    tagged.extend_from_slice(&jpeg[jfif_end..]);
+    tagged
+}
+
+#[test]
+fn no_jpeg_is_re_encoded() {
+    let dir = common::temp_dir();
+    let plain = common::jpeg_stream(&common::photo(600, 450), 95);
+    let mut tagged = common::jpeg_stream(&common::photo(640, 480), 95);
+    tagged.set_content(with_exif(&tagged.content));
+    common::build(&dir.path().join("photos.pdf"), vec![plain, tagged], "Photos");
+
+    // Settings that would otherwise re-encode and shrink every image
+    let stdout = common::run_ok(
+        dir.path(),
+        &["optimize", "photos.pdf", "out.pdf", "--lossless", "--preset", "maximum", "--quality", "30"],
+    );
+    assert!(stdout.contains("Mode: lossless (no image pixels re-encoded)"), "{}", stdout);
+
+    let before: Vec<Vec<u8>> = jpeg_streams(&dir.path().join("photos.pdf")).into_values().collect();
+    let after: Vec<Vec<u8>> = jpeg_streams(&dir.path().join("out.pdf")).into_values().collect();
+    assert_eq!(after.len(), 2);
+    for (before, after) in before.iter().zip(&after) {
+        let decode = |data: &[u8]| image::load_from_memory(data).unwrap().to_rgb8();
+        assert_eq!(decode(before), decode(after));
+    }
+    // The untouched stream is the same bytes; the other only lost its EXIF segment
+    assert_eq!(before[0], after[0]);
+    assert_eq!(before[1].len() - after[1].len(), 30_004);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+fn inputs(dir: &Path) {
+    for name in ["a.pdf", "b.pdf", "c.pdf"] {
+        common::photo_pdf(dir, name);
+    }
+}
+
+fn batch(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = common::command(dir)
+        .args(["batch", "--no-progress"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn comments_and_blank_lines_are_skipped() {
+    let dir = common::temp_dir();
+    inputs(dir.path());
+    std::fs::write(dir.path().join("list.txt"), "# Monthly reports\na.pdf\n\n   \n  # c.pdf is done\nb.pdf\r\n").unwrap();
+    let output = batch(dir.path(), &["--files-from", "list.txt", "--output-dir", "out"], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Batch processing 2 files"));
+    assert!(dir.path().join("out/a.pdf").exists() && dir.path().join("out/b.pdf").exists());
+    assert!(!dir.path().join("out/c.pdf").exists());
+}
+
+#[test]
+fn tabs_give_inputs_their_own_outputs() {
+    let dir = common::temp_dir();
+    inputs(dir.path());
+    std::fs::write(dir.path().join("list.txt"), "a.pdf\tsmall/first.pdf\nb.pdf\n").unwrap();
+    let output = batch(dir.path(), &["--files-from", "list.txt", "--output-dir", "out"], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.path().join("small/first.pdf").exists());
+    assert!(!dir.path().join("out/a.pdf").exists());
+    assert!(dir.path().join("out/b.pdf").exists());
+}
+
+// Windows doesn't allow newlines in file names
+#[cfg(unix)]
+#[test]
+fn null_separated_inputs_are_read_from_stdin() {
+    let dir = common::temp_dir();
+    inputs(dir.path());
+    std::fs::rename(dir.path().join("c.pdf"), dir.path().join("with\nnewline.pdf")).unwrap();
+    let output = batch(dir.path(), &["--files-from", "-", "--null", "--output-dir", "out"], b"a.pdf\0with\nnewline.pdf\0");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.path().join("out/a.pdf").exists() && dir.path().join("out/with\nnewline.pdf").exists());
+    assert!(!dir.path().join("out/b.pdf").exists());
+}
+
+#[test]
+fn malformed_lines_are_reported_with_their_number() {
+    let dir = common::temp_dir();
+    inputs(dir.path());
+    std::fs::write(dir.path().join("list.txt"), "a.pdf\n# outputs\nb.pdf\tout/b.pdf\textra\n").unwrap();
+    let output = batch(dir.path(), &["--files-from", "list.txt"], b"");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("list.txt line 3: 3 tab-separated fields"), "{}", stderr);
+    assert!(!dir.path().join("out").exists());
+
+    let output = batch(dir.path(), &["--files-from", "-"], b"a.pdf\n\tout/b.pdf\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("stdin line 2: empty path"));
+}
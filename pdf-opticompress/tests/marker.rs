@@ -0,0 +1,24 @@
+mod common;
+
+use lopdf::Document;
+
+#[test]
+fn optimized_outputs_are_skipped_when_run_again() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    common::run_ok(dir.path(), &["optimize", "photo.pdf", "once.pdf"]);
+
+    let stdout = common::run_ok(dir.path(), &["batch", "once.pdf", "--output-dir", "again", "--skip-optimized"]);
+    assert!(stdout.contains("Files skipped (already optimized): 1"), "{}", stdout);
+    assert!(!dir.path().join("again/once.pdf").exists());
+}
+
+#[test]
+fn strip_metadata_leaves_no_document_information() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    common::run_ok(dir.path(), &["optimize", "photo.pdf", "stripped.pdf", "--strip-metadata"]);
+
+    let doc = Document::load(dir.path().join("stripped.pdf")).unwrap();
+    assert!(doc.trailer.get(b"Info").is_err(), "trailer: {:?}", doc.trailer);
+}
@@ -0,0 +1,95 @@
+//! Commands given files whose names aren't valid UTF-8, which Linux allows
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::Path;
+use std::process::Output;
+
+/// A copy of a text fixture named "café.pdf" in Latin-1
+fn latin1_pdf(dir: &Path) -> OsString {
+    let name = OsString::from_vec(b"caf\xe9.pdf".to_vec());
+    common::text_pdf(dir, "text.pdf");
+    std::fs::rename(dir.join("text.pdf"), dir.join(&name)).unwrap();
+    name
+}
+
+fn run(dir: &Path, args: &[&OsString]) -> Output {
+    let output = common::command(dir).args(args).output().unwrap();
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn arg(text: &str) -> OsString {
+    OsString::from(text)
+}
+
+#[test]
+fn repair() {
+    let dir = common::temp_dir();
+    let input = latin1_pdf(dir.path());
+    run(dir.path(), &[&arg("repair"), &input, &arg("repaired.pdf")]);
+    assert!(dir.path().join("repaired.pdf").exists());
+}
+
+#[test]
+fn check() {
+    let dir = common::temp_dir();
+    let input = latin1_pdf(dir.path());
+    run(dir.path(), &[&arg("check"), &input]);
+}
+
+#[test]
+fn info() {
+    let dir = common::temp_dir();
+    let input = latin1_pdf(dir.path());
+    let output = run(dir.path(), &[&arg("info"), &input]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("caf"));
+}
+
+#[test]
+fn decompress() {
+    let dir = common::temp_dir();
+    let input = latin1_pdf(dir.path());
+    run(dir.path(), &[&arg("decompress"), &input, &arg("decompressed.pdf")]);
+    assert!(dir.path().join("decompressed.pdf").exists());
+}
+
+#[test]
+fn metadata() {
+    let dir = common::temp_dir();
+    let input = latin1_pdf(dir.path());
+    let output = run(dir.path(), &[&arg("metadata"), &arg("show"), &input]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Fixture"));
+    run(dir.path(), &[&arg("metadata"), &arg("set"), &input, &arg("set.pdf"), &arg("--title=Renamed")]);
+    run(dir.path(), &[&arg("metadata"), &arg("strip"), &input, &arg("stripped.pdf")]);
+    assert!(dir.path().join("set.pdf").exists() && dir.path().join("stripped.pdf").exists());
+}
+
+#[test]
+fn split() {
+    let dir = common::temp_dir();
+    let input = latin1_pdf(dir.path());
+    run(dir.path(), &[&arg("split"), &input, &arg("--every=1"), &arg("--out-dir=parts")]);
+    assert_eq!(std::fs::read_dir(dir.path().join("parts")).unwrap().count(), 1);
+}
+
+#[test]
+fn merge() {
+    let dir = common::temp_dir();
+    let input = latin1_pdf(dir.path());
+    common::text_pdf(dir.path(), "other.pdf");
+    let output = run(dir.path(), &[&arg("merge"), &input, &arg("other.pdf"), &arg("--output=merged.pdf")]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Merged 2 files (2 pages)"));
+}
+
+#[test]
+fn delete_pages() {
+    let dir = common::temp_dir();
+    let name = OsString::from_vec(b"caf\xe9.pdf".to_vec());
+    common::build(&dir.path().join(&name), vec![common::jpeg_stream(&common::photo(60, 40), 80); 2], "Two");
+    let output = run(dir.path(), &[&arg("delete-pages"), &name, &arg("trimmed.pdf"), &arg("--pages=1")]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Deleted 1 page (1 remains)"));
+}
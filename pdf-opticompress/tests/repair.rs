@@ -0,0 +1,72 @@
+mod common;
+
+use lopdf::{Document, Object};
+use std::path::Path;
+
+/// The page count, text and image of a document, to compare before and after a repair
+fn content(path: &Path) -> (usize, Vec<u8>, Vec<u8>) {
+    let doc = Document::load(path).unwrap();
+    let pages = doc.get_pages();
+    let page = *pages.values().next().unwrap();
+    let text = doc.get_page_content(page).unwrap();
+    let resources = doc.get_dictionary(page).unwrap().get(b"Resources").unwrap().as_dict().unwrap();
+    let image = resources.get(b"XObject").unwrap().as_dict().unwrap().get(b"Im1").and_then(Object::as_reference).unwrap();
+    let image = doc.get_object(image).unwrap().as_stream().unwrap().content.clone();
+    (pages.len(), text, image)
+}
+
+fn title(path: &Path) -> Option<Vec<u8>> {
+    let doc = Document::load(path).unwrap();
+    let info = doc.trailer.get(b"Info").and_then(|info| doc.dereference(info)).ok()?.1.as_dict().ok()?;
+    info.get(b"Title").and_then(Object::as_str).ok().map(<[u8]>::to_vec)
+}
+
+fn find(data: &[u8], needle: &[u8]) -> usize {
+    data.windows(needle.len()).rposition(|window| window == needle).unwrap()
+}
+
+#[test]
+fn a_truncated_cross_reference_table_is_rebuilt() {
+    let dir = common::temp_dir();
+    let original = common::photo_pdf(dir.path(), "photo.pdf");
+    let data = std::fs::read(&original).unwrap();
+    // Cut off in the middle of the cross-reference stream, which holds the trailer too
+    let xref = find(&data, b"/Type/XRef");
+    std::fs::write(dir.path().join("truncated.pdf"), &data[..xref + 40]).unwrap();
+    assert!(Document::load(dir.path().join("truncated.pdf")).is_err());
+
+    let stdout = common::run_ok(dir.path(), &["repair", "truncated.pdf", "repaired.pdf"]);
+    assert!(stdout.contains("Repaired truncated.pdf: it can't be read as it is"), "{}", stdout);
+    assert!(stdout.contains("rebuilt the cross-reference table from"), "{}", stdout);
+    assert!(stdout.contains("Wrote repaired.pdf (1 pages)"), "{}", stdout);
+    assert_eq!(content(&dir.path().join("repaired.pdf")), content(&original));
+}
+
+#[test]
+fn a_table_pointing_to_the_wrong_places_is_rebuilt() {
+    let dir = common::temp_dir();
+    let original = common::photo_pdf(dir.path(), "photo.pdf");
+    let data = std::fs::read(&original).unwrap();
+    // A comment after the header moves every object past where the table says it is
+    let header = find(&data[..20], b"\n") + 1;
+    let mut shifted = data[..header].to_vec();
+    shifted.extend_from_slice(b"% added by a careless tool, moving every object 64 bytes along\n");
+    shifted.extend_from_slice(&data[header..]);
+    std::fs::write(dir.path().join("shifted.pdf"), shifted).unwrap();
+
+    let stdout = common::run_ok(dir.path(), &["repair", "shifted.pdf", "repaired.pdf"]);
+    assert!(stdout.contains("Repaired shifted.pdf: "), "{}", stdout);
+    assert!(stdout.contains("rebuilt the cross-reference table from"), "{}", stdout);
+    assert_eq!(content(&dir.path().join("repaired.pdf")), content(&original));
+    // The trailer survived, and with it the document information
+    assert_eq!(title(&dir.path().join("repaired.pdf")).as_deref(), Some(&b"Fixture"[..]));
+}
+
+#[test]
+fn an_intact_file_is_rewritten_as_it_is() {
+    let dir = common::temp_dir();
+    let original = common::photo_pdf(dir.path(), "photo.pdf");
+    let stdout = common::run_ok(dir.path(), &["repair", "photo.pdf", "copy.pdf"]);
+    assert!(stdout.contains("photo.pdf isn't damaged; rewrote it as it is"), "{}", stdout);
+    assert_eq!(content(&dir.path().join("copy.pdf")), content(&original));
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use serde_json::Value;
+
+const COLUMNS: [&str; 10] = [
+    "input",
+    "output",
+    "status",
+    "original_size",
+    "optimized_size",
+    "percent_saved",
+    "images_optimized",
+    "processing_time_seconds",
+    "error",
+    "dry_run",
+];
+
+/// A batch of a good PDF and a file that only claims to be one
+fn batch_with_a_bad_file(report: &str) -> tempfile::TempDir {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "good.pdf");
+    std::fs::write(dir.path().join("bad.pdf"), "%PDF-1.4\nnot really a PDF\n").unwrap();
+    let output = common::run(dir.path(), &["batch", "good.pdf", "bad.pdf", "--output-dir", "out", "--no-progress", "--report", report]);
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+    dir
+}
+
+#[test]
+fn csv_report_has_a_row_per_input() {
+    let dir = batch_with_a_bad_file("report.csv");
+    let mut reader = csv::Reader::from_path(dir.path().join("report.csv")).unwrap();
+    assert_eq!(reader.headers().unwrap().iter().collect::<Vec<_>>(), COLUMNS);
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 2);
+
+    let good = rows.iter().find(|row| &row[0] == "good.pdf").unwrap();
+    assert_eq!(&good[2], "ok");
+    assert!(good[4].parse::<u64>().unwrap() < good[3].parse::<u64>().unwrap());
+    assert_eq!(&good[8], "");
+
+    let bad = rows.iter().find(|row| &row[0] == "bad.pdf").unwrap();
+    assert_eq!(&bad[1], "");
+    assert_eq!(&bad[2], "failed");
+    assert_eq!(&bad[4], "");
+    assert!(!bad[8].is_empty());
+    assert_eq!(&bad[9], "false");
+}
+
+#[test]
+fn json_report_has_an_object_per_input() {
+    let dir = batch_with_a_bad_file("report.json");
+    let report: Value = serde_json::from_str(&std::fs::read_to_string(dir.path().join("report.json")).unwrap()).unwrap();
+    let rows = report.as_array().unwrap();
+    assert_eq!(rows.len(), 2);
+    for row in rows {
+        assert_eq!(row.as_object().unwrap().keys().collect::<Vec<_>>(), COLUMNS);
+    }
+
+    let good = rows.iter().find(|row| row["input"] == "good.pdf").unwrap();
+    assert_eq!(good["status"], "ok");
+    assert!(good["optimized_size"].as_u64().unwrap() < good["original_size"].as_u64().unwrap());
+    assert!(good["percent_saved"].is_f64());
+
+    let bad = rows.iter().find(|row| row["input"] == "bad.pdf").unwrap();
+    assert_eq!(bad["status"], "failed");
+    assert!(bad["output"].is_null());
+    assert!(bad["optimized_size"].is_null());
+    assert!(bad["error"].as_str().is_some_and(|error| !error.is_empty()));
+}
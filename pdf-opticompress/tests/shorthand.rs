@@ -0,0 +1,66 @@
+//! `pdf-opticompress FILE...` without a command
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+
+#[test]
+fn one_file_is_optimized_next_to_itself() {
+    let dir = common::temp_dir();
+    std::fs::create_dir(dir.path().join("scans")).unwrap();
+    common::photo_pdf(&dir.path().join("scans"), "photo.pdf");
+    let stdout = common::run_ok(dir.path(), &["scans/photo.pdf"]);
+    assert!(stdout.contains("Optimization Results:"), "{}", stdout);
+    assert_eq!(common::marker(&dir.path().join("scans/photo.optimized.pdf")), ("web".to_string(), 80));
+
+    let output = common::run(dir.path(), &["scans/photo.pdf"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists (use --force to replace it)"));
+
+    common::run_ok(dir.path(), &["scans/photo.pdf", "-f", "-q", "40", "-p", "print"]);
+    assert_eq!(common::marker(&dir.path().join("scans/photo.optimized.pdf")), ("print".to_string(), 40));
+}
+
+#[test]
+fn several_files_are_a_batch() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "a.pdf");
+    common::photo_pdf(dir.path(), "b.pdf");
+    let stdout = common::run_ok(dir.path(), &["a.pdf", "b.pdf", "--quality=50", "--preset=scan"]);
+    assert!(stdout.contains("Batch processing 2 files"), "{}", stdout);
+    for output in ["a.optimized.pdf", "b.optimized.pdf"] {
+        assert_eq!(common::marker(&dir.path().join(output)), ("scan".to_string(), 50));
+    }
+    assert!(!common::run(dir.path(), &["a.pdf", "b.pdf"]).status.success());
+    common::run_ok(dir.path(), &["a.pdf", "b.pdf", "--force"]);
+}
+
+#[test]
+fn no_files_start_interactive_mode() {
+    let dir = common::temp_dir();
+    let mut child = common::command(dir.path()).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"4\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("Interactive mode for pdf-opticompress"), "{}", stdout);
+    assert!(stdout.contains("  4) Quit"), "{}", stdout);
+}
+
+#[test]
+fn options_need_files_and_files_come_after_commands() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    assert_eq!(common::run(dir.path(), &["-q", "40"]).status.code(), Some(2));
+    let output = common::run(dir.path(), &["photo.pdf", "info", "photo.pdf"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("photo.pdf was given before the command"));
+}
+
+#[test]
+fn help_documents_the_shorthand() {
+    let stdout = common::run_ok(&std::env::temp_dir(), &["--help"]);
+    assert!(stdout.contains("pdf-opticompress [OPTIONS] [FILES]...\n       pdf-opticompress <COMMAND>"), "{}", stdout);
+    assert!(stdout.contains("Without a command, one PDF is optimized to <STEM>.optimized.pdf next to it"), "{}", stdout);
+    assert!(stdout.contains("-q, --quality <QUALITY>") && stdout.contains("-p, --preset <PRESET>"), "{}", stdout);
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+fn optimize(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = common::command(dir)
+        .arg("optimize")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The process may fail before reading it all
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn stdin_to_stdout_round_trip() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    let input = std::fs::read(dir.path().join("photo.pdf")).unwrap();
+
+    let output = optimize(dir.path(), &["-", "-", "--format", "json"], &input);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // The PDF alone goes to stdout, the results to stderr
+    let doc = lopdf::Document::load_mem(&output.stdout).unwrap();
+    assert_eq!(doc.get_pages().len(), 1);
+    let results: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(results["original_size"], input.len());
+    assert_eq!(results["optimized_size"], output.stdout.len());
+    assert!(output.stdout.len() < input.len());
+
+    let output = optimize(dir.path(), &["-", "-"], &input);
+    assert!(output.status.success());
+    assert!(output.stdout.starts_with(b"%PDF-"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Optimization Results:"));
+}
+
+#[test]
+fn file_to_stdout_and_stdin_to_file() {
+    let dir = common::temp_dir();
+    common::photo_pdf(dir.path(), "photo.pdf");
+    let output = optimize(dir.path(), &["photo.pdf", "-"], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    lopdf::Document::load_mem(&output.stdout).unwrap();
+
+    let input = std::fs::read(dir.path().join("photo.pdf")).unwrap();
+    let output = optimize(dir.path(), &["-", "out.pdf"], &input);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    lopdf::Document::load(dir.path().join("out.pdf")).unwrap();
+}
+
+#[test]
+fn stdin_needs_an_output_and_something_to_read() {
+    let dir = common::temp_dir();
+    let output = optimize(dir.path(), &["-", "--suffix", ".small"], b"%PDF-1.4\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no name to give its output"));
+
+    let output = optimize(dir.path(), &["-", "-"], b"");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Nothing to read from stdin"));
+
+    let output = optimize(dir.path(), &["-", "-"], b"plain text\n");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}